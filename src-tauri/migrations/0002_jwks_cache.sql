@@ -0,0 +1,7 @@
+-- Cached JWKS document of the auth server (single row table)
+CREATE TABLE IF NOT EXISTS jwks_cache (
+    id INTEGER PRIMARY KEY DEFAULT 1 CHECK (id = 1),
+    document TEXT,
+    fetched_at DATETIME
+);
+INSERT OR IGNORE INTO jwks_cache (id) VALUES (1);
@@ -0,0 +1,5 @@
+-- Time of the last successful sync per domain (relations, conversations, ...)
+CREATE TABLE IF NOT EXISTS sync_status (
+    domain TEXT PRIMARY KEY,
+    last_success_at INTEGER NOT NULL
+);
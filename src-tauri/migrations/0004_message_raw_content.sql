@@ -0,0 +1,9 @@
+-- Original payload of messages this client could not decode, re-decoded after upgrades.
+-- raw_format is 'ws-frame' (binary WsMessage) or 'json' (messages API object).
+ALTER TABLE messages ADD COLUMN raw_format TEXT;
+ALTER TABLE messages ADD COLUMN raw_content BLOB;
+CREATE INDEX IF NOT EXISTS idx_messages_raw_content ON messages(message_id) WHERE raw_content IS NOT NULL;
+
+-- Unknown message and command types used to be stored as the next free discriminant
+UPDATE messages SET message_type = -1 WHERE message_type NOT IN (1, 2);
+UPDATE messages SET command_type = -1 WHERE command_type NOT IN (0, 1, 2, 3);
@@ -0,0 +1,19 @@
+-- One token pair per account database, with when it was issued and when it expires
+-- (unix milliseconds). Older builds could leave several rows behind; the newest one wins.
+CREATE TABLE oauth_tokens_new (
+      id INTEGER PRIMARY KEY CHECK (id = 1),
+      access_token TEXT NOT NULL,
+      refresh_token TEXT NOT NULL,
+      issued_at INTEGER NOT NULL,
+      expires_at INTEGER
+);
+
+INSERT INTO oauth_tokens_new (id, access_token, refresh_token, issued_at)
+SELECT 1, access_token, refresh_token,
+       CAST(strftime('%s', COALESCE(created_at, CURRENT_TIMESTAMP)) AS INTEGER) * 1000
+FROM oauth_tokens
+ORDER BY id DESC
+LIMIT 1;
+
+DROP TABLE oauth_tokens;
+ALTER TABLE oauth_tokens_new RENAME TO oauth_tokens;
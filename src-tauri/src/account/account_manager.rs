@@ -0,0 +1,1149 @@
+use crate::account::account_registry::{AccountEntry, AccountRegistry};
+use crate::app_config::{AppConfig, DatabaseKeyConfig};
+use crate::http_client::HttpClients;
+use crate::ripple_api::api_response::GroupMemberData;
+#[cfg(feature = "sqlite-store")]
+use crate::ripple_api::auth_token_parser::AuthTokenParser;
+use crate::ripple_api::oauth_client::OauthClient;
+use crate::ripple_api::token_validator::TokenValidator;
+use crate::ripple_api::RippleApi;
+use crate::ripple_syncer::event_emitter::EventEmitter;
+use crate::ripple_syncer::{DataSyncManager, RippleWsSyncHandler};
+use crate::ripple_ws::connection_stats::ConnectionStats;
+use crate::ripple_ws::{RippleWsManager, SyncAwareWsMessageHandler};
+use crate::store_engine::key_provider::{
+    has_databases, key_provider_with_fallback, load_key_source,
+};
+#[cfg(feature = "sqlite-store")]
+use crate::store_engine::key_provider::{key_provider, save_key_source};
+#[cfg(feature = "sqlite-store")]
+use crate::store_engine::store_engine::RippleStorage;
+#[cfg(feature = "sqlite-store")]
+use crate::store_engine::SqliteStore;
+use crate::store_engine::{close_store, create_store, BackupInfo, KeyProvider};
+use crate::DefaultStoreEngine;
+use oauth2::TokenResponse;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::{Mutex, RwLock};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Secret of the single database older builds kept in the app data dir.
+#[cfg(feature = "sqlite-store")]
+const LEGACY_KEY_NAME: &str = "ripple";
+
+#[derive(Clone, Serialize)]
+pub struct AccountInfo {
+    #[serde(rename = "userId")]
+    pub user_id: String,
+    #[serde(rename = "nickName")]
+    pub nick_name: Option<String>,
+    pub avatar: Option<String>,
+    pub active: bool,
+}
+
+type SessionWsManager<E> = RippleWsManager<
+    SyncAwareWsMessageHandler<RippleWsSyncHandler<DefaultStoreEngine, E>>,
+    DataSyncManager<DefaultStoreEngine>,
+>;
+
+/// Everything bound to one signed-in account. Dropped and rebuilt when the account changes.
+struct AccountSession<E: EventEmitter> {
+    user_id: String,
+    ripple_api: RippleApi<DefaultStoreEngine>,
+    data_sync: DataSyncManager<DefaultStoreEngine>,
+    ws_manager: Arc<SessionWsManager<E>>,
+    connection_stats: Arc<ConnectionStats>,
+    emitter: E,
+    store: DefaultStoreEngine,
+    /// Background group member sync started after the critical startup phase.
+    group_members_sync: std::sync::Mutex<Option<JoinHandle<()>>>,
+}
+
+/// Owns the account registry and the session of the active account.
+///
+/// Each account gets its own database under `accounts/<user_id>` and its own secret from the
+/// key provider, so switching accounts never has to wipe another account's data. Every session
+/// reports to the UI through a clone of `emitter`.
+pub struct AccountManager<E: EventEmitter> {
+    emitter: E,
+    app_config: AppConfig,
+    #[cfg(feature = "sqlite-store")]
+    app_data_dir: PathBuf,
+    accounts_dir: PathBuf,
+    keys: std::sync::RwLock<Arc<dyn KeyProvider>>,
+    http_clients: HttpClients,
+    oauth_client: OauthClient,
+    /// Checks the first token of a login before anything is created for its account.
+    token_validator: TokenValidator<DefaultStoreEngine>,
+    registry: Mutex<AccountRegistry>,
+    session: RwLock<Option<Arc<AccountSession<E>>>>,
+}
+
+impl<E: EventEmitter> AccountManager<E> {
+    pub async fn new(
+        emitter: E,
+        app_config: AppConfig,
+        app_data_dir: &Path,
+        http_clients: HttpClients,
+        oauth_client: OauthClient,
+    ) -> anyhow::Result<Self> {
+        let accounts_dir = app_data_dir.join("accounts");
+        fs::create_dir_all(&accounts_dir)?;
+        let registry = AccountRegistry::load(app_data_dir.join("accounts.json"))?;
+        let key_source = load_key_source(app_data_dir, &app_config.database_key)?;
+        let keys = key_provider_with_fallback(
+            app_data_dir,
+            &key_source,
+            has_databases(app_data_dir) || has_databases(&accounts_dir),
+        )?;
+        let token_validator = TokenValidator::without_cache(&app_config, http_clients.api.clone());
+        let manager = AccountManager {
+            emitter,
+            app_config,
+            #[cfg(feature = "sqlite-store")]
+            app_data_dir: app_data_dir.to_path_buf(),
+            accounts_dir,
+            keys: std::sync::RwLock::new(keys),
+            http_clients,
+            oauth_client,
+            token_validator,
+            registry: Mutex::new(registry),
+            session: RwLock::new(None),
+        };
+        if let Err(e) = manager.migrate_account_dirs().await {
+            error!("Failed to rename account directories: {}", e);
+        }
+        #[cfg(feature = "sqlite-store")]
+        if let Err(e) = manager.migrate_legacy_stores(app_data_dir).await {
+            error!("Failed to migrate legacy databases: {}", e);
+        }
+        let active_user_id = manager
+            .registry
+            .lock()
+            .await
+            .active_user_id()
+            .map(str::to_string);
+        // A passphrase-protected store stays closed until `unlock_database`
+        if let Some(user_id) = active_user_id.filter(|_| !manager.keys().is_locked()) {
+            let session = manager.open_session(&user_id).await?;
+            manager.session.write().await.replace(session);
+        }
+        Ok(manager)
+    }
+
+    /// Whether the active account's database waits for the passphrase.
+    pub async fn database_locked(&self) -> bool {
+        self.keys().is_locked() && self.registry.lock().await.active_user_id().is_some()
+    }
+
+    /// Opens the active account's database with a key derived from `passphrase`.
+    pub async fn unlock_database(&self, passphrase: &str) -> anyhow::Result<()> {
+        let keys = self.keys();
+        if !keys.is_locked() {
+            return Ok(());
+        }
+        if passphrase.is_empty() {
+            anyhow::bail!("The passphrase must not be empty");
+        }
+        keys.unlock(passphrase);
+        let active_user_id = self
+            .registry
+            .lock()
+            .await
+            .active_user_id()
+            .map(str::to_string);
+        if let Some(user_id) = active_user_id {
+            match self.open_session(&user_id).await {
+                Ok(session) => self.activate(session).await,
+                Err(e) => {
+                    keys.lock();
+                    warn!("Failed to unlock the database: {}", e);
+                    anyhow::bail!("Wrong passphrase");
+                }
+            }
+        }
+        info!("Unlocked the database");
+        Ok(())
+    }
+
+    /// Re-encrypts every account's database with secrets from `source` and keeps using it
+    /// from then on, overriding the config. `passphrase` is required for a passphrase source.
+    ///
+    /// Accounts already moved are moved back if one fails, so the databases never end up split
+    /// between two sources. The UI resumes through the `account-switched` event.
+    #[cfg(feature = "sqlite-store")]
+    pub async fn set_key_source(
+        &self,
+        source: &DatabaseKeyConfig,
+        passphrase: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let from = self.keys();
+        if from.is_locked() {
+            anyhow::bail!("Unlock the database before changing its key source");
+        }
+        let to = key_provider(source, &self.app_data_dir.join("keys"))?;
+        if let Some(passphrase) = passphrase.filter(|p| !p.is_empty()) {
+            to.unlock(passphrase);
+        }
+        if to.is_locked() {
+            anyhow::bail!("Enter a passphrase for the new key source");
+        }
+        let active = self.session.write().await.take();
+        if let Some(previous) = &active {
+            Self::close_session(previous).await;
+        }
+        let user_ids: Vec<String> = self
+            .registry
+            .lock()
+            .await
+            .accounts()
+            .iter()
+            .map(|a| a.user_id.clone())
+            .collect();
+        let cipher = &self.app_config.database_cipher;
+        let mut migrated = Vec::new();
+        let mut failure = None;
+        for user_id in &user_ids {
+            let (dir, key_name) = (self.account_dir(user_id), Self::key_name(user_id));
+            match SqliteStore::migrate_key(&dir, &key_name, from.as_ref(), to.as_ref(), cipher)
+                .await
+            {
+                Ok(()) => migrated.push((dir, key_name)),
+                Err(e) => {
+                    failure = Some(e.context(format!("Failed to re-encrypt account {}", user_id)));
+                    break;
+                }
+            }
+        }
+        let result = match failure {
+            Some(e) => {
+                for (dir, key_name) in &migrated {
+                    let undone =
+                        SqliteStore::migrate_key(dir, key_name, to.as_ref(), from.as_ref(), cipher)
+                            .await;
+                    if let Err(e) = undone {
+                        error!("Failed to move {} back to the old key: {}", key_name, e);
+                    }
+                }
+                Err(e)
+            }
+            None => {
+                save_key_source(&self.app_data_dir, source)?;
+                *self.keys.write().unwrap() = to;
+                info!("Database keys now come from the {}", self.keys().name());
+                Ok(())
+            }
+        };
+        // Reopen either way so a failed migration leaves the account usable
+        if let Some(previous) = active {
+            self.reopen_and_notify(&previous.user_id).await?;
+        }
+        result
+    }
+
+    #[cfg(feature = "memory-store")]
+    pub async fn set_key_source(
+        &self,
+        _source: &DatabaseKeyConfig,
+        _passphrase: Option<&str>,
+    ) -> anyhow::Result<()> {
+        anyhow::bail!("The in-memory store has no key source")
+    }
+
+    pub fn oauth_auth_url(&self) -> String {
+        self.oauth_client.auth_url()
+    }
+
+    pub fn oauth_state_equal(&self, state: &str) -> bool {
+        self.oauth_client.state_equal(state)
+    }
+
+    pub async fn ripple_api(&self) -> anyhow::Result<RippleApi<DefaultStoreEngine>> {
+        Ok(self.current_session().await?.ripple_api.clone())
+    }
+
+    pub async fn data_sync(&self) -> anyhow::Result<DataSyncManager<DefaultStoreEngine>> {
+        Ok(self.current_session().await?.data_sync.clone())
+    }
+
+    pub async fn store(&self) -> anyhow::Result<DefaultStoreEngine> {
+        Ok(self.current_session().await?.store.clone())
+    }
+
+    pub async fn connection_stats(&self) -> anyhow::Result<Arc<ConnectionStats>> {
+        Ok(self.current_session().await?.connection_stats.clone())
+    }
+
+    /// Members of a group, fetched from the server the first time the group is opened.
+    pub async fn group_members(&self, group_id: &str) -> anyhow::Result<Vec<GroupMemberData>> {
+        let session = self.current_session().await?;
+        session
+            .data_sync
+            .ensure_group_members(group_id, &session.emitter)
+            .await
+    }
+
+    pub async fn exists_token(&self) -> anyhow::Result<bool> {
+        match self.session.read().await.as_ref() {
+            Some(session) => session.data_sync.exists_token().await,
+            None => Ok(false),
+        }
+    }
+
+    /// Exchanges the authorization code and makes the signed-in user the active account,
+    /// adding it to the registry if this device has not seen it before.
+    pub async fn login_with_code(&self, code: String) -> anyhow::Result<()> {
+        let token_response = self.oauth_client.request_token(code).await?;
+        let claims = self
+            .token_validator
+            .validate(token_response.access_token().secret())
+            .await?;
+        let user_id = claims.get_sub();
+        let session = match self.session.read().await.as_ref() {
+            Some(current) if current.user_id == user_id => current.clone(),
+            _ => self.open_session(&user_id).await?,
+        };
+        session.ripple_api.oauth_save_token(&token_response).await?;
+        let mut registry = self.registry.lock().await;
+        if !registry.contains(&user_id) {
+            registry.upsert(AccountEntry {
+                user_id: user_id.clone(),
+                nick_name: None,
+                avatar: None,
+            })?;
+        }
+        registry.set_active(Some(user_id.clone()))?;
+        drop(registry);
+        self.activate(session).await;
+        info!("Signed in account {}", user_id);
+        Ok(())
+    }
+
+    /// Starts token refresh, the initial sync and the WebSocket for the active account.
+    ///
+    /// Only the critical sync phase is awaited; group members sync in the background.
+    pub async fn start_active_session(&self) -> anyhow::Result<()> {
+        let session = self.current_session().await?;
+        session.data_sync.start_token_refresh();
+        session.data_sync.init_with_events(&session.emitter).await?;
+        let data_sync = session.data_sync.clone();
+        let emitter = session.emitter.clone();
+        let concurrency = self.app_config.group_member_sync_concurrency;
+        let handle = tokio::spawn(async move {
+            if let Err(e) = data_sync
+                .sync_all_groups_members(concurrency, &emitter)
+                .await
+            {
+                warn!("Group members sync failed: {}", e);
+            }
+        });
+        if let Some(previous) = session.group_members_sync.lock().unwrap().replace(handle) {
+            previous.abort();
+        }
+        if let Some(profile) = session.data_sync.get_profile().await? {
+            self.registry.lock().await.upsert(AccountEntry {
+                user_id: session.user_id.clone(),
+                nick_name: Some(profile.nick_name),
+                avatar: profile.avatar,
+            })?;
+        }
+        let ws_manager = session.ws_manager.clone();
+        let ws_gateway_url = self.app_config.ws_gateway_url.clone();
+        tokio::spawn(async move {
+            if let Err(e) = ws_manager.start(&ws_gateway_url).await {
+                error!("Failed to start WebSocket: {}", e);
+            }
+        });
+        Ok(())
+    }
+
+    pub async fn list_accounts(&self) -> Vec<AccountInfo> {
+        let registry = self.registry.lock().await;
+        let active_user_id = registry.active_user_id();
+        registry
+            .accounts()
+            .iter()
+            .map(|a| AccountInfo {
+                user_id: a.user_id.clone(),
+                nick_name: a.nick_name.clone(),
+                avatar: a.avatar.clone(),
+                active: active_user_id == Some(a.user_id.as_str()),
+            })
+            .collect()
+    }
+
+    /// Makes another known account active. The UI resumes it through the normal login path.
+    pub async fn switch_account(&self, user_id: &str) -> anyhow::Result<()> {
+        if !self.registry.lock().await.contains(user_id) {
+            anyhow::bail!("Unknown account: {}", user_id);
+        }
+        if let Some(current) = self.session.read().await.as_ref() {
+            if current.user_id == user_id {
+                return Ok(());
+            }
+        }
+        // The UI reads the registry on the event, so it names the new account first
+        let previous = {
+            let mut registry = self.registry.lock().await;
+            let previous = registry.active_user_id().map(str::to_string);
+            registry.set_active(Some(user_id.to_string()))?;
+            previous
+        };
+        if let Err(e) = self.reopen_and_notify(user_id).await {
+            self.registry.lock().await.set_active(previous)?;
+            return Err(e);
+        }
+        info!("Switched to account {}", user_id);
+        Ok(())
+    }
+
+    /// Signs out the active account. Its local data is kept unless `wipe_local_data` is set.
+    pub async fn logout(&self, wipe_local_data: bool) -> anyhow::Result<()> {
+        let session = self.current_session().await?;
+        Self::stop_session(&session).await;
+        // Best effort: a failed revocation must not keep the user logged in
+        if let Err(e) = session.data_sync.revoke_token().await {
+            warn!("Failed to revoke tokens: {}", e);
+        }
+        session.data_sync.clear_token().await?;
+        if wipe_local_data {
+            session.data_sync.clear_all_data().await?;
+        }
+        Ok(())
+    }
+
+    /// Signs out the account if needed and deletes its database and its secret.
+    pub async fn remove_account(&self, user_id: &str) -> anyhow::Result<()> {
+        if !self.registry.lock().await.contains(user_id) {
+            anyhow::bail!("Unknown account: {}", user_id);
+        }
+        let is_active = self
+            .session
+            .read()
+            .await
+            .as_ref()
+            .is_some_and(|s| s.user_id == user_id);
+        let session = if is_active {
+            self.session.write().await.take()
+        } else {
+            None
+        };
+        match session {
+            Some(session) => {
+                Self::stop_session(&session).await;
+                if let Err(e) = session.data_sync.revoke_token().await {
+                    warn!("Failed to revoke tokens: {}", e);
+                }
+                // Tokens may live outside the account directory, in the keyring
+                session.data_sync.clear_token().await?;
+                // The account directory is deleted next
+                if let Err(e) = close_store(&session.store).await {
+                    warn!("Failed to close the store of {}: {}", user_id, e);
+                }
+            }
+            None => self.discard_tokens(user_id).await?,
+        }
+        self.registry.lock().await.remove(user_id)?;
+        let account_dir = self.account_dir(user_id);
+        if account_dir.exists() {
+            fs::remove_dir_all(&account_dir)?;
+        }
+        #[cfg(feature = "sqlite-store")]
+        SqliteStore::delete_cipher_key(self.keys().as_ref(), &Self::key_name(user_id))?;
+        info!("Removed account {}", user_id);
+        Ok(())
+    }
+
+    /// Revokes and clears the tokens of an account without a session, opening only its store.
+    /// An account whose database is gone has nothing left to revoke.
+    #[cfg(feature = "sqlite-store")]
+    async fn discard_tokens(&self, user_id: &str) -> anyhow::Result<()> {
+        let account_dir = self.account_dir(user_id);
+        if !account_dir.join("sqlite.db").exists() {
+            return Ok(());
+        }
+        let store = create_store(
+            account_dir,
+            &Self::key_name(user_id),
+            self.keys().as_ref(),
+            &self.app_config,
+        )
+        .await?;
+        let discarded = async {
+            if let Some(token) = store.tokens().get_token().await? {
+                if let Err(e) = self
+                    .oauth_client
+                    .revoke_tokens(token.access_token, token.refresh_token)
+                    .await
+                {
+                    warn!("Failed to revoke tokens: {}", e);
+                }
+            }
+            store.tokens().clear_token().await
+        }
+        .await;
+        let closed = store.close().await;
+        discarded.and(closed)
+    }
+
+    #[cfg(feature = "memory-store")]
+    async fn discard_tokens(&self, _user_id: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Writes the active account's database to `path`, encrypted with `passphrase`.
+    #[cfg(feature = "sqlite-store")]
+    pub async fn backup_database(
+        &self,
+        path: &Path,
+        passphrase: &str,
+    ) -> anyhow::Result<BackupInfo> {
+        self.current_session()
+            .await?
+            .store
+            .backup_to(path, passphrase)
+            .await
+    }
+
+    #[cfg(feature = "memory-store")]
+    pub async fn backup_database(
+        &self,
+        _path: &Path,
+        _passphrase: &str,
+    ) -> anyhow::Result<BackupInfo> {
+        anyhow::bail!("The in-memory store cannot be backed up")
+    }
+
+    /// Replaces the active account's database with the backup at `path`, keeping this device's
+    /// sign-in and device id. The backup is restored and checked beside the live database
+    /// first, so a failed restore leaves the account as it was. The UI reloads through the
+    /// `account-switched` event, as after switching accounts.
+    #[cfg(feature = "sqlite-store")]
+    pub async fn restore_database(
+        &self,
+        path: &Path,
+        passphrase: &str,
+    ) -> anyhow::Result<BackupInfo> {
+        let session = self.current_session().await?;
+        let user_id = session.user_id.clone();
+        let account_dir = self.account_dir(&user_id);
+        let staging_dir = account_dir.join("restore");
+        if staging_dir.exists() {
+            fs::remove_dir_all(&staging_dir)?;
+        }
+        fs::create_dir_all(&staging_dir)?;
+        let restored = async {
+            let (store, info) = SqliteStore::restore_from(
+                path,
+                passphrase,
+                staging_dir.clone(),
+                &Self::key_name(&user_id),
+                self.keys().as_ref(),
+                &self.app_config.database_cipher,
+            )
+            .await?;
+            let checked = async {
+                if let Some(owner) = store.get_stored_user_id().await? {
+                    if owner != user_id {
+                        anyhow::bail!("The backup belongs to another account ({})", owner);
+                    }
+                }
+                if let Some(token) = session.store.tokens().get_token().await? {
+                    store.tokens().save_token(&token).await?;
+                }
+                if let Some(device_id) = session.store.get_device_id().await? {
+                    store.save_device_id(&device_id).await?;
+                }
+                anyhow::Ok(())
+            }
+            .await;
+            // Only a checkpointed database can be moved without its WAL
+            let closed = store.close().await;
+            checked.and(closed).map(|()| info)
+        }
+        .await;
+        let info = match restored {
+            Ok(info) => info,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&staging_dir);
+                return Err(e);
+            }
+        };
+
+        if let Some(previous) = self.session.write().await.take() {
+            Self::close_session(&previous).await;
+        }
+        let replaced = SqliteStore::move_database(
+            &staging_dir.join("sqlite.db"),
+            &account_dir.join("sqlite.db"),
+        );
+        // A leftover staging directory is cleared by the next restore
+        if let Err(e) = fs::remove_dir_all(&staging_dir) {
+            warn!("Failed to remove {}: {}", staging_dir.display(), e);
+        }
+        // Reopen either way so a failed restore leaves the previous database usable
+        self.reopen_and_notify(&user_id).await?;
+        replaced.map_err(|e| {
+            anyhow::anyhow!("Failed to replace the database with the backup: {}", e)
+        })?;
+        info!("Restored the database of account {}", user_id);
+        Ok(info)
+    }
+
+    #[cfg(feature = "memory-store")]
+    pub async fn restore_database(
+        &self,
+        _path: &Path,
+        _passphrase: &str,
+    ) -> anyhow::Result<BackupInfo> {
+        anyhow::bail!("The in-memory store cannot be restored from a backup")
+    }
+
+    /// Re-encrypts the active account's database under a new secret from the key provider. The session is
+    /// restarted around the rekey; the UI resumes it through the `account-switched` event.
+    #[cfg(feature = "sqlite-store")]
+    pub async fn rotate_db_key(&self) -> anyhow::Result<()> {
+        let session = self.current_session().await?;
+        let user_id = session.user_id.clone();
+        if let Some(previous) = self.session.write().await.take() {
+            Self::close_session(&previous).await;
+        }
+        let rotated = SqliteStore::rotate_cipher_key(
+            &self.account_dir(&user_id),
+            &Self::key_name(&user_id),
+            self.keys().as_ref(),
+            &self.app_config.database_cipher,
+        )
+        .await;
+        // Reopen either way so a failed rotation leaves the account usable
+        self.reopen_and_notify(&user_id).await?;
+        rotated?;
+        info!("Rotated the database key of account {}", user_id);
+        Ok(())
+    }
+
+    #[cfg(feature = "memory-store")]
+    pub async fn rotate_db_key(&self) -> anyhow::Result<()> {
+        anyhow::bail!("The in-memory store has no key to rotate")
+    }
+
+    async fn current_session(&self) -> anyhow::Result<Arc<AccountSession<E>>> {
+        self.session
+            .read()
+            .await
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("No active account. Please login."))
+    }
+
+    /// Replaces the active session, shutting the previous one down.
+    async fn activate(&self, session: Arc<AccountSession<E>>) {
+        let previous = self.session.write().await.replace(session.clone());
+        if let Some(previous) = previous {
+            if !Arc::ptr_eq(&previous, &session) {
+                Self::close_session(&previous).await;
+            }
+        }
+    }
+
+    /// Opens the session of `user_id`, makes it the active one and lets the UI resume it
+    /// through the `account-switched` event.
+    async fn reopen_and_notify(&self, user_id: &str) -> anyhow::Result<()> {
+        let session = self.open_session(user_id).await?;
+        self.activate(session).await;
+        if let Err(e) = self.emitter.emit_account_switched(user_id.to_string()) {
+            warn!("{}", e);
+        }
+        Ok(())
+    }
+
+    /// Stops the session and closes its store. A failed checkpoint leaves the WAL beside the
+    /// database, which the next open replays, so it is only logged.
+    async fn close_session(session: &AccountSession<E>) {
+        Self::stop_session(session).await;
+        if let Err(e) = close_store(&session.store).await {
+            warn!("Failed to close the store of {}: {}", session.user_id, e);
+        }
+    }
+
+    async fn stop_session(session: &AccountSession<E>) {
+        // Ignore errors if the WebSocket was never started
+        let _ = session.ws_manager.stop().await;
+        session.data_sync.stop_token_refresh();
+        if let Some(handle) = session.group_members_sync.lock().unwrap().take() {
+            handle.abort();
+        }
+    }
+
+    async fn open_session(&self, user_id: &str) -> anyhow::Result<Arc<AccountSession<E>>> {
+        let account_dir = self.account_dir(user_id);
+        fs::create_dir_all(&account_dir)?;
+        let store = create_store(
+            account_dir,
+            &Self::key_name(user_id),
+            self.keys().as_ref(),
+            &self.app_config,
+        )
+        .await?;
+        let ripple_api = RippleApi::from_config(
+            &self.app_config,
+            self.http_clients.clone(),
+            self.oauth_client.clone(),
+            store.clone(),
+        );
+        let data_sync = DataSyncManager::new(ripple_api.clone(), store.clone());
+        let emitter = self.emitter.clone();
+        let syncer = RippleWsSyncHandler::new(data_sync.clone(), emitter.clone());
+        let connection_stats = Arc::new(ConnectionStats::default());
+        let sync_aware_msg_handler =
+            SyncAwareWsMessageHandler::new(syncer, connection_stats.clone());
+        let ws_manager = Arc::new(RippleWsManager::new(
+            sync_aware_msg_handler,
+            data_sync.clone(),
+            connection_stats.clone(),
+        ));
+        // The refresh token was rejected: drop the live session and send the UI to login
+        let expired_ws_manager = ws_manager.clone();
+        let expired_emitter = emitter.clone();
+        ripple_api
+            .token_manager()
+            .set_session_expired_handler(move || {
+                let ws_manager = expired_ws_manager.clone();
+                let emitter = expired_emitter.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = ws_manager.stop().await {
+                        error!("Failed to stop WebSocket on session expiry: {}", e);
+                    }
+                    if let Err(e) = emitter.emit_session_expired() {
+                        warn!("{}", e);
+                    }
+                });
+            });
+        Ok(Arc::new(AccountSession {
+            user_id: user_id.to_string(),
+            ripple_api,
+            data_sync,
+            ws_manager,
+            connection_stats,
+            emitter,
+            store,
+            group_members_sync: std::sync::Mutex::new(None),
+        }))
+    }
+
+    fn account_dir(&self, user_id: &str) -> PathBuf {
+        self.accounts_dir.join(Self::account_dir_name(user_id))
+    }
+
+    /// `sub` comes from the auth server; keep it from escaping the accounts dir. Plain
+    /// alphanumeric ids are used as they are, anything else is hex-encoded behind an `x-`
+    /// prefix, which no plain id can have, so two ids never share a directory.
+    fn account_dir_name(user_id: &str) -> String {
+        if !user_id.is_empty() && user_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return user_id.to_string();
+        }
+        let hex: String = user_id.bytes().map(|b| format!("{:02x}", b)).collect();
+        format!("x-{}", hex)
+    }
+
+    /// The directory name older builds derived from `user_id`, which could collide.
+    fn legacy_account_dir_name(user_id: &str) -> String {
+        user_id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
+
+    /// Renames account directories created under the old, lossy naming scheme.
+    async fn migrate_account_dirs(&self) -> anyhow::Result<()> {
+        let registry = self.registry.lock().await;
+        for account in registry.accounts() {
+            let legacy_dir = self
+                .accounts_dir
+                .join(Self::legacy_account_dir_name(&account.user_id));
+            let account_dir = self.account_dir(&account.user_id);
+            if legacy_dir != account_dir && legacy_dir.exists() && !account_dir.exists() {
+                fs::rename(&legacy_dir, &account_dir)?;
+                info!("Renamed the directory of account {}", account.user_id);
+            }
+        }
+        Ok(())
+    }
+
+    fn keys(&self) -> Arc<dyn KeyProvider> {
+        self.keys.read().unwrap().clone()
+    }
+
+    fn key_name(user_id: &str) -> String {
+        format!("ripple-{}", user_id)
+    }
+
+    /// Moves the pre-multi-account databases into the account directories of the users they
+    /// belong to: the one in the app data dir and those that `RIPPLE_INSTANCE` runs kept in
+    /// `instance_*` subdirectories. They all share one secret, which each account gets a copy
+    /// of; it is deleted once every database has moved.
+    #[cfg(feature = "sqlite-store")]
+    async fn migrate_legacy_stores(&self, app_data_dir: &Path) -> anyhow::Result<()> {
+        let mut legacy_dirs = vec![app_data_dir.to_path_buf()];
+        for entry in fs::read_dir(app_data_dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir()
+                && entry.file_name().to_string_lossy().starts_with("instance_")
+            {
+                legacy_dirs.push(entry.path());
+            }
+        }
+        legacy_dirs.retain(|dir| dir.join("sqlite.db").exists());
+        if legacy_dirs.is_empty() {
+            return Ok(());
+        }
+        let mut all_moved = true;
+        for dir in &legacy_dirs {
+            // Only the app data dir's own database was the active account
+            let activate = dir.as_path() == app_data_dir;
+            if let Err(e) = self.migrate_legacy_store(dir, activate).await {
+                error!("Failed to migrate {}: {}", dir.display(), e);
+                all_moved = false;
+            }
+        }
+        if all_moved {
+            SqliteStore::delete_cipher_key(self.keys().as_ref(), LEGACY_KEY_NAME)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "sqlite-store")]
+    async fn migrate_legacy_store(&self, dir: &Path, activate: bool) -> anyhow::Result<()> {
+        let legacy_db = dir.join("sqlite.db");
+        let keys = self.keys();
+        let store = SqliteStore::new(
+            dir.to_path_buf(),
+            LEGACY_KEY_NAME,
+            keys.as_ref(),
+            &self.app_config.database_cipher,
+        )
+        .await?;
+        let user_id = match store.tokens().get_token().await? {
+            Some(token) => {
+                Some(AuthTokenParser::decode_jwt_payload(&token.access_token)?.get_sub())
+            }
+            None => store.get_stored_user_id().await?,
+        };
+        store.close().await?;
+        let user_id = match user_id {
+            Some(id) => id,
+            None => {
+                info!(
+                    "Legacy database {} has no account, removing it",
+                    dir.display()
+                );
+                fs::remove_file(&legacy_db)?;
+                return Ok(());
+            }
+        };
+        let account_dir = self.account_dir(&user_id);
+        if account_dir.join("sqlite.db").exists() {
+            anyhow::bail!("Account {} already has a database", user_id);
+        }
+        let key_name = Self::key_name(&user_id);
+        let secret = keys.key(LEGACY_KEY_NAME)?;
+        if keys.existing_key(&key_name)?.as_deref() != Some(secret.as_str()) {
+            keys.set_key(&key_name, &secret)?;
+        }
+        fs::create_dir_all(&account_dir)?;
+        SqliteStore::move_database(&legacy_db, &account_dir.join("sqlite.db"))?;
+        let mut registry = self.registry.lock().await;
+        registry.upsert(AccountEntry {
+            user_id: user_id.clone(),
+            nick_name: None,
+            avatar: None,
+        })?;
+        if activate || registry.active_user_id().is_none() {
+            registry.set_active(Some(user_id.clone()))?;
+        }
+        info!("Migrated legacy database to account {}", user_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_gateway::{MockGateway, RecordingEmitter};
+    use crate::ripple_syncer::ui_event::UIEvent;
+    use crate::store_engine::TempDir;
+    use serde_json::json;
+
+    const ALICE: &str = "1001";
+    const BOB: &str = "1002";
+
+    async fn gateway_with_users() -> MockGateway {
+        let gateway = MockGateway::start().await.unwrap();
+        gateway.state().add_user(ALICE, "Alice");
+        gateway.state().add_user(BOB, "Bob");
+        gateway
+    }
+
+    /// A manager on `dir` whose databases are keyed from a file, as the keyring may be missing.
+    async fn manager(
+        gateway: &MockGateway,
+        dir: &TempDir,
+    ) -> (AccountManager<RecordingEmitter>, RecordingEmitter) {
+        let key_file = dir.path().join("db.key");
+        if !key_file.exists() {
+            fs::write(&key_file, "first secret").unwrap();
+        }
+        let mut config = gateway.app_config();
+        config.database_key = DatabaseKeyConfig::File { path: key_file };
+        let http_clients = HttpClients::new(&config.http).unwrap();
+        let oauth_client = OauthClient::new(&config, http_clients.api.clone()).unwrap();
+        let emitter = RecordingEmitter::default();
+        let manager = AccountManager::new(
+            emitter.clone(),
+            config,
+            dir.path(),
+            http_clients,
+            oauth_client,
+        )
+        .await
+        .unwrap();
+        (manager, emitter)
+    }
+
+    async fn sign_in(
+        manager: &AccountManager<RecordingEmitter>,
+        gateway: &MockGateway,
+        user_id: &str,
+    ) {
+        manager.oauth_auth_url();
+        let code = gateway.state().authorize(user_id);
+        manager.login_with_code(code).await.unwrap();
+    }
+
+    async fn active_user_id(manager: &AccountManager<RecordingEmitter>) -> Option<String> {
+        let session = manager.session.read().await;
+        session.as_ref().map(|s| s.user_id.clone())
+    }
+
+    async fn listed_accounts(manager: &AccountManager<RecordingEmitter>) -> Vec<(String, bool)> {
+        let accounts = manager.list_accounts().await;
+        accounts
+            .into_iter()
+            .map(|a| (a.user_id, a.active))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn switching_reopens_the_other_account() {
+        let gateway = gateway_with_users().await;
+        let dir = TempDir::new("ripple-accounts");
+        let (manager, emitter) = manager(&gateway, &dir).await;
+        sign_in(&manager, &gateway, ALICE).await;
+        sign_in(&manager, &gateway, BOB).await;
+        assert_eq!(
+            listed_accounts(&manager).await,
+            [(ALICE.to_string(), false), (BOB.to_string(), true)]
+        );
+        assert_ne!(manager.account_dir(ALICE), manager.account_dir(BOB));
+
+        manager.switch_account(ALICE).await.unwrap();
+        assert_eq!(active_user_id(&manager).await.as_deref(), Some(ALICE));
+        // An in-memory store starts empty each time its account is opened
+        #[cfg(feature = "sqlite-store")]
+        assert!(manager.exists_token().await.unwrap());
+        assert_eq!(emitter.payloads(UIEvent::AccountSwitched), [json!(ALICE)]);
+
+        // The active account is not reopened, and unknown ones are refused
+        manager.switch_account(ALICE).await.unwrap();
+        assert!(manager.switch_account("1003").await.is_err());
+        assert_eq!(emitter.payloads(UIEvent::AccountSwitched).len(), 1);
+
+        drop(manager);
+        let (manager, _) = self::manager(&gateway, &dir).await;
+        assert_eq!(active_user_id(&manager).await.as_deref(), Some(ALICE));
+    }
+
+    #[cfg(feature = "sqlite-store")]
+    #[tokio::test]
+    async fn failed_switch_keeps_the_active_account() {
+        let gateway = gateway_with_users().await;
+        let dir = TempDir::new("ripple-accounts");
+        let (manager, emitter) = manager(&gateway, &dir).await;
+        sign_in(&manager, &gateway, BOB).await;
+        sign_in(&manager, &gateway, ALICE).await;
+        fs::write(manager.account_dir(BOB).join("sqlite.db"), "not a database").unwrap();
+
+        assert!(manager.switch_account(BOB).await.is_err());
+        assert_eq!(active_user_id(&manager).await.as_deref(), Some(ALICE));
+        assert_eq!(
+            listed_accounts(&manager).await,
+            [(BOB.to_string(), false), (ALICE.to_string(), true)]
+        );
+        assert!(manager.exists_token().await.unwrap());
+        assert!(emitter.payloads(UIEvent::AccountSwitched).is_empty());
+    }
+
+    #[tokio::test]
+    async fn removing_accounts_deletes_their_data() {
+        let gateway = gateway_with_users().await;
+        let dir = TempDir::new("ripple-accounts");
+        let (manager, _) = manager(&gateway, &dir).await;
+        sign_in(&manager, &gateway, ALICE).await;
+        sign_in(&manager, &gateway, BOB).await;
+        let (alice_dir, bob_dir) = (manager.account_dir(ALICE), manager.account_dir(BOB));
+        assert!(alice_dir.exists() && bob_dir.exists());
+
+        manager.remove_account(ALICE).await.unwrap();
+        assert!(!alice_dir.exists());
+        assert_eq!(active_user_id(&manager).await.as_deref(), Some(BOB));
+        assert_eq!(listed_accounts(&manager).await, [(BOB.to_string(), true)]);
+
+        manager.remove_account(BOB).await.unwrap();
+        assert!(!bob_dir.exists());
+        assert_eq!(active_user_id(&manager).await, None);
+        assert!(listed_accounts(&manager).await.is_empty());
+        assert!(!manager.exists_token().await.unwrap());
+        assert!(manager.remove_account(BOB).await.is_err());
+    }
+
+    #[cfg(feature = "sqlite-store")]
+    #[tokio::test]
+    async fn key_source_change_reencrypts_every_account() {
+        let gateway = gateway_with_users().await;
+        let dir = TempDir::new("ripple-accounts");
+        let (manager, _) = manager(&gateway, &dir).await;
+        sign_in(&manager, &gateway, ALICE).await;
+        sign_in(&manager, &gateway, BOB).await;
+        let new_key = dir.path().join("new.key");
+        fs::write(&new_key, "second secret").unwrap();
+        let source = DatabaseKeyConfig::File { path: new_key };
+
+        manager.set_key_source(&source, None).await.unwrap();
+        assert_eq!(
+            load_key_source(dir.path(), &DatabaseKeyConfig::Passphrase).unwrap(),
+            source
+        );
+        // Only the new secret can open the accounts now
+        fs::write(dir.path().join("db.key"), "wrong secret").unwrap();
+        manager.switch_account(ALICE).await.unwrap();
+        assert!(manager.exists_token().await.unwrap());
+    }
+
+    #[cfg(feature = "sqlite-store")]
+    #[tokio::test]
+    async fn failed_key_source_change_moves_accounts_back() {
+        let gateway = gateway_with_users().await;
+        let dir = TempDir::new("ripple-accounts");
+        let (manager, emitter) = manager(&gateway, &dir).await;
+        sign_in(&manager, &gateway, ALICE).await;
+        sign_in(&manager, &gateway, BOB).await;
+        manager.switch_account(ALICE).await.unwrap();
+        let store = manager.store().await.unwrap();
+        store.save_sync_timestamp("relations", 7).await.unwrap();
+        // Alice is re-encrypted first, then Bob fails
+        fs::write(manager.account_dir(BOB).join("sqlite.db"), "not a database").unwrap();
+        let new_key = dir.path().join("new.key");
+        fs::write(&new_key, "second secret").unwrap();
+
+        let source = DatabaseKeyConfig::File { path: new_key };
+        let e = manager.set_key_source(&source, None).await.unwrap_err();
+        assert!(
+            e.to_string().contains("Failed to re-encrypt account 1002"),
+            "{e}"
+        );
+        assert_eq!(
+            load_key_source(dir.path(), &DatabaseKeyConfig::Passphrase).unwrap(),
+            DatabaseKeyConfig::Passphrase
+        );
+        // Reopened with the old secret, so Alice's database was moved back to it
+        assert_eq!(active_user_id(&manager).await.as_deref(), Some(ALICE));
+        let store = manager.store().await.unwrap();
+        assert_eq!(
+            store.get_sync_timestamps().await.unwrap(),
+            [("relations".to_string(), 7)]
+        );
+        assert_eq!(
+            emitter.payloads(UIEvent::AccountSwitched).last(),
+            Some(&json!(ALICE))
+        );
+    }
+
+    #[cfg(feature = "sqlite-store")]
+    #[tokio::test]
+    async fn restore_brings_back_the_backup_and_keeps_the_sign_in() {
+        let gateway = gateway_with_users().await;
+        let dir = TempDir::new("ripple-accounts");
+        let (manager, emitter) = manager(&gateway, &dir).await;
+        sign_in(&manager, &gateway, ALICE).await;
+        let store = manager.store().await.unwrap();
+        store.save_user_id(ALICE).await.unwrap();
+        store.save_sync_timestamp("relations", 1).await.unwrap();
+        let backup = dir.path().join("alice.backup");
+        manager
+            .backup_database(&backup, "backup pass")
+            .await
+            .unwrap();
+        store.save_sync_timestamp("relations", 2).await.unwrap();
+
+        manager
+            .restore_database(&backup, "backup pass")
+            .await
+            .unwrap();
+        let store = manager.store().await.unwrap();
+        assert_eq!(
+            store.get_sync_timestamps().await.unwrap(),
+            [("relations".to_string(), 1)]
+        );
+        assert!(manager.exists_token().await.unwrap());
+        assert_eq!(emitter.payloads(UIEvent::AccountSwitched), [json!(ALICE)]);
+        assert!(!manager.account_dir(ALICE).join("restore").exists());
+    }
+
+    #[cfg(feature = "sqlite-store")]
+    #[tokio::test]
+    async fn restore_refuses_another_accounts_backup() {
+        let gateway = gateway_with_users().await;
+        let dir = TempDir::new("ripple-accounts");
+        let (manager, emitter) = manager(&gateway, &dir).await;
+        sign_in(&manager, &gateway, BOB).await;
+        manager
+            .store()
+            .await
+            .unwrap()
+            .save_user_id(BOB)
+            .await
+            .unwrap();
+        let backup = dir.path().join("bob.backup");
+        manager
+            .backup_database(&backup, "backup pass")
+            .await
+            .unwrap();
+        sign_in(&manager, &gateway, ALICE).await;
+        let store = manager.store().await.unwrap();
+        store.save_user_id(ALICE).await.unwrap();
+        store.save_sync_timestamp("relations", 5).await.unwrap();
+
+        let e = manager
+            .restore_database(&backup, "backup pass")
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("belongs to another account"), "{e}");
+        // The live database was never closed
+        assert_eq!(
+            store.get_sync_timestamps().await.unwrap(),
+            [("relations".to_string(), 5)]
+        );
+        assert_eq!(active_user_id(&manager).await.as_deref(), Some(ALICE));
+        assert!(emitter.payloads(UIEvent::AccountSwitched).is_empty());
+        assert!(!manager.account_dir(ALICE).join("restore").exists());
+    }
+}
@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountEntry {
+    #[serde(rename = "userId")]
+    pub user_id: String,
+    #[serde(rename = "nickName", default)]
+    pub nick_name: Option<String>,
+    #[serde(default)]
+    pub avatar: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RegistryFile {
+    active_user_id: Option<String>,
+    accounts: Vec<AccountEntry>,
+}
+
+/// List of accounts signed in on this device, persisted as `accounts.json` in the app data dir.
+pub struct AccountRegistry {
+    path: PathBuf,
+    file: RegistryFile,
+}
+
+impl AccountRegistry {
+    pub fn load(path: PathBuf) -> anyhow::Result<Self> {
+        let file = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            RegistryFile::default()
+        };
+        Ok(AccountRegistry { path, file })
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        fs::write(&self.path, serde_json::to_string_pretty(&self.file)?)?;
+        Ok(())
+    }
+
+    pub fn accounts(&self) -> &[AccountEntry] {
+        &self.file.accounts
+    }
+
+    pub fn active_user_id(&self) -> Option<&str> {
+        self.file.active_user_id.as_deref()
+    }
+
+    pub fn contains(&self, user_id: &str) -> bool {
+        self.file.accounts.iter().any(|a| a.user_id == user_id)
+    }
+
+    /// Adds the account or refreshes its cached profile fields.
+    pub fn upsert(&mut self, entry: AccountEntry) -> anyhow::Result<()> {
+        match self
+            .file
+            .accounts
+            .iter_mut()
+            .find(|a| a.user_id == entry.user_id)
+        {
+            Some(existing) => *existing = entry,
+            None => self.file.accounts.push(entry),
+        }
+        self.save()
+    }
+
+    pub fn set_active(&mut self, user_id: Option<String>) -> anyhow::Result<()> {
+        self.file.active_user_id = user_id;
+        self.save()
+    }
+
+    pub fn remove(&mut self, user_id: &str) -> anyhow::Result<()> {
+        self.file.accounts.retain(|a| a.user_id != user_id);
+        if self.file.active_user_id.as_deref() == Some(user_id) {
+            self.file.active_user_id = None;
+        }
+        self.save()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store_engine::TempDir;
+
+    fn entry(user_id: &str, nick_name: Option<&str>) -> AccountEntry {
+        AccountEntry {
+            user_id: user_id.to_string(),
+            nick_name: nick_name.map(str::to_string),
+            avatar: None,
+        }
+    }
+
+    #[test]
+    fn accounts_survive_a_reload() {
+        let dir = TempDir::new("ripple-registry");
+        let path = dir.path().join("accounts.json");
+        let mut registry = AccountRegistry::load(path.clone()).unwrap();
+        assert!(registry.accounts().is_empty());
+        assert_eq!(registry.active_user_id(), None);
+
+        registry.upsert(entry("1001", None)).unwrap();
+        registry.upsert(entry("1002", Some("bob"))).unwrap();
+        registry.upsert(entry("1001", Some("alice"))).unwrap();
+        registry.set_active(Some("1002".to_string())).unwrap();
+
+        let registry = AccountRegistry::load(path).unwrap();
+        let accounts: Vec<_> = registry
+            .accounts()
+            .iter()
+            .map(|a| (a.user_id.as_str(), a.nick_name.as_deref()))
+            .collect();
+        assert_eq!(accounts, [("1001", Some("alice")), ("1002", Some("bob"))]);
+        assert_eq!(registry.active_user_id(), Some("1002"));
+        assert!(registry.contains("1001"));
+        assert!(!registry.contains("1003"));
+    }
+
+    #[test]
+    fn removing_the_active_account_clears_it() {
+        let dir = TempDir::new("ripple-registry");
+        let path = dir.path().join("accounts.json");
+        let mut registry = AccountRegistry::load(path.clone()).unwrap();
+        registry.upsert(entry("1001", None)).unwrap();
+        registry.upsert(entry("1002", None)).unwrap();
+        registry.set_active(Some("1001".to_string())).unwrap();
+
+        registry.remove("1002").unwrap();
+        assert_eq!(registry.active_user_id(), Some("1001"));
+        registry.remove("1001").unwrap();
+
+        let registry = AccountRegistry::load(path).unwrap();
+        assert!(registry.accounts().is_empty());
+        assert_eq!(registry.active_user_id(), None);
+    }
+}
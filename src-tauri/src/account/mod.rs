@@ -0,0 +1,4 @@
+pub mod account_manager;
+pub mod account_registry;
+
+pub use account_manager::AccountManager;
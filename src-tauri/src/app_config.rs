@@ -1,17 +1,313 @@
 use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AppConfig {
-    #[serde(skip, default)]
-    pub is_dev: bool,
     pub signup_url: String,
     pub oauth2_client_id: String,
     pub oauth2_client_secret: String,
     pub oauth2_auth_url: String,
     pub oauth2_token_url: String,
+    /// RFC 7009 revocation endpoint; tokens are only dropped locally on logout when unset.
+    #[serde(default)]
+    pub oauth2_revocation_url: Option<String>,
+    /// Expected `iss` claim of access tokens; not checked when unset.
+    #[serde(default)]
+    pub oauth2_issuer: Option<String>,
+    /// JWKS endpoint used to verify access token signatures; not verified when unset.
+    #[serde(default)]
+    pub oauth2_jwks_url: Option<String>,
     pub callback_server_addr: String,
     pub oauth2_redirect_uri: String,
     pub upload_gateway_url: String,
     pub api_gateway_url: String,
     pub ws_gateway_url: String,
+    /// Maximum number of groups whose members are synced at the same time after login. `0`
+    /// counts as 1.
+    #[serde(default = "default_group_member_sync_concurrency")]
+    pub group_member_sync_concurrency: usize,
+    /// Retry behaviour of REST calls after transient failures (5xx gateway errors, 429,
+    /// connection resets and timeouts).
+    #[serde(default)]
+    pub api_retry: ApiRetryConfig,
+    /// Timeouts, connection pooling and proxy of the HTTP client used for REST, OAuth and
+    /// upload requests.
+    #[serde(default)]
+    pub http: HttpClientConfig,
+    /// SQLCipher settings of the local database.
+    #[serde(default)]
+    pub database_cipher: DatabaseCipherConfig,
+    /// Where the secrets encrypting the local databases come from.
+    #[serde(default)]
+    pub database_key: DatabaseKeyConfig,
+    /// Where the OAuth tokens of each account are kept.
+    #[serde(default)]
+    pub token_storage: TokenStorageConfig,
+}
+
+impl AppConfig {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file_content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read app config {}: {}", path.display(), e))?;
+        Ok(serde_json::from_str(&file_content)?)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct HttpClientConfig {
+    pub connect_timeout_ms: u64,
+    /// Longest wait between two reads of a response.
+    pub read_timeout_ms: u64,
+    /// Upper bound for a whole request, body included; unlimited when unset.
+    pub request_timeout_ms: Option<u64>,
+    /// Idle connections are closed after this long.
+    pub pool_idle_timeout_ms: u64,
+    pub pool_max_idle_per_host: usize,
+    /// Speak HTTP/2 without ALPN negotiation, for gateways that serve cleartext h2. Over TLS
+    /// HTTP/2 is negotiated either way.
+    pub http2_prior_knowledge: bool,
+    /// Overrides for the upload endpoints, which move large bodies over slow links.
+    pub upload: UploadTimeoutConfig,
+    pub proxy: ProxyConfig,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        HttpClientConfig {
+            connect_timeout_ms: 10_000,
+            read_timeout_ms: 30_000,
+            request_timeout_ms: None,
+            pool_idle_timeout_ms: 90_000,
+            pool_max_idle_per_host: 8,
+            http2_prior_knowledge: false,
+            upload: UploadTimeoutConfig::default(),
+            proxy: ProxyConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct UploadTimeoutConfig {
+    pub read_timeout_ms: u64,
+    pub request_timeout_ms: Option<u64>,
+}
+
+impl Default for UploadTimeoutConfig {
+    fn default() -> Self {
+        UploadTimeoutConfig {
+            read_timeout_ms: 120_000,
+            request_timeout_ms: Some(600_000),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum ProxyConfig {
+    /// Proxy from the `HTTP_PROXY`/`HTTPS_PROXY`/`ALL_PROXY` environment variables or, on
+    /// macOS and Windows, the system settings.
+    #[default]
+    System,
+    /// Always connect directly.
+    Direct,
+    /// Fixed `http://`, `https://` or `socks5://` proxy for all requests. `no_proxy` is a
+    /// comma separated list of hosts, domains and CIDR ranges to reach directly.
+    Manual {
+        url: String,
+        #[serde(default)]
+        no_proxy: Option<String>,
+    },
+}
+
+/// Encryption settings of the local database. A database created with other known settings
+/// is re-encrypted with these when it is opened.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct DatabaseCipherConfig {
+    /// Power of two from 512 to 65536
+    pub page_size: u32,
+    pub kdf_iter: u32,
+    pub hmac_algorithm: CipherHmacAlgorithm,
+    pub kdf_algorithm: CipherKdfAlgorithm,
+}
+
+impl DatabaseCipherConfig {
+    /// The SQLCipher 4 defaults.
+    pub const SQLCIPHER_4: DatabaseCipherConfig = DatabaseCipherConfig {
+        page_size: 4096,
+        kdf_iter: 256_000,
+        hmac_algorithm: CipherHmacAlgorithm::HmacSha512,
+        kdf_algorithm: CipherKdfAlgorithm::Pbkdf2HmacSha512,
+    };
+
+    /// The SQLCipher 3 defaults, which every database was created with before these settings
+    /// became configurable.
+    pub const SQLCIPHER_3: DatabaseCipherConfig = DatabaseCipherConfig {
+        page_size: 1024,
+        kdf_iter: 64_000,
+        hmac_algorithm: CipherHmacAlgorithm::HmacSha1,
+        kdf_algorithm: CipherKdfAlgorithm::Pbkdf2HmacSha1,
+    };
+}
+
+impl Default for DatabaseCipherConfig {
+    fn default() -> Self {
+        Self::SQLCIPHER_4
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum CipherHmacAlgorithm {
+    HmacSha1,
+    HmacSha256,
+    HmacSha512,
+}
+
+impl CipherHmacAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CipherHmacAlgorithm::HmacSha1 => "HMAC_SHA1",
+            CipherHmacAlgorithm::HmacSha256 => "HMAC_SHA256",
+            CipherHmacAlgorithm::HmacSha512 => "HMAC_SHA512",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum CipherKdfAlgorithm {
+    Pbkdf2HmacSha1,
+    Pbkdf2HmacSha256,
+    Pbkdf2HmacSha512,
+}
+
+impl CipherKdfAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CipherKdfAlgorithm::Pbkdf2HmacSha1 => "PBKDF2_HMAC_SHA1",
+            CipherKdfAlgorithm::Pbkdf2HmacSha256 => "PBKDF2_HMAC_SHA256",
+            CipherKdfAlgorithm::Pbkdf2HmacSha512 => "PBKDF2_HMAC_SHA512",
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum DatabaseKeyConfig {
+    /// A random secret per account in the OS keyring. Without a keyring backend the key comes
+    /// from `$RIPPLE_DB_KEY` when set, else from a passphrase.
+    #[default]
+    Keyring,
+    /// Derived from a passphrase with Argon2id; the passphrase is asked for on every start.
+    Passphrase,
+    /// The value of an environment variable, for CI and headless machines.
+    Env {
+        #[serde(default = "default_db_key_var")]
+        var: String,
+    },
+    /// The contents of a file, such as a mounted secret.
+    File { path: PathBuf },
+}
+
+impl FromStr for DatabaseKeyConfig {
+    type Err = anyhow::Error;
+
+    /// `keyring`, `passphrase`, `env`, `env:<VAR>` or `file:<path>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "keyring" => Ok(DatabaseKeyConfig::Keyring),
+            None if s == "passphrase" => Ok(DatabaseKeyConfig::Passphrase),
+            None if s == "env" => Ok(DatabaseKeyConfig::Env {
+                var: default_db_key_var(),
+            }),
+            Some(("env", var)) if !var.is_empty() => Ok(DatabaseKeyConfig::Env {
+                var: var.to_string(),
+            }),
+            Some(("file", path)) if !path.is_empty() => Ok(DatabaseKeyConfig::File {
+                path: PathBuf::from(path),
+            }),
+            _ => anyhow::bail!(
+                "Unknown key source {}; use keyring, passphrase, env[:<VAR>] or file:<path>",
+                s
+            ),
+        }
+    }
+}
+
+pub fn default_db_key_var() -> String {
+    "RIPPLE_DB_KEY".to_string()
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenStorageConfig {
+    /// In the account's encrypted database.
+    #[default]
+    Database,
+    /// Only in the OS keyring. Falls back to the database where there is no keyring.
+    Keyring,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ApiRetryConfig {
+    /// Calls that are safe to repeat: reads, syncs and set-style updates.
+    #[serde(default = "RetryPolicy::idempotent")]
+    pub idempotent: RetryPolicy,
+    /// Calls that may create something twice, such as sending a message.
+    #[serde(default = "RetryPolicy::non_idempotent")]
+    pub non_idempotent: RetryPolicy,
+}
+
+impl Default for ApiRetryConfig {
+    fn default() -> Self {
+        ApiRetryConfig {
+            idempotent: RetryPolicy::idempotent(),
+            non_idempotent: RetryPolicy::non_idempotent(),
+        }
+    }
+}
+
+/// Jittered exponential backoff. A server `Retry-After` replaces the computed delay unless it
+/// exceeds `max_retry_after_ms`, in which case the call fails right away.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_interval_ms: u64,
+    pub max_interval_ms: u64,
+    /// Longest server requested wait to honor; rate limits often ask for more than the backoff
+    /// would ever wait.
+    #[serde(default = "default_max_retry_after_ms")]
+    pub max_retry_after_ms: u64,
+}
+
+impl RetryPolicy {
+    fn idempotent() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_interval_ms: 300,
+            max_interval_ms: 5_000,
+            max_retry_after_ms: default_max_retry_after_ms(),
+        }
+    }
+
+    fn non_idempotent() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            initial_interval_ms: 300,
+            max_interval_ms: 5_000,
+            max_retry_after_ms: default_max_retry_after_ms(),
+        }
+    }
+}
+
+fn default_max_retry_after_ms() -> u64 {
+    30_000
+}
+
+fn default_group_member_sync_concurrency() -> usize {
+    4
 }
@@ -0,0 +1,3 @@
+fn main() -> std::process::ExitCode {
+    ripple_im_app_lib::cli::run()
+}
@@ -0,0 +1,342 @@
+use crate::app_config::DatabaseKeyConfig;
+use crate::conversation_export::{AttachmentMode, ExportFormat, ExportOptions, ExportRange};
+use chrono::{DateTime, NaiveDate};
+use std::path::PathBuf;
+
+pub(super) const USAGE: &str = "\
+Usage: ripple-cli [--config <file>] [--data-dir <dir>] <command>
+
+Commands:
+  login [--paste-redirect]             Sign in; --paste-redirect prints the sign-in URL and
+                                       asks for the address the browser was sent back to
+  logout                               Revoke and forget the stored token
+  conversations                        Sync and list conversations (tab separated)
+  send <conversation-id> <text>...     Send a text message; `-` reads the text from stdin
+  tail [<conversation-id>] [--json]    Print messages as they arrive until Ctrl-C
+  export <conversation-id> [--output <file>] [--format jsonl|markdown|html]
+         [--since <time>] [--until <time>] [--download-attachments]
+                                       Write the conversation history; the format defaults
+                                       to the output extension, else JSON lines. Times are
+                                       YYYY-MM-DD, RFC 3339 or unix milliseconds
+  bridge --rules <file>                Forward messages matching the rules to webhooks and
+                                       send the replies posted back, until Ctrl-C
+  backup <file>                        Write the local database, encrypted with a passphrase
+                                       from $RIPPLE_BACKUP_PASSPHRASE or stdin, to <file>
+  restore <file>                       Restore a backup into a data directory without a
+                                       database, then `login` to sign in again
+  key-source <source>                  Re-encrypt the local database with a key from
+                                       keyring, passphrase, env[:<VAR>] or file:<path>
+                                       and use that source from now on
+
+Options:
+  --config <file>    App config JSON (default: $RIPPLE_CONFIG)
+  --data-dir <dir>   Where the token and local database live
+                     (default: $RIPPLE_CLI_DATA_DIR or the user data dir)
+
+A passphrase-protected database is unlocked with $RIPPLE_DB_PASSPHRASE or a line of stdin.
+";
+
+#[derive(Debug, PartialEq)]
+pub(super) enum Command {
+    Help,
+    Login {
+        paste_redirect: bool,
+    },
+    Logout,
+    Conversations,
+    Send {
+        conversation_id: String,
+        text: String,
+    },
+    Tail {
+        conversation_id: Option<String>,
+        json: bool,
+    },
+    Export {
+        conversation_id: String,
+        output: Option<PathBuf>,
+        options: ExportOptions,
+    },
+    Bridge {
+        rules: PathBuf,
+    },
+    Backup {
+        output: PathBuf,
+    },
+    Restore {
+        input: PathBuf,
+    },
+    KeySource {
+        source: DatabaseKeyConfig,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) struct Args {
+    pub config: Option<PathBuf>,
+    pub data_dir: Option<PathBuf>,
+    pub command: Command,
+}
+
+impl Args {
+    /// Parses the arguments after the program name. Options may appear anywhere.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Args> {
+        let mut config = None;
+        let mut data_dir = None;
+        let mut output: Option<PathBuf> = None;
+        let mut format = None;
+        let mut range = ExportRange::default();
+        let mut download_attachments = false;
+        let mut rules = None;
+        let mut paste_redirect = false;
+        let mut json = false;
+        let mut help = false;
+        let mut positional = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| anyhow::anyhow!("{} needs a value", name))
+            };
+            match arg.as_str() {
+                "--config" => config = Some(PathBuf::from(value("--config")?)),
+                "--data-dir" => data_dir = Some(PathBuf::from(value("--data-dir")?)),
+                "--output" | "-o" => output = Some(PathBuf::from(value("--output")?)),
+                "--format" => format = Some(value("--format")?.parse::<ExportFormat>()?),
+                "--since" => range.since = Some(parse_time(&value("--since")?)?),
+                "--until" => range.until = Some(parse_time(&value("--until")?)?),
+                "--download-attachments" => download_attachments = true,
+                "--rules" => rules = Some(PathBuf::from(value("--rules")?)),
+                "--paste-redirect" => paste_redirect = true,
+                "--json" => json = true,
+                "--help" | "-h" => help = true,
+                "-" => positional.push(arg),
+                _ if arg.starts_with('-') => anyhow::bail!("Unknown option {}", arg),
+                _ => positional.push(arg),
+            }
+        }
+        let mut positional = positional.into_iter();
+        let command = match positional.next().as_deref() {
+            _ if help => Command::Help,
+            None | Some("help") => Command::Help,
+            Some("login") => Command::Login { paste_redirect },
+            Some("logout") => Command::Logout,
+            Some("conversations") => Command::Conversations,
+            Some("send") => {
+                let conversation_id = positional
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("send needs a conversation id"))?;
+                let text = positional.collect::<Vec<_>>().join(" ");
+                if text.is_empty() {
+                    anyhow::bail!("send needs a message text");
+                }
+                Command::Send {
+                    conversation_id,
+                    text,
+                }
+            }
+            Some("tail") => Command::Tail {
+                conversation_id: positional.next(),
+                json,
+            },
+            Some("export") => {
+                let conversation_id = positional
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("export needs a conversation id"))?;
+                let format = match format {
+                    Some(format) => format,
+                    None => output
+                        .as_ref()
+                        .and_then(|path| path.extension())
+                        .and_then(|ext| ext.to_str()?.parse().ok())
+                        .unwrap_or_default(),
+                };
+                let attachments = if download_attachments {
+                    AttachmentMode::Download
+                } else {
+                    AttachmentMode::Reference
+                };
+                Command::Export {
+                    conversation_id,
+                    output,
+                    options: ExportOptions {
+                        format,
+                        range,
+                        attachments,
+                    },
+                }
+            }
+            Some("bridge") => Command::Bridge {
+                rules: rules.ok_or_else(|| anyhow::anyhow!("bridge needs --rules <file>"))?,
+            },
+            Some("backup") => Command::Backup {
+                output: positional
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("backup needs an output file"))?
+                    .into(),
+            },
+            Some("restore") => Command::Restore {
+                input: positional
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("restore needs a backup file"))?
+                    .into(),
+            },
+            Some("key-source") => Command::KeySource {
+                source: positional
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("key-source needs a source"))?
+                    .parse()?,
+            },
+            Some(other) => anyhow::bail!("Unknown command {}", other),
+        };
+        Ok(Args {
+            config,
+            data_dir,
+            command,
+        })
+    }
+}
+
+/// Unix milliseconds, a `YYYY-MM-DD` date (midnight UTC) or an RFC 3339 time.
+fn parse_time(value: &str) -> anyhow::Result<i64> {
+    if let Ok(millis) = value.parse::<i64>() {
+        return Ok(millis);
+    }
+    if let Some(midnight) = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+    {
+        return Ok(midnight.and_utc().timestamp_millis());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.timestamp_millis())
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "Invalid time {}; use YYYY-MM-DD, RFC 3339 or unix milliseconds",
+                value
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> anyhow::Result<Args> {
+        Args::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn options_can_follow_the_command() {
+        let args = parse(&["tail", "c42", "--json", "--config", "dev.json"]).unwrap();
+        assert_eq!(args.config, Some(PathBuf::from("dev.json")));
+        assert_eq!(
+            args.command,
+            Command::Tail {
+                conversation_id: Some("c42".to_string()),
+                json: true,
+            }
+        );
+    }
+
+    #[test]
+    fn send_joins_the_remaining_words() {
+        let args = parse(&["send", "c42", "hello", "there"]).unwrap();
+        assert_eq!(
+            args.command,
+            Command::Send {
+                conversation_id: "c42".to_string(),
+                text: "hello there".to_string(),
+            }
+        );
+        assert!(parse(&["send", "c42"]).is_err());
+    }
+
+    #[test]
+    fn login_can_paste_the_redirect() {
+        assert_eq!(
+            parse(&["login", "--paste-redirect"]).unwrap().command,
+            Command::Login {
+                paste_redirect: true
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_commands_and_options() {
+        assert!(parse(&["sned"]).is_err());
+        assert!(parse(&["login", "--browser"]).is_err());
+        assert!(parse(&["export", "c42", "--output"]).is_err());
+        assert!(parse(&["bridge"]).is_err());
+        assert!(parse(&["restore"]).is_err());
+        assert!(parse(&["key-source", "vault"]).is_err());
+    }
+
+    #[test]
+    fn key_sources() {
+        let source = |s: &str| match parse(&["key-source", s]).unwrap().command {
+            Command::KeySource { source } => source,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(source("passphrase"), DatabaseKeyConfig::Passphrase);
+        assert_eq!(
+            source("env"),
+            DatabaseKeyConfig::Env {
+                var: "RIPPLE_DB_KEY".to_string()
+            }
+        );
+        assert_eq!(
+            source("file:/run/secrets/db:key"),
+            DatabaseKeyConfig::File {
+                path: PathBuf::from("/run/secrets/db:key")
+            }
+        );
+    }
+
+    #[test]
+    fn export_options() {
+        let args = parse(&[
+            "export",
+            "c42",
+            "-o",
+            "lunch.html",
+            "--since",
+            "2024-01-01",
+            "--until",
+            "1704153600000",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.command,
+            Command::Export {
+                conversation_id: "c42".to_string(),
+                output: Some(PathBuf::from("lunch.html")),
+                options: ExportOptions {
+                    format: ExportFormat::Html,
+                    range: ExportRange {
+                        since: Some(1704067200000),
+                        until: Some(1704153600000),
+                    },
+                    attachments: AttachmentMode::Reference,
+                },
+            }
+        );
+        let Command::Export { options, .. } =
+            parse(&["export", "c42", "--format", "md", "--download-attachments"])
+                .unwrap()
+                .command
+        else {
+            panic!("not an export");
+        };
+        assert_eq!(options.format, ExportFormat::Markdown);
+        assert_eq!(options.attachments, AttachmentMode::Download);
+        assert!(parse(&["export", "c42", "--since", "yesterday"]).is_err());
+        assert!(parse(&["export", "c42", "--format", "pdf"]).is_err());
+    }
+
+    #[test]
+    fn no_command_prints_help() {
+        assert_eq!(parse(&[]).unwrap().command, Command::Help);
+        assert_eq!(parse(&["send", "--help"]).unwrap().command, Command::Help);
+    }
+}
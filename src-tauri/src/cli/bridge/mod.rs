@@ -0,0 +1,284 @@
+//! Bot bridge: forwards incoming messages that match a rule to a local webhook and sends the
+//! replies the bot posts back.
+//!
+//! Every matching message is POSTed to the rule's webhook as
+//! `{"rule": <name>, "message": <MessageItem>, "replyUrl": <url>}`. The bot answers by POSTing
+//! `{"conversationId": <id>, "text": <text>}` to `replyUrl`, which responds with
+//! `{"messageId": <id>}` or `{"error": <reason>}`. With a `secret` configured, both directions
+//! carry it as `Authorization: Bearer <secret>`.
+
+mod rules;
+#[cfg(test)]
+mod tests;
+
+pub use rules::BridgeConfig;
+
+use crate::cli::push::{PushHandler, PushedMessages};
+use crate::ripple_api::api_response::{
+    ApiEnvelope, MessageItem, MessageItemType, SendMessageRequest,
+};
+use crate::ripple_api::RippleApi;
+use crate::ripple_syncer::DataSyncManager;
+use crate::ripple_ws::connection_stats::ConnectionStats;
+use crate::ripple_ws::RippleWsManager;
+use crate::server::Server;
+use crate::store_engine::store_engine::RippleStorage;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::{Json, Router};
+use oauth2::reqwest;
+use rules::Rule;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+const REPLY_PATH: &str = "/messages";
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WebhookEvent<'a> {
+    rule: &'a str,
+    message: &'a MessageItem,
+    reply_url: &'a str,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReplyRequest {
+    conversation_id: String,
+    text: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReplyResponse {
+    message_id: String,
+}
+
+#[derive(Serialize)]
+struct ReplyError {
+    error: String,
+}
+
+type ReplyResult = Result<Json<ReplyResponse>, (StatusCode, Json<ReplyError>)>;
+
+fn reply_error(status: StatusCode, error: impl ToString) -> (StatusCode, Json<ReplyError>) {
+    (
+        status,
+        Json(ReplyError {
+            error: error.to_string(),
+        }),
+    )
+}
+
+/// A running bridge: the reply endpoint plus the WebSocket connection feeding the rules.
+pub struct Bridge<S: RippleStorage> {
+    server: Server,
+    local_addr: SocketAddr,
+    ws_manager: RippleWsManager<PushedMessages<BridgeHandler<S>>, DataSyncManager<S>>,
+}
+
+impl<S: RippleStorage> Bridge<S> {
+    /// Starts the reply endpoint, then connects to `ws_gateway_url`. `user_id` is the signed-in
+    /// account, whose own messages are never forwarded so a bot cannot answer itself.
+    pub async fn start(
+        config: &BridgeConfig,
+        user_id: String,
+        ripple_api: RippleApi<S>,
+        data_sync: DataSyncManager<S>,
+        http_client: reqwest::Client,
+        ws_gateway_url: &str,
+    ) -> anyhow::Result<Bridge<S>> {
+        let rules = config.compile_rules()?;
+        let reply_state = Arc::new(ReplyState {
+            user_id: user_id.clone(),
+            ripple_api,
+            data_sync: data_sync.clone(),
+            secret: config.secret.clone(),
+        });
+        let app = Router::new()
+            .route(REPLY_PATH, post(reply::<S>))
+            .with_state(reply_state);
+        let mut server = Server::new();
+        let local_addr = server
+            .start(config.listen_addr.as_str(), app)
+            .await
+            .map_err(|e| {
+                anyhow::anyhow!(
+                    "Failed to listen for replies on {}: {}",
+                    config.listen_addr,
+                    e
+                )
+            })?;
+        let handler = BridgeHandler {
+            data_sync: data_sync.clone(),
+            user_id,
+            rules: Arc::new(rules),
+            http_client,
+            secret: config.secret.clone(),
+            reply_url: format!("http://{}{}", local_addr, REPLY_PATH),
+        };
+        let ws_manager = RippleWsManager::new(
+            PushedMessages(handler),
+            data_sync,
+            Arc::new(ConnectionStats::default()),
+        );
+        if let Err(e) = ws_manager.start(ws_gateway_url).await {
+            server.stop().await;
+            return Err(e);
+        }
+        Ok(Bridge {
+            server,
+            local_addr,
+            ws_manager,
+        })
+    }
+
+    /// Address the reply endpoint is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub async fn stop(mut self) {
+        // Ignore errors if the connection never came up
+        let _ = self.ws_manager.stop().await;
+        self.server.stop().await;
+    }
+}
+
+struct ReplyState<S: RippleStorage> {
+    user_id: String,
+    ripple_api: RippleApi<S>,
+    data_sync: DataSyncManager<S>,
+    secret: Option<String>,
+}
+
+fn authorized(secret: &Option<String>, headers: &HeaderMap) -> bool {
+    let Some(secret) = secret else {
+        return true;
+    };
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| token == secret)
+}
+
+async fn reply<S: RippleStorage>(
+    State(state): State<Arc<ReplyState<S>>>,
+    headers: HeaderMap,
+    Json(request): Json<ReplyRequest>,
+) -> ReplyResult {
+    if !authorized(&state.secret, &headers) {
+        return Err(reply_error(
+            StatusCode::UNAUTHORIZED,
+            "Missing or wrong secret",
+        ));
+    }
+    if request.text.is_empty() {
+        return Err(reply_error(StatusCode::BAD_REQUEST, "Empty text"));
+    }
+    let conversation = state
+        .data_sync
+        .find_conversation(&request.conversation_id)
+        .await
+        .map_err(|e| reply_error(StatusCode::BAD_GATEWAY, e))?
+        .ok_or_else(|| {
+            reply_error(
+                StatusCode::NOT_FOUND,
+                format!("Unknown conversation {}", request.conversation_id),
+            )
+        })?;
+    let response = state
+        .ripple_api
+        .send_message(SendMessageRequest {
+            sender_id: state.user_id.clone(),
+            conversation_id: request.conversation_id,
+            receiver_id: conversation.peer_id,
+            group_id: conversation.group_id,
+            text_content: Some(request.text),
+            file_url: None,
+            file_name: None,
+        })
+        .await
+        .map_err(|e| reply_error(StatusCode::BAD_GATEWAY, e))?
+        .into_result()
+        .map_err(|e| reply_error(StatusCode::BAD_GATEWAY, e))?;
+    let message_id = response
+        .data
+        .map(|data| data.message_id)
+        .ok_or_else(|| reply_error(StatusCode::BAD_GATEWAY, "No message ID returned"))?;
+    Ok(Json(ReplyResponse { message_id }))
+}
+
+/// Hands pushed messages matching a rule to its webhook.
+#[derive(Clone)]
+struct BridgeHandler<S: RippleStorage> {
+    data_sync: DataSyncManager<S>,
+    user_id: String,
+    rules: Arc<Vec<Rule>>,
+    http_client: reqwest::Client,
+    secret: Option<String>,
+    reply_url: String,
+}
+
+impl<S: RippleStorage> BridgeHandler<S> {
+    /// Delivers in the background so a slow bot does not hold up the connection. Failed
+    /// deliveries are logged and dropped; the message stays in the local store.
+    fn forward(&self, rule: &Rule, message: &MessageItem) {
+        let body = match serde_json::to_vec(&WebhookEvent {
+            rule: &rule.name,
+            message,
+            reply_url: &self.reply_url,
+        }) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Failed to encode message {}: {}", message.message_id, e);
+                return;
+            }
+        };
+        let mut request = self
+            .http_client
+            .post(&rule.webhook_url)
+            .header("Content-Type", "application/json")
+            .body(body);
+        if let Some(secret) = &self.secret {
+            request = request.header("Authorization", format!("Bearer {}", secret));
+        }
+        let rule_name = rule.name.clone();
+        let message_id = message.message_id.clone();
+        tokio::spawn(async move {
+            match request.send().await.and_then(|res| res.error_for_status()) {
+                Ok(_) => info!("Forwarded message {} by rule {}", message_id, rule_name),
+                Err(e) => warn!(
+                    "Failed to forward message {} by rule {}: {}",
+                    message_id, rule_name, e
+                ),
+            }
+        });
+    }
+}
+
+impl<S: RippleStorage> PushHandler for BridgeHandler<S> {
+    type Store = S;
+
+    fn data_sync(&self) -> &DataSyncManager<S> {
+        &self.data_sync
+    }
+
+    fn connected_notice(&self) -> &'static str {
+        "Bridge connected (Ctrl-C to stop)"
+    }
+
+    async fn handle_pushed(&self, item: MessageItem) -> anyhow::Result<()> {
+        if item.sender_id == self.user_id || item.message_type != MessageItemType::Text {
+            return Ok(());
+        }
+        for rule in self.rules.iter().filter(|rule| rule.matches(&item)) {
+            self.forward(rule, &item);
+        }
+        Ok(())
+    }
+}
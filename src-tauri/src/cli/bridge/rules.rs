@@ -0,0 +1,203 @@
+use crate::ripple_api::api_response::MessageItem;
+use regex::Regex;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::Path;
+
+/// Bridge configuration, read from the JSON file given to `ripple-cli bridge --rules`.
+#[derive(Debug, Deserialize)]
+pub struct BridgeConfig {
+    /// Where the reply endpoint listens. Anything but loopback requires `secret`.
+    #[serde(default = "default_listen_addr")]
+    pub listen_addr: String,
+    /// Webhook of the rules that do not name their own.
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    /// Shared secret: sent as a bearer token with every webhook call and required on replies.
+    #[serde(default)]
+    pub secret: Option<String>,
+    pub rules: Vec<RuleConfig>,
+}
+
+fn default_listen_addr() -> String {
+    "127.0.0.1:8787".to_string()
+}
+
+/// Criteria a message has to meet to be forwarded; unset criteria match everything.
+#[derive(Debug, Deserialize)]
+pub struct RuleConfig {
+    pub name: String,
+    #[serde(default)]
+    pub conversation_id: Option<String>,
+    #[serde(default)]
+    pub sender_id: Option<String>,
+    /// Regular expression searched for in the message text.
+    #[serde(default)]
+    pub pattern: Option<String>,
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+}
+
+#[derive(Debug)]
+pub(super) struct Rule {
+    pub name: String,
+    conversation_id: Option<String>,
+    sender_id: Option<String>,
+    pattern: Option<Regex>,
+    pub webhook_url: String,
+}
+
+impl BridgeConfig {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file_content = std::fs::read_to_string(path).map_err(|e| {
+            anyhow::anyhow!("Failed to read bridge rules {}: {}", path.display(), e)
+        })?;
+        Ok(serde_json::from_str(&file_content)?)
+    }
+
+    /// Compiles the patterns and resolves each rule's webhook, rejecting unusable rules.
+    pub(super) fn compile_rules(&self) -> anyhow::Result<Vec<Rule>> {
+        if self.rules.is_empty() {
+            anyhow::bail!("The bridge has no rules");
+        }
+        if self.secret.is_none() && !is_loopback(&self.listen_addr) {
+            anyhow::bail!(
+                "listen_addr {} is reachable from other machines; set a secret or listen on loopback",
+                self.listen_addr
+            );
+        }
+        self.rules
+            .iter()
+            .map(|rule| {
+                let webhook_url = rule
+                    .webhook_url
+                    .clone()
+                    .or_else(|| self.webhook_url.clone())
+                    .ok_or_else(|| anyhow::anyhow!("Rule {} has no webhook_url", rule.name))?;
+                let pattern = rule
+                    .pattern
+                    .as_deref()
+                    .map(Regex::new)
+                    .transpose()
+                    .map_err(|e| {
+                        anyhow::anyhow!("Rule {} has an invalid pattern: {}", rule.name, e)
+                    })?;
+                Ok(Rule {
+                    name: rule.name.clone(),
+                    conversation_id: rule.conversation_id.clone(),
+                    sender_id: rule.sender_id.clone(),
+                    pattern,
+                    webhook_url,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Whether `addr` (`host:port`) only accepts connections from this machine.
+fn is_loopback(addr: &str) -> bool {
+    if let Ok(addr) = addr.parse::<SocketAddr>() {
+        return addr.ip().is_loopback();
+    }
+    addr.rsplit_once(':')
+        .is_some_and(|(host, _)| host.eq_ignore_ascii_case("localhost"))
+}
+
+impl Rule {
+    pub fn matches(&self, message: &MessageItem) -> bool {
+        self.conversation_id
+            .as_ref()
+            .is_none_or(|id| *id == message.conversation_id)
+            && self
+                .sender_id
+                .as_ref()
+                .is_none_or(|id| *id == message.sender_id)
+            && self.pattern.as_ref().is_none_or(|re| {
+                message
+                    .text
+                    .as_deref()
+                    .is_some_and(|text| re.is_match(text))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(rules: serde_json::Value) -> BridgeConfig {
+        serde_json::from_value(serde_json::json!({
+            "webhook_url": "http://127.0.0.1:9000/hook",
+            "rules": rules,
+        }))
+        .unwrap()
+    }
+
+    fn message(conversation_id: &str, sender_id: &str, text: Option<&str>) -> MessageItem {
+        serde_json::from_value(serde_json::json!({
+            "conversationId": conversation_id,
+            "messageId": "1",
+            "senderId": sender_id,
+            "sendTimestamp": "0",
+            "messageType": 1,
+            "text": text,
+            "commandType": 0,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn all_criteria_must_match() {
+        let rules = config(serde_json::json!([
+            {"name": "alerts", "conversation_id": "c1", "sender_id": "1001", "pattern": "(?i)^alert"}
+        ]))
+        .compile_rules()
+        .unwrap();
+        let rule = &rules[0];
+        assert!(rule.matches(&message("c1", "1001", Some("ALERT: disk full"))));
+        assert!(!rule.matches(&message("c2", "1001", Some("alert"))));
+        assert!(!rule.matches(&message("c1", "1002", Some("alert"))));
+        assert!(!rule.matches(&message("c1", "1001", Some("all good"))));
+        assert!(!rule.matches(&message("c1", "1001", None)));
+    }
+
+    #[test]
+    fn empty_rule_matches_everything() {
+        let rules = config(serde_json::json!([{"name": "all"}]))
+            .compile_rules()
+            .unwrap();
+        assert!(rules[0].matches(&message("c1", "1001", None)));
+        assert_eq!(rules[0].webhook_url, "http://127.0.0.1:9000/hook");
+    }
+
+    #[test]
+    fn rejects_unusable_rules() {
+        assert!(config(serde_json::json!([])).compile_rules().is_err());
+        assert!(config(serde_json::json!([{"name": "bad", "pattern": "("}]))
+            .compile_rules()
+            .is_err());
+        let no_webhook: BridgeConfig =
+            serde_json::from_value(serde_json::json!({"rules": [{"name": "lost"}]})).unwrap();
+        assert!(no_webhook.compile_rules().is_err());
+    }
+
+    #[test]
+    fn public_listen_addr_requires_a_secret() {
+        let listening = |listen_addr: &str, secret: Option<&str>| {
+            serde_json::from_value::<BridgeConfig>(serde_json::json!({
+                "listen_addr": listen_addr,
+                "secret": secret,
+                "webhook_url": "http://127.0.0.1:9000/hook",
+                "rules": [{"name": "all"}],
+            }))
+            .unwrap()
+            .compile_rules()
+        };
+        assert!(listening("127.0.0.1:8787", None).is_ok());
+        assert!(listening("[::1]:8787", None).is_ok());
+        assert!(listening("localhost:8787", None).is_ok());
+        assert!(listening("0.0.0.0:8787", None).is_err());
+        assert!(listening("bot.internal:8787", None).is_err());
+        assert!(listening("0.0.0.0:8787", Some("s3cret")).is_ok());
+    }
+}
@@ -0,0 +1,138 @@
+use super::{Bridge, BridgeConfig};
+use crate::mock_gateway::{eventually, MockGateway, TestClient};
+use crate::ripple_api::api_response::SendMessageRequest;
+use crate::server::Server;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::{Json, Router};
+use oauth2::reqwest;
+use tokio::sync::mpsc;
+
+const ALICE: &str = "1001";
+const BOB: &str = "1002";
+const SECRET: &str = "s3cret";
+
+type Deliveries = mpsc::UnboundedSender<(Option<String>, serde_json::Value)>;
+
+async fn webhook(
+    State(deliveries): State<Deliveries>,
+    headers: HeaderMap,
+    Json(event): Json<serde_json::Value>,
+) -> StatusCode {
+    let auth = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let _ = deliveries.send((auth, event));
+    StatusCode::NO_CONTENT
+}
+
+async fn send_text(client: &TestClient, conversation_id: &str, text: &str) {
+    client
+        .api
+        .send_message(SendMessageRequest {
+            sender_id: ALICE.to_string(),
+            conversation_id: conversation_id.to_string(),
+            receiver_id: Some(BOB.to_string()),
+            group_id: None,
+            text_content: Some(text.to_string()),
+            file_url: None,
+            file_name: None,
+        })
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn forwards_matching_messages_and_sends_replies() {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.state().add_user(ALICE, "Alice");
+    gateway.state().add_user(BOB, "Bob");
+    let conversation_id = gateway.state().befriend(ALICE, BOB);
+    let alice = TestClient::login(&gateway, ALICE).await;
+    let bot = TestClient::login(&gateway, BOB).await;
+    bot.data_sync.init().await.unwrap();
+
+    let (deliveries_tx, mut deliveries) = mpsc::unbounded_channel();
+    let mut webhook_server = Server::new();
+    let webhook_addr = webhook_server
+        .start(
+            "127.0.0.1:0",
+            Router::new()
+                .route("/hook", post(webhook))
+                .with_state(deliveries_tx),
+        )
+        .await
+        .unwrap();
+    let config: BridgeConfig = serde_json::from_value(serde_json::json!({
+        "listen_addr": "127.0.0.1:0",
+        "webhook_url": format!("http://{}/hook", webhook_addr),
+        "secret": SECRET,
+        "rules": [{"name": "deploys", "conversation_id": conversation_id, "pattern": "^deploy"}],
+    }))
+    .unwrap();
+    let bridge = Bridge::start(
+        &config,
+        BOB.to_string(),
+        bot.api.clone(),
+        bot.data_sync.clone(),
+        reqwest::Client::new(),
+        &bot.config.ws_gateway_url,
+    )
+    .await
+    .unwrap();
+    eventually(|| gateway.state().ws_connections(BOB) == 1).await;
+
+    send_text(&alice, &conversation_id, "hello").await;
+    send_text(&alice, &conversation_id, "deploy finished").await;
+    let (auth, event) = deliveries.recv().await.unwrap();
+    assert_eq!(auth.as_deref(), Some("Bearer s3cret"));
+    assert_eq!(event["rule"], "deploys");
+    assert_eq!(event["message"]["text"], "deploy finished");
+    assert_eq!(event["message"]["senderId"], ALICE);
+    assert!(deliveries.try_recv().is_err());
+
+    let reply_url = event["replyUrl"].as_str().unwrap();
+    assert_eq!(
+        reply_url,
+        format!("http://{}/messages", bridge.local_addr())
+    );
+    let reply = serde_json::json!({"conversationId": conversation_id, "text": "ack"});
+    let http = reqwest::Client::new();
+    let res = http.post(reply_url).json(&reply).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = http
+        .post(reply_url)
+        .bearer_auth(SECRET)
+        .json(&serde_json::json!({"conversationId": "unknown", "text": "ack"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let res = http
+        .post(reply_url)
+        .bearer_auth(SECRET)
+        .json(&reply)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: serde_json::Value = res.json().await.unwrap();
+    let message_id = body["messageId"].as_str().unwrap().to_string();
+
+    let messages = alice
+        .api
+        .read_messages(conversation_id.clone(), "0".to_string(), 50)
+        .await
+        .unwrap()
+        .data
+        .messages;
+    let last = messages.last().unwrap();
+    assert_eq!(last.message_id, message_id);
+    assert_eq!(last.sender_id, BOB);
+    assert_eq!(last.text.as_deref(), Some("ack"));
+
+    bridge.stop().await;
+    webhook_server.stop().await;
+}
@@ -0,0 +1,128 @@
+use crate::app_config::AppConfig;
+use crate::ripple_api::oauth_client::OauthClient;
+use crate::server::Server;
+use axum::extract::{Query, State};
+use axum::response::Html;
+use axum::routing::get;
+use axum::Router;
+use oauth2::url::Url;
+use serde::Deserialize;
+use std::io::BufRead;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(300);
+const SIGNED_IN_PAGE: &str =
+    "<h1>Signed in</h1><p>You can close this window and return to the terminal.</p>";
+
+#[derive(Deserialize)]
+struct CallbackParams {
+    code: String,
+    state: String,
+}
+
+type CallbackSender = Arc<Mutex<Option<oneshot::Sender<CallbackParams>>>>;
+
+/// Runs the authorization code flow up to the code, checking the returned `state`.
+///
+/// By default the code arrives on the same loopback callback the desktop app listens on.
+/// With `paste_redirect` the user opens the URL in any browser and pastes back the address
+/// it was redirected to, so no listener is needed on the machine running the CLI. The
+/// redirected page does not have to load for that; its address already holds the code.
+pub(super) async fn authorize(
+    oauth_client: &OauthClient,
+    app_config: &AppConfig,
+    paste_redirect: bool,
+) -> anyhow::Result<String> {
+    let auth_url = oauth_client.auth_url();
+    let params = if paste_redirect {
+        eprintln!(
+            "Open this URL in a browser and sign in:\n\n  {}\n",
+            auth_url
+        );
+        eprintln!("Then paste the address your browser was redirected to:");
+        let line = tokio::task::spawn_blocking(|| {
+            let mut line = String::new();
+            std::io::stdin().lock().read_line(&mut line).map(|_| line)
+        })
+        .await??;
+        parse_redirect(line.trim())?
+    } else {
+        wait_for_callback(&app_config.callback_server_addr, &auth_url).await?
+    };
+    if !oauth_client.state_equal(&params.state) {
+        anyhow::bail!("The sign-in response does not belong to this login attempt");
+    }
+    Ok(params.code)
+}
+
+async fn wait_for_callback(addr: &str, auth_url: &str) -> anyhow::Result<CallbackParams> {
+    let (tx, rx) = oneshot::channel();
+    let sender: CallbackSender = Arc::new(Mutex::new(Some(tx)));
+    let app = Router::new()
+        .route("/callback", get(callback))
+        .with_state(sender);
+    let mut server = Server::new();
+    server.start(addr, app).await.map_err(|e| {
+        anyhow::anyhow!(
+            "Failed to listen for the sign-in callback on {}: {}",
+            addr,
+            e
+        )
+    })?;
+    eprintln!(
+        "Opening the sign-in page in your browser. If it does not open, visit:\n\n  {}\n",
+        auth_url
+    );
+    if let Err(e) = open::that_detached(auth_url) {
+        eprintln!("Could not open a browser: {}", e);
+    }
+    let result = tokio::time::timeout(CALLBACK_TIMEOUT, rx).await;
+    server.stop().await;
+    match result {
+        Ok(Ok(params)) => Ok(params),
+        Ok(Err(_)) => anyhow::bail!("The sign-in callback server stopped"),
+        Err(_) => anyhow::bail!("Timed out waiting for the sign-in to finish"),
+    }
+}
+
+async fn callback(
+    State(sender): State<CallbackSender>,
+    Query(params): Query<CallbackParams>,
+) -> Html<&'static str> {
+    if let Some(tx) = sender.lock().unwrap().take() {
+        let _ = tx.send(params);
+    }
+    Html(SIGNED_IN_PAGE)
+}
+
+fn parse_redirect(redirect: &str) -> anyhow::Result<CallbackParams> {
+    let url = Url::parse(redirect)
+        .map_err(|e| anyhow::anyhow!("Not a URL ({}); paste the full address", e))?;
+    let query = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+            .ok_or_else(|| anyhow::anyhow!("The address has no `{}` parameter", name))
+    };
+    Ok(CallbackParams {
+        code: query("code")?,
+        state: query("state")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_pasted_redirect() {
+        let params =
+            parse_redirect("http://127.0.0.1:9090/callback?code=abc%3D&state=xyz").unwrap();
+        assert_eq!(params.code, "abc=");
+        assert_eq!(params.state, "xyz");
+        assert!(parse_redirect("http://127.0.0.1:9090/callback?state=xyz").is_err());
+        assert!(parse_redirect("abc").is_err());
+    }
+}
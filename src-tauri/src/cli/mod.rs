@@ -0,0 +1,482 @@
+//! `ripple-cli`: the Ripple client without a window, for bots, smoke tests and bulk exports.
+//!
+//! It drives the same API client, store, syncer and WebSocket manager as the desktop app.
+//! One data directory holds one signed-in account.
+
+mod args;
+mod bridge;
+mod login;
+mod push;
+mod tail;
+
+use crate::app_config::{AppConfig, DatabaseKeyConfig};
+use crate::conversation_export::{
+    AttachmentMode, ConversationExporter, ExportOptions, ExportStage,
+};
+use crate::http_client::HttpClients;
+use crate::ripple_api::api_response::{ApiEnvelope, SendMessageRequest};
+use crate::ripple_api::auth_token_parser::AuthTokenParser;
+use crate::ripple_api::oauth_client::OauthClient;
+use crate::ripple_api::token_validator::TokenValidator;
+use crate::ripple_api::RippleApi;
+use crate::ripple_syncer::DataSyncManager;
+use crate::ripple_ws::connection_stats::ConnectionStats;
+use crate::ripple_ws::RippleWsManager;
+#[cfg(feature = "sqlite-store")]
+use crate::store_engine::SqliteStore;
+use crate::store_engine::key_provider::{
+    has_databases, key_provider_with_fallback, load_key_source,
+};
+#[cfg(feature = "sqlite-store")]
+use crate::store_engine::key_provider::{key_provider, save_key_source};
+use crate::store_engine::{close_store, create_store, KeyProvider};
+use crate::DefaultStoreEngine;
+use args::{Args, Command, USAGE};
+use bridge::{Bridge, BridgeConfig};
+use oauth2::reqwest;
+use oauth2::TokenResponse;
+use push::PushedMessages;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use tail::TailHandler;
+use tracing_subscriber::EnvFilter;
+
+const KEY_NAME: &str = "ripple-cli";
+
+/// Entry point of the `ripple-cli` binary.
+pub fn run() -> ExitCode {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    if args.command == Command::Help {
+        print!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "warn".into()))
+        .with_writer(std::io::stderr)
+        .init();
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    match runtime.block_on(execute(args)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// The account stack of the data directory.
+struct Client {
+    app_config: AppConfig,
+    oauth_client: OauthClient,
+    ripple_api: RippleApi<DefaultStoreEngine>,
+    data_sync: DataSyncManager<DefaultStoreEngine>,
+    store: DefaultStoreEngine,
+    http_client: reqwest::Client,
+}
+
+impl Client {
+    async fn open(args: &Args) -> anyhow::Result<Client> {
+        let app_config = app_config(args)?;
+        let data_dir = data_dir(args)?;
+        let keys = keys(&data_dir, &app_config)?;
+        let store = create_store(data_dir, KEY_NAME, keys.as_ref(), &app_config).await?;
+        let http_clients = HttpClients::new(&app_config.http)?;
+        let http_client = http_clients.api.clone();
+        let oauth_client = OauthClient::new(&app_config, http_client.clone())?;
+        let ripple_api = RippleApi::from_config(
+            &app_config,
+            http_clients,
+            oauth_client.clone(),
+            store.clone(),
+        );
+        let data_sync = DataSyncManager::new(ripple_api.clone(), store.clone());
+        Ok(Client {
+            app_config,
+            oauth_client,
+            ripple_api,
+            data_sync,
+            store,
+            http_client,
+        })
+    }
+
+    /// User id of the signed-in account.
+    async fn user_id(&self) -> anyhow::Result<String> {
+        if !self.data_sync.exists_token().await? {
+            anyhow::bail!("Not signed in; run `ripple-cli login` first");
+        }
+        let token = self.data_sync.get_token().await?;
+        Ok(AuthTokenParser::decode_jwt_payload(&token.access_token)?.get_sub())
+    }
+}
+
+fn app_config(args: &Args) -> anyhow::Result<AppConfig> {
+    let config_path = args
+        .config
+        .clone()
+        .or_else(|| std::env::var_os("RIPPLE_CONFIG").map(PathBuf::from))
+        .ok_or_else(|| anyhow::anyhow!("No app config; pass --config or set RIPPLE_CONFIG"))?;
+    AppConfig::load(&config_path)
+}
+
+/// The data directory of the arguments, created if missing.
+fn data_dir(args: &Args) -> anyhow::Result<PathBuf> {
+    let data_dir = match &args.data_dir {
+        Some(dir) => dir.clone(),
+        None => match std::env::var_os("RIPPLE_CLI_DATA_DIR") {
+            Some(dir) => PathBuf::from(dir),
+            None => dirs::data_dir()
+                .ok_or_else(|| anyhow::anyhow!("No user data dir; pass --data-dir"))?
+                .join("ripple-cli"),
+        },
+    };
+    std::fs::create_dir_all(&data_dir)?;
+    Ok(data_dir)
+}
+
+/// The key provider of the data directory, unlocked if it needs a passphrase.
+fn keys(data_dir: &Path, app_config: &AppConfig) -> anyhow::Result<Arc<dyn KeyProvider>> {
+    let source = load_key_source(data_dir, &app_config.database_key)?;
+    let keys = key_provider_with_fallback(data_dir, &source, has_databases(data_dir))?;
+    if keys.is_locked() {
+        keys.unlock(&read_passphrase(
+            "RIPPLE_DB_PASSPHRASE",
+            "Database passphrase",
+        )?);
+    }
+    Ok(keys)
+}
+
+async fn execute(args: Args) -> anyhow::Result<()> {
+    // Restoring needs a data directory without a database, so it runs before the store opens
+    if let Command::Restore { input } = &args.command {
+        return restore(&args, input).await;
+    }
+    // Changing the key source needs the store closed
+    if let Command::KeySource { source } = &args.command {
+        return key_source(&args, source).await;
+    }
+    let client = Client::open(&args).await?;
+    let result = match args.command {
+        Command::Help => Ok(()),
+        Command::Login { paste_redirect } => login(&client, paste_redirect).await,
+        Command::Logout => logout(&client).await,
+        Command::Conversations => conversations(&client).await,
+        Command::Send {
+            conversation_id,
+            text,
+        } => send(&client, conversation_id, text).await,
+        Command::Tail {
+            conversation_id,
+            json,
+        } => tail(&client, conversation_id, json).await,
+        Command::Export {
+            conversation_id,
+            output,
+            options,
+        } => export(&client, conversation_id, output, options).await,
+        Command::Bridge { rules } => bridge(&client, rules).await,
+        Command::Backup { output } => backup(&client, &output).await,
+        Command::Restore { .. } | Command::KeySource { .. } => {
+            unreachable!("handled before opening the store")
+        }
+    };
+    let closed = close_store(&client.store).await;
+    result.and(closed)
+}
+
+async fn login(client: &Client, paste_redirect: bool) -> anyhow::Result<()> {
+    let code = login::authorize(&client.oauth_client, &client.app_config, paste_redirect).await?;
+    let token = client.oauth_client.request_token(code).await?;
+    // Only a verified token may replace the data of the account signed in before
+    let user_id = TokenValidator::new(
+        &client.app_config,
+        client.http_client.clone(),
+        client.store.clone(),
+    )
+    .validate(token.access_token().secret())
+    .await?
+    .get_sub();
+    // The data dir holds one account; drop what another account left behind
+    if client.data_sync.exists_token().await? && client.user_id().await? != user_id {
+        client.data_sync.clear_all_data().await?;
+    }
+    client.ripple_api.oauth_save_token(&token).await?;
+    client.data_sync.init().await?;
+    let nick_name = client
+        .data_sync
+        .get_profile()
+        .await?
+        .map(|p| p.nick_name)
+        .unwrap_or_default();
+    println!("Signed in as {} ({})", nick_name, user_id);
+    Ok(())
+}
+
+async fn logout(client: &Client) -> anyhow::Result<()> {
+    client.user_id().await?;
+    // Best effort, like the desktop app: a failed revocation must not keep the user signed in
+    if let Err(e) = client.data_sync.revoke_token().await {
+        eprintln!("Failed to revoke tokens: {}", e);
+    }
+    client.data_sync.clear_token().await?;
+    println!("Signed out");
+    Ok(())
+}
+
+async fn conversations(client: &Client) -> anyhow::Result<()> {
+    client.user_id().await?;
+    client.data_sync.init().await?;
+    let mut conversations = client.data_sync.get_conversations().await?;
+    conversations.sort_by_key(|c| std::cmp::Reverse(c.last_message_timestamp));
+    for c in conversations {
+        println!(
+            "{}\t{}\t{}\t{}",
+            c.conversation_id,
+            c.unread_count,
+            c.name,
+            c.last_message_text.unwrap_or_default().replace('\n', " ")
+        );
+    }
+    Ok(())
+}
+
+async fn send(client: &Client, conversation_id: String, text: String) -> anyhow::Result<()> {
+    let user_id = client.user_id().await?;
+    let text = if text == "-" {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)?;
+        text
+    } else {
+        text
+    };
+    let conversation = client
+        .data_sync
+        .find_conversation(&conversation_id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Unknown conversation {}", conversation_id))?;
+    let response = client
+        .ripple_api
+        .send_message(SendMessageRequest {
+            sender_id: user_id,
+            conversation_id,
+            receiver_id: conversation.peer_id,
+            group_id: conversation.group_id,
+            text_content: Some(text),
+            file_url: None,
+            file_name: None,
+        })
+        .await?
+        .into_result()?;
+    let data = response
+        .data
+        .ok_or_else(|| anyhow::anyhow!("No message ID returned"))?;
+    println!("{}", data.message_id);
+    Ok(())
+}
+
+async fn tail(client: &Client, conversation_id: Option<String>, json: bool) -> anyhow::Result<()> {
+    client.user_id().await?;
+    client.data_sync.init().await?;
+    let ws_manager = RippleWsManager::new(
+        PushedMessages(TailHandler {
+            data_sync: client.data_sync.clone(),
+            conversation_id,
+            json,
+        }),
+        client.data_sync.clone(),
+        Arc::new(ConnectionStats::default()),
+    );
+    client.data_sync.start_token_refresh();
+    ws_manager.start(&client.app_config.ws_gateway_url).await?;
+    let result = run_until_stopped(client).await;
+    client.data_sync.stop_token_refresh();
+    // Ignore errors if the connection never came up
+    let _ = ws_manager.stop().await;
+    result
+}
+
+async fn bridge(client: &Client, rules: PathBuf) -> anyhow::Result<()> {
+    let config = BridgeConfig::load(&rules)?;
+    let user_id = client.user_id().await?;
+    client.data_sync.init().await?;
+    client.data_sync.start_token_refresh();
+    let bridge = match Bridge::start(
+        &config,
+        user_id,
+        client.ripple_api.clone(),
+        client.data_sync.clone(),
+        client.http_client.clone(),
+        &client.app_config.ws_gateway_url,
+    )
+    .await
+    {
+        Ok(bridge) => bridge,
+        Err(e) => {
+            client.data_sync.stop_token_refresh();
+            return Err(e);
+        }
+    };
+    eprintln!(
+        "Accepting replies on http://{}/messages",
+        bridge.local_addr()
+    );
+    let result = run_until_stopped(client).await;
+    client.data_sync.stop_token_refresh();
+    bridge.stop().await;
+    result
+}
+
+/// Waits for Ctrl-C, or fails once the session can no longer be refreshed.
+async fn run_until_stopped(client: &Client) -> anyhow::Result<()> {
+    let expired = Arc::new(tokio::sync::Notify::new());
+    let expired_notify = expired.clone();
+    client
+        .ripple_api
+        .token_manager()
+        .set_session_expired_handler(move || expired_notify.notify_one());
+    tokio::select! {
+        signal = tokio::signal::ctrl_c() => signal.map_err(anyhow::Error::from),
+        _ = expired.notified() => Err(anyhow::anyhow!("Session expired; run `ripple-cli login` again")),
+    }
+}
+
+async fn export(
+    client: &Client,
+    conversation_id: String,
+    output: Option<PathBuf>,
+    options: ExportOptions,
+) -> anyhow::Result<()> {
+    client.user_id().await?;
+    client.data_sync.init().await?;
+    let exporter = ConversationExporter::new(client.ripple_api.clone(), client.data_sync.clone());
+    let summary = exporter
+        .export(&conversation_id, options, output.as_deref(), |progress| {
+            if progress.stage == ExportStage::Attachments {
+                eprint!(
+                    "\rDownloading attachments {}/{}",
+                    progress.completed,
+                    progress.total.unwrap_or_default()
+                );
+            }
+        })
+        .await?;
+    if summary.attachments > 0 && options.attachments == AttachmentMode::Download {
+        eprintln!();
+    }
+    eprintln!("Exported {} messages", summary.messages);
+    if summary.failed_attachments > 0 {
+        eprintln!(
+            "{} attachments could not be downloaded and are linked instead",
+            summary.failed_attachments
+        );
+    }
+    Ok(())
+}
+
+/// A passphrase from `$<var>`, else the first line of stdin.
+fn read_passphrase(var: &str, prompt: &str) -> anyhow::Result<String> {
+    if let Ok(passphrase) = std::env::var(var) {
+        return Ok(passphrase);
+    }
+    eprint!("{}: ", prompt);
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(feature = "sqlite-store")]
+async fn backup(client: &Client, output: &Path) -> anyhow::Result<()> {
+    let passphrase = read_passphrase("RIPPLE_BACKUP_PASSPHRASE", "Backup passphrase")?;
+    let info = client.store.backup_to(output, &passphrase).await?;
+    eprintln!(
+        "Backed up the database (schema version {}) to {}",
+        info.schema_version,
+        output.display()
+    );
+    Ok(())
+}
+
+#[cfg(feature = "memory-store")]
+async fn backup(_client: &Client, _output: &Path) -> anyhow::Result<()> {
+    anyhow::bail!("The in-memory store cannot be backed up")
+}
+
+#[cfg(feature = "sqlite-store")]
+async fn restore(args: &Args, input: &Path) -> anyhow::Result<()> {
+    let app_config = app_config(args)?;
+    let data_dir = data_dir(args)?;
+    let keys = keys(&data_dir, &app_config)?;
+    let passphrase = read_passphrase("RIPPLE_BACKUP_PASSPHRASE", "Backup passphrase")?;
+    let (store, info) = SqliteStore::restore_from(
+        input,
+        &passphrase,
+        data_dir.clone(),
+        KEY_NAME,
+        keys.as_ref(),
+        &app_config.database_cipher,
+    )
+    .await?;
+    store.close().await?;
+    eprintln!(
+        "Restored the database (schema version {}) into {}; run `ripple-cli login` to sign in",
+        info.schema_version,
+        data_dir.display()
+    );
+    Ok(())
+}
+
+#[cfg(feature = "memory-store")]
+async fn restore(_args: &Args, _input: &Path) -> anyhow::Result<()> {
+    anyhow::bail!("The in-memory store cannot be restored from a backup")
+}
+
+/// Re-encrypts the database with a key from `source` and remembers the source in the data
+/// directory. A new passphrase comes from `$RIPPLE_NEW_DB_PASSPHRASE` or stdin.
+#[cfg(feature = "sqlite-store")]
+async fn key_source(args: &Args, source: &DatabaseKeyConfig) -> anyhow::Result<()> {
+    let app_config = app_config(args)?;
+    let data_dir = data_dir(args)?;
+    let from = keys(&data_dir, &app_config)?;
+    let to = key_provider(source, &data_dir.join("keys"))?;
+    if to.is_locked() {
+        let passphrase = read_passphrase("RIPPLE_NEW_DB_PASSPHRASE", "New database passphrase")?;
+        if passphrase.is_empty() {
+            anyhow::bail!("The passphrase must not be empty");
+        }
+        to.unlock(&passphrase);
+    }
+    SqliteStore::migrate_key(
+        &data_dir,
+        KEY_NAME,
+        from.as_ref(),
+        to.as_ref(),
+        &app_config.database_cipher,
+    )
+    .await?;
+    save_key_source(&data_dir, source)?;
+    eprintln!("The database key now comes from the {}", to.name());
+    Ok(())
+}
+
+#[cfg(feature = "memory-store")]
+async fn key_source(_args: &Args, _source: &DatabaseKeyConfig) -> anyhow::Result<()> {
+    anyhow::bail!("The in-memory store has no key source")
+}
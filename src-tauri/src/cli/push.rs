@@ -0,0 +1,87 @@
+use crate::ripple_api::api_response::MessageItem;
+use crate::ripple_syncer::DataSyncManager;
+use crate::ripple_ws::syncer_control::SyncerControl;
+use crate::ripple_ws::ws_message_handler::RippleWsMsgHandler;
+use crate::store_engine::store_engine::RippleStorage;
+use futures_channel::mpsc::UnboundedSender;
+use prost::Message as ProstMessage;
+use ripple_proto::ripple_pb::push_message_request::Payload;
+use ripple_proto::ripple_pb::ws_message::MessageType;
+use ripple_proto::ripple_pb::WsMessage;
+use std::future::Future;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::warn;
+
+/// What a long-running command does with each chat message pushed to the signed-in user.
+///
+/// Event pushes (profile, relation and conversation changes) never reach it; the next command
+/// that syncs picks those up.
+pub(super) trait PushHandler: Clone + Send + Sync + 'static {
+    type Store: RippleStorage;
+
+    fn data_sync(&self) -> &DataSyncManager<Self::Store>;
+
+    /// Printed to stderr once the WebSocket is connected.
+    fn connected_notice(&self) -> &'static str;
+
+    /// Called with every pushed message after it has been cached locally.
+    fn handle_pushed(&self, item: MessageItem) -> impl Future<Output = anyhow::Result<()>> + Send;
+}
+
+/// Runs a [`PushHandler`] on the WebSocket: decodes message pushes and caches them, so a
+/// later `export` finds them, before handing them over.
+#[derive(Clone)]
+pub(super) struct PushedMessages<H>(pub H);
+
+impl<H: PushHandler> RippleWsMsgHandler for PushedMessages<H> {
+    async fn handle_message(
+        &self,
+        _send_tx: &UnboundedSender<Message>,
+        message: Message,
+    ) -> anyhow::Result<()> {
+        let Message::Binary(frame) = message else {
+            return Ok(());
+        };
+        let Some(MessageType::PushMessageRequest(push)) = WsMessage::decode(frame)?.message_type
+        else {
+            return Ok(());
+        };
+        if !matches!(push.payload, Some(Payload::MessagePayload(_))) {
+            return Ok(());
+        }
+        let item = match MessageItem::try_from(&push) {
+            Ok(item) => item,
+            Err(e) => {
+                warn!("Skipping message push: {}", e);
+                return Ok(());
+            }
+        };
+        if let Err(e) = self.0.data_sync().store_message(item.clone()).await {
+            warn!("Failed to cache message {}: {}", item.message_id, e);
+        }
+        self.0.handle_pushed(item).await
+    }
+
+    async fn notify_connect(&self) {
+        eprintln!("{}", self.0.connected_notice());
+    }
+
+    async fn notify_disconnect(&self) {
+        eprintln!("Disconnected, reconnecting");
+    }
+
+    async fn notify_ws_stop(&self, err_msg: String) {
+        eprintln!("WebSocket stopped: {}", err_msg);
+    }
+}
+
+/// Nothing runs alongside the connection; pushes are handled inline.
+impl<H: PushHandler> SyncerControl for PushedMessages<H> {
+    async fn start_syncer(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn stop_syncer(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+use crate::cli::push::PushHandler;
+use crate::ripple_api::api_response::{MessageItem, MessageItemType};
+use crate::ripple_syncer::DataSyncManager;
+use crate::store_engine::store_engine::RippleStorage;
+
+/// Prints pushed messages, optionally only those of one conversation.
+#[derive(Clone)]
+pub(super) struct TailHandler<S: RippleStorage> {
+    pub data_sync: DataSyncManager<S>,
+    pub conversation_id: Option<String>,
+    pub json: bool,
+}
+
+impl<S: RippleStorage> TailHandler<S> {
+    fn print(&self, message: &MessageItem) -> anyhow::Result<()> {
+        if self.json {
+            println!("{}", serde_json::to_string(message)?);
+            return Ok(());
+        }
+        let body = match message.message_type {
+            MessageItemType::Text => {
+                let mut body = message.text.clone().unwrap_or_default();
+                if let Some(file_name) = &message.file_name {
+                    body.push_str(&format!(" [file: {}]", file_name));
+                }
+                body
+            }
+            MessageItemType::Command => {
+                format!("[{}]", message.command_data.as_deref().unwrap_or("command"))
+            }
+            MessageItemType::Unknown => "[unsupported message]".to_string(),
+        };
+        println!(
+            "{}\t{}\t{}\t{}",
+            message.send_timestamp,
+            message.conversation_id,
+            message.sender_id,
+            body.replace('\n', " ")
+        );
+        Ok(())
+    }
+}
+
+impl<S: RippleStorage> PushHandler for TailHandler<S> {
+    type Store = S;
+
+    fn data_sync(&self) -> &DataSyncManager<S> {
+        &self.data_sync
+    }
+
+    fn connected_notice(&self) -> &'static str {
+        "Connected, waiting for messages (Ctrl-C to stop)"
+    }
+
+    async fn handle_pushed(&self, item: MessageItem) -> anyhow::Result<()> {
+        if self
+            .conversation_id
+            .as_ref()
+            .is_none_or(|id| *id == item.conversation_id)
+        {
+            self.print(&item)?;
+        }
+        Ok(())
+    }
+}
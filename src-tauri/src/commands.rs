@@ -1,50 +1,41 @@
-use crate::app_config::AppConfig;
+use crate::account::account_manager::AccountInfo;
+use crate::app_config::{AppConfig, DatabaseKeyConfig};
+use crate::conversation_export::{
+    AttachmentMode, ConversationExporter, ExportFormat, ExportOptions, ExportRange, ExportSummary,
+};
+use crate::desktop::DefaultAccountManager;
+use crate::diagnostics::{DiagnosticsBundle, PendingOperations};
+use crate::errors;
 use crate::file_utils::FileUtils;
+use crate::logging::LogController;
 use crate::ripple_api::api_response::{
-    GroupMemberData, ReadMessagesData, RelationUsers, SendMessageRequest, UserProfileData,
+    ApiEnvelope, GroupMemberData, ReadMessagesData, RelationUsers, SendMessageRequest,
+    UserProfileData,
 };
-use crate::ripple_api::RippleApi;
 use crate::ripple_syncer::event_emitter::UIConversations;
-use crate::ripple_syncer::DataSyncManager;
-use crate::server::Server;
-use crate::{errors, DefaultStoreEngine, DefaultWsManager};
+use crate::server::{callback_router, Server};
+use crate::store_engine::BackupInfo;
 use anyhow::anyhow;
 use serde::Serialize;
 use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::path::Path;
-use tauri::{AppHandle, Manager, State};
+use tauri::{AppHandle, Emitter, Manager, State};
 use tauri_plugin_opener::OpenerExt;
+use tracing::{debug, info, warn};
 
 #[tauri::command]
 pub async fn exists_token(
-    data_sync: State<'_, DataSyncManager<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<bool, errors::CommandError> {
-    Ok(data_sync.exists_token().await?)
+    Ok(accounts.exists_token().await?)
 }
 
 #[tauri::command]
-pub async fn resume_session(app: AppHandle) -> Result<(), errors::CommandError> {
-    let data_sync = app.state::<DataSyncManager<DefaultStoreEngine>>();
-    match data_sync.check_and_clear_on_user_change().await {
-        Ok(cleared) => {
-            if cleared {
-                println!("[resume_session] User changed, all stored data has been cleared");
-            }
-        }
-        Err(e) => {
-            eprintln!("[resume_session] Failed to check user change: {}", e);
-        }
-    }
-    data_sync.init().await?;
-    let app_handle = app.clone();
-    tauri::async_runtime::spawn(async move {
-        let ws_manager = app_handle.state::<DefaultWsManager>();
-        let config = app_handle.state::<AppConfig>();
-        if let Err(e) = ws_manager.start(&config.ws_gateway_url).await {
-            eprintln!("[resume_session] Failed to start WebSocket: {}", e);
-        }
-    });
-    Ok(())
+pub async fn resume_session(
+    accounts: State<'_, DefaultAccountManager>,
+) -> Result<(), errors::CommandError> {
+    Ok(accounts.start_active_session().await?)
 }
 
 #[tauri::command]
@@ -53,8 +44,11 @@ pub async fn start_server(app: AppHandle) -> Result<(), errors::CommandError> {
     let state_server = app.state::<tokio::sync::Mutex<Server>>();
     let mut server = state_server.lock().await;
     server
-        .start(app_config.callback_server_addr.clone(), app.clone())
-        .await;
+        .start(
+            app_config.callback_server_addr.clone(),
+            callback_router(app.clone()),
+        )
+        .await?;
     Ok(())
 }
 
@@ -78,19 +72,20 @@ pub fn open_signup_url(app: AppHandle) -> Result<(), errors::CommandError> {
 
 #[tauri::command]
 pub fn open_auth_url(app: AppHandle) -> Result<(), errors::CommandError> {
-    let ripple = app.state::<RippleApi<DefaultStoreEngine>>();
+    let accounts = app.state::<DefaultAccountManager>();
     Ok(app
         .opener()
-        .open_url(ripple.oauth_auth_url(), None::<&str>)?)
+        .open_url(accounts.oauth_auth_url(), None::<&str>)?)
 }
 
 #[tauri::command]
 pub async fn get_user_profile(
-    data_sync: State<'_, DataSyncManager<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<UserProfileData, errors::CommandError> {
+    let data_sync = accounts.data_sync().await?;
     let profile = data_sync.get_profile().await?;
     match profile {
-        Some(profile_data) => Ok(profile_data.into()),
+        Some(profile_data) => Ok(profile_data),
         None => Err(anyhow!("User profile not found").into()),
     }
 }
@@ -103,24 +98,17 @@ pub async fn upload_user_avatar_blob(
     use base64::{engine::general_purpose::STANDARD, Engine};
     use mime::IMAGE_PNG;
 
-    let ripple = app.state::<RippleApi<DefaultStoreEngine>>();
+    let ripple = app.state::<DefaultAccountManager>().ripple_api().await?;
 
     // Decode base64 to bytes
     let image_bytes = STANDARD
         .decode(&image_data)
         .map_err(|e| anyhow::anyhow!("Failed to decode base64: {}", e))?;
 
-    let res = ripple
+    ripple
         .upload_avatar("avatar.png".to_string(), IMAGE_PNG, image_bytes)
-        .await?;
-
-    if res.code != 200 {
-        return Err(errors::CommandError::RippleAPIError(
-            "upload_user_avatar_blob".to_string(),
-            res.code,
-            res.message,
-        ));
-    }
+        .await?
+        .into_result()?;
     Ok(())
 }
 
@@ -132,7 +120,7 @@ pub async fn upload_image_blob(
     use base64::{engine::general_purpose::STANDARD, Engine};
     use mime::IMAGE_PNG;
 
-    let ripple = app.state::<RippleApi<DefaultStoreEngine>>();
+    let ripple = app.state::<DefaultAccountManager>().ripple_api().await?;
 
     // Decode base64 to bytes
     let image_bytes = STANDARD
@@ -141,23 +129,10 @@ pub async fn upload_image_blob(
 
     let res = ripple
         .upload_avatar("avatar.png".to_string(), IMAGE_PNG, image_bytes)
-        .await?;
-
-    if res.code != 200 {
-        return Err(errors::CommandError::RippleAPIError(
-            "upload_image_blob".to_string(),
-            res.code,
-            res.message,
-        ));
-    }
+        .await?
+        .into_result()?;
 
-    res.data.map(|d| d.url).ok_or_else(|| {
-        errors::CommandError::RippleAPIError(
-            "upload_image_blob".to_string(),
-            500,
-            "No URL returned".to_string(),
-        )
-    })
+    Ok(res.data.map(|d| d.url).ok_or(anyhow!("No URL returned"))?)
 }
 
 #[tauri::command]
@@ -169,198 +144,160 @@ pub async fn upload_group_avatar_blob(
     use base64::{engine::general_purpose::STANDARD, Engine};
     use mime::IMAGE_PNG;
 
-    let ripple = app.state::<RippleApi<DefaultStoreEngine>>();
+    let ripple = app.state::<DefaultAccountManager>().ripple_api().await?;
 
     // Decode base64 to bytes
     let image_bytes = STANDARD
         .decode(&image_data)
         .map_err(|e| anyhow::anyhow!("Failed to decode base64: {}", e))?;
 
-    let res = ripple
+    ripple
         .upload_group_avatar(group_id, "avatar.png".to_string(), IMAGE_PNG, image_bytes)
-        .await?;
-
-    if res.code != 200 {
-        return Err(errors::CommandError::RippleAPIError(
-            "upload_group_avatar_blob".to_string(),
-            res.code,
-            res.message,
-        ));
-    }
+        .await?
+        .into_result()?;
     Ok(())
 }
 
 #[tauri::command]
 pub async fn update_user_nickname(
     nickname: String,
-    state_ripple: State<'_, RippleApi<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<(), errors::CommandError> {
-    let response = state_ripple.update_profile(Some(nickname)).await?;
-    if response.code != 200 {
-        return Err(errors::CommandError::RippleAPIError(
-            "update_nickname".to_string(),
-            response.code,
-            response.message,
-        ));
-    }
+    let state_ripple = accounts.ripple_api().await?;
+    state_ripple
+        .update_profile(Some(nickname))
+        .await?
+        .into_result()?;
     Ok(())
 }
 
 #[tauri::command]
 pub async fn remove_user_avatar(
-    state_ripple: State<'_, RippleApi<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<(), errors::CommandError> {
-    let response = state_ripple.delete_user_avatar().await?;
-    if response.code == 200 {
-        Ok(())
-    } else {
-        Err(errors::CommandError::RippleAPIError(
-            "delete_user_avatar".to_string(),
-            response.code,
-            response.message,
-        ))
-    }
+    let state_ripple = accounts.ripple_api().await?;
+    state_ripple.delete_user_avatar().await?.into_result()?;
+    Ok(())
 }
 
 #[tauri::command]
 pub async fn add_friend(
     target_user_id: String,
-    state_ripple: State<'_, RippleApi<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<(), errors::CommandError> {
-    let response = state_ripple.add_friend(target_user_id).await?;
-    if response.code == 200 {
-        Ok(())
-    } else {
-        Err(errors::CommandError::RippleAPIError(
-            "add_friend".to_string(),
-            response.code,
-            response.message,
-        ))
-    }
+    let state_ripple = accounts.ripple_api().await?;
+    state_ripple
+        .add_friend(target_user_id)
+        .await?
+        .into_result()?;
+    Ok(())
 }
 
 #[tauri::command]
 pub async fn remove_friend(
     friend_id: String,
-    state_ripple: State<'_, RippleApi<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<(), errors::CommandError> {
-    let response = state_ripple.remove_friend(friend_id).await?;
-    if response.code == 200 {
-        Ok(())
-    } else {
-        Err(errors::CommandError::RippleAPIError(
-            "remove_friend".to_string(),
-            response.code,
-            response.message,
-        ))
-    }
+    let state_ripple = accounts.ripple_api().await?;
+    state_ripple.remove_friend(friend_id).await?.into_result()?;
+    Ok(())
 }
 
 #[tauri::command]
 pub async fn update_friend_display_name(
     friend_id: String,
     remark_name: String,
-    state_ripple: State<'_, RippleApi<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<(), errors::CommandError> {
-    let response = state_ripple
+    let state_ripple = accounts.ripple_api().await?;
+    state_ripple
         .update_friend(friend_id, Some(remark_name))
-        .await?;
-    if response.code != 200 {
-        return Err(errors::CommandError::RippleAPIError(
-            "update_friend_display_name".to_string(),
-            response.code,
-            response.message,
-        ));
-    }
+        .await?
+        .into_result()?;
     Ok(())
 }
 
 #[tauri::command]
 pub async fn block_user(
     target_user_id: String,
-    state_ripple: State<'_, RippleApi<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<(), errors::CommandError> {
-    let response = state_ripple.block_user(target_user_id).await?;
-    if response.code != 200 {
-        return Err(errors::CommandError::RippleAPIError(
-            "block_user".to_string(),
-            response.code,
-            response.message,
-        ));
-    }
+    let state_ripple = accounts.ripple_api().await?;
+    state_ripple
+        .block_user(target_user_id)
+        .await?
+        .into_result()?;
     Ok(())
 }
 
 #[tauri::command]
 pub async fn unblock_user(
     target_user_id: String,
-    state_ripple: State<'_, RippleApi<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<(), errors::CommandError> {
-    let response = state_ripple.unblock_user(target_user_id).await?;
-    if response.code != 200 {
-        return Err(errors::CommandError::RippleAPIError(
-            "unblock_user".to_string(),
-            response.code,
-            response.message,
-        ));
-    }
+    let state_ripple = accounts.ripple_api().await?;
+    state_ripple
+        .unblock_user(target_user_id)
+        .await?
+        .into_result()?;
     Ok(())
 }
 
 #[tauri::command]
 pub async fn hide_blocked_user(
     target_user_id: String,
-    state_ripple: State<'_, RippleApi<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<(), errors::CommandError> {
-    let response = state_ripple
+    let state_ripple = accounts.ripple_api().await?;
+    state_ripple
         .update_blocked_user(target_user_id, Some(true))
-        .await?;
-    if response.code != 200 {
-        return Err(errors::CommandError::RippleAPIError(
-            "hide_blocked_user".to_string(),
-            response.code,
-            response.message,
-        ));
-    }
+        .await?
+        .into_result()?;
     Ok(())
 }
 
 #[tauri::command]
 pub async fn get_user_profile_by_id(
     user_id: String,
-    state_ripple: State<'_, RippleApi<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<UserProfileData, errors::CommandError> {
+    let state_ripple = accounts.ripple_api().await?;
     let response = state_ripple
         .get_user_profile_by_id(user_id.parse().unwrap())
-        .await?;
-    if response.code == 200 {
-        Ok(response.data)
-    } else {
-        Err(errors::CommandError::RippleAPIError(
-            "get_user_profile_by_id".to_string(),
-            response.code,
-            response.message,
-        ))
-    }
+        .await?
+        .into_result()?;
+    Ok(response.data)
 }
 
 #[tauri::command]
 pub async fn get_relations(
-    sync_manager: State<'_, DataSyncManager<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<RelationUsers, errors::CommandError> {
+    let sync_manager = accounts.data_sync().await?;
     Ok(RelationUsers {
-        users: sync_manager.get_relations().await?.into(),
+        users: sync_manager.get_relations().await?,
     })
 }
 
 #[tauri::command]
 pub async fn get_conversations(
-    sync_manager: State<'_, DataSyncManager<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<UIConversations, errors::CommandError> {
+    let sync_manager = accounts.data_sync().await?;
     Ok(sync_manager.get_conversations().await?.into())
 }
 
+/// Last successful sync time (unix millis) per domain, e.g. `{"relations": 1700000000000}`.
+#[tauri::command]
+pub async fn get_sync_status(
+    accounts: State<'_, DefaultAccountManager>,
+) -> Result<HashMap<String, i64>, errors::CommandError> {
+    let sync_manager = accounts.data_sync().await?;
+    Ok(sync_manager.get_sync_timestamps().await?)
+}
+
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn send_message(
     sender_id: String,
     conversation_id: String,
@@ -369,8 +306,11 @@ pub async fn send_message(
     text: Option<String>,
     file_url: Option<String>,
     file_name: Option<String>,
-    state_ripple: State<'_, RippleApi<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
+    pending: State<'_, PendingOperations>,
 ) -> Result<String, errors::CommandError> {
+    let state_ripple = accounts.ripple_api().await?;
+    let _pending = pending.begin_message(&conversation_id, text.is_some(), file_url.is_some());
     let request = SendMessageRequest {
         sender_id,
         conversation_id,
@@ -381,25 +321,9 @@ pub async fn send_message(
         file_name,
     };
 
-    let response = state_ripple.send_message(request).await?;
-
-    if response.code == 200 {
-        if let Some(data) = response.data {
-            Ok(data.message_id)
-        } else {
-            Err(errors::CommandError::RippleAPIError(
-                "send_message".to_string(),
-                500,
-                "No message ID returned".to_string(),
-            ))
-        }
-    } else {
-        Err(errors::CommandError::RippleAPIError(
-            "send_message".to_string(),
-            response.code,
-            response.message,
-        ))
-    }
+    let response = state_ripple.send_message(request).await?.into_result()?;
+    let data = response.data.ok_or(anyhow!("No message ID returned"))?;
+    Ok(data.message_id)
 }
 
 #[tauri::command]
@@ -407,19 +331,20 @@ pub async fn read_latest_messages(
     conversation_id: String,
     read_size: u32,
     last_read_message_id: String,
-    data_sync: State<'_, DataSyncManager<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<ReadMessagesData, errors::CommandError> {
+    let data_sync = accounts.data_sync().await?;
     // API has a max limit of 200 messages per request
     let capped_read_size = read_size.min(200);
 
     if !last_read_message_id.is_empty() {
-        println!(
-            "[Commands] read_latest_messages: conv={}, size={}, last_read={}",
+        debug!(
+            "read_latest_messages: conv={}, size={}, last_read={}",
             conversation_id, capped_read_size, last_read_message_id
         );
     } else {
-        println!(
-            "[Commands] read_latest_messages: conv={}, size={} (first visit)",
+        debug!(
+            "read_latest_messages: conv={}, size={} (first visit)",
             conversation_id, capped_read_size
         );
     }
@@ -435,12 +360,13 @@ pub async fn read_messages_before(
     conversation_id: String,
     before_message_id: String,
     read_size: u32,
-    data_sync: State<'_, DataSyncManager<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<ReadMessagesData, errors::CommandError> {
+    let data_sync = accounts.data_sync().await?;
     // API has a max limit of 200 messages per request
     let capped_read_size = read_size.min(200);
-    println!(
-        "[Commands] read_messages_before: capping read_size {} -> {}",
+    debug!(
+        "read_messages_before: capping read_size {} -> {}",
         read_size, capped_read_size
     );
 
@@ -454,21 +380,14 @@ pub async fn read_messages_before(
 pub async fn mark_last_read_message_id(
     conversation_id: String,
     message_id: String,
-    data_sync: State<'_, DataSyncManager<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<(), errors::CommandError> {
-    let response = data_sync
+    let data_sync = accounts.data_sync().await?;
+    data_sync
         .mark_last_read_message_id(conversation_id, message_id)
-        .await?;
-
-    if response.code == 200 {
-        Ok(())
-    } else {
-        Err(errors::CommandError::RippleAPIError(
-            "mark_last_read_message_id".to_string(),
-            response.code,
-            response.message,
-        ))
-    }
+        .await?
+        .into_result()?;
+    Ok(())
 }
 
 #[tauri::command]
@@ -476,29 +395,15 @@ pub async fn create_group(
     sender_id: String,
     group_name: String,
     member_ids: Vec<String>,
-    ripple_api: State<'_, RippleApi<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<String, errors::CommandError> {
+    let ripple_api = accounts.ripple_api().await?;
     let response = ripple_api
         .create_group(sender_id, group_name, member_ids)
-        .await?;
-
-    if response.code == 200 {
-        if let Some(data) = response.data {
-            Ok(data.group_id)
-        } else {
-            Err(errors::CommandError::RippleAPIError(
-                "create_group".to_string(),
-                response.code,
-                "No group ID in response".to_string(),
-            ))
-        }
-    } else {
-        Err(errors::CommandError::RippleAPIError(
-            "create_group".to_string(),
-            response.code,
-            response.message,
-        ))
-    }
+        .await?
+        .into_result()?;
+    let data = response.data.ok_or(anyhow!("No group ID in response"))?;
+    Ok(data.group_id)
 }
 
 #[tauri::command]
@@ -508,74 +413,22 @@ pub async fn invite_members(
     member_ids: Vec<String>,
     group_name: String,
     group_avatar: Option<String>,
-    ripple_api: State<'_, RippleApi<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<(), errors::CommandError> {
-    let response = ripple_api
+    let ripple_api = accounts.ripple_api().await?;
+    ripple_api
         .add_group_members(group_id, sender_id, member_ids, group_name, group_avatar)
-        .await?;
-
-    if response.code == 200 {
-        Ok(())
-    } else {
-        Err(errors::CommandError::RippleAPIError(
-            "invite_members".to_string(),
-            response.code,
-            response.message,
-        ))
-    }
+        .await?
+        .into_result()?;
+    Ok(())
 }
 
 #[tauri::command]
 pub async fn get_group_members(
     group_id: String,
-    data_sync: State<'_, DataSyncManager<DefaultStoreEngine>>,
-    ripple_api: State<'_, RippleApi<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<Vec<GroupMemberData>, errors::CommandError> {
-    // Check if members exist in local cache
-    if data_sync.exist_group_members(&group_id).await? {
-        println!(
-            "[Commands] get_group_members: returning cached members for group {}",
-            group_id
-        );
-        return Ok(data_sync.get_group_members(&group_id).await?);
-    }
-
-    // Fetch all members from API using pagination
-    println!(
-        "[Commands] get_group_members: fetching from API for group {}",
-        group_id
-    );
-    let mut all_members: Vec<GroupMemberData> = Vec::new();
-    let mut next_page_token: Option<String> = None;
-    let page_size = 50;
-
-    loop {
-        let response = ripple_api
-            .get_group_members(group_id.clone(), next_page_token.clone(), page_size)
-            .await?;
-
-        if response.code != 200 {
-            return Err(errors::CommandError::RippleAPIError(
-                "get_group_members".to_string(),
-                response.code,
-                response.message,
-            ));
-        }
-
-        all_members.extend(response.data.members);
-
-        if !response.data.has_more {
-            break;
-        }
-        next_page_token = response.data.next_page_token;
-    }
-
-    // Store members in local cache
-    data_sync
-        .store_group_members(&group_id, all_members.clone())
-        .await?;
-
-    Ok(all_members)
+    Ok(accounts.group_members(&group_id).await?)
 }
 
 #[tauri::command]
@@ -583,51 +436,38 @@ pub async fn update_group_name(
     group_id: String,
     sender_id: String,
     group_name: String,
-    ripple_api: State<'_, RippleApi<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<(), errors::CommandError> {
-    let response = ripple_api
+    let ripple_api = accounts.ripple_api().await?;
+    ripple_api
         .update_group(group_id, sender_id, Some(group_name))
-        .await?;
-    if response.code == 200 {
-        Ok(())
-    } else {
-        Err(errors::CommandError::RippleAPIError(
-            "update_group_name".to_string(),
-            response.code,
-            response.message,
-        ))
-    }
+        .await?
+        .into_result()?;
+    Ok(())
 }
 
 #[tauri::command]
 pub async fn leave_group(
     group_id: String,
-    data_sync: State<'_, DataSyncManager<DefaultStoreEngine>>,
-    ripple_api: State<'_, RippleApi<DefaultStoreEngine>>,
+    accounts: State<'_, DefaultAccountManager>,
 ) -> Result<(), errors::CommandError> {
-    let response = ripple_api.leave_group(group_id.clone()).await?;
-
-    if response.code == 200 {
-        // Clear group members cache since we can no longer access this group's member list
-        if let Err(e) = data_sync.clear_group_members(&group_id).await {
-            eprintln!(
-                "[Commands] leave_group: failed to clear group members cache: {}",
-                e
-            );
-        } else {
-            println!(
-                "[Commands] leave_group: cleared group members cache for group {}",
-                group_id
-            );
-        }
-        Ok(())
+    let data_sync = accounts.data_sync().await?;
+    let ripple_api = accounts.ripple_api().await?;
+    ripple_api
+        .leave_group(group_id.clone())
+        .await?
+        .into_result()?;
+
+    // Clear group members cache since we can no longer access this group's member list
+    if let Err(e) = data_sync.clear_group_members(&group_id).await {
+        warn!("leave_group: failed to clear group members cache: {}", e);
     } else {
-        Err(errors::CommandError::RippleAPIError(
-            "leave_group".to_string(),
-            response.code,
-            response.message,
-        ))
+        info!(
+            "leave_group: cleared group members cache for group {}",
+            group_id
+        );
     }
+    Ok(())
 }
 
 #[derive(Clone, Serialize)]
@@ -640,11 +480,13 @@ pub async fn upload_attachment(
     app: AppHandle,
     file_path: String,
 ) -> Result<UploadAttachmentResponse, errors::CommandError> {
-    let ripple = app.state::<RippleApi<DefaultStoreEngine>>();
+    let ripple = app.state::<DefaultAccountManager>().ripple_api().await?;
     let filepath = Path::new(&file_path);
 
     let file_data = std::fs::read(filepath).map_err(|e| anyhow!("Failed to read file: {}", e))?;
     let file_size = file_data.len() as i64;
+    let pending = app.state::<PendingOperations>();
+    let upload = pending.begin_upload(file_size);
 
     let mut hasher = Sha256::new();
     hasher.update(&file_data);
@@ -659,19 +501,16 @@ pub async fn upload_attachment(
             file_sha256.clone(),
             original_filename.to_string(),
         )
-        .await?;
-
-    if init_response.code != 200 {
-        return Err(errors::CommandError::RippleAPIError(
-            "initiate_attachment_upload".to_string(),
-            init_response.code,
-            init_response.message,
-        ));
-    }
+        .await?
+        .into_result()?;
 
     let init_data = init_response
         .data
         .ok_or(anyhow!("No data in initiate response"))?;
+    upload.update_upload(|session| {
+        session.upload_mode = Some(init_data.upload_mode);
+        session.total_chunks = init_data.total_chunks;
+    });
 
     match init_data.upload_mode {
         // Mode 0: File already exists
@@ -695,15 +534,8 @@ pub async fn upload_attachment(
                     file_data,
                     original_filename.to_string(),
                 )
-                .await?;
-
-            if upload_response.code != 200 {
-                return Err(errors::CommandError::RippleAPIError(
-                    "upload_attachment_single".to_string(),
-                    upload_response.code,
-                    upload_response.message,
-                ));
-            }
+                .await?
+                .into_result()?;
 
             let file_url = upload_response
                 .data
@@ -748,28 +580,18 @@ pub async fn upload_attachment(
                     )
                     .await?;
 
-                if chunk_response.code != 200 {
+                if let Err(e) = chunk_response.into_result() {
                     // Abort upload on failure
                     let _ = ripple.abort_attachment_upload(object_name.clone()).await;
-                    return Err(errors::CommandError::RippleAPIError(
-                        "upload_attachment_chunk".to_string(),
-                        chunk_response.code,
-                        chunk_response.message,
-                    ));
+                    return Err(e.into());
                 }
+                upload.update_upload(|session| session.uploaded_chunks = chunk_number);
             }
 
             let complete_response = ripple
                 .complete_attachment_upload(object_name, total_chunks)
-                .await?;
-
-            if complete_response.code != 200 {
-                return Err(errors::CommandError::RippleAPIError(
-                    "complete_attachment_upload".to_string(),
-                    complete_response.code,
-                    complete_response.message,
-                ));
-            }
+                .await?
+                .into_result()?;
 
             let file_url = complete_response
                 .data
@@ -778,24 +600,185 @@ pub async fn upload_attachment(
             Ok(UploadAttachmentResponse { file_url })
         }
 
-        _ => Err(errors::CommandError::RippleAPIError(
-            "upload_attachment".to_string(),
-            400,
-            format!("Unknown upload mode: {}", init_data.upload_mode),
-        )),
+        _ => Err(anyhow!("Unknown upload mode: {}", init_data.upload_mode).into()),
     }
 }
 
 #[tauri::command]
 pub async fn logout(
-    data_sync: State<'_, DataSyncManager<DefaultStoreEngine>>,
-    ws_manager: State<'_, crate::DefaultWsManager>,
+    accounts: State<'_, DefaultAccountManager>,
+    wipe_local_data: Option<bool>,
+) -> Result<(), errors::CommandError> {
+    Ok(accounts.logout(wipe_local_data.unwrap_or(false)).await?)
+}
+
+/// Starts the OAuth callback server and opens the sign-in page for another account.
+/// The new account becomes active once the callback completes.
+#[tauri::command]
+pub async fn add_account(app: AppHandle) -> Result<(), errors::CommandError> {
+    start_server(app.clone()).await?;
+    open_auth_url(app)
+}
+
+#[tauri::command]
+pub async fn list_accounts(
+    accounts: State<'_, DefaultAccountManager>,
+) -> Result<Vec<AccountInfo>, errors::CommandError> {
+    Ok(accounts.list_accounts().await)
+}
+
+#[tauri::command]
+pub async fn switch_account(
+    accounts: State<'_, DefaultAccountManager>,
+    user_id: String,
+) -> Result<(), errors::CommandError> {
+    Ok(accounts.switch_account(&user_id).await?)
+}
+
+#[tauri::command]
+pub async fn remove_account(
+    accounts: State<'_, DefaultAccountManager>,
+    user_id: String,
+) -> Result<(), errors::CommandError> {
+    Ok(accounts.remove_account(&user_id).await?)
+}
+
+/// Writes the active account's database to `path`, encrypted with a key derived from
+/// `passphrase`. Tokens are not included.
+#[tauri::command]
+pub async fn backup_database(
+    accounts: State<'_, DefaultAccountManager>,
+    path: String,
+    passphrase: String,
+) -> Result<BackupInfo, errors::CommandError> {
+    Ok(accounts
+        .backup_database(Path::new(&path), &passphrase)
+        .await?)
+}
+
+/// Replaces the active account's local history with the backup at `path`. An
+/// `account-switched` event follows, as after switching accounts.
+#[tauri::command]
+pub async fn restore_database(
+    accounts: State<'_, DefaultAccountManager>,
+    path: String,
+    passphrase: String,
+) -> Result<BackupInfo, errors::CommandError> {
+    Ok(accounts
+        .restore_database(Path::new(&path), &passphrase)
+        .await?)
+}
+
+/// Re-encrypts the active account's database under a newly generated keyring secret. An
+/// `account-switched` event follows, as after switching accounts.
+#[tauri::command]
+pub async fn rotate_db_key(
+    accounts: State<'_, DefaultAccountManager>,
+) -> Result<(), errors::CommandError> {
+    Ok(accounts.rotate_db_key().await?)
+}
+
+/// Whether the database waits for its passphrase; the UI asks for it before resuming.
+#[tauri::command]
+pub async fn database_locked(
+    accounts: State<'_, DefaultAccountManager>,
+) -> Result<bool, errors::CommandError> {
+    Ok(accounts.database_locked().await)
+}
+
+#[tauri::command]
+pub async fn unlock_database(
+    accounts: State<'_, DefaultAccountManager>,
+    passphrase: String,
+) -> Result<(), errors::CommandError> {
+    Ok(accounts.unlock_database(&passphrase).await?)
+}
+
+/// Re-encrypts every account's database with keys from `source`, e.g.
+/// `{"source": "passphrase"}`, and keeps using it. An `account-switched` event follows.
+#[tauri::command]
+pub async fn set_db_key_source(
+    accounts: State<'_, DefaultAccountManager>,
+    source: DatabaseKeyConfig,
+    passphrase: Option<String>,
 ) -> Result<(), errors::CommandError> {
-    // Stop WebSocket connection (ignore errors if already stopped)
-    let _ = ws_manager.stop().await;
+    Ok(accounts
+        .set_key_source(&source, passphrase.as_deref())
+        .await?)
+}
 
-    // Clear token from storage
-    data_sync.clear_token().await?;
+#[tauri::command]
+pub fn get_log_level(logs: State<'_, LogController>) -> String {
+    logs.level()
+}
 
+/// Changes the backend log filter without restarting, e.g. `debug` or
+/// `info,ripple_im_app_lib::ripple_ws=trace`.
+#[tauri::command]
+pub fn set_log_level(
+    logs: State<'_, LogController>,
+    level: String,
+) -> Result<(), errors::CommandError> {
+    Ok(logs.set_level(&level)?)
+}
+
+/// Writes a zip for bug reports to `path`: recent logs, app version, redacted config,
+/// WebSocket history, store statistics and in-flight sends and uploads. Account sections are
+/// left out when nobody is signed in.
+#[tauri::command]
+pub async fn export_diagnostics(app: AppHandle, path: String) -> Result<(), errors::CommandError> {
+    let mut bundle = DiagnosticsBundle::default();
+    bundle.add_summary(&app.package_info().version.to_string())?;
+    bundle.add_config(app.state::<AppConfig>().inner())?;
+    bundle.add_json(
+        "pending_operations.json",
+        &app.state::<PendingOperations>().snapshot(),
+    )?;
+    let accounts = app.state::<DefaultAccountManager>();
+    if let Ok(stats) = accounts.connection_stats().await {
+        bundle.add_json("websocket.json", &stats.snapshot())?;
+    }
+    if let Ok(store) = accounts.store().await {
+        bundle.add_store(&store).await?;
+    }
+    bundle.add_recent_logs(app.state::<LogController>().log_dir())?;
+    bundle.write_to(Path::new(&path))?;
+    info!("Diagnostics bundle exported");
     Ok(())
 }
+
+const EXPORT_PROGRESS_EVENT: &str = "export-progress";
+
+/// Writes the history of a conversation within `range` to `path`, reporting progress with
+/// `export-progress` events. Attachments are linked unless `attachments` is `download`.
+#[tauri::command]
+pub async fn export_conversation(
+    app: AppHandle,
+    conversation_id: String,
+    format: ExportFormat,
+    range: Option<ExportRange>,
+    attachments: Option<AttachmentMode>,
+    path: String,
+) -> Result<ExportSummary, errors::CommandError> {
+    let accounts = app.state::<DefaultAccountManager>();
+    let exporter =
+        ConversationExporter::new(accounts.ripple_api().await?, accounts.data_sync().await?);
+    let options = ExportOptions {
+        format,
+        range: range.unwrap_or_default(),
+        attachments: attachments.unwrap_or_default(),
+    };
+    let summary = exporter
+        .export(
+            &conversation_id,
+            options,
+            Some(Path::new(&path)),
+            |progress| {
+                if let Err(e) = app.emit(EXPORT_PROGRESS_EVENT, &progress) {
+                    warn!("Failed to emit export progress event: {}", e);
+                }
+            },
+        )
+        .await?;
+    Ok(summary)
+}
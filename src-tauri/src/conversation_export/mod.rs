@@ -0,0 +1,424 @@
+//! Archives of a single conversation as JSON Lines, Markdown or a self-contained HTML page.
+//!
+//! History is paged backwards with `DataSyncManager::read_messages_before`, so whatever is
+//! cached locally is read from the store and only the missing part is fetched from the API.
+
+mod render;
+#[cfg(test)]
+mod tests;
+
+use crate::file_utils::FileUtils;
+use crate::ripple_api::api_response::MessageItem;
+use crate::ripple_api::RippleApi;
+use crate::ripple_syncer::DataSyncManager;
+use crate::store_engine::store_engine::{ConversationRecord, RippleStorage};
+use base64::Engine;
+use render::{Attachment, ExportedMessage};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::{info, warn};
+
+/// Largest page the messages API serves.
+const PAGE_SIZE: u32 = 200;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Jsonl,
+    Markdown,
+    Html,
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "jsonl" | "json" => Ok(ExportFormat::Jsonl),
+            "markdown" | "md" => Ok(ExportFormat::Markdown),
+            "html" => Ok(ExportFormat::Html),
+            _ => anyhow::bail!("Unknown export format {}; use jsonl, markdown or html", s),
+        }
+    }
+}
+
+/// Time window in unix milliseconds: `since` is inclusive, `until` exclusive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+pub struct ExportRange {
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+}
+
+impl ExportRange {
+    fn contains(&self, timestamp: i64) -> bool {
+        self.since.is_none_or(|since| timestamp >= since)
+            && self.until.is_none_or(|until| timestamp < until)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttachmentMode {
+    /// Link to the file URLs
+    #[default]
+    Reference,
+    /// Embed the files in HTML exports, save them next to the export otherwise
+    Download,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    pub range: ExportRange,
+    pub attachments: AttachmentMode,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportStage {
+    Messages,
+    Attachments,
+    Done,
+}
+
+#[derive(Clone, Serialize, Debug)]
+pub struct ExportProgress {
+    #[serde(rename = "conversationId")]
+    pub conversation_id: String,
+    pub stage: ExportStage,
+    pub completed: usize,
+    /// Unknown while messages are still being paged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<usize>,
+}
+
+#[derive(Clone, Serialize, Debug, Default)]
+pub struct ExportSummary {
+    pub messages: usize,
+    pub attachments: usize,
+    /// Attachments that could not be downloaded and are linked instead
+    #[serde(rename = "failedAttachments")]
+    pub failed_attachments: usize,
+}
+
+/// The pages of an export, found before any of it is written.
+#[derive(Default)]
+struct History {
+    latest: Vec<MessageItem>,
+    /// Ids the older pages are read before, newest first
+    cursors: Vec<String>,
+    /// In range
+    messages: usize,
+    attachments: usize,
+}
+
+impl History {
+    fn count(&mut self, page: &[MessageItem], range: ExportRange) {
+        for message in page.iter().filter(|m| range.contains(timestamp(m))) {
+            self.messages += 1;
+            if message.file_url.is_some() {
+                self.attachments += 1;
+            }
+        }
+    }
+}
+
+pub struct ConversationExporter<S: RippleStorage> {
+    ripple_api: RippleApi<S>,
+    data_sync: DataSyncManager<S>,
+}
+
+impl<S: RippleStorage> ConversationExporter<S> {
+    pub fn new(ripple_api: RippleApi<S>, data_sync: DataSyncManager<S>) -> Self {
+        ConversationExporter {
+            ripple_api,
+            data_sync,
+        }
+    }
+
+    /// Writes the history of `conversation_id` within `options.range`, oldest first, to `path`
+    /// or to stdout. Downloaded attachments of JSON Lines and Markdown exports go to a
+    /// `<name>_files` directory beside `path`.
+    pub async fn export(
+        &self,
+        conversation_id: &str,
+        options: ExportOptions,
+        path: Option<&Path>,
+        mut progress: impl FnMut(ExportProgress),
+    ) -> anyhow::Result<ExportSummary> {
+        let attachment_dir = match (options.attachments, options.format, path) {
+            (AttachmentMode::Reference, _, _) | (_, ExportFormat::Html, _) => None,
+            (AttachmentMode::Download, _, Some(path)) => Some(files_dir(path)),
+            (AttachmentMode::Download, _, None) => {
+                anyhow::bail!("Downloading attachments needs an output file")
+            }
+        };
+        let conversation = self
+            .data_sync
+            .find_conversation(conversation_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Unknown conversation {}", conversation_id))?;
+        let mut report = |stage, completed, total| {
+            progress(ExportProgress {
+                conversation_id: conversation_id.to_string(),
+                stage,
+                completed,
+                total,
+            })
+        };
+
+        let history = self
+            .find_history(conversation_id, options.range, &mut report)
+            .await?;
+        let names = self.sender_names(&conversation).await?;
+        let mut summary = ExportSummary {
+            messages: history.messages,
+            ..Default::default()
+        };
+
+        // Held across the page reads, so it has to be `Send`
+        let mut out: Box<dyn Write + Send> = match path {
+            Some(path) => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+            None => Box::new(std::io::BufWriter::new(std::io::stdout())),
+        };
+        render::write_header(&mut out, options.format, &conversation.name)?;
+        // Replay the pages oldest first; the older ones are in the local cache by now
+        let mut latest = Some(history.latest);
+        let cursors = history.cursors.iter().rev().map(Some).chain([None]);
+        for cursor in cursors {
+            let page = match cursor {
+                Some(before) => {
+                    self.data_sync
+                        .read_messages_before(
+                            conversation_id.to_string(),
+                            before.clone(),
+                            PAGE_SIZE,
+                        )
+                        .await?
+                        .messages
+                }
+                None => latest.take().unwrap_or_default(),
+            };
+            let mut exported = Vec::with_capacity(page.len());
+            for message in page {
+                if !options.range.contains(timestamp(&message)) {
+                    continue;
+                }
+                let attachment = match &message.file_url {
+                    Some(file_url) => {
+                        let attachment = self
+                            .attachment(
+                                &message,
+                                file_url,
+                                options,
+                                attachment_dir.as_deref(),
+                                path,
+                            )
+                            .await;
+                        summary.attachments += 1;
+                        if options.attachments == AttachmentMode::Download && !attachment.downloaded
+                        {
+                            summary.failed_attachments += 1;
+                        }
+                        report(
+                            ExportStage::Attachments,
+                            summary.attachments,
+                            Some(history.attachments),
+                        );
+                        Some(attachment)
+                    }
+                    None => None,
+                };
+                exported.push(ExportedMessage {
+                    sender_name: names
+                        .get(&message.sender_id)
+                        .cloned()
+                        .unwrap_or_else(|| message.sender_id.clone()),
+                    message,
+                    attachment,
+                });
+            }
+            render::write_messages(&mut out, options.format, &exported)?;
+        }
+        render::write_footer(&mut out, options.format)?;
+        out.flush()?;
+        report(ExportStage::Done, summary.messages, Some(summary.messages));
+        info!(
+            "Exported {} messages of conversation {}",
+            summary.messages, conversation_id
+        );
+        Ok(summary)
+    }
+
+    /// Pages backwards from the newest message until the start of `range` or of the history,
+    /// counting what is in range. Only the newest page is kept: it could shift if a message
+    /// arrives meanwhile, while the older ones are cached and read again one at a time.
+    async fn find_history(
+        &self,
+        conversation_id: &str,
+        range: ExportRange,
+        report: &mut impl FnMut(ExportStage, usize, Option<usize>),
+    ) -> anyhow::Result<History> {
+        let latest = self
+            .data_sync
+            .read_latest_messages(conversation_id.to_string(), PAGE_SIZE, String::new())
+            .await?
+            .messages;
+        let mut history = History::default();
+        let mut fetched = latest.len();
+        history.count(&latest, range);
+        report(ExportStage::Messages, fetched, None);
+        let mut oldest = latest.first().cloned();
+        history.latest = latest;
+        while let Some(message) = oldest {
+            if range.since.is_some_and(|since| timestamp(&message) < since) {
+                break;
+            }
+            let page = self
+                .data_sync
+                .read_messages_before(
+                    conversation_id.to_string(),
+                    message.message_id.clone(),
+                    PAGE_SIZE,
+                )
+                .await?
+                .messages;
+            history.cursors.push(message.message_id);
+            history.count(&page, range);
+            fetched += page.len();
+            report(ExportStage::Messages, fetched, None);
+            if page.len() < PAGE_SIZE as usize {
+                break;
+            }
+            oldest = page.into_iter().next();
+        }
+        Ok(history)
+    }
+
+    /// Display names by user id: remark or nickname of relations, then group member names,
+    /// then the own profile.
+    async fn sender_names(
+        &self,
+        conversation: &ConversationRecord,
+    ) -> anyhow::Result<HashMap<String, String>> {
+        let mut names = HashMap::new();
+        for relation in self.data_sync.get_relations().await? {
+            let name = relation
+                .remark_name
+                .filter(|remark| !remark.is_empty())
+                .unwrap_or(relation.nick_name);
+            names.insert(relation.user_id, name);
+        }
+        if let Some(group_id) = &conversation.group_id {
+            if !self.data_sync.exist_group_members(group_id).await? {
+                if let Err(e) = self.data_sync.sync_all_group_members(group_id).await {
+                    warn!(
+                        "Exporting without member names of group {}: {}",
+                        group_id, e
+                    );
+                }
+            }
+            for member in self.data_sync.get_group_members(group_id).await? {
+                names.entry(member.user_id).or_insert(member.name);
+            }
+        }
+        if let Some(profile) = self.data_sync.get_profile().await? {
+            names.insert(profile.user_id, profile.nick_name);
+        }
+        Ok(names)
+    }
+
+    /// Downloads the attachment when asked to; a failed download falls back to the file URL.
+    async fn attachment(
+        &self,
+        message: &MessageItem,
+        file_url: &str,
+        options: ExportOptions,
+        attachment_dir: Option<&Path>,
+        path: Option<&Path>,
+    ) -> Attachment {
+        let name = message
+            .file_name
+            .clone()
+            .unwrap_or_else(|| file_url.rsplit('/').next().unwrap_or("file").to_string());
+        let mime = FileUtils::get_mime_type(Path::new(&name));
+        let mut attachment = Attachment {
+            is_image: mime.is_some(),
+            name,
+            href: file_url.to_string(),
+            downloaded: false,
+        };
+        if options.attachments == AttachmentMode::Reference {
+            return attachment;
+        }
+        let saved = match self.ripple_api.download_attachment(file_url).await {
+            Ok(data) => match attachment_dir {
+                // HTML exports embed the file
+                None => Ok(format!(
+                    "data:{};base64,{}",
+                    mime.map(|m| m.to_string())
+                        .unwrap_or_else(|| "application/octet-stream".to_string()),
+                    base64::engine::general_purpose::STANDARD.encode(data)
+                )),
+                Some(dir) => save_attachment(dir, path, message, &attachment.name, &data),
+            },
+            Err(e) => Err(e),
+        };
+        match saved {
+            Ok(href) => {
+                attachment.href = href;
+                attachment.downloaded = true;
+            }
+            Err(e) => warn!(
+                "Linking attachment of message {} instead of downloading it: {}",
+                message.message_id, e
+            ),
+        }
+        attachment
+    }
+}
+
+fn timestamp(message: &MessageItem) -> i64 {
+    message.send_timestamp.parse().unwrap_or(0)
+}
+
+fn files_dir(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "export".to_string());
+    path.with_file_name(format!("{}_files", stem))
+}
+
+/// Saves the file as `<message id>-<name>` and returns its path relative to the export.
+fn save_attachment(
+    dir: &Path,
+    path: Option<&Path>,
+    message: &MessageItem,
+    name: &str,
+    data: &[u8],
+) -> anyhow::Result<String> {
+    let safe_name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let file_name = format!("{}-{}", message.message_id, safe_name);
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(dir.join(&file_name), data)?;
+    let dir_name = match path.and_then(|p| p.parent()) {
+        Some(parent) => dir.strip_prefix(parent).unwrap_or(dir).to_path_buf(),
+        None => dir.to_path_buf(),
+    };
+    Ok(dir_name
+        .join(file_name)
+        .to_string_lossy()
+        .replace('\\', "/"))
+}
@@ -0,0 +1,332 @@
+use crate::conversation_export::ExportFormat;
+use crate::ripple_api::api_response::{MessageItem, MessageItemType};
+use chrono::DateTime;
+use std::io::Write;
+
+/// A message with everything the formats need resolved.
+pub(super) struct ExportedMessage {
+    pub message: MessageItem,
+    pub sender_name: String,
+    pub attachment: Option<Attachment>,
+}
+
+pub(super) struct Attachment {
+    pub name: String,
+    /// Original URL, path relative to the export, or a `data:` URI
+    pub href: String,
+    pub is_image: bool,
+    /// Whether `href` points at a downloaded copy
+    pub downloaded: bool,
+}
+
+impl Attachment {
+    /// `href` if it is safe to link: http(s), a relative path, or the `data:` URI of an
+    /// embedded download. Other schemes, such as `javascript:`, are only shown as text.
+    fn link(&self) -> Option<&str> {
+        let href = self.href.as_str();
+        let scheme_end = href.find([':', '/', '?', '#']);
+        let scheme = match scheme_end {
+            Some(end) if href[end..].starts_with(':') => &href[..end],
+            // No scheme: a path relative to the export, unless it names another host
+            _ => return (!href.starts_with("//")).then_some(href),
+        };
+        let allowed = scheme.eq_ignore_ascii_case("http")
+            || scheme.eq_ignore_ascii_case("https")
+            || (scheme.eq_ignore_ascii_case("data") && self.downloaded);
+        allowed.then_some(href)
+    }
+}
+
+impl ExportedMessage {
+    fn timestamp(&self) -> String {
+        self.message
+            .send_timestamp
+            .parse::<i64>()
+            .ok()
+            .and_then(DateTime::from_timestamp_millis)
+            .map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+            .unwrap_or_else(|| self.message.send_timestamp.clone())
+    }
+
+    fn body(&self) -> Option<String> {
+        match self.message.message_type {
+            MessageItemType::Text => self.message.text.clone().filter(|text| !text.is_empty()),
+            MessageItemType::Command => Some(format!(
+                "[{}]",
+                self.message.command_data.as_deref().unwrap_or("command")
+            )),
+            MessageItemType::Unknown => Some("[unsupported message]".to_string()),
+        }
+    }
+}
+
+/// Starts the document: the title of Markdown exports, the head of HTML pages.
+pub(super) fn write_header(
+    out: &mut dyn Write,
+    format: ExportFormat,
+    title: &str,
+) -> anyhow::Result<()> {
+    match format {
+        ExportFormat::Jsonl => {}
+        ExportFormat::Markdown => writeln!(out, "# {}\n", title)?,
+        ExportFormat::Html => writeln!(
+            out,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<h1>{}</h1>",
+            escape_html(title),
+            HTML_STYLE,
+            escape_html(title)
+        )?,
+    }
+    Ok(())
+}
+
+/// Appends one page of messages, so a long history never has to be held at once.
+pub(super) fn write_messages(
+    out: &mut dyn Write,
+    format: ExportFormat,
+    messages: &[ExportedMessage],
+) -> anyhow::Result<()> {
+    match format {
+        ExportFormat::Jsonl => write_jsonl(out, messages),
+        ExportFormat::Markdown => write_markdown(out, messages),
+        ExportFormat::Html => write_html(out, messages),
+    }
+}
+
+pub(super) fn write_footer(out: &mut dyn Write, format: ExportFormat) -> anyhow::Result<()> {
+    if format == ExportFormat::Html {
+        writeln!(out, "</body>\n</html>")?;
+    }
+    Ok(())
+}
+
+/// One `MessageItem` per line, with the resolved `senderName` and, for downloaded
+/// attachments, the `localFile` they were saved to.
+fn write_jsonl(out: &mut dyn Write, messages: &[ExportedMessage]) -> anyhow::Result<()> {
+    for exported in messages {
+        let mut value = serde_json::to_value(&exported.message)?;
+        if let Some(object) = value.as_object_mut() {
+            object.insert(
+                "senderName".to_string(),
+                exported.sender_name.clone().into(),
+            );
+            if let Some(attachment) = exported.attachment.as_ref().filter(|a| a.downloaded) {
+                object.insert("localFile".to_string(), attachment.href.clone().into());
+            }
+        }
+        serde_json::to_writer(&mut *out, &value)?;
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn write_markdown(out: &mut dyn Write, messages: &[ExportedMessage]) -> anyhow::Result<()> {
+    for exported in messages {
+        writeln!(
+            out,
+            "**{}** · {}\n",
+            exported.sender_name,
+            exported.timestamp()
+        )?;
+        if let Some(body) = exported.body() {
+            // Hard line breaks keep multi-line messages together
+            writeln!(out, "{}\n", body.replace('\n', "  \n"))?;
+        }
+        if let Some(attachment) = &exported.attachment {
+            match attachment.link() {
+                Some(href) => {
+                    let marker = if attachment.is_image { "!" } else { "" };
+                    writeln!(out, "{}[{}](<{}>)\n", marker, attachment.name, href)?;
+                }
+                None => writeln!(out, "{} ({})\n", attachment.name, attachment.href)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+const HTML_STYLE: &str = "\
+body{font-family:system-ui,sans-serif;max-width:760px;margin:2rem auto;padding:0 1rem;color:#1f2937}\
+h1{font-size:1.4rem;border-bottom:1px solid #e5e7eb;padding-bottom:.5rem}\
+.message{margin:1rem 0}\
+.meta{font-size:.8rem;color:#6b7280}\
+.sender{font-weight:600;color:#111827;margin-right:.5rem}\
+.body{white-space:pre-wrap;margin-top:.25rem}\
+.command{color:#6b7280;font-style:italic}\
+img{max-width:100%;border-radius:6px;margin-top:.25rem}";
+
+/// A single page with inline styles and no scripts; downloaded attachments are embedded as
+/// `data:` URIs, so the file stands alone.
+fn write_html(out: &mut dyn Write, messages: &[ExportedMessage]) -> anyhow::Result<()> {
+    for exported in messages {
+        writeln!(
+            out,
+            "<div class=\"message\" id=\"m{}\">\n<div class=\"meta\"><span class=\"sender\">{}</span>{}</div>",
+            escape_html(&exported.message.message_id),
+            escape_html(&exported.sender_name),
+            escape_html(&exported.timestamp())
+        )?;
+        if let Some(body) = exported.body() {
+            let class = match exported.message.message_type {
+                MessageItemType::Text => "body",
+                _ => "body command",
+            };
+            writeln!(out, "<div class=\"{}\">{}</div>", class, escape_html(&body))?;
+        }
+        if let Some(attachment) = &exported.attachment {
+            let name = escape_html(&attachment.name);
+            match attachment.link() {
+                Some(href) if attachment.is_image => {
+                    writeln!(out, "<img src=\"{}\" alt=\"{}\">", escape_html(href), name)?;
+                }
+                Some(href) => writeln!(
+                    out,
+                    "<a href=\"{}\" download=\"{}\">{}</a>",
+                    escape_html(href),
+                    name,
+                    name
+                )?,
+                None => writeln!(
+                    out,
+                    "<div class=\"body command\">{} ({})</div>",
+                    name,
+                    escape_html(&attachment.href)
+                )?,
+            }
+        }
+        writeln!(out, "</div>")?;
+    }
+    Ok(())
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exported(text: &str, attachment: Option<Attachment>) -> ExportedMessage {
+        let message = serde_json::from_value(serde_json::json!({
+            "conversationId": "c1",
+            "messageId": "42",
+            "senderId": "1001",
+            "sendTimestamp": "1700000000000",
+            "messageType": 1,
+            "text": text,
+            "commandType": 0,
+        }))
+        .unwrap();
+        ExportedMessage {
+            message,
+            sender_name: "Alice".to_string(),
+            attachment,
+        }
+    }
+
+    fn render(format: ExportFormat, messages: &[ExportedMessage]) -> String {
+        let mut out = Vec::new();
+        write_header(&mut out, format, "Lunch <team>").unwrap();
+        write_messages(&mut out, format, messages).unwrap();
+        write_footer(&mut out, format).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn linked(href: &str, downloaded: bool) -> ExportedMessage {
+        exported(
+            "",
+            Some(Attachment {
+                name: "file".to_string(),
+                href: href.to_string(),
+                is_image: false,
+                downloaded,
+            }),
+        )
+    }
+
+    #[test]
+    fn jsonl_adds_sender_name_and_local_file() {
+        let messages = [exported(
+            "hi",
+            Some(Attachment {
+                name: "a.pdf".to_string(),
+                href: "export_files/42-a.pdf".to_string(),
+                is_image: false,
+                downloaded: true,
+            }),
+        )];
+        let output = render(ExportFormat::Jsonl, &messages);
+        let line: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(line["senderName"], "Alice");
+        assert_eq!(line["localFile"], "export_files/42-a.pdf");
+        assert_eq!(line["messageId"], "42");
+    }
+
+    #[test]
+    fn markdown_lists_messages_with_utc_time() {
+        let output = render(
+            ExportFormat::Markdown,
+            &[exported("line one\nline two", None)],
+        );
+        assert!(output.starts_with("# Lunch <team>\n"));
+        assert!(output.contains("**Alice** · 2023-11-14 22:13:20 UTC"));
+        assert!(output.contains("line one  \nline two"));
+    }
+
+    #[test]
+    fn html_escapes_user_content() {
+        let output = render(
+            ExportFormat::Html,
+            &[exported(
+                "<script>alert(1)</script>",
+                Some(Attachment {
+                    name: "cat.png".to_string(),
+                    href: "data:image/png;base64,AAAA".to_string(),
+                    is_image: true,
+                    downloaded: true,
+                }),
+            )],
+        );
+        assert!(!output.contains("<script>"));
+        assert!(output.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(output.contains("<title>Lunch &lt;team&gt;</title>"));
+        assert!(output.contains("<img src=\"data:image/png;base64,AAAA\" alt=\"cat.png\">"));
+    }
+
+    #[test]
+    fn only_safe_links_are_live() {
+        for (href, downloaded) in [
+            ("https://cdn.example/a.pdf", false),
+            ("HTTP://cdn.example/a.pdf", false),
+            ("history_files/42-a.pdf", true),
+            ("data:application/pdf;base64,AAAA", true),
+        ] {
+            let output = render(ExportFormat::Html, &[linked(href, downloaded)]);
+            assert!(output.contains("<a href="), "{}", href);
+        }
+        for (href, downloaded) in [
+            ("javascript:alert(1)", false),
+            (" JavaScript:alert(1)", false),
+            ("data:text/html,<script>alert(1)</script>", false),
+            ("file:///etc/passwd", false),
+            ("//evil.example/a.pdf", false),
+        ] {
+            let html = render(ExportFormat::Html, &[linked(href, downloaded)]);
+            assert!(!html.contains("<a href="), "{}", href);
+            let markdown = render(ExportFormat::Markdown, &[linked(href, downloaded)]);
+            assert!(!markdown.contains("]("), "{}", href);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use super::{ConversationExporter, ExportFormat, ExportOptions, ExportRange, ExportStage};
+use crate::mock_gateway::{MockGateway, TestClient};
+use crate::store_engine::TempDir;
+use std::path::PathBuf;
+
+const ALICE: &str = "1001";
+const BOB: &str = "1002";
+const HISTORY_START: i64 = 1_700_000_000_000;
+const HISTORY_LEN: usize = 450;
+
+/// `name` in a directory that is removed with the returned guard.
+fn temp_file(name: &str) -> (TempDir, PathBuf) {
+    let dir = TempDir::new("ripple-export");
+    let path = dir.path().join(name);
+    (dir, path)
+}
+
+/// Bob has a long conversation with Alice but only the newest message cached, so exports
+/// page through the API.
+async fn client_with_history() -> (MockGateway, TestClient, String) {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.state().add_user(ALICE, "Alice");
+    gateway.state().add_user(BOB, "Bob");
+    let conversation_id = gateway.state().befriend(ALICE, BOB);
+    for i in 0..HISTORY_LEN {
+        gateway.state().add_history_message(
+            ALICE,
+            &conversation_id,
+            BOB,
+            &format!("m{}", i),
+            HISTORY_START + i as i64 * 1000,
+        );
+    }
+    let bob = TestClient::login(&gateway, BOB).await;
+    bob.data_sync.init().await.unwrap();
+    let newest = bob
+        .api
+        .read_messages(conversation_id.clone(), "0".to_string(), 1)
+        .await
+        .unwrap()
+        .data
+        .messages;
+    bob.data_sync
+        .store_message(newest[0].clone())
+        .await
+        .unwrap();
+    (gateway, bob, conversation_id)
+}
+
+#[tokio::test]
+async fn exports_whole_history_in_order() {
+    let (_gateway, bob, conversation_id) = client_with_history().await;
+    let exporter = ConversationExporter::new(bob.api.clone(), bob.data_sync.clone());
+    let (_dir, path) = temp_file("history.jsonl");
+    let mut progress = Vec::new();
+
+    let summary = exporter
+        .export(
+            &conversation_id,
+            ExportOptions::default(),
+            Some(&path),
+            |p| progress.push(p),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(summary.messages, HISTORY_LEN);
+    let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let texts: Vec<String> = lines
+        .iter()
+        .map(|line| line["text"].as_str().unwrap().to_string())
+        .collect();
+    let expected: Vec<String> = (0..HISTORY_LEN).map(|i| format!("m{}", i)).collect();
+    assert_eq!(texts, expected);
+    assert_eq!(lines[0]["senderName"], "Alice");
+    assert!(progress
+        .iter()
+        .any(|p| p.stage == ExportStage::Messages && p.completed > 1));
+    let done = progress.last().unwrap();
+    assert_eq!(done.stage, ExportStage::Done);
+    assert_eq!(done.completed, HISTORY_LEN);
+}
+
+#[tokio::test]
+async fn exports_only_the_requested_range() {
+    let (_gateway, bob, conversation_id) = client_with_history().await;
+    let exporter = ConversationExporter::new(bob.api.clone(), bob.data_sync.clone());
+    let options = ExportOptions {
+        format: ExportFormat::Markdown,
+        range: ExportRange {
+            since: Some(HISTORY_START + 100_000),
+            until: Some(HISTORY_START + 200_000),
+        },
+        ..Default::default()
+    };
+    let (_dir, path) = temp_file("range.md");
+
+    let summary = exporter
+        .export(&conversation_id, options, Some(&path), |_| {})
+        .await
+        .unwrap();
+
+    assert_eq!(summary.messages, 100);
+    let markdown = std::fs::read_to_string(&path).unwrap();
+    assert!(markdown.contains("**Alice** · "));
+    assert!(markdown.contains("\nm100\n"));
+    assert!(markdown.contains("\nm199\n"));
+    assert!(!markdown.contains("\nm99\n"));
+    assert!(!markdown.contains("\nm200\n"));
+}
+
+#[tokio::test]
+async fn unknown_conversation_fails() {
+    let (_gateway, bob, _) = client_with_history().await;
+    let exporter = ConversationExporter::new(bob.api.clone(), bob.data_sync.clone());
+    let (_dir, path) = temp_file("missing.html");
+    let options = ExportOptions {
+        format: ExportFormat::Html,
+        ..Default::default()
+    };
+
+    assert!(exporter
+        .export("c-missing", options, Some(&path), |_| {})
+        .await
+        .is_err());
+    assert!(!path.exists());
+}
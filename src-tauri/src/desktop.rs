@@ -0,0 +1,116 @@
+//! The Tauri application: window, plugins, managed state and the command handlers.
+
+use crate::account::AccountManager;
+use crate::app_config::AppConfig;
+use crate::commands;
+use crate::diagnostics::PendingOperations;
+use crate::http_client::HttpClients;
+use crate::logging::LogController;
+use crate::ripple_api::oauth_client::OauthClient;
+use crate::ripple_syncer::DefaultEventEmitter;
+use crate::server::Server;
+use std::fs;
+use std::path::PathBuf;
+use tauri::path::BaseDirectory;
+use tauri::Manager;
+
+// Account manager whose sessions report to the Tauri frontend
+pub type DefaultAccountManager = AccountManager<DefaultEventEmitter>;
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    let mut config_file_path = "resources/prod_app_config.json";
+    if cfg!(debug_assertions) {
+        config_file_path = "resources/dev_app_config.json";
+    }
+    tauri::Builder::default()
+        .setup(move |app| {
+            let app_data_dir = app.path().app_data_dir()?;
+            // Ensure the app data directory exists
+            fs::create_dir_all(&app_data_dir).map_err(|e| {
+                format!(
+                    "Failed to create app data directory '{}': {}",
+                    app_data_dir.display(),
+                    e
+                )
+            })?;
+            let log_controller = LogController::init(&app_data_dir)?;
+            let resource_path = app
+                .path()
+                .resolve(config_file_path, BaseDirectory::Resource)?;
+            let app_config = parse_app_config(resource_path);
+            let http_clients = HttpClients::new(&app_config.http)?;
+            let oauth_client = OauthClient::new(&app_config, http_clients.api.clone())?;
+            let account_manager = tauri::async_runtime::block_on(DefaultAccountManager::new(
+                DefaultEventEmitter::new(app.handle().clone()),
+                app_config.clone(),
+                &app_data_dir,
+                http_clients,
+                oauth_client,
+            ))?;
+            app.manage(account_manager);
+            app.manage(app_config); // read-only, no mutex needed
+            app.manage(tokio::sync::Mutex::new(Server::new()));
+            app.manage(log_controller);
+            app.manage(PendingOperations::default());
+            Ok(())
+        })
+        .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_fs::init())
+        .invoke_handler(tauri::generate_handler![
+            commands::exists_token,
+            commands::resume_session,
+            commands::start_server,
+            commands::stop_server,
+            commands::open_signup_url,
+            commands::open_auth_url,
+            commands::get_user_profile,
+            commands::get_user_profile_by_id,
+            commands::get_relations,
+            commands::get_conversations,
+            commands::upload_user_avatar_blob,
+            commands::upload_image_blob,
+            commands::upload_group_avatar_blob,
+            commands::update_user_nickname,
+            commands::remove_user_avatar,
+            commands::add_friend,
+            commands::remove_friend,
+            commands::update_friend_display_name,
+            commands::block_user,
+            commands::unblock_user,
+            commands::hide_blocked_user,
+            commands::get_sync_status,
+            commands::send_message,
+            commands::read_latest_messages,
+            commands::read_messages_before,
+            commands::mark_last_read_message_id,
+            commands::create_group,
+            commands::invite_members,
+            commands::get_group_members,
+            commands::update_group_name,
+            commands::leave_group,
+            commands::upload_attachment,
+            commands::logout,
+            commands::add_account,
+            commands::list_accounts,
+            commands::switch_account,
+            commands::remove_account,
+            commands::backup_database,
+            commands::restore_database,
+            commands::rotate_db_key,
+            commands::database_locked,
+            commands::unlock_database,
+            commands::set_db_key_source,
+            commands::get_log_level,
+            commands::set_log_level,
+            commands::export_diagnostics,
+            commands::export_conversation,
+        ])
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
+
+fn parse_app_config(resource_path: PathBuf) -> AppConfig {
+    AppConfig::load(&resource_path).expect("Failed to load app config")
+}
@@ -0,0 +1,5 @@
+mod bundle;
+mod pending_operations;
+
+pub use bundle::DiagnosticsBundle;
+pub use pending_operations::PendingOperations;
@@ -0,0 +1,129 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// An outgoing message whose send request has not returned yet. Only metadata is kept.
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingMessage {
+    pub conversation_id: String,
+    pub has_text: bool,
+    pub has_attachment: bool,
+    pub started_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadSession {
+    pub file_size: i64,
+    pub upload_mode: Option<i32>,
+    pub total_chunks: Option<i32>,
+    pub uploaded_chunks: i32,
+    pub started_at: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PendingSnapshot {
+    pub outbox: Vec<PendingMessage>,
+    pub uploads: Vec<UploadSession>,
+}
+
+/// Message sends and attachment uploads currently in flight. Entries are removed when
+/// their guard is dropped, so whatever is left in a snapshot was stuck at that moment.
+#[derive(Default)]
+pub struct PendingOperations {
+    next_id: AtomicU64,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    outbox: BTreeMap<u64, PendingMessage>,
+    uploads: BTreeMap<u64, UploadSession>,
+}
+
+pub struct PendingGuard<'a> {
+    operations: &'a PendingOperations,
+    id: u64,
+}
+
+impl PendingOperations {
+    pub fn begin_message(
+        &self,
+        conversation_id: &str,
+        has_text: bool,
+        has_attachment: bool,
+    ) -> PendingGuard<'_> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.inner.lock().unwrap().outbox.insert(
+            id,
+            PendingMessage {
+                conversation_id: conversation_id.to_string(),
+                has_text,
+                has_attachment,
+                started_at: now_millis(),
+            },
+        );
+        PendingGuard {
+            operations: self,
+            id,
+        }
+    }
+
+    pub fn begin_upload(&self, file_size: i64) -> PendingGuard<'_> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.inner.lock().unwrap().uploads.insert(
+            id,
+            UploadSession {
+                file_size,
+                upload_mode: None,
+                total_chunks: None,
+                uploaded_chunks: 0,
+                started_at: now_millis(),
+            },
+        );
+        PendingGuard {
+            operations: self,
+            id,
+        }
+    }
+
+    pub fn snapshot(&self) -> PendingSnapshot {
+        let inner = self.inner.lock().unwrap();
+        PendingSnapshot {
+            outbox: inner.outbox.values().cloned().collect(),
+            uploads: inner.uploads.values().cloned().collect(),
+        }
+    }
+}
+
+impl PendingGuard<'_> {
+    /// Updates the upload tracked by this guard; a no-op for message guards.
+    pub fn update_upload(&self, update: impl FnOnce(&mut UploadSession)) {
+        if let Some(upload) = self
+            .operations
+            .inner
+            .lock()
+            .unwrap()
+            .uploads
+            .get_mut(&self.id)
+        {
+            update(upload);
+        }
+    }
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        let mut inner = self.operations.inner.lock().unwrap();
+        inner.outbox.remove(&self.id);
+        inner.uploads.remove(&self.id);
+    }
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
@@ -1,11 +1,15 @@
-use oauth2::{reqwest, url};
+use oauth2::reqwest;
+use reqwest::StatusCode;
+use serde::Serialize;
 
+/// Error returned by the Tauri commands to the frontend.
+#[cfg(feature = "desktop")]
 #[derive(Debug, thiserror::Error)]
 pub enum CommandError {
     #[error(transparent)]
     Io(#[from] std::io::Error),
     #[error(transparent)]
-    Url(#[from] url::ParseError),
+    Url(#[from] oauth2::url::ParseError),
     #[error(transparent)]
     RequestError(#[from] reqwest::Error),
     #[error(transparent)]
@@ -13,16 +17,197 @@ pub enum CommandError {
     #[error(transparent)]
     Base64DecodeError(#[from] base64::DecodeError),
     #[error(transparent)]
-    AnyhowError(#[from] anyhow::Error),
-    #[error("Ripple API error: API `{0}`, status code {1}, message: {2}")]
-    RippleAPIError(String, i32, String),
+    Api(#[from] ApiError),
+    #[error(transparent)]
+    AnyhowError(anyhow::Error),
+}
+
+#[cfg(feature = "desktop")]
+impl From<anyhow::Error> for CommandError {
+    fn from(e: anyhow::Error) -> Self {
+        // Keep API failures typed even when they were propagated through anyhow
+        match e.downcast::<ApiError>() {
+            Ok(api) => CommandError::Api(api),
+            Err(e) => CommandError::AnyhowError(e),
+        }
+    }
 }
 
-impl serde::Serialize for CommandError {
+/// Serialized as `{"kind": ..., "message": ...}`. API failures use their own kinds and extra
+/// fields (see [`ApiError`]); everything else is `network` or `internal`.
+#[cfg(feature = "desktop")]
+impl Serialize for CommandError {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::ser::Serializer,
     {
-        serializer.serialize_str(self.to_string().as_ref())
+        use serde::ser::SerializeStruct;
+
+        let kind = match self {
+            CommandError::Api(e) => return e.serialize(serializer),
+            CommandError::RequestError(_) => "network",
+            _ => "internal",
+        };
+        let mut state = serializer.serialize_struct("CommandError", 2)?;
+        state.serialize_field("kind", kind)?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
+
+/// A failed Ripple API call, classified from the HTTP status or from the `code` field of the
+/// response body so callers (and the UI) can react to the kind of failure.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, Serialize)]
+#[serde(
+    tag = "kind",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum ApiError {
+    #[error("Network error: {message}")]
+    Network { message: String },
+    #[error("Unauthorized: {message}")]
+    Unauthorized { message: String },
+    #[error("Forbidden: {message}")]
+    Forbidden { message: String },
+    #[error("Not found: {message}")]
+    NotFound { message: String },
+    #[error("Rate limited: {message}")]
+    RateLimited {
+        /// Seconds to wait before retrying, when the server said so.
+        retry_after: Option<u64>,
+        message: String,
+    },
+    #[error("Invalid request: {message}")]
+    Validation { message: String },
+    #[error("Server error {status}: {message}")]
+    Server { status: u16, message: String },
+    #[error("Request rejected with code {code}: {message}")]
+    Business { code: i32, message: String },
+}
+
+impl ApiError {
+    pub fn network(message: impl Into<String>) -> Self {
+        ApiError::Network {
+            message: message.into(),
+        }
+    }
+
+    pub fn from_status(
+        status: StatusCode,
+        retry_after: Option<u64>,
+        message: impl Into<String>,
+    ) -> Self {
+        let message = message.into();
+        match status.as_u16() {
+            400 | 409 | 413 | 422 => ApiError::Validation { message },
+            401 => ApiError::Unauthorized { message },
+            403 => ApiError::Forbidden { message },
+            404 => ApiError::NotFound { message },
+            429 => ApiError::RateLimited {
+                retry_after,
+                message,
+            },
+            status @ 500..=599 => ApiError::Server { status, message },
+            code => ApiError::Business {
+                code: code as i32,
+                message,
+            },
+        }
+    }
+
+    /// Maps the `code` of a response body. Codes in the HTTP error range mean the same as the
+    /// status would; anything else is an application-specific rejection.
+    pub fn from_body_code(code: i32, message: impl Into<String>) -> Self {
+        let status = u16::try_from(code)
+            .ok()
+            .and_then(|c| StatusCode::from_u16(c).ok());
+        match status {
+            Some(status) if status.is_client_error() || status.is_server_error() => {
+                ApiError::from_status(status, None, message)
+            }
+            _ => ApiError::Business {
+                code,
+                message: message.into(),
+            },
+        }
+    }
+}
+
+/// Errors raised when server payloads cannot be mapped onto local types, typically because
+/// a newer server sent an operation or message this client does not know about.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum ConversionError {
+    #[error("unknown {0} operation")]
+    UnknownOperation(&'static str),
+    #[error("{context} is missing field `{field}`")]
+    MissingField {
+        context: &'static str,
+        field: &'static str,
+    },
+    #[error("push message has no message payload")]
+    MissingPayload,
+    #[error("message payload has no message data")]
+    MissingMessageData,
+    #[error("unknown push event type {0}")]
+    UnknownEventType(i32),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_http_statuses() {
+        assert_eq!(
+            ApiError::from_status(StatusCode::TOO_MANY_REQUESTS, Some(30), "slow down"),
+            ApiError::RateLimited {
+                retry_after: Some(30),
+                message: "slow down".to_string()
+            }
+        );
+        assert!(matches!(
+            ApiError::from_status(StatusCode::BAD_GATEWAY, None, ""),
+            ApiError::Server { status: 502, .. }
+        ));
+        assert!(matches!(
+            ApiError::from_status(StatusCode::FORBIDDEN, None, ""),
+            ApiError::Forbidden { .. }
+        ));
+    }
+
+    #[test]
+    fn maps_body_codes() {
+        assert!(matches!(
+            ApiError::from_body_code(404, "no such user"),
+            ApiError::NotFound { .. }
+        ));
+        assert_eq!(
+            ApiError::from_body_code(40012, "blocked by user"),
+            ApiError::Business {
+                code: 40012,
+                message: "blocked by user".to_string()
+            }
+        );
+    }
+
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn serializes_as_tagged_json() {
+        use serde_json::json;
+
+        let api = CommandError::from(anyhow::Error::new(ApiError::RateLimited {
+            retry_after: Some(5),
+            message: "slow down".to_string(),
+        }));
+        assert_eq!(
+            serde_json::to_value(&api).unwrap(),
+            json!({"kind": "rateLimited", "retryAfter": 5, "message": "slow down"})
+        );
+        let internal = CommandError::from(anyhow::anyhow!("disk full"));
+        assert_eq!(
+            serde_json::to_value(&internal).unwrap(),
+            json!({"kind": "internal", "message": "disk full"})
+        );
     }
 }
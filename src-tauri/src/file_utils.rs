@@ -9,10 +9,7 @@ impl FileUtils {
     }
 
     pub fn get_mime_type(filepath: &Path) -> Option<Mime> {
-        let file_extension = match Self::get_extension(filepath) {
-            Some(ext) => ext,
-            None => return None,
-        };
+        let file_extension = Self::get_extension(filepath)?;
         match file_extension.to_lowercase().as_str() {
             "jpg" | "jpeg" => Some(mime::IMAGE_JPEG),
             "png" => Some(mime::IMAGE_PNG),
@@ -0,0 +1,159 @@
+use crate::app_config::{HttpClientConfig, ProxyConfig};
+use oauth2::reqwest;
+use std::time::Duration;
+
+/// HTTP clients built from [`HttpClientConfig`]. Both share proxy and pool settings; the
+/// upload client only differs in its longer timeouts.
+#[derive(Clone)]
+pub struct HttpClients {
+    pub api: reqwest::Client,
+    pub upload: reqwest::Client,
+}
+
+impl HttpClients {
+    pub fn new(config: &HttpClientConfig) -> anyhow::Result<Self> {
+        let api = builder(config, config.read_timeout_ms, config.request_timeout_ms)?.build()?;
+        let upload = builder(
+            config,
+            config.upload.read_timeout_ms,
+            config.upload.request_timeout_ms,
+        )?
+        .build()?;
+        Ok(HttpClients { api, upload })
+    }
+}
+
+fn builder(
+    config: &HttpClientConfig,
+    read_timeout_ms: u64,
+    request_timeout_ms: Option<u64>,
+) -> anyhow::Result<reqwest::ClientBuilder> {
+    let mut builder = reqwest::ClientBuilder::new()
+        .redirect(reqwest::redirect::Policy::none())
+        .connect_timeout(Duration::from_millis(config.connect_timeout_ms))
+        .read_timeout(Duration::from_millis(read_timeout_ms))
+        .pool_idle_timeout(Duration::from_millis(config.pool_idle_timeout_ms))
+        .pool_max_idle_per_host(config.pool_max_idle_per_host);
+    if let Some(timeout) = request_timeout_ms {
+        builder = builder.timeout(Duration::from_millis(timeout));
+    }
+    if config.http2_prior_knowledge {
+        builder = builder.http2_prior_knowledge();
+    }
+    builder = match &config.proxy {
+        // reqwest picks up the environment and system proxy on its own
+        ProxyConfig::System => builder,
+        ProxyConfig::Direct => builder.no_proxy(),
+        ProxyConfig::Manual { url, no_proxy } => {
+            // The URL may carry credentials, so it is left out of the error
+            let proxy = reqwest::Proxy::all(url)
+                .map_err(|e| anyhow::Error::new(e).context("Invalid proxy URL in http.proxy"))?
+                .no_proxy(no_proxy.as_deref().and_then(reqwest::NoProxy::from_string));
+            builder.proxy(proxy)
+        }
+    };
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_config::UploadTimeoutConfig;
+    use crate::mock_gateway::MockGateway;
+
+    const JWKS_PATH: &str = "/oauth2/jwks";
+
+    /// Clients with short timeouts for the API and long ones for uploads.
+    fn config() -> HttpClientConfig {
+        HttpClientConfig {
+            read_timeout_ms: 200,
+            upload: UploadTimeoutConfig {
+                read_timeout_ms: 5_000,
+                request_timeout_ms: None,
+            },
+            proxy: ProxyConfig::Direct,
+            ..HttpClientConfig::default()
+        }
+    }
+
+    async fn slow_gateway(delay_ms: u64) -> (MockGateway, String) {
+        let gateway = MockGateway::start().await.unwrap();
+        gateway
+            .state()
+            .delay_requests(JWKS_PATH, Duration::from_millis(delay_ms));
+        let url = format!("{}{}", gateway.app_config().api_gateway_url, JWKS_PATH);
+        (gateway, url)
+    }
+
+    #[tokio::test]
+    async fn read_timeout_cuts_off_a_slow_response() {
+        let (_gateway, url) = slow_gateway(1_000).await;
+        let clients = HttpClients::new(&config()).unwrap();
+
+        let e = clients.api.get(&url).send().await.unwrap_err();
+        assert!(e.is_timeout(), "{e}");
+        // The upload override waits long enough
+        let response = clients.upload.get(&url).send().await.unwrap();
+        assert!(response.status().is_success());
+    }
+
+    #[tokio::test]
+    async fn request_timeout_bounds_the_whole_request() {
+        let (_gateway, url) = slow_gateway(1_000).await;
+        let clients = HttpClients::new(&HttpClientConfig {
+            read_timeout_ms: 5_000,
+            request_timeout_ms: Some(200),
+            upload: UploadTimeoutConfig {
+                read_timeout_ms: 5_000,
+                request_timeout_ms: Some(200),
+            },
+            ..config()
+        })
+        .unwrap();
+
+        let started = std::time::Instant::now();
+        let e = clients.upload.get(&url).send().await.unwrap_err();
+        assert!(e.is_timeout(), "{e}");
+        assert!(started.elapsed() < Duration::from_millis(900));
+        assert!(clients.api.get(&url).send().await.unwrap_err().is_timeout());
+    }
+
+    #[tokio::test]
+    async fn manual_proxy_carries_requests_unless_excluded() {
+        let gateway = MockGateway::start().await.unwrap();
+        let proxy_url = gateway.app_config().api_gateway_url;
+        // Only reachable through the proxy, which is the gateway itself
+        let url = format!("http://ripple.invalid{}", JWKS_PATH);
+        let manual = |no_proxy: Option<&str>| HttpClientConfig {
+            proxy: ProxyConfig::Manual {
+                url: proxy_url.clone(),
+                no_proxy: no_proxy.map(str::to_string),
+            },
+            ..config()
+        };
+
+        let clients = HttpClients::new(&manual(None)).unwrap();
+        let response = clients.api.get(&url).send().await.unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(gateway.state().jwks_fetches(), 1);
+        let clients = HttpClients::new(&manual(Some("ripple.invalid"))).unwrap();
+        assert!(clients.api.get(&url).send().await.is_err());
+        let clients = HttpClients::new(&config()).unwrap();
+        assert!(clients.api.get(&url).send().await.is_err());
+        assert_eq!(gateway.state().jwks_fetches(), 1);
+    }
+
+    #[test]
+    fn bad_proxy_url_fails_when_building_the_clients() {
+        let e = HttpClients::new(&HttpClientConfig {
+            proxy: ProxyConfig::Manual {
+                url: "http://[proxy".to_string(),
+                no_proxy: None,
+            },
+            ..config()
+        })
+        .err()
+        .unwrap();
+        assert!(e.to_string().contains("proxy URL"), "{e}");
+    }
+}
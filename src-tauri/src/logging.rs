@@ -0,0 +1,78 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::info;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+const LOG_DIR: &str = "logs";
+const LOG_FILE_PREFIX: &str = "ripple";
+const LOG_FILE_SUFFIX: &str = "log";
+const MAX_LOG_FILES: usize = 7;
+const DEFAULT_LEVEL: &str = "info";
+const DEFAULT_DEV_LEVEL: &str = "info,ripple_im_app_lib=debug";
+
+/// Owns the global tracing subscriber: stdout plus a daily rotating file under
+/// `<app_data_dir>/logs`, with a filter that can be swapped at runtime.
+pub struct LogController {
+    filter: reload::Handle<EnvFilter, Registry>,
+    level: Mutex<String>,
+    log_dir: PathBuf,
+    // Dropping the guard stops the background writer, so it lives as long as the app
+    _guard: WorkerGuard,
+}
+
+impl LogController {
+    /// Installs the global subscriber. `RUST_LOG` overrides the default filter.
+    pub fn init(app_data_dir: &Path) -> anyhow::Result<Self> {
+        let log_dir = app_data_dir.join(LOG_DIR);
+        let level = std::env::var("RUST_LOG").unwrap_or_else(|_| {
+            if cfg!(debug_assertions) {
+                DEFAULT_DEV_LEVEL.to_string()
+            } else {
+                DEFAULT_LEVEL.to_string()
+            }
+        });
+        let file_appender = RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix(LOG_FILE_PREFIX)
+            .filename_suffix(LOG_FILE_SUFFIX)
+            .max_log_files(MAX_LOG_FILES)
+            .build(&log_dir)?;
+        let (file_writer, guard) = tracing_appender::non_blocking(file_appender);
+        let (filter, handle) = reload::Layer::new(EnvFilter::try_new(&level)?);
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(fmt::layer())
+            .with(fmt::layer().with_ansi(false).with_writer(file_writer))
+            .try_init()?;
+        info!("Logging to {} with filter '{}'", log_dir.display(), level);
+        Ok(LogController {
+            filter: handle,
+            level: Mutex::new(level),
+            log_dir,
+            _guard: guard,
+        })
+    }
+
+    /// Current filter directive, e.g. `info` or `info,ripple_im_app_lib=debug`.
+    pub fn level(&self) -> String {
+        self.level.lock().unwrap().clone()
+    }
+
+    /// Replaces the active filter. Accepts anything `EnvFilter` parses, from a bare level
+    /// (`debug`) to per-target directives.
+    pub fn set_level(&self, level: &str) -> anyhow::Result<()> {
+        let filter = EnvFilter::try_new(level)?;
+        self.filter.reload(filter)?;
+        *self.level.lock().unwrap() = level.to_string();
+        info!("Log filter changed to '{}'", level);
+        Ok(())
+    }
+
+    pub fn log_dir(&self) -> &Path {
+        &self.log_dir
+    }
+}
@@ -0,0 +1,168 @@
+use crate::ripple_api::api_response::{RelationUser, UserGroupData, UserProfileData};
+use crate::ripple_syncer::event_emitter::{EventEmitter, UIConversationItem, UIMessageItem};
+use crate::ripple_syncer::ui_event::{
+    ConversationReceivedMessageEvent, MessageUpdateEvent, SyncCompletedEvent, SyncDomain,
+    SyncFailedEvent, SyncProgressEvent, SyncStartedEvent, UIEvent,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+
+/// [`EventEmitter`] that keeps every event with the payload the UI would receive, in order.
+#[derive(Clone, Default)]
+pub struct RecordingEmitter {
+    events: Arc<Mutex<Vec<(String, Value)>>>,
+}
+
+impl RecordingEmitter {
+    pub fn events(&self) -> Vec<(String, Value)> {
+        self.events.lock().unwrap().clone()
+    }
+
+    /// Payloads of the events named `event`.
+    pub fn payloads(&self, event: UIEvent) -> Vec<Value> {
+        let name = event.to_string();
+        self.events()
+            .into_iter()
+            .filter(|(n, _)| *n == name)
+            .map(|(_, payload)| payload)
+            .collect()
+    }
+
+    fn record(&self, event: UIEvent, payload: impl Serialize) -> anyhow::Result<()> {
+        let payload = serde_json::to_value(payload)?;
+        self.events
+            .lock()
+            .unwrap()
+            .push((event.to_string(), payload));
+        Ok(())
+    }
+}
+
+impl EventEmitter for RecordingEmitter {
+    fn emit_user_profile_updated(&self, profile: UserProfileData) -> anyhow::Result<()> {
+        self.record(UIEvent::UserProfileUpdated, profile)
+    }
+
+    fn emit_relation_insert(&self, user: RelationUser) -> anyhow::Result<()> {
+        self.record(UIEvent::RelationInserted, user)
+    }
+
+    fn emit_relation_update(&self, user: RelationUser) -> anyhow::Result<()> {
+        self.record(UIEvent::RelationUpdated, user)
+    }
+
+    fn emit_relation_delete(&self, user_id: String) -> anyhow::Result<()> {
+        self.record(UIEvent::RelationDeleted, user_id)
+    }
+
+    fn emit_relations_clear_all(&self) -> anyhow::Result<()> {
+        self.record(UIEvent::RelationClearedAll, ())
+    }
+
+    fn emit_conversation_insert(&self, conversation: UIConversationItem) -> anyhow::Result<()> {
+        self.record(UIEvent::ConversationInserted, conversation)
+    }
+
+    fn emit_conversation_update(&self, conversation: UIConversationItem) -> anyhow::Result<()> {
+        self.record(UIEvent::ConversationUpdated, conversation)
+    }
+
+    fn emit_conversation_delete(&self, conversation_id: String) -> anyhow::Result<()> {
+        self.record(UIEvent::ConversationsDeleted, conversation_id)
+    }
+
+    fn emit_conversation_delete_all(&self) -> anyhow::Result<()> {
+        self.record(UIEvent::ConversationsClearedAll, ())
+    }
+
+    fn emit_conversations_received(
+        &self,
+        conversation_id: String,
+        unread_count: i32,
+        message: String,
+        timestamp: String,
+    ) -> anyhow::Result<()> {
+        let event = ConversationReceivedMessageEvent {
+            conversation_id,
+            unread_count,
+            message,
+            timestamp,
+        };
+        self.record(UIEvent::ConversationReceivedNewMessage, event)
+    }
+
+    fn emit_message_updated(
+        &self,
+        action: i32,
+        message: Option<UIMessageItem>,
+    ) -> anyhow::Result<()> {
+        self.record(
+            UIEvent::MessageUpdated,
+            MessageUpdateEvent { action, message },
+        )
+    }
+
+    fn emit_messages_cleared(&self) -> anyhow::Result<()> {
+        let event = MessageUpdateEvent {
+            action: -1,
+            message: None,
+        };
+        self.record(UIEvent::MessageUpdated, event)
+    }
+
+    fn emit_user_group_insert(&self, group: UserGroupData) -> anyhow::Result<()> {
+        self.record(UIEvent::UserGroupInserted, group)
+    }
+
+    fn emit_user_group_update(&self, group: UserGroupData) -> anyhow::Result<()> {
+        self.record(UIEvent::UserGroupUpdated, group)
+    }
+
+    fn emit_user_group_delete(&self, group_id: String) -> anyhow::Result<()> {
+        self.record(UIEvent::UserGroupDeleted, group_id)
+    }
+
+    fn emit_user_groups_clear_all(&self) -> anyhow::Result<()> {
+        self.record(UIEvent::UserGroupsClearedAll, ())
+    }
+
+    fn emit_session_expired(&self) -> anyhow::Result<()> {
+        self.record(UIEvent::SessionExpired, ())
+    }
+
+    fn emit_account_switched(&self, user_id: String) -> anyhow::Result<()> {
+        self.record(UIEvent::AccountSwitched, user_id)
+    }
+
+    fn emit_sync_started(&self, domain: SyncDomain) -> anyhow::Result<()> {
+        self.record(UIEvent::SyncStarted, SyncStartedEvent { domain })
+    }
+
+    fn emit_sync_progress(
+        &self,
+        domain: SyncDomain,
+        group_id: Option<String>,
+        completed: usize,
+        total: usize,
+    ) -> anyhow::Result<()> {
+        let event = SyncProgressEvent {
+            domain,
+            group_id,
+            completed,
+            total,
+        };
+        self.record(UIEvent::SyncProgress, event)
+    }
+
+    fn emit_sync_completed(&self, domain: SyncDomain, timestamp: i64) -> anyhow::Result<()> {
+        self.record(
+            UIEvent::SyncCompleted,
+            SyncCompletedEvent { domain, timestamp },
+        )
+    }
+
+    fn emit_sync_failed(&self, domain: SyncDomain, error: String) -> anyhow::Result<()> {
+        self.record(UIEvent::SyncFailed, SyncFailedEvent { domain, error })
+    }
+}
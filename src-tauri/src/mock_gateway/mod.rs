@@ -0,0 +1,230 @@
+//! In-process fake of the Ripple gateways for integration tests.
+//!
+//! One axum server answers the REST paths of [`ApiPaths`](crate::ripple_api::api_paths::ApiPaths)
+//! for both the API and upload gateway, the OAuth token, revocation and JWKS endpoints and the
+//! WebSocket gateway speaking `WsMessage` protobufs. Tests seed and inspect it through
+//! [`GatewayState`] and point an [`AppConfig`] at it with [`MockGateway::app_config`]. What
+//! the client reports to the UI is captured by a [`RecordingEmitter`].
+
+mod emitter;
+mod oauth;
+mod rest;
+mod state;
+mod tests;
+mod ws;
+
+use crate::app_config::AppConfig;
+use crate::http_client::HttpClients;
+use crate::ripple_api::oauth_client::OauthClient;
+use crate::ripple_api::RippleApi;
+use crate::ripple_syncer::DataSyncManager;
+use crate::store_engine::store_engine::{MemoryStore, RippleStorage};
+use axum::routing::{delete, get, patch, post, put};
+use axum::{middleware, Router};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+pub use emitter::RecordingEmitter;
+pub use state::GatewayState;
+
+const CLIENT_ID: &str = "ripple-im-desktop";
+// Characters that Basic auth credentials have to form-urlencode
+const CLIENT_SECRET: &str = "mock secret:+/%";
+
+pub struct MockGateway {
+    addr: SocketAddr,
+    state: Arc<GatewayState>,
+    server: JoinHandle<()>,
+}
+
+impl MockGateway {
+    /// Binds to an ephemeral localhost port and serves until dropped.
+    pub async fn start() -> anyhow::Result<Self> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(GatewayState::new(CLIENT_ID, &format!("http://{}", addr)));
+        let app = router(state.clone());
+        let server = tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+        Ok(MockGateway {
+            addr,
+            state,
+            server,
+        })
+    }
+
+    pub fn state(&self) -> &GatewayState {
+        &self.state
+    }
+
+    /// Client configuration with every gateway and OAuth endpoint pointing at this server.
+    pub fn app_config(&self) -> AppConfig {
+        let base = format!("http://{}", self.addr);
+        serde_json::from_value(serde_json::json!({
+            "signup_url": format!("{}/signup", base),
+            "oauth2_client_id": CLIENT_ID,
+            "oauth2_client_secret": CLIENT_SECRET,
+            "oauth2_auth_url": format!("{}/oauth2/authorize", base),
+            "oauth2_token_url": format!("{}/oauth2/token", base),
+            "oauth2_revocation_url": format!("{}/oauth2/revoke", base),
+            "oauth2_issuer": base,
+            "oauth2_jwks_url": format!("{}/oauth2/jwks", base),
+            "callback_server_addr": "127.0.0.1:0",
+            "oauth2_redirect_uri": "http://127.0.0.1/callback",
+            "upload_gateway_url": base,
+            "api_gateway_url": base,
+            "ws_gateway_url": format!("ws://{}/ws", self.addr),
+        }))
+        .expect("mock app config deserializes")
+    }
+}
+
+impl Drop for MockGateway {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// One signed-in client backed by a memory store.
+pub struct TestClient {
+    pub config: AppConfig,
+    pub store: MemoryStore,
+    pub api: RippleApi<MemoryStore>,
+    pub data_sync: DataSyncManager<MemoryStore>,
+}
+
+impl TestClient {
+    /// Runs the authorization code flow against the gateway, skipping only the browser.
+    pub async fn login(gateway: &MockGateway, user_id: &str) -> TestClient {
+        let config = gateway.app_config();
+        let http_clients = HttpClients::new(&config.http).unwrap();
+        let oauth_client = OauthClient::new(&config, http_clients.api.clone()).unwrap();
+        let store = MemoryStore::new();
+        let api =
+            RippleApi::from_config(&config, http_clients, oauth_client.clone(), store.clone());
+        oauth_client.auth_url();
+        let code = gateway.state().authorize(user_id);
+        let token = oauth_client.request_token(code).await.unwrap();
+        api.oauth_save_token(&token).await.unwrap();
+        let data_sync = DataSyncManager::new(api.clone(), store.clone());
+        TestClient {
+            config,
+            store,
+            api,
+            data_sync,
+        }
+    }
+
+    pub async fn access_token(&self) -> String {
+        self.store
+            .tokens()
+            .get_token()
+            .await
+            .unwrap()
+            .unwrap()
+            .access_token
+    }
+}
+
+/// Waits until `condition` holds, failing the test after 10 seconds.
+pub async fn eventually(mut condition: impl FnMut() -> bool) {
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !condition() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("condition not met within 10s");
+}
+
+fn router(state: Arc<GatewayState>) -> Router {
+    Router::new()
+        .route("/oauth2/token", post(oauth::token))
+        .route("/oauth2/revoke", post(oauth::revoke))
+        .route("/oauth2/jwks", get(oauth::jwks))
+        .route("/ws", get(ws::upgrade))
+        // Upload gateway
+        .route("/api/upload/avatar", put(rest::upload_avatar))
+        .route(
+            "/api/upload/groups/{group_id}/avatar",
+            put(rest::upload_group_avatar),
+        )
+        .route(
+            "/api/upload/attachment/initiate",
+            post(rest::initiate_upload),
+        )
+        .route("/api/upload/attachment/single", put(rest::upload_single))
+        .route("/api/upload/attachment/chunk", put(rest::upload_chunk))
+        .route(
+            "/api/upload/attachment/chunk/complete",
+            post(rest::complete_upload),
+        )
+        .route("/api/upload/attachment/abort", delete(rest::abort_upload))
+        // Profile
+        .route(
+            "/api/users/me/profile",
+            get(rest::my_profile).patch(rest::update_profile),
+        )
+        .route("/api/users/me/avatar", delete(rest::delete_avatar))
+        .route("/api/users/{user_id}/profile", get(rest::user_profile))
+        // Relations
+        .route("/api/users/me/relations", get(rest::relations))
+        .route("/api/users/me/relations/sync", get(rest::relations_sync))
+        .route("/api/users/me/friends", post(rest::add_friend))
+        .route(
+            "/api/users/me/friends/{friend_id}",
+            delete(rest::remove_friend).patch(rest::update_friend),
+        )
+        .route("/api/users/me/blocked-users", post(rest::block_user))
+        .route(
+            "/api/users/me/blocked-users/{user_id}",
+            delete(rest::unblock_user).patch(rest::update_blocked_user),
+        )
+        // Conversations and messages
+        .route("/api/users/me/conversations", get(rest::conversations))
+        .route(
+            "/api/users/me/conversations/sync",
+            get(rest::conversations_sync),
+        )
+        .route(
+            "/api/users/me/conversations/summary",
+            post(rest::conversations_summary),
+        )
+        .route(
+            "/api/users/me/conversations/messages",
+            post(rest::send_message),
+        )
+        .route(
+            "/api/users/me/conversations/{conversation_id}/messages",
+            get(rest::read_messages),
+        )
+        .route(
+            "/api/users/me/conversations/{conversation_id}/read-position",
+            patch(rest::update_read_position),
+        )
+        // Groups
+        .route("/api/groups", post(rest::create_group))
+        .route("/api/groups/{group_id}", patch(rest::update_group))
+        .route(
+            "/api/groups/{group_id}/members",
+            get(rest::group_members).post(rest::add_group_members),
+        )
+        .route(
+            "/api/groups/{group_id}/members/sync",
+            get(rest::group_members_sync),
+        )
+        .route(
+            "/api/groups/{group_id}/members/me",
+            delete(rest::leave_group),
+        )
+        .route("/api/users/me/groups", get(rest::user_groups))
+        .route("/api/users/me/groups/sync", get(rest::user_groups_sync))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            rest::injected_faults,
+        ))
+        .with_state(state)
+}
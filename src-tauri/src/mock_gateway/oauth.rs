@@ -0,0 +1,101 @@
+use crate::mock_gateway::state::{self, GatewayState};
+use crate::mock_gateway::{CLIENT_ID, CLIENT_SECRET};
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Form, Json};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use oauth2::url::form_urlencoded;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+
+/// RFC 6749 §2.3.1 client authentication: HTTP Basic with form-urlencoded credentials.
+fn client_authenticated(headers: &HeaderMap) -> bool {
+    let encode =
+        |value: &str| form_urlencoded::byte_serialize(value.as_bytes()).collect::<String>();
+    let expected = format!("{}:{}", encode(CLIENT_ID), encode(CLIENT_SECRET));
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|credentials| STANDARD.decode(credentials).ok())
+        .is_some_and(|credentials| credentials == expected.as_bytes())
+}
+
+fn invalid_client() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(json!({"error": "invalid_client"})),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub(super) struct TokenRequest {
+    grant_type: String,
+    code: Option<String>,
+    refresh_token: Option<String>,
+}
+
+/// RFC 6749 token endpoint for the `authorization_code` and `refresh_token` grants. Unknown
+/// codes and refresh tokens are answered with `invalid_grant`, like the real server.
+pub(super) async fn token(
+    State(state): State<Arc<GatewayState>>,
+    headers: HeaderMap,
+    Form(request): Form<TokenRequest>,
+) -> Response {
+    if !client_authenticated(&headers) {
+        return invalid_client();
+    }
+    let granted = match request.grant_type.as_str() {
+        "authorization_code" => state.grant(request.code.as_deref(), None),
+        "refresh_token" => state.grant(None, request.refresh_token.as_deref()),
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "unsupported_grant_type"})),
+            )
+                .into_response()
+        }
+    };
+    match granted {
+        Some((access_token, refresh_token)) => Json(json!({
+            "access_token": access_token,
+            "token_type": "bearer",
+            "expires_in": 3600,
+            "refresh_token": refresh_token,
+            "scope": "user",
+        }))
+        .into_response(),
+        None => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "invalid_grant"})),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub(super) struct RevokeRequest {
+    token: String,
+}
+
+/// RFC 7009 revocation endpoint for authenticated clients; unknown tokens are accepted silently as the spec requires.
+pub(super) async fn revoke(
+    State(state): State<Arc<GatewayState>>,
+    headers: HeaderMap,
+    Form(request): Form<RevokeRequest>,
+) -> Response {
+    if !client_authenticated(&headers) {
+        return invalid_client();
+    }
+    state.revoke(&request.token);
+    StatusCode::OK.into_response()
+}
+
+pub(super) async fn jwks(State(state): State<Arc<GatewayState>>) -> Json<serde_json::Value> {
+    state.with(|data| data.jwks_fetches += 1);
+    Json(state::jwks())
+}
@@ -0,0 +1,783 @@
+use crate::mock_gateway::state::{sync_page, GatewayState, BLOCKED_FLAG, FRIEND_FLAG};
+use crate::ripple_api::api_response::{
+    AbortUploadResponse, AddFriendRequest, BlockUserRequest, ChunkUploadResponse, CommonResponse,
+    CompleteUploadData, CompleteUploadRequest, CompleteUploadResponse, ConversationSummariesData,
+    ConversationSummariesResponse, ConversationSummary, ConversationSummaryRequest,
+    ConversationSyncData, ConversationSyncResponse, ConversationsData, ConversationsResponse,
+    CreateGroupRequest, CreateGroupResponse, GetGroupMembersResponse, GetUserGroupsResponse,
+    GroupData, GroupMembersPageData, GroupSyncData, GroupSyncResponse, InitiateUploadData,
+    InitiateUploadRequest, InitiateUploadResponse, InviteGroupMemberRequest, MessageResponse,
+    MessageResponseData, ReadMessagesData, ReadMessagesResponse, RelationOperation, RelationUser,
+    RelationsPageData, RelationsPageResponse, RelationsSyncData, RelationsSyncResponse,
+    SendMessageRequest, SingleUploadResponse, UpdateFriendRequest, UpdateGroupRequest,
+    UpdateProfileRequest, UpdateReadPositionRequest, UploadImageData, UploadImageResponse,
+    UserGroupOperation, UserGroupSyncData, UserGroupSyncResponse, UserGroupsPageData,
+    UserProfileResponse,
+};
+use axum::extract::{FromRequestParts, Path, Query, Request, State};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Deserialize;
+use std::sync::Arc;
+
+const SUCCESS: &str = "success";
+const SINGLE_UPLOAD_LIMIT: i64 = 5 * 1024 * 1024;
+
+type Gateway = State<Arc<GatewayState>>;
+
+/// User id behind the bearer token of a request; rejects unknown or expired tokens with 401.
+pub(super) struct AuthUser(pub String);
+
+impl FromRequestParts<Arc<GatewayState>> for AuthUser {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<GatewayState>,
+    ) -> Result<Self, Self::Rejection> {
+        parts
+            .headers
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .and_then(|token| state.user_for_access_token(token))
+            .map(AuthUser)
+            .ok_or(StatusCode::UNAUTHORIZED)
+    }
+}
+
+fn ok() -> Json<CommonResponse> {
+    Json(CommonResponse {
+        code: 200,
+        message: SUCCESS.to_string(),
+    })
+}
+
+/// Rejects the requests that [`GatewayState::fail_requests`] was told to fail and holds back
+/// those that [`GatewayState::delay_requests`] was told to delay.
+pub(super) async fn injected_faults(
+    State(state): Gateway,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    if let Some(delay) = state.delay_for(path) {
+        tokio::time::sleep(delay).await;
+    }
+    if state.fails(path) {
+        let body = Json(CommonResponse {
+            code: 400,
+            message: "injected failure".to_string(),
+        });
+        return (StatusCode::BAD_REQUEST, body).into_response();
+    }
+    next.run(request).await
+}
+
+fn not_found() -> Json<CommonResponse> {
+    Json(CommonResponse {
+        code: 404,
+        message: "not found".to_string(),
+    })
+}
+
+#[derive(Deserialize)]
+pub(super) struct PageParams {
+    #[serde(rename = "nextPageToken")]
+    next_page_token: Option<String>,
+    #[serde(rename = "pageSize")]
+    page_size: u32,
+}
+
+impl PageParams {
+    /// Slices `items` at the offset carried in the page token.
+    fn page<T: Clone>(&self, items: Vec<T>) -> (Vec<T>, Option<String>, bool) {
+        let offset: usize = self
+            .next_page_token
+            .as_deref()
+            .and_then(|t| t.parse().ok())
+            .unwrap_or(0);
+        let end = (offset + self.page_size.max(1) as usize).min(items.len());
+        let has_more = end < items.len();
+        let page = items.get(offset..end).unwrap_or_default().to_vec();
+        (page, has_more.then(|| end.to_string()), has_more)
+    }
+}
+
+#[derive(Deserialize)]
+pub(super) struct SyncParams {
+    version: Option<String>,
+}
+
+// ==================== Profile ====================
+
+pub(super) async fn my_profile(
+    State(state): Gateway,
+    AuthUser(user_id): AuthUser,
+) -> Result<Json<UserProfileResponse>, StatusCode> {
+    user_profile(State(state), AuthUser(user_id.clone()), Path(user_id)).await
+}
+
+pub(super) async fn user_profile(
+    State(state): Gateway,
+    _: AuthUser,
+    Path(user_id): Path<String>,
+) -> Result<Json<UserProfileResponse>, StatusCode> {
+    let profile = state.with(|data| data.profiles.get(&user_id).cloned());
+    let profile = profile.ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(UserProfileResponse {
+        code: 200,
+        message: SUCCESS.to_string(),
+        data: profile,
+    }))
+}
+
+pub(super) async fn update_profile(
+    State(state): Gateway,
+    AuthUser(user_id): AuthUser,
+    Json(request): Json<UpdateProfileRequest>,
+) -> Json<CommonResponse> {
+    state.with(|data| {
+        if let (Some(profile), Some(nickname)) = (data.profiles.get_mut(&user_id), request.nickname)
+        {
+            profile.nick_name = nickname;
+        }
+    });
+    ok()
+}
+
+pub(super) async fn delete_avatar(
+    State(state): Gateway,
+    AuthUser(user_id): AuthUser,
+) -> Json<CommonResponse> {
+    state.with(|data| {
+        if let Some(profile) = data.profiles.get_mut(&user_id) {
+            profile.avatar = None;
+        }
+    });
+    ok()
+}
+
+// ==================== Relations ====================
+
+pub(super) async fn relations(
+    State(state): Gateway,
+    AuthUser(user_id): AuthUser,
+    Query(params): Query<PageParams>,
+) -> Json<RelationsPageResponse> {
+    let (users, last_version) = state.with(|data| {
+        let users: Vec<RelationUser> = data
+            .relations
+            .get(&user_id)
+            .map(|r| r.values().cloned().collect())
+            .unwrap_or_default();
+        let last_version = data
+            .relation_changes
+            .get(&user_id)
+            .and_then(|log| log.last())
+            .map(|c| c.version.clone());
+        (users, last_version)
+    });
+    let (users, next_page_token, has_more) = params.page(users);
+    Json(RelationsPageResponse {
+        code: 200,
+        message: SUCCESS.to_string(),
+        data: RelationsPageData {
+            users,
+            next_page_token,
+            has_more,
+            last_version,
+        },
+    })
+}
+
+pub(super) async fn relations_sync(
+    State(state): Gateway,
+    AuthUser(user_id): AuthUser,
+    Query(params): Query<SyncParams>,
+) -> Json<RelationsSyncResponse> {
+    let page = state.with(|data| {
+        sync_page(
+            data.relation_changes
+                .get(&user_id)
+                .map(Vec::as_slice)
+                .unwrap_or_default(),
+            params.version.as_deref(),
+            |c| &c.version,
+        )
+    });
+    Json(RelationsSyncResponse {
+        code: 200,
+        message: SUCCESS.to_string(),
+        data: RelationsSyncData {
+            full_sync: page.full_sync,
+            latest_version: page.latest_version,
+            changes: page.changes,
+        },
+    })
+}
+
+pub(super) async fn add_friend(
+    State(state): Gateway,
+    AuthUser(user_id): AuthUser,
+    Json(request): Json<AddFriendRequest>,
+) -> Json<CommonResponse> {
+    state.with(|data| data.add_friend(&user_id, &request.target_user_id));
+    ok()
+}
+
+pub(super) async fn remove_friend(
+    State(state): Gateway,
+    AuthUser(user_id): AuthUser,
+    Path(friend_id): Path<String>,
+) -> Json<CommonResponse> {
+    if state
+        .with(|data| data.remove_relation(&user_id, &friend_id, RelationOperation::DeleteFriend))
+    {
+        ok()
+    } else {
+        not_found()
+    }
+}
+
+pub(super) async fn update_friend(
+    State(state): Gateway,
+    AuthUser(user_id): AuthUser,
+    Path(friend_id): Path<String>,
+    Json(request): Json<UpdateFriendRequest>,
+) -> Json<CommonResponse> {
+    let updated = state.with(|data| {
+        let user = data.relations.get_mut(&user_id)?.get_mut(&friend_id)?;
+        user.remark_name = request.remark_name;
+        let user = user.clone();
+        data.log_relation(&user_id, RelationOperation::UpdateFriendRemarkName, &user);
+        Some(())
+    });
+    match updated {
+        Some(()) => ok(),
+        None => not_found(),
+    }
+}
+
+pub(super) async fn block_user(
+    State(state): Gateway,
+    AuthUser(user_id): AuthUser,
+    Json(request): Json<BlockUserRequest>,
+) -> Json<CommonResponse> {
+    state.with(|data| {
+        let profile = data.profiles.get(&request.target_user_id).cloned();
+        let relations = data.relations.entry(user_id.clone()).or_default();
+        let user = relations
+            .entry(request.target_user_id.clone())
+            .or_insert_with(|| RelationUser {
+                user_id: request.target_user_id.clone(),
+                nick_name: profile.map(|p| p.nick_name).unwrap_or_default(),
+                avatar: None,
+                remark_name: None,
+                relation_flags: 0,
+            });
+        let operation = if user.relation_flags & FRIEND_FLAG != 0 {
+            RelationOperation::AddBlock
+        } else {
+            RelationOperation::BlockStranger
+        };
+        user.relation_flags |= BLOCKED_FLAG;
+        let user = user.clone();
+        data.log_relation(&user_id, operation, &user);
+    });
+    ok()
+}
+
+pub(super) async fn unblock_user(
+    State(state): Gateway,
+    AuthUser(user_id): AuthUser,
+    Path(target_user_id): Path<String>,
+) -> Json<CommonResponse> {
+    let unblocked = state.with(|data| {
+        let user = data.relations.get_mut(&user_id)?.get_mut(&target_user_id)?;
+        user.relation_flags &= !BLOCKED_FLAG;
+        let user = user.clone();
+        if user.relation_flags & FRIEND_FLAG != 0 {
+            data.log_relation(&user_id, RelationOperation::UnblockRestoreFriend, &user);
+        } else {
+            data.remove_relation(&user_id, &target_user_id, RelationOperation::DeleteBlock);
+        }
+        Some(())
+    });
+    match unblocked {
+        Some(()) => ok(),
+        None => not_found(),
+    }
+}
+
+pub(super) async fn update_blocked_user() -> Json<CommonResponse> {
+    ok()
+}
+
+// ==================== Conversations and messages ====================
+
+pub(super) async fn conversations(
+    State(state): Gateway,
+    AuthUser(user_id): AuthUser,
+    Query(params): Query<PageParams>,
+) -> Json<ConversationsResponse> {
+    let (conversations, last_version) = state.with(|data| {
+        let conversations: Vec<_> = data
+            .conversations
+            .get(&user_id)
+            .map(|c| c.values().cloned().collect())
+            .unwrap_or_default();
+        let last_version = data
+            .conversation_changes
+            .get(&user_id)
+            .and_then(|log| log.last())
+            .map(|c| c.version.clone());
+        (conversations, last_version)
+    });
+    let (conversations, next_page_token, has_more) = params.page(conversations);
+    Json(ConversationsResponse {
+        code: 200,
+        message: SUCCESS.to_string(),
+        data: ConversationsData {
+            conversations,
+            next_page_token,
+            has_more,
+            last_version,
+        },
+    })
+}
+
+pub(super) async fn conversations_sync(
+    State(state): Gateway,
+    AuthUser(user_id): AuthUser,
+    Query(params): Query<SyncParams>,
+) -> Json<ConversationSyncResponse> {
+    let page = state.with(|data| {
+        sync_page(
+            data.conversation_changes
+                .get(&user_id)
+                .map(Vec::as_slice)
+                .unwrap_or_default(),
+            params.version.as_deref(),
+            |c| &c.version,
+        )
+    });
+    Json(ConversationSyncResponse {
+        code: 200,
+        message: SUCCESS.to_string(),
+        data: ConversationSyncData {
+            full_sync: page.full_sync,
+            latest_version: page.latest_version,
+            changes: page.changes,
+        },
+    })
+}
+
+pub(super) async fn conversations_summary(
+    State(state): Gateway,
+    AuthUser(user_id): AuthUser,
+    Json(request): Json<ConversationSummaryRequest>,
+) -> Json<ConversationSummariesResponse> {
+    let summaries = state.with(|data| {
+        let conversations = data.conversations.get(&user_id);
+        request
+            .conversation_ids
+            .iter()
+            .filter_map(|id| conversations?.get(id))
+            .map(|c| ConversationSummary {
+                conversation_id: c.conversation_id.clone(),
+                unread_count: c.unread_count,
+                last_message_text: c.last_message_text.clone(),
+                last_message_timestamp: c.last_message_timestamp.unwrap_or(0),
+                last_message_id: c.last_message_id.clone(),
+            })
+            .collect()
+    });
+    Json(ConversationSummariesResponse {
+        code: 200,
+        message: SUCCESS.to_string(),
+        data: ConversationSummariesData { summaries },
+    })
+}
+
+pub(super) async fn send_message(
+    State(state): Gateway,
+    AuthUser(user_id): AuthUser,
+    Json(request): Json<SendMessageRequest>,
+) -> Json<MessageResponse> {
+    if request.sender_id != user_id {
+        return Json(MessageResponse {
+            code: 403,
+            message: "sender does not match token".to_string(),
+            data: None,
+        });
+    }
+    let message_id = state.with(|data| {
+        data.deliver_message(
+            &user_id,
+            &request.conversation_id,
+            request.receiver_id.as_deref(),
+            request.group_id.as_deref(),
+            request.text_content.as_deref().unwrap_or_default(),
+        )
+    });
+    Json(MessageResponse {
+        code: 200,
+        message: SUCCESS.to_string(),
+        data: Some(MessageResponseData { message_id }),
+    })
+}
+
+#[derive(Deserialize)]
+pub(super) struct ReadMessagesParams {
+    #[serde(rename = "beforeMessageId")]
+    before_message_id: Option<String>,
+    #[serde(rename = "afterMessageId")]
+    after_message_id: Option<String>,
+    #[serde(rename = "readSize")]
+    read_size: usize,
+}
+
+/// Oldest-first page of messages before or after a message id; `0` means from the newest
+/// (before) or from the start (after).
+pub(super) async fn read_messages(
+    State(state): Gateway,
+    _: AuthUser,
+    Path(conversation_id): Path<String>,
+    Query(params): Query<ReadMessagesParams>,
+) -> Json<ReadMessagesResponse> {
+    let id_of = |id: &str| id.parse::<u64>().unwrap_or(0);
+    let messages = state.with(|data| {
+        let all = data
+            .messages
+            .get(&conversation_id)
+            .cloned()
+            .unwrap_or_default();
+        match (&params.after_message_id, &params.before_message_id) {
+            (Some(after), _) => all
+                .into_iter()
+                .filter(|m| id_of(&m.message_id) > id_of(after))
+                .take(params.read_size)
+                .collect(),
+            (None, before) => {
+                let before = before.as_deref().map(id_of).unwrap_or(0);
+                let older: Vec<_> = all
+                    .into_iter()
+                    .filter(|m| before == 0 || id_of(&m.message_id) < before)
+                    .collect();
+                let skip = older.len().saturating_sub(params.read_size);
+                older.into_iter().skip(skip).collect()
+            }
+        }
+    });
+    Json(ReadMessagesResponse {
+        code: 200,
+        message: SUCCESS.to_string(),
+        data: ReadMessagesData { messages },
+    })
+}
+
+pub(super) async fn update_read_position(
+    State(state): Gateway,
+    AuthUser(user_id): AuthUser,
+    Path(conversation_id): Path<String>,
+    Json(request): Json<UpdateReadPositionRequest>,
+) -> Json<CommonResponse> {
+    state.with(|data| {
+        if let Some(conversation) = data
+            .conversations
+            .get_mut(&user_id)
+            .and_then(|c| c.get_mut(&conversation_id))
+        {
+            conversation.last_read_message_id = Some(request.message_id);
+            conversation.unread_count = 0;
+        }
+    });
+    ok()
+}
+
+// ==================== Groups ====================
+
+pub(super) async fn create_group(
+    State(state): Gateway,
+    AuthUser(user_id): AuthUser,
+    Json(request): Json<CreateGroupRequest>,
+) -> Json<CreateGroupResponse> {
+    let mut members = vec![user_id.as_str()];
+    members.extend(request.member_ids.iter().map(String::as_str));
+    let group_id = state.create_group(&request.group_name, &members);
+    Json(CreateGroupResponse {
+        code: 200,
+        message: SUCCESS.to_string(),
+        data: Some(GroupData { group_id }),
+    })
+}
+
+pub(super) async fn update_group(
+    State(state): Gateway,
+    _: AuthUser,
+    Path(group_id): Path<String>,
+    Json(request): Json<UpdateGroupRequest>,
+) -> Json<CommonResponse> {
+    let members = state.with(|data| {
+        let group = data.groups.get_mut(&group_id)?;
+        group.name = request.name?;
+        Some(group.members.clone())
+    });
+    let Some(members) = members else {
+        return not_found();
+    };
+    state.with(|data| {
+        for member in &members {
+            data.log_user_group(member, UserGroupOperation::UpdateGroupName, &group_id);
+        }
+    });
+    ok()
+}
+
+pub(super) async fn add_group_members(
+    State(state): Gateway,
+    _: AuthUser,
+    Path(group_id): Path<String>,
+    Json(request): Json<InviteGroupMemberRequest>,
+) -> Json<CommonResponse> {
+    let added = state.with(|data| {
+        let group = data.groups.get_mut(&group_id)?;
+        group.members.extend(request.new_member_ids.iter().cloned());
+        for member in &request.new_member_ids {
+            data.log_user_group(member, UserGroupOperation::Join, &group_id);
+        }
+        Some(())
+    });
+    match added {
+        Some(()) => ok(),
+        None => not_found(),
+    }
+}
+
+pub(super) async fn group_members(
+    State(state): Gateway,
+    _: AuthUser,
+    Path(group_id): Path<String>,
+    Query(params): Query<PageParams>,
+) -> Json<GetGroupMembersResponse> {
+    let delay = state.begin_group_member_request();
+    tokio::time::sleep(delay).await;
+    state.end_group_member_request();
+    let (members, last_version) = state.with(|data| {
+        let last_version = data
+            .groups
+            .get(&group_id)
+            .and_then(|g| g.changes.last())
+            .map(|c| c.version.clone());
+        (data.group_members(&group_id), last_version)
+    });
+    let (members, next_page_token, has_more) = params.page(members);
+    Json(GetGroupMembersResponse {
+        code: 200,
+        message: SUCCESS.to_string(),
+        data: GroupMembersPageData {
+            members,
+            next_page_token,
+            has_more,
+            last_version,
+        },
+    })
+}
+
+pub(super) async fn group_members_sync(
+    State(state): Gateway,
+    _: AuthUser,
+    Path(group_id): Path<String>,
+    Query(params): Query<SyncParams>,
+) -> Json<GroupSyncResponse> {
+    let page = state.with(|data| {
+        sync_page(
+            data.groups
+                .get(&group_id)
+                .map(|g| g.changes.as_slice())
+                .unwrap_or_default(),
+            params.version.as_deref(),
+            |c| &c.version,
+        )
+    });
+    Json(GroupSyncResponse {
+        code: 200,
+        message: SUCCESS.to_string(),
+        data: GroupSyncData {
+            full_sync: page.full_sync,
+            latest_version: page.latest_version,
+            changes: page.changes,
+        },
+    })
+}
+
+pub(super) async fn leave_group(
+    State(state): Gateway,
+    AuthUser(user_id): AuthUser,
+    Path(group_id): Path<String>,
+) -> Json<CommonResponse> {
+    let left = state.with(|data| {
+        let group = data.groups.get_mut(&group_id)?;
+        group.members.retain(|m| *m != user_id);
+        data.log_user_group(&user_id, UserGroupOperation::Quit, &group_id);
+        Some(())
+    });
+    match left {
+        Some(()) => ok(),
+        None => not_found(),
+    }
+}
+
+pub(super) async fn user_groups(
+    State(state): Gateway,
+    AuthUser(user_id): AuthUser,
+    Query(params): Query<PageParams>,
+) -> Json<GetUserGroupsResponse> {
+    let (groups, last_version) = state.with(|data| {
+        let last_version = data
+            .user_group_changes
+            .get(&user_id)
+            .and_then(|log| log.last())
+            .map(|c| c.version.clone());
+        (data.user_groups(&user_id), last_version)
+    });
+    let (groups, next_page_token, has_more) = params.page(groups);
+    Json(GetUserGroupsResponse {
+        code: 200,
+        message: SUCCESS.to_string(),
+        data: UserGroupsPageData {
+            groups,
+            next_page_token,
+            has_more,
+            last_version,
+        },
+    })
+}
+
+pub(super) async fn user_groups_sync(
+    State(state): Gateway,
+    AuthUser(user_id): AuthUser,
+    Query(params): Query<SyncParams>,
+) -> Json<UserGroupSyncResponse> {
+    let page = state.with(|data| {
+        sync_page(
+            data.user_group_changes
+                .get(&user_id)
+                .map(Vec::as_slice)
+                .unwrap_or_default(),
+            params.version.as_deref(),
+            |c| &c.version,
+        )
+    });
+    Json(UserGroupSyncResponse {
+        code: 200,
+        message: SUCCESS.to_string(),
+        data: UserGroupSyncData {
+            full_sync: page.full_sync,
+            latest_version: page.latest_version,
+            changes: page.changes,
+        },
+    })
+}
+
+// ==================== Uploads ====================
+
+pub(super) async fn upload_avatar(_: AuthUser) -> Json<UploadImageResponse> {
+    Json(UploadImageResponse {
+        code: 200,
+        message: SUCCESS.to_string(),
+        data: Some(UploadImageData {
+            url: format!("https://cdn.mock/avatars/{}", uuid::Uuid::new_v4()),
+        }),
+    })
+}
+
+pub(super) async fn upload_group_avatar(
+    auth: AuthUser,
+    Path(_group_id): Path<String>,
+) -> Json<UploadImageResponse> {
+    upload_avatar(auth).await
+}
+
+pub(super) async fn initiate_upload(
+    _: AuthUser,
+    Json(request): Json<InitiateUploadRequest>,
+) -> Json<InitiateUploadResponse> {
+    let object_name = format!("{}-{}", request.file_sha256, request.original_filename);
+    let data = if request.file_size <= SINGLE_UPLOAD_LIMIT {
+        InitiateUploadData {
+            upload_mode: 1,
+            chunk_size: None,
+            total_chunks: None,
+            start_chunk_number: None,
+            object_name: Some(object_name),
+            file_url: None,
+        }
+    } else {
+        InitiateUploadData {
+            upload_mode: 2,
+            chunk_size: Some(SINGLE_UPLOAD_LIMIT),
+            total_chunks: Some(((request.file_size - 1) / SINGLE_UPLOAD_LIMIT + 1) as i32),
+            start_chunk_number: Some(1),
+            object_name: Some(object_name),
+            file_url: None,
+        }
+    };
+    Json(InitiateUploadResponse {
+        code: 200,
+        message: SUCCESS.to_string(),
+        data: Some(data),
+    })
+}
+
+#[derive(Deserialize)]
+pub(super) struct ObjectParams {
+    #[serde(rename = "objectName")]
+    object_name: String,
+}
+
+fn file_url(object_name: &str) -> Option<CompleteUploadData> {
+    Some(CompleteUploadData {
+        file_url: format!("https://cdn.mock/attachments/{}", object_name),
+    })
+}
+
+pub(super) async fn upload_single(
+    _: AuthUser,
+    Query(params): Query<ObjectParams>,
+) -> Json<SingleUploadResponse> {
+    Json(SingleUploadResponse {
+        code: 200,
+        message: SUCCESS.to_string(),
+        data: file_url(&params.object_name),
+    })
+}
+
+pub(super) async fn upload_chunk(_: AuthUser) -> Json<ChunkUploadResponse> {
+    Json(ChunkUploadResponse {
+        code: 200,
+        message: SUCCESS.to_string(),
+    })
+}
+
+pub(super) async fn complete_upload(
+    _: AuthUser,
+    Json(request): Json<CompleteUploadRequest>,
+) -> Json<CompleteUploadResponse> {
+    Json(CompleteUploadResponse {
+        code: 200,
+        message: SUCCESS.to_string(),
+        data: file_url(&request.object_name),
+    })
+}
+
+pub(super) async fn abort_upload(_: AuthUser) -> Json<AbortUploadResponse> {
+    Json(AbortUploadResponse {
+        code: 200,
+        message: SUCCESS.to_string(),
+    })
+}
@@ -0,0 +1,656 @@
+use crate::ripple_api::api_response::{
+    ConversationChange, ConversationItem, ConversationOperation, GroupChange, GroupChangeDetail,
+    GroupMemberData, GroupMemberOperation, MessageCommandType, MessageItem, MessageItemType,
+    RelationChange, RelationOperation, RelationUser, UserGroupChange, UserGroupData,
+    UserGroupOperation, UserProfileData,
+};
+use axum::extract::ws::Message;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use prost::Message as ProstMessage;
+use ripple_proto::ripple_pb::{
+    push_message_request, send_message_req, ws_message, PushMessagePayload, PushMessageRequest,
+    PushMessageType, SendMessageReq, SingleMessageContent, WsMessage,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::UnboundedSender;
+
+pub(super) const FRIEND_FLAG: i32 = 0b0001;
+pub(super) const BLOCKED_FLAG: i32 = 0b0010;
+/// Test-only RSA key the access tokens are signed with; [`jwks`] publishes its public half.
+const SIGNING_KEY_PEM: &[u8] = include_bytes!("signing_key.pem");
+const SIGNING_KEY_MODULUS: &str = "s_hkhse1zjgf9DmUtyTI2x_yVNo6uQ5-GZNG7OE-B_T1FqkNnhlyftAEVB2EUrEiGyfOtmSNPK7hneIUwpDGRI1TjASlx5DxJd1R_Zw_auslORmneyQ1f75h61lCc-YxKkInTaNg0t9iFR4lnZ0Bb89rbrqd0bqzZv7BUHRZa4DkX8pNfeV9zDKiyKHSrt3wxfSQqqoQ6QzncXB-0Ekv6XFX95axUbcr-4p9r5rBw60z-my0UQh2oit77a8TXThnRi5PDxqoxxvGfO_pdS80uJMg3YCbSd5Oybpreyxjf8kBv3DCVlQMbFKyzaQCwPsEdLbnXhE7UNMB-cBqpLWaBw";
+pub(super) const SIGNING_KEY_ID: &str = "mock-key-1";
+const ACCESS_TOKEN_TTL_SECS: u64 = 3600;
+const ID_BASE: u64 = 1_000_000_000;
+
+/// Everything the fake gateway knows, behind one lock. Versions and ids come from a single
+/// counter so they are unique and increase across all domains.
+pub struct GatewayState {
+    pub(super) client_id: String,
+    pub(super) issuer: String,
+    data: Mutex<GatewayData>,
+}
+
+#[derive(Default)]
+pub(super) struct GatewayData {
+    next_id: u64,
+    pub profiles: HashMap<String, UserProfileData>,
+    auth_codes: HashMap<String, String>,
+    access_tokens: HashMap<String, String>,
+    refresh_tokens: HashMap<String, String>,
+    pub refresh_grants: usize,
+    pub jwks_fetches: usize,
+    pub relations: HashMap<String, BTreeMap<String, RelationUser>>,
+    pub relation_changes: HashMap<String, Vec<RelationChange>>,
+    pub conversations: HashMap<String, BTreeMap<String, ConversationItem>>,
+    pub conversation_changes: HashMap<String, Vec<ConversationChange>>,
+    pub messages: HashMap<String, Vec<MessageItem>>,
+    pub groups: BTreeMap<String, MockGroup>,
+    pub user_group_changes: HashMap<String, Vec<UserGroupChange>>,
+    sockets: HashMap<String, Vec<UnboundedSender<Message>>>,
+    pub ws_connections: HashMap<String, usize>,
+    /// How long each group member page takes to answer.
+    group_members_delay: Duration,
+    group_member_requests: usize,
+    max_group_member_requests: usize,
+    failing_paths: HashSet<String>,
+    delayed_paths: HashMap<String, Duration>,
+}
+
+pub(super) struct MockGroup {
+    pub name: String,
+    pub avatar: Option<String>,
+    pub members: Vec<String>,
+    pub changes: Vec<GroupChange>,
+}
+
+/// Response of a sync endpoint: everything after `version`, or a full sync when the client
+/// has no version yet.
+pub(super) struct SyncPage<C> {
+    pub full_sync: bool,
+    pub latest_version: Option<String>,
+    pub changes: Vec<C>,
+}
+
+pub(super) fn sync_page<C: Clone>(
+    log: &[C],
+    version: Option<&str>,
+    version_of: impl Fn(&C) -> &str,
+) -> SyncPage<C> {
+    let latest_version = log.last().map(|c| version_of(c).to_string());
+    let since = match version.and_then(|v| v.parse::<u64>().ok()) {
+        Some(since) => since,
+        None => {
+            return SyncPage {
+                full_sync: true,
+                latest_version,
+                changes: Vec::new(),
+            }
+        }
+    };
+    let changes = log
+        .iter()
+        .filter(|c| version_of(c).parse::<u64>().unwrap_or(0) > since)
+        .cloned()
+        .collect();
+    SyncPage {
+        full_sync: false,
+        latest_version: latest_version.or_else(|| version.map(str::to_string)),
+        changes,
+    }
+}
+
+/// Signs `claims` with the gateway key, or with a shared secret when `header` asks for HMAC.
+pub(super) fn sign(header: &Header, claims: &serde_json::Value) -> String {
+    let key = match header.alg {
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+            EncodingKey::from_secret(SIGNING_KEY_MODULUS.as_bytes())
+        }
+        _ => EncodingKey::from_rsa_pem(SIGNING_KEY_PEM).expect("mock signing key parses"),
+    };
+    jsonwebtoken::encode(header, claims, &key).expect("mock access token encodes")
+}
+
+/// The JWKS document with the public half of the signing key.
+pub(super) fn jwks() -> serde_json::Value {
+    serde_json::json!({
+        "keys": [{
+            "kty": "RSA",
+            "use": "sig",
+            "alg": "RS256",
+            "kid": SIGNING_KEY_ID,
+            "n": SIGNING_KEY_MODULUS,
+            "e": "AQAB",
+        }]
+    })
+}
+
+impl GatewayState {
+    pub fn new(client_id: &str, issuer: &str) -> Self {
+        GatewayState {
+            client_id: client_id.to_string(),
+            issuer: issuer.to_string(),
+            data: Mutex::new(GatewayData::default()),
+        }
+    }
+
+    pub(super) fn with<T>(&self, f: impl FnOnce(&mut GatewayData) -> T) -> T {
+        f(&mut self.data.lock().unwrap())
+    }
+
+    pub fn add_user(&self, user_id: &str, nick_name: &str) {
+        self.with(|data| {
+            data.profiles.insert(
+                user_id.to_string(),
+                UserProfileData {
+                    user_id: user_id.to_string(),
+                    nick_name: nick_name.to_string(),
+                    avatar: None,
+                },
+            );
+        });
+    }
+
+    /// Issues an authorization code for `user_id`, as the login page would after sign-in.
+    pub fn authorize(&self, user_id: &str) -> String {
+        self.with(|data| {
+            let code = format!("code-{}", data.next_id());
+            data.auth_codes.insert(code.clone(), user_id.to_string());
+            code
+        })
+    }
+
+    /// Exchanges an authorization code or refresh token for a new token pair.
+    pub(super) fn grant(
+        &self,
+        code: Option<&str>,
+        refresh_token: Option<&str>,
+    ) -> Option<(String, String)> {
+        let user_id = self.with(|data| match (code, refresh_token) {
+            (Some(code), _) => data.auth_codes.remove(code),
+            (None, Some(refresh_token)) => {
+                let user_id = data.refresh_tokens.remove(refresh_token)?;
+                data.refresh_grants += 1;
+                Some(user_id)
+            }
+            (None, None) => None,
+        })?;
+        let access_token = self.sign_access_token(&user_id);
+        self.with(|data| {
+            let refresh_token = format!("refresh-{}", data.next_id());
+            data.access_tokens
+                .insert(access_token.clone(), user_id.clone());
+            data.refresh_tokens.insert(refresh_token.clone(), user_id);
+            Some((access_token, refresh_token))
+        })
+    }
+
+    fn sign_access_token(&self, user_id: &str) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(SIGNING_KEY_ID.to_string());
+        sign(&header, &self.access_token_claims(user_id))
+    }
+
+    /// The claims of a fresh access token for `user_id`, for tests that sign their own.
+    pub(super) fn access_token_claims(&self, user_id: &str) -> serde_json::Value {
+        let now = now_secs();
+        serde_json::json!({
+            "sub": user_id,
+            "aud": self.client_id,
+            "iss": self.issuer,
+            "nbf": now,
+            "iat": now,
+            "exp": now + ACCESS_TOKEN_TTL_SECS,
+            "jti": uuid::Uuid::new_v4().to_string(),
+            "scope": ["user"],
+        })
+    }
+
+    pub(super) fn user_for_access_token(&self, access_token: &str) -> Option<String> {
+        self.with(|data| data.access_tokens.get(access_token).cloned())
+    }
+
+    pub(super) fn revoke(&self, token: &str) {
+        self.with(|data| {
+            data.access_tokens.remove(token);
+            data.refresh_tokens.remove(token);
+        });
+    }
+
+    /// Forgets every access token issued so far, so the next request is rejected with 401.
+    pub fn expire_access_tokens(&self) {
+        self.with(|data| data.access_tokens.clear());
+    }
+
+    /// Forgets every refresh token, so the next refresh fails with `invalid_grant`.
+    pub fn revoke_refresh_tokens(&self) {
+        self.with(|data| data.refresh_tokens.clear());
+    }
+
+    pub fn refresh_grants(&self) -> usize {
+        self.with(|data| data.refresh_grants)
+    }
+
+    pub fn jwks_fetches(&self) -> usize {
+        self.with(|data| data.jwks_fetches)
+    }
+
+    /// Answers every later request to `path` with 400, as for a request the server rejects.
+    pub fn fail_requests(&self, path: &str) {
+        self.with(|data| data.failing_paths.insert(path.to_string()));
+    }
+
+    pub(super) fn fails(&self, path: &str) -> bool {
+        self.with(|data| data.failing_paths.contains(path))
+    }
+
+    /// Waits `delay` before answering every later request to `path`, as a slow server would.
+    pub fn delay_requests(&self, path: &str, delay: Duration) {
+        self.with(|data| data.delayed_paths.insert(path.to_string(), delay));
+    }
+
+    pub(super) fn delay_for(&self, path: &str) -> Option<Duration> {
+        self.with(|data| data.delayed_paths.get(path).copied())
+    }
+
+    /// Delays every group member page by `delay`, so concurrent requests overlap.
+    pub fn slow_down_group_members(&self, delay: Duration) {
+        self.with(|data| data.group_members_delay = delay);
+    }
+
+    /// The most group member pages that were being answered at the same time.
+    pub fn max_concurrent_group_member_requests(&self) -> usize {
+        self.with(|data| data.max_group_member_requests)
+    }
+
+    /// Counts a group member request as in flight until the returned delay has passed.
+    pub(super) fn begin_group_member_request(&self) -> Duration {
+        self.with(|data| {
+            data.group_member_requests += 1;
+            data.max_group_member_requests = data
+                .max_group_member_requests
+                .max(data.group_member_requests);
+            data.group_members_delay
+        })
+    }
+
+    pub(super) fn end_group_member_request(&self) {
+        self.with(|data| data.group_member_requests -= 1);
+    }
+
+    /// Makes two users friends and opens a single conversation between them.
+    pub fn befriend(&self, user_a: &str, user_b: &str) -> String {
+        self.with(|data| {
+            data.add_friend(user_a, user_b);
+            data.add_friend(user_b, user_a);
+            let conversation_id = format!("c{}", data.next_id());
+            data.add_conversation(user_a, &conversation_id, Some(user_b), None);
+            data.add_conversation(user_b, &conversation_id, Some(user_a), None);
+            conversation_id
+        })
+    }
+
+    pub fn create_group(&self, name: &str, members: &[&str]) -> String {
+        self.with(|data| data.create_group(name, members))
+    }
+
+    /// Stores a direct message as if it had been sent at `send_timestamp` (unix millis).
+    pub fn add_history_message(
+        &self,
+        sender_id: &str,
+        conversation_id: &str,
+        receiver_id: &str,
+        text: &str,
+        send_timestamp: i64,
+    ) -> String {
+        self.with(|data| {
+            let message_id =
+                data.deliver_message(sender_id, conversation_id, Some(receiver_id), None, text);
+            if let Some(message) = data
+                .messages
+                .get_mut(conversation_id)
+                .and_then(|messages| messages.last_mut())
+            {
+                message.send_timestamp = send_timestamp.to_string();
+            }
+            message_id
+        })
+    }
+
+    pub(super) fn register_socket(&self, user_id: &str, sender: UnboundedSender<Message>) {
+        self.with(|data| {
+            data.sockets
+                .entry(user_id.to_string())
+                .or_default()
+                .push(sender);
+            *data.ws_connections.entry(user_id.to_string()).or_default() += 1;
+        });
+    }
+
+    /// Number of WebSocket connections `user_id` has opened since the gateway started.
+    pub fn ws_connections(&self, user_id: &str) -> usize {
+        self.with(|data| data.ws_connections.get(user_id).copied().unwrap_or(0))
+    }
+
+    /// Closes every socket of `user_id`, as a gateway restart would.
+    pub fn disconnect(&self, user_id: &str) {
+        self.with(|data| {
+            for socket in data.sockets.remove(user_id).unwrap_or_default() {
+                let _ = socket.send(Message::Close(None));
+            }
+        });
+    }
+}
+
+impl GatewayData {
+    /// Ids have a fixed width, so like the server's snowflake ids they also sort as strings.
+    pub fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        ID_BASE + self.next_id
+    }
+
+    pub fn add_friend(&mut self, owner: &str, peer_id: &str) {
+        let profile = self.profiles.get(peer_id).cloned();
+        let user = RelationUser {
+            user_id: peer_id.to_string(),
+            nick_name: profile
+                .as_ref()
+                .map(|p| p.nick_name.clone())
+                .unwrap_or_default(),
+            avatar: profile.and_then(|p| p.avatar),
+            remark_name: None,
+            relation_flags: FRIEND_FLAG,
+        };
+        self.log_relation(owner, RelationOperation::AddFriend, &user);
+        self.relations
+            .entry(owner.to_string())
+            .or_default()
+            .insert(peer_id.to_string(), user);
+    }
+
+    pub fn remove_relation(
+        &mut self,
+        owner: &str,
+        peer_id: &str,
+        operation: RelationOperation,
+    ) -> bool {
+        let removed = self
+            .relations
+            .get_mut(owner)
+            .and_then(|relations| relations.remove(peer_id));
+        match removed {
+            Some(user) => {
+                self.log_relation(owner, operation, &user);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn log_relation(&mut self, owner: &str, operation: RelationOperation, user: &RelationUser) {
+        let version = self.next_id().to_string();
+        self.relation_changes
+            .entry(owner.to_string())
+            .or_default()
+            .push(RelationChange {
+                version,
+                operation,
+                user_id: user.user_id.clone(),
+                nick_name: Some(user.nick_name.clone()),
+                avatar: user.avatar.clone(),
+                remark_name: user.remark_name.clone(),
+                relation_flags: user.relation_flags,
+            });
+    }
+
+    fn add_conversation(
+        &mut self,
+        owner: &str,
+        conversation_id: &str,
+        peer_id: Option<&str>,
+        group_id: Option<&str>,
+    ) {
+        let name = match (peer_id, group_id) {
+            (Some(peer_id), _) => self
+                .profiles
+                .get(peer_id)
+                .map(|p| p.nick_name.clone())
+                .unwrap_or_default(),
+            (None, Some(group_id)) => self
+                .groups
+                .get(group_id)
+                .map(|g| g.name.clone())
+                .unwrap_or_default(),
+            (None, None) => String::new(),
+        };
+        let version = self.next_id().to_string();
+        self.conversation_changes
+            .entry(owner.to_string())
+            .or_default()
+            .push(ConversationChange {
+                version,
+                operation: ConversationOperation::CreateConversation,
+                conversation_id: conversation_id.to_string(),
+                peer_id: peer_id.map(str::to_string),
+                group_id: group_id.map(str::to_string),
+                last_read_message_id: None,
+                name: Some(name.clone()),
+                avatar: None,
+            });
+        self.conversations
+            .entry(owner.to_string())
+            .or_default()
+            .insert(
+                conversation_id.to_string(),
+                ConversationItem {
+                    conversation_id: conversation_id.to_string(),
+                    peer_id: peer_id.map(str::to_string),
+                    group_id: group_id.map(str::to_string),
+                    last_message_id: None,
+                    last_read_message_id: None,
+                    unread_count: 0,
+                    last_message_text: None,
+                    last_message_timestamp: None,
+                    name,
+                    avatar: None,
+                },
+            );
+    }
+
+    pub fn create_group(&mut self, name: &str, members: &[&str]) -> String {
+        let group_id = self.next_id().to_string();
+        let version = self.next_id().to_string();
+        let data = members
+            .iter()
+            .map(|member| GroupChangeDetail {
+                operation: GroupMemberOperation::MemberJoin,
+                user_id: Some(member.to_string()),
+                name: self.profiles.get(*member).map(|p| p.nick_name.clone()),
+                avatar: None,
+                group_name: None,
+                group_avatar: None,
+            })
+            .collect();
+        self.groups.insert(
+            group_id.clone(),
+            MockGroup {
+                name: name.to_string(),
+                avatar: None,
+                members: members.iter().map(|m| m.to_string()).collect(),
+                changes: vec![GroupChange {
+                    group_id: group_id.clone(),
+                    version,
+                    data,
+                }],
+            },
+        );
+        let conversation_id = format!("g{}", group_id);
+        for member in members {
+            self.log_user_group(member, UserGroupOperation::Join, &group_id);
+            self.add_conversation(member, &conversation_id, None, Some(&group_id));
+        }
+        group_id
+    }
+
+    pub fn log_user_group(&mut self, owner: &str, operation: UserGroupOperation, group_id: &str) {
+        let version = self.next_id().to_string();
+        let group = self.groups.get(group_id);
+        let change = UserGroupChange {
+            version,
+            operation,
+            group_id: group_id.to_string(),
+            group_name: group.map(|g| g.name.clone()),
+            group_avatar: group.and_then(|g| g.avatar.clone()),
+        };
+        self.user_group_changes
+            .entry(owner.to_string())
+            .or_default()
+            .push(change);
+    }
+
+    pub fn user_groups(&self, user_id: &str) -> Vec<UserGroupData> {
+        self.groups
+            .iter()
+            .filter(|(_, group)| group.members.iter().any(|m| m == user_id))
+            .map(|(group_id, group)| UserGroupData {
+                group_id: group_id.clone(),
+                group_name: group.name.clone(),
+                group_avatar: group.avatar.clone(),
+            })
+            .collect()
+    }
+
+    pub fn group_members(&self, group_id: &str) -> Vec<GroupMemberData> {
+        self.groups
+            .get(group_id)
+            .map(|group| {
+                group
+                    .members
+                    .iter()
+                    .map(|member| GroupMemberData {
+                        user_id: member.clone(),
+                        name: self
+                            .profiles
+                            .get(member)
+                            .map(|p| p.nick_name.clone())
+                            .unwrap_or_default(),
+                        avatar: None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Stores a text message, updates the conversation of every participant and pushes the
+    /// message to the sockets of everyone but the sender.
+    pub fn deliver_message(
+        &mut self,
+        sender_id: &str,
+        conversation_id: &str,
+        receiver_id: Option<&str>,
+        group_id: Option<&str>,
+        text: &str,
+    ) -> String {
+        let message_id = self.next_id();
+        let send_timestamp = now_secs() as i64 * 1000;
+        self.messages
+            .entry(conversation_id.to_string())
+            .or_default()
+            .push(MessageItem {
+                conversation_id: conversation_id.to_string(),
+                message_id: message_id.to_string(),
+                sender_id: sender_id.to_string(),
+                receiver_id: receiver_id.map(str::to_string),
+                group_id: group_id.map(str::to_string),
+                send_timestamp: send_timestamp.to_string(),
+                message_type: MessageItemType::Text,
+                text: Some(text.to_string()),
+                file_url: None,
+                file_name: None,
+                command_type: MessageCommandType::Empty,
+                command_data: None,
+                raw_content: None,
+            });
+        let recipients: Vec<String> = match (receiver_id, group_id) {
+            (Some(receiver_id), _) => vec![sender_id.to_string(), receiver_id.to_string()],
+            (None, Some(group_id)) => self
+                .groups
+                .get(group_id)
+                .map(|g| g.members.clone())
+                .unwrap_or_default(),
+            (None, None) => vec![sender_id.to_string()],
+        };
+        for recipient in &recipients {
+            let Some(conversation) = self
+                .conversations
+                .get_mut(recipient)
+                .and_then(|c| c.get_mut(conversation_id))
+            else {
+                continue;
+            };
+            conversation.last_message_id = Some(message_id.to_string());
+            conversation.last_message_text = Some(text.to_string());
+            conversation.last_message_timestamp = Some(send_timestamp);
+            if recipient != sender_id {
+                conversation.unread_count += 1;
+            }
+            let unread_count = conversation.unread_count as i32;
+            if recipient == sender_id {
+                continue;
+            }
+            let push = WsMessage {
+                message_type: Some(ws_message::MessageType::PushMessageRequest(
+                    PushMessageRequest {
+                        send_user_id: sender_id.to_string(),
+                        receive_user_id: recipient.clone(),
+                        receive_device_id: String::new(),
+                        payload: Some(push_message_request::Payload::MessagePayload(
+                            PushMessagePayload {
+                                message_type: if group_id.is_some() {
+                                    PushMessageType::Group
+                                } else {
+                                    PushMessageType::Single
+                                } as i32,
+                                message_data: Some(SendMessageReq {
+                                    sender_id: sender_id.parse().unwrap_or(0),
+                                    conversation_id: conversation_id.to_string(),
+                                    receiver_id: receiver_id
+                                        .and_then(|r| r.parse().ok())
+                                        .unwrap_or(0),
+                                    group_id: group_id.and_then(|g| g.parse().ok()).unwrap_or(0),
+                                    message_id: message_id as i64,
+                                    send_timestamp,
+                                    message: Some(send_message_req::Message::SingleMessageContent(
+                                        SingleMessageContent {
+                                            text: text.to_string(),
+                                            ..Default::default()
+                                        },
+                                    )),
+                                }),
+                                unread_count,
+                            },
+                        )),
+                    },
+                )),
+            };
+            self.push(recipient, &push);
+        }
+        message_id.to_string()
+    }
+
+    pub fn push(&mut self, user_id: &str, message: &WsMessage) -> usize {
+        let frame = message.encode_to_vec();
+        let sockets = self.sockets.entry(user_id.to_string()).or_default();
+        sockets.retain(|socket| socket.send(Message::Binary(frame.clone().into())).is_ok());
+        sockets.len()
+    }
+}
+
+pub(super) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,679 @@
+use super::{eventually, MockGateway, RecordingEmitter, TestClient};
+use crate::ripple_api::api_response::{ApiEnvelope, SendMessageRequest};
+use crate::ripple_api::auth_token_parser::AuthTokenParser;
+use crate::ripple_syncer::data_sync_manager::RelationSyncResult;
+use crate::ripple_syncer::ui_event::UIEvent;
+use crate::store_engine::store_engine::RippleStorage;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const ALICE: &str = "1001";
+const BOB: &str = "1002";
+const CAROL: &str = "1003";
+
+async fn gateway_with_users() -> MockGateway {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.state().add_user(ALICE, "Alice");
+    gateway.state().add_user(BOB, "Bob");
+    gateway.state().add_user(CAROL, "Carol");
+    gateway
+}
+
+#[tokio::test]
+async fn login_stores_validated_token() {
+    let gateway = gateway_with_users().await;
+    let alice = TestClient::login(&gateway, ALICE).await;
+
+    let claims = AuthTokenParser::decode_jwt_payload(&alice.access_token().await).unwrap();
+    assert_eq!(claims.get_sub(), ALICE);
+    let profile = alice.api.get_user_profile().await.unwrap();
+    assert_eq!(profile.into_result().unwrap().data.nick_name, "Alice");
+}
+
+#[tokio::test]
+async fn initial_sync_fills_store_and_message_gap() {
+    let gateway = gateway_with_users().await;
+    let conversation_id = gateway.state().befriend(ALICE, BOB);
+    let group_id = gateway.state().create_group("Lunch", &[ALICE, BOB, CAROL]);
+    let alice = TestClient::login(&gateway, ALICE).await;
+    let bob = TestClient::login(&gateway, BOB).await;
+    for text in ["hi", "are you there?"] {
+        bob.api
+            .send_message(SendMessageRequest {
+                sender_id: BOB.to_string(),
+                conversation_id: conversation_id.clone(),
+                receiver_id: Some(ALICE.to_string()),
+                group_id: None,
+                text_content: Some(text.to_string()),
+                file_url: None,
+                file_name: None,
+            })
+            .await
+            .unwrap()
+            .into_result()
+            .unwrap();
+    }
+
+    alice.data_sync.init().await.unwrap();
+    alice
+        .data_sync
+        .sync_all_group_members(&group_id)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        alice
+            .store
+            .get_user_profile()
+            .await
+            .unwrap()
+            .unwrap()
+            .nick_name,
+        "Alice"
+    );
+    let relations = alice.store.get_all_relations().await.unwrap();
+    assert_eq!(relations.len(), 1);
+    assert_eq!(relations[0].user_id, BOB);
+    assert_eq!(alice.store.get_all_conversations().await.unwrap().len(), 2);
+    assert_eq!(alice.store.get_all_user_groups().await.unwrap().len(), 1);
+    assert_eq!(
+        alice
+            .store
+            .get_all_group_members(&group_id)
+            .await
+            .unwrap()
+            .len(),
+        3
+    );
+    let messages = alice
+        .data_sync
+        .read_latest_messages(conversation_id, 50, String::new())
+        .await
+        .unwrap()
+        .messages;
+    let texts: Vec<_> = messages.iter().filter_map(|m| m.text.as_deref()).collect();
+    assert_eq!(texts, ["hi", "are you there?"]);
+}
+
+/// Sync lifecycle events as (event, domain) pairs, in the order they were emitted.
+fn sync_phases(emitter: &RecordingEmitter) -> Vec<(String, String)> {
+    emitter
+        .events()
+        .into_iter()
+        .filter(|(event, _)| event.starts_with("sync-"))
+        .map(|(event, payload)| (event, payload["domain"].as_str().unwrap().to_string()))
+        .collect()
+}
+
+fn phases(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+    expected
+        .iter()
+        .map(|(event, domain)| (event.to_string(), domain.to_string()))
+        .collect()
+}
+
+#[tokio::test]
+async fn init_reports_each_domain_in_order() {
+    let gateway = gateway_with_users().await;
+    gateway.state().befriend(ALICE, BOB);
+    let alice = TestClient::login(&gateway, ALICE).await;
+    let emitter = RecordingEmitter::default();
+
+    alice.data_sync.init_with_events(&emitter).await.unwrap();
+    assert_eq!(
+        sync_phases(&emitter),
+        phases(&[
+            ("sync-started", "relations"),
+            ("sync-completed", "relations"),
+            ("sync-started", "conversations"),
+            ("sync-completed", "conversations"),
+            ("sync-started", "user-groups"),
+            ("sync-completed", "user-groups"),
+        ])
+    );
+    let timestamps = alice.data_sync.get_sync_timestamps().await.unwrap();
+    for completed in emitter.payloads(UIEvent::SyncCompleted) {
+        let domain = completed["domain"].as_str().unwrap();
+        assert_eq!(completed["timestamp"], timestamps[domain]);
+    }
+}
+
+#[tokio::test]
+async fn failed_domain_stops_init_without_a_timestamp() {
+    let gateway = gateway_with_users().await;
+    gateway.state().fail_requests("/api/users/me/conversations");
+    let alice = TestClient::login(&gateway, ALICE).await;
+    let emitter = RecordingEmitter::default();
+
+    assert!(alice.data_sync.init_with_events(&emitter).await.is_err());
+    assert_eq!(
+        sync_phases(&emitter),
+        phases(&[
+            ("sync-started", "relations"),
+            ("sync-completed", "relations"),
+            ("sync-started", "conversations"),
+            ("sync-failed", "conversations"),
+        ])
+    );
+    let timestamps = alice.data_sync.get_sync_timestamps().await.unwrap();
+    let mut domains: Vec<_> = timestamps.keys().map(String::as_str).collect();
+    domains.sort();
+    assert_eq!(domains, ["relations"]);
+}
+
+#[tokio::test]
+async fn failed_domain_keeps_its_last_successful_sync_time() {
+    let gateway = gateway_with_users().await;
+    gateway.state().befriend(ALICE, BOB);
+    let alice = TestClient::login(&gateway, ALICE).await;
+    alice.data_sync.init().await.unwrap();
+    let first = alice.data_sync.get_sync_timestamps().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    gateway
+        .state()
+        .fail_requests("/api/users/me/conversations/sync");
+
+    assert!(alice.data_sync.init().await.is_err());
+    let second = alice.data_sync.get_sync_timestamps().await.unwrap();
+    assert!(second["relations"] > first["relations"]);
+    assert_eq!(second["conversations"], first["conversations"]);
+    assert_eq!(second["user-groups"], first["user-groups"]);
+}
+
+/// Signs in Alice with `groups` groups synced, none of them with members stored yet.
+async fn alice_in_groups(gateway: &MockGateway, groups: usize) -> TestClient {
+    for i in 0..groups {
+        gateway
+            .state()
+            .create_group(&format!("Group {}", i), &[ALICE, BOB]);
+    }
+    let alice = TestClient::login(gateway, ALICE).await;
+    alice.data_sync.init().await.unwrap();
+    alice
+}
+
+#[tokio::test]
+async fn group_member_sync_stays_within_concurrency() {
+    let gateway = gateway_with_users().await;
+    let alice = alice_in_groups(&gateway, 6).await;
+    gateway
+        .state()
+        .slow_down_group_members(Duration::from_millis(100));
+    let emitter = RecordingEmitter::default();
+
+    alice
+        .data_sync
+        .sync_all_groups_members(2, &emitter)
+        .await
+        .unwrap();
+    assert_eq!(gateway.state().max_concurrent_group_member_requests(), 2);
+    assert_eq!(emitter.payloads(UIEvent::SyncProgress).len(), 6);
+    assert_eq!(emitter.payloads(UIEvent::SyncCompleted).len(), 1);
+    for group in alice.store.get_all_user_groups().await.unwrap() {
+        assert!(alice
+            .store
+            .exist_group_members(&group.group_id)
+            .await
+            .unwrap());
+    }
+}
+
+#[tokio::test]
+async fn group_member_sync_with_zero_concurrency_syncs_one_group_at_a_time() {
+    let gateway = gateway_with_users().await;
+    let alice = alice_in_groups(&gateway, 3).await;
+    gateway
+        .state()
+        .slow_down_group_members(Duration::from_millis(50));
+    let emitter = RecordingEmitter::default();
+
+    tokio::time::timeout(
+        Duration::from_secs(10),
+        alice.data_sync.sync_all_groups_members(0, &emitter),
+    )
+    .await
+    .expect("group member sync stalled")
+    .unwrap();
+    assert_eq!(gateway.state().max_concurrent_group_member_requests(), 1);
+    assert_eq!(emitter.payloads(UIEvent::SyncCompleted).len(), 1);
+}
+
+#[tokio::test]
+async fn relation_sync_after_init_is_incremental() {
+    let gateway = gateway_with_users().await;
+    gateway.state().befriend(ALICE, BOB);
+    let alice = TestClient::login(&gateway, ALICE).await;
+    alice.data_sync.init().await.unwrap();
+
+    gateway.state().befriend(ALICE, CAROL);
+    let result = alice.data_sync.process_relations_sync(true).await.unwrap();
+
+    match result {
+        Some(RelationSyncResult::IncrementalSync { insert, .. }) => {
+            assert_eq!(insert.len(), 1);
+            assert_eq!(insert[0].user_id, CAROL);
+        }
+        other => panic!("expected an incremental sync, got {:?}", other),
+    }
+    assert_eq!(alice.store.get_all_relations().await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn user_groups_without_last_version_are_not_stored() {
+    let gateway = gateway_with_users().await;
+    gateway.state().create_group("Lunch", &[ALICE, BOB]);
+    gateway.state().with(|data| data.user_group_changes.clear());
+    let alice = TestClient::login(&gateway, ALICE).await;
+
+    alice.data_sync.sync_all_user_groups().await.unwrap();
+
+    assert!(alice.store.get_all_user_groups().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn expired_access_token_is_refreshed_once() {
+    let gateway = gateway_with_users().await;
+    let alice = TestClient::login(&gateway, ALICE).await;
+    let stale_token = alice.access_token().await;
+
+    gateway.state().expire_access_tokens();
+    let (first, second) = tokio::join!(alice.api.get_user_profile(), alice.api.get_user_profile());
+
+    assert!(first.is_ok() && second.is_ok());
+    assert_eq!(gateway.state().refresh_grants(), 1);
+    assert_ne!(alice.access_token().await, stale_token);
+}
+
+#[tokio::test]
+async fn rejected_refresh_token_ends_session() {
+    let gateway = gateway_with_users().await;
+    let alice = TestClient::login(&gateway, ALICE).await;
+    let expired = Arc::new(AtomicBool::new(false));
+    let flag = expired.clone();
+    alice
+        .api
+        .token_manager()
+        .set_session_expired_handler(move || flag.store(true, Ordering::SeqCst));
+
+    gateway.state().expire_access_tokens();
+    gateway.state().revoke_refresh_tokens();
+
+    assert!(alice.api.get_user_profile().await.is_err());
+    assert!(expired.load(Ordering::SeqCst));
+    assert!(alice.store.tokens().get_token().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn logout_revokes_tokens_on_the_gateway() {
+    let gateway = gateway_with_users().await;
+    let alice = TestClient::login(&gateway, ALICE).await;
+
+    alice.api.oauth_revoke_token().await.unwrap();
+
+    assert!(gateway
+        .state()
+        .user_for_access_token(&alice.access_token().await)
+        .is_none());
+    assert!(alice.config.oauth2_revocation_url.is_some());
+}
+
+mod token_validation {
+    use super::*;
+    use crate::mock_gateway::state::{now_secs, sign, SIGNING_KEY_ID};
+    use crate::ripple_api::token_validator::TokenValidator;
+    use crate::store_engine::store_engine::MemoryStore;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use jsonwebtoken::{Algorithm, Header};
+    use oauth2::reqwest;
+
+    fn validator(gateway: &MockGateway) -> TokenValidator<MemoryStore> {
+        TokenValidator::new(
+            &gateway.app_config(),
+            reqwest::Client::new(),
+            MemoryStore::new(),
+        )
+    }
+
+    fn header(alg: Algorithm, kid: &str) -> Header {
+        let mut header = Header::new(alg);
+        header.kid = Some(kid.to_string());
+        header
+    }
+
+    fn claims_with(
+        gateway: &MockGateway,
+        field: &str,
+        value: serde_json::Value,
+    ) -> serde_json::Value {
+        let mut claims = gateway.state().access_token_claims(ALICE);
+        claims[field] = value;
+        claims
+    }
+
+    #[tokio::test]
+    async fn accepts_token_signed_with_published_key() {
+        let gateway = gateway_with_users().await;
+        let token = sign(
+            &header(Algorithm::RS256, SIGNING_KEY_ID),
+            &gateway.state().access_token_claims(ALICE),
+        );
+
+        let claims = validator(&gateway).validate(&token).await.unwrap();
+        assert_eq!(claims.get_sub(), ALICE);
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_key_id() {
+        let gateway = gateway_with_users().await;
+        let token = sign(
+            &header(Algorithm::RS256, "rotated-away"),
+            &gateway.state().access_token_claims(ALICE),
+        );
+
+        assert!(validator(&gateway).validate(&token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn unknown_key_id_refetches_jwks_once_per_interval() {
+        let gateway = gateway_with_users().await;
+        let claims = gateway.state().access_token_claims(ALICE);
+        let unknown = sign(&header(Algorithm::RS256, "rotated-away"), &claims);
+        let known = sign(&header(Algorithm::RS256, SIGNING_KEY_ID), &claims);
+        let validator = validator(&gateway);
+
+        for _ in 0..3 {
+            assert!(validator.validate(&unknown).await.is_err());
+        }
+        validator.validate(&known).await.unwrap();
+        assert_eq!(gateway.state().jwks_fetches(), 1);
+    }
+
+    #[tokio::test]
+    async fn rejects_algorithm_the_key_is_not_published_for() {
+        let gateway = gateway_with_users().await;
+        let claims = gateway.state().access_token_claims(ALICE);
+        // HMAC keyed with the public key material, the classic algorithm confusion
+        let hmac = sign(&header(Algorithm::HS256, SIGNING_KEY_ID), &claims);
+        // A valid RS256 signature under a header that claims ES256
+        let rs256 = sign(&header(Algorithm::RS256, SIGNING_KEY_ID), &claims);
+        let es256_header = URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&header(Algorithm::ES256, SIGNING_KEY_ID)).unwrap());
+        let relabelled = format!("{}.{}", es256_header, rs256.split_once('.').unwrap().1);
+
+        let validator = validator(&gateway);
+        for token in [hmac, relabelled] {
+            assert!(validator.validate(&token).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_audience_or_issuer() {
+        let gateway = gateway_with_users().await;
+        let validator = validator(&gateway);
+        let header = header(Algorithm::RS256, SIGNING_KEY_ID);
+
+        for (field, value) in [("aud", "someone-else"), ("iss", "http://evil.example")] {
+            let token = sign(&header, &claims_with(&gateway, field, value.into()));
+            assert!(validator.validate(&token).await.is_err(), "{}", field);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_expired_token() {
+        let gateway = gateway_with_users().await;
+        let expired = claims_with(&gateway, "exp", (now_secs() - 3600).into());
+        let token = sign(&header(Algorithm::RS256, SIGNING_KEY_ID), &expired);
+
+        assert!(validator(&gateway).validate(&token).await.is_err());
+    }
+}
+
+mod ws {
+    use super::*;
+    use crate::ripple_syncer::DataSyncManager;
+    use crate::ripple_ws::connection_stats::ConnectionStats;
+    use crate::ripple_ws::syncer_control::SyncerControl;
+    use crate::ripple_ws::ws_message_handler::RippleWsMsgHandler;
+    use crate::ripple_ws::ws_token_source::RippleWsTokenSource;
+    use crate::ripple_ws::RippleWsManager;
+    use crate::store_engine::store_engine::MemoryStore;
+    use futures_channel::mpsc::UnboundedSender;
+    use prost::Message as ProstMessage;
+    use ripple_proto::ripple_pb::{
+        push_message_request, send_message_req, ws_message, PushMessageRequest, WsMessage,
+    };
+    use tokio::sync::mpsc;
+    use tokio_tungstenite::tungstenite::Message;
+    use uuid::Uuid;
+
+    /// Forwards every push the manager receives to the test.
+    #[derive(Clone)]
+    struct RecordingHandler {
+        pushes: mpsc::UnboundedSender<PushMessageRequest>,
+    }
+
+    impl RippleWsMsgHandler for RecordingHandler {
+        async fn handle_message(
+            &self,
+            _send_tx: &UnboundedSender<Message>,
+            message: Message,
+        ) -> anyhow::Result<()> {
+            if let Message::Binary(frame) = message {
+                if let Some(ws_message::MessageType::PushMessageRequest(push)) =
+                    WsMessage::decode(frame)?.message_type
+                {
+                    let _ = self.pushes.send(push);
+                }
+            }
+            Ok(())
+        }
+
+        async fn notify_connect(&self) {}
+
+        async fn notify_disconnect(&self) {}
+
+        async fn notify_ws_stop(&self, _err_msg: String) {}
+    }
+
+    impl SyncerControl for RecordingHandler {
+        async fn start_syncer(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn stop_syncer(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Fails on every push and reports why the connection task stopped.
+    #[derive(Clone)]
+    struct RejectingHandler {
+        stops: mpsc::UnboundedSender<String>,
+    }
+
+    impl RippleWsMsgHandler for RejectingHandler {
+        async fn handle_message(
+            &self,
+            _send_tx: &UnboundedSender<Message>,
+            message: Message,
+        ) -> anyhow::Result<()> {
+            if let Message::Binary(frame) = message {
+                if let Some(ws_message::MessageType::PushMessageRequest(_)) =
+                    WsMessage::decode(frame)?.message_type
+                {
+                    anyhow::bail!("push rejected");
+                }
+            }
+            Ok(())
+        }
+
+        async fn notify_connect(&self) {}
+
+        async fn notify_disconnect(&self) {}
+
+        async fn notify_ws_stop(&self, err_msg: String) {
+            let _ = self.stops.send(err_msg);
+        }
+    }
+
+    impl SyncerControl for RejectingHandler {
+        async fn start_syncer(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn stop_syncer(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Fails to hand out credentials while `offline` is set.
+    #[derive(Clone)]
+    struct FlakyTokenSource {
+        inner: DataSyncManager<MemoryStore>,
+        offline: Arc<AtomicBool>,
+    }
+
+    impl RippleWsTokenSource for FlakyTokenSource {
+        async fn access_token(&self) -> anyhow::Result<String> {
+            if self.offline.load(Ordering::SeqCst) {
+                anyhow::bail!("token unavailable");
+            }
+            self.inner.access_token().await
+        }
+
+        async fn device_id(&self) -> anyhow::Result<Uuid> {
+            self.inner.device_id().await
+        }
+    }
+
+    fn pushed_text(push: &PushMessageRequest) -> Option<&str> {
+        match push.payload.as_ref()? {
+            push_message_request::Payload::MessagePayload(payload) => {
+                match payload.message_data.as_ref()?.message.as_ref()? {
+                    send_message_req::Message::SingleMessageContent(content) => Some(&content.text),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    async fn send_text(client: &TestClient, conversation_id: &str, receiver: &str, text: &str) {
+        client
+            .api
+            .send_message(SendMessageRequest {
+                sender_id: ALICE.to_string(),
+                conversation_id: conversation_id.to_string(),
+                receiver_id: Some(receiver.to_string()),
+                group_id: None,
+                text_content: Some(text.to_string()),
+                file_url: None,
+                file_name: None,
+            })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn ws_delivers_pushes_and_reconnects() {
+        let gateway = gateway_with_users().await;
+        let conversation_id = gateway.state().befriend(ALICE, BOB);
+        let alice = TestClient::login(&gateway, ALICE).await;
+        let bob = TestClient::login(&gateway, BOB).await;
+        bob.data_sync.init().await.unwrap();
+        let (pushes_tx, mut pushes) = mpsc::unbounded_channel();
+        let manager = RippleWsManager::new(
+            RecordingHandler { pushes: pushes_tx },
+            bob.data_sync.clone(),
+            Arc::new(ConnectionStats::default()),
+        );
+        manager.start(&bob.config.ws_gateway_url).await.unwrap();
+        eventually(|| gateway.state().ws_connections(BOB) == 1).await;
+
+        send_text(&alice, &conversation_id, BOB, "before restart").await;
+        let push = pushes.recv().await.unwrap();
+        assert_eq!(pushed_text(&push), Some("before restart"));
+
+        gateway.state().disconnect(BOB);
+        eventually(|| gateway.state().ws_connections(BOB) == 2).await;
+        send_text(&alice, &conversation_id, BOB, "after restart").await;
+        let push = pushes.recv().await.unwrap();
+        assert_eq!(pushed_text(&push), Some("after restart"));
+
+        manager.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ws_start_can_be_retried_after_token_failure() {
+        let gateway = gateway_with_users().await;
+        let bob = TestClient::login(&gateway, BOB).await;
+        bob.data_sync.init().await.unwrap();
+        let offline = Arc::new(AtomicBool::new(true));
+        let (pushes_tx, _pushes) = mpsc::unbounded_channel();
+        let manager = RippleWsManager::new(
+            RecordingHandler { pushes: pushes_tx },
+            FlakyTokenSource {
+                inner: bob.data_sync.clone(),
+                offline: offline.clone(),
+            },
+            Arc::new(ConnectionStats::default()),
+        );
+        assert!(manager.start(&bob.config.ws_gateway_url).await.is_err());
+
+        offline.store(false, Ordering::SeqCst);
+        manager.start(&bob.config.ws_gateway_url).await.unwrap();
+        eventually(|| gateway.state().ws_connections(BOB) == 1).await;
+
+        manager.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ws_reconnect_uses_refreshed_token() {
+        let gateway = gateway_with_users().await;
+        let bob = TestClient::login(&gateway, BOB).await;
+        bob.data_sync.init().await.unwrap();
+        let (pushes_tx, _pushes) = mpsc::unbounded_channel();
+        let manager = RippleWsManager::new(
+            RecordingHandler { pushes: pushes_tx },
+            bob.data_sync.clone(),
+            Arc::new(ConnectionStats::default()),
+        );
+        manager.start(&bob.config.ws_gateway_url).await.unwrap();
+        eventually(|| gateway.state().ws_connections(BOB) == 1).await;
+
+        // The upgrade is rejected until the client refreshes, which a REST call triggers
+        gateway.state().expire_access_tokens();
+        gateway.state().disconnect(BOB);
+        bob.api.get_user_profile().await.unwrap();
+        eventually(|| gateway.state().ws_connections(BOB) == 2).await;
+
+        manager.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ws_start_can_be_retried_after_handler_error() {
+        let gateway = gateway_with_users().await;
+        let conversation_id = gateway.state().befriend(ALICE, BOB);
+        let alice = TestClient::login(&gateway, ALICE).await;
+        let bob = TestClient::login(&gateway, BOB).await;
+        bob.data_sync.init().await.unwrap();
+        let (stops_tx, mut stops) = mpsc::unbounded_channel();
+        let manager = RippleWsManager::new(
+            RejectingHandler { stops: stops_tx },
+            bob.data_sync.clone(),
+            Arc::new(ConnectionStats::default()),
+        );
+        manager.start(&bob.config.ws_gateway_url).await.unwrap();
+        eventually(|| gateway.state().ws_connections(BOB) == 1).await;
+
+        send_text(&alice, &conversation_id, BOB, "rejected").await;
+        assert_eq!(stops.recv().await.unwrap(), "push rejected");
+
+        manager.start(&bob.config.ws_gateway_url).await.unwrap();
+        eventually(|| gateway.state().ws_connections(BOB) == 2).await;
+
+        manager.stop().await.unwrap();
+    }
+}
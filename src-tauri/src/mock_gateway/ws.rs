@@ -0,0 +1,66 @@
+use crate::mock_gateway::rest::AuthUser;
+use crate::mock_gateway::state::GatewayState;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures_util::{SinkExt, StreamExt};
+use prost::Message as ProstMessage;
+use ripple_proto::ripple_pb::{ws_message, HeartbeatResponse, WsMessage};
+use std::sync::Arc;
+
+const HEADER_RIPPLE_DEVICE_ID: &str = "Ripple-Device-ID";
+
+/// WebSocket gateway: authenticates the upgrade with the bearer token, answers heartbeats and
+/// forwards whatever the gateway state pushes to the user.
+pub(super) async fn upgrade(
+    State(state): State<Arc<GatewayState>>,
+    AuthUser(user_id): AuthUser,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Response {
+    if !headers.contains_key(HEADER_RIPPLE_DEVICE_ID) {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    ws.on_upgrade(move |socket| serve(state, user_id, socket))
+}
+
+async fn serve(state: Arc<GatewayState>, user_id: String, socket: WebSocket) {
+    let (mut sink, mut stream) = socket.split();
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<Message>();
+    state.register_socket(&user_id, sender.clone());
+    let writer = tokio::spawn(async move {
+        while let Some(message) = receiver.recv().await {
+            let is_close = matches!(message, Message::Close(_));
+            if sink.send(message).await.is_err() || is_close {
+                break;
+            }
+        }
+    });
+    while let Some(Ok(message)) = stream.next().await {
+        let Message::Binary(frame) = message else {
+            continue;
+        };
+        let Ok(ws) = WsMessage::decode(frame) else {
+            continue;
+        };
+        if let Some(ws_message::MessageType::HeartbeatRequest(heartbeat)) = ws.message_type {
+            let response = WsMessage {
+                message_type: Some(ws_message::MessageType::HeartbeatResponse(
+                    HeartbeatResponse {
+                        user_id: heartbeat.user_id,
+                        client_timestamp: heartbeat.timestamp,
+                        server_timestamp: heartbeat.timestamp,
+                    },
+                )),
+            };
+            if sender
+                .send(Message::Binary(response.encode_to_vec().into()))
+                .is_err()
+            {
+                break;
+            }
+        }
+    }
+    writer.abort();
+}
@@ -1,6 +1,64 @@
+use crate::errors::{ApiError, ConversionError};
 use crate::ripple_syncer::incremental_operations::{Categorized, OpCategory};
-use ripple_proto::ripple_pb::{push_message_request, send_message_req, PushMessageRequest};
-use serde::{Deserialize, Serialize};
+use prost::Message as ProstMessage;
+use ripple_proto::ripple_pb::{
+    push_message_request, send_message_req, ws_message, PushMessageRequest, WsMessage,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// The `code`/`message` envelope every gateway response carries next to its data.
+pub trait ApiEnvelope: Sized {
+    fn code(&self) -> i32;
+    fn message(&self) -> &str;
+
+    /// Passes the response through when `code` is 200, otherwise turns it into an [`ApiError`].
+    fn into_result(self) -> Result<Self, ApiError> {
+        if self.code() == 200 {
+            Ok(self)
+        } else {
+            Err(ApiError::from_body_code(self.code(), self.message()))
+        }
+    }
+}
+
+macro_rules! impl_api_envelope {
+    ($($response:ty),* $(,)?) => {
+        $(
+            impl ApiEnvelope for $response {
+                fn code(&self) -> i32 {
+                    self.code
+                }
+
+                fn message(&self) -> &str {
+                    &self.message
+                }
+            }
+        )*
+    };
+}
+
+impl_api_envelope!(
+    UserProfileResponse,
+    CommonResponse,
+    UploadImageResponse,
+    RelationsPageResponse,
+    RelationsSyncResponse,
+    ConversationsResponse,
+    ConversationSummariesResponse,
+    ConversationSyncResponse,
+    MessageResponse,
+    ReadMessagesResponse,
+    CreateGroupResponse,
+    GetGroupMembersResponse,
+    GetUserGroupsResponse,
+    UserGroupSyncResponse,
+    GroupSyncResponse,
+    InitiateUploadResponse,
+    SingleUploadResponse,
+    ChunkUploadResponse,
+    CompleteUploadResponse,
+    AbortUploadResponse,
+);
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct UserProfileData {
@@ -417,7 +475,8 @@ pub struct MessageResponse {
 pub enum MessageItemType {
     Text = 1,
     Command = 2,
-    Unknown,
+    /// Type introduced by a newer server; the original payload is kept in `raw_content`
+    Unknown = -1,
 }
 
 impl From<i32> for MessageItemType {
@@ -443,7 +502,7 @@ pub enum MessageCommandType {
     GroupJoin = 1,
     GroupQuit = 2,
     InfoUpdate = 3,
-    Unknown,
+    Unknown = -1,
 }
 
 impl From<i32> for MessageCommandType {
@@ -490,20 +549,97 @@ pub struct MessageItem {
     pub command_type: MessageCommandType,
     #[serde(rename = "commandData")]
     pub command_data: Option<String>,
+    /// Original payload of a message this client could not fully decode
+    #[serde(skip)]
+    pub raw_content: Option<RawMessageContent>,
 }
 
-impl From<&PushMessageRequest> for MessageItem {
-    fn from(req: &PushMessageRequest) -> Self {
+impl MessageItem {
+    /// Whether the message uses a type or command this client does not understand.
+    pub fn is_unsupported(&self) -> bool {
+        matches!(self.message_type, MessageItemType::Unknown)
+            || matches!(self.command_type, MessageCommandType::Unknown)
+    }
+
+    /// Parses a message returned by the messages API, keeping the JSON object as raw content
+    /// when the message is unsupported.
+    pub fn from_json(value: serde_json::Value) -> serde_json::Result<Self> {
+        let mut item: MessageItem = serde_json::from_value(value.clone())?;
+        if item.is_unsupported() {
+            item.raw_content = Some(RawMessageContent::Json(serde_json::to_vec(&value)?));
+        }
+        Ok(item)
+    }
+}
+
+/// Original encoding of an unsupported message, stored so the message can be decoded again
+/// once a newer client understands its type.
+#[derive(Clone, Debug)]
+pub enum RawMessageContent {
+    /// Binary `WsMessage` frame the message was pushed in
+    WsFrame(Vec<u8>),
+    /// JSON object returned by the messages API
+    Json(Vec<u8>),
+}
+
+impl RawMessageContent {
+    pub fn format(&self) -> &'static str {
+        match self {
+            RawMessageContent::WsFrame(_) => "ws-frame",
+            RawMessageContent::Json(_) => "json",
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            RawMessageContent::WsFrame(bytes) | RawMessageContent::Json(bytes) => bytes,
+        }
+    }
+
+    pub fn from_parts(format: &str, bytes: Vec<u8>) -> Option<Self> {
+        match format {
+            "ws-frame" => Some(RawMessageContent::WsFrame(bytes)),
+            "json" => Some(RawMessageContent::Json(bytes)),
+            _ => None,
+        }
+    }
+
+    /// Decodes the raw content again with the message types known to this client.
+    pub fn decode(&self) -> anyhow::Result<MessageItem> {
+        let mut item = match self {
+            RawMessageContent::WsFrame(bytes) => {
+                match WsMessage::decode(bytes.as_slice())?.message_type {
+                    Some(ws_message::MessageType::PushMessageRequest(push_req)) => {
+                        MessageItem::try_from(&push_req)?
+                    }
+                    _ => anyhow::bail!("WsMessage frame does not contain a push message"),
+                }
+            }
+            RawMessageContent::Json(bytes) => {
+                MessageItem::from_json(serde_json::from_slice(bytes)?)?
+            }
+        };
+        if item.is_unsupported() {
+            item.raw_content = Some(self.clone());
+        }
+        Ok(item)
+    }
+}
+
+impl TryFrom<&PushMessageRequest> for MessageItem {
+    type Error = ConversionError;
+
+    fn try_from(req: &PushMessageRequest) -> Result<Self, Self::Error> {
         match req.payload.as_ref() {
             Some(push_message_request::Payload::MessagePayload(msg_payload)) => {
                 let message_data = msg_payload
                     .message_data
                     .as_ref()
-                    .expect("MessagePayload must have message_data");
+                    .ok_or(ConversionError::MissingMessageData)?;
 
                 match &message_data.message {
                     Some(send_message_req::Message::SingleMessageContent(msg_content)) => {
-                        MessageItem {
+                        Ok(MessageItem {
                             conversation_id: message_data.conversation_id.clone(),
                             message_id: message_data.message_id.to_string(),
                             sender_id: message_data.sender_id.to_string(),
@@ -518,10 +654,11 @@ impl From<&PushMessageRequest> for MessageItem {
                             file_name: Some(msg_content.file_name.clone()),
                             command_type: MessageCommandType::Empty,
                             command_data: None,
-                        }
+                            raw_content: None,
+                        })
                     }
                     Some(send_message_req::Message::GroupCommandMessageContent(cmd_content)) => {
-                        MessageItem {
+                        Ok(MessageItem {
                             conversation_id: message_data.conversation_id.clone(),
                             message_id: message_data.message_id.to_string(),
                             sender_id: message_data.sender_id.to_string(),
@@ -536,21 +673,50 @@ impl From<&PushMessageRequest> for MessageItem {
                             file_name: None,
                             command_type: cmd_content.command_type.into(),
                             command_data: Some(cmd_content.text.clone()),
-                        }
+                            raw_content: None,
+                        })
                     }
-                    None => panic!("SendMessageReq must have a message variant"),
+                    // Content variant added by a newer server, which prost skips when decoding
+                    None => Ok(MessageItem {
+                        conversation_id: message_data.conversation_id.clone(),
+                        message_id: message_data.message_id.to_string(),
+                        sender_id: message_data.sender_id.to_string(),
+                        receiver_id: (message_data.receiver_id != 0)
+                            .then(|| message_data.receiver_id.to_string()),
+                        group_id: (message_data.group_id != 0)
+                            .then(|| message_data.group_id.to_string()),
+                        send_timestamp: message_data.send_timestamp.to_string(),
+                        message_type: MessageItemType::Unknown,
+                        text: None,
+                        file_url: None,
+                        file_name: None,
+                        command_type: MessageCommandType::Empty,
+                        command_data: None,
+                        raw_content: None,
+                    }),
                 }
             }
-            _ => panic!("PushMessageRequest must have MessagePayload for message storage"),
+            _ => Err(ConversionError::MissingPayload),
         }
     }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct ReadMessagesData {
+    #[serde(deserialize_with = "deserialize_messages")]
     pub messages: Vec<MessageItem>,
 }
 
+fn deserialize_messages<'de, D>(deserializer: D) -> Result<Vec<MessageItem>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<serde_json::Value>::deserialize(deserializer)?
+        .into_iter()
+        .map(|value| MessageItem::from_json(value).map_err(serde::de::Error::custom))
+        .collect()
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct ReadMessagesResponse {
     pub code: i32,
@@ -906,3 +1072,115 @@ pub struct AbortUploadResponse {
     pub code: i32,
     pub message: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ripple_proto::ripple_pb::{PushEventPayload, PushMessagePayload, SendMessageReq};
+
+    fn push_request(message: Option<send_message_req::Message>) -> PushMessageRequest {
+        PushMessageRequest {
+            payload: Some(push_message_request::Payload::MessagePayload(
+                PushMessagePayload {
+                    message_data: Some(SendMessageReq {
+                        conversation_id: "c1".to_string(),
+                        message_id: 7,
+                        sender_id: 1,
+                        group_id: 0,
+                        message,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn message_item_from_text_push() {
+        let req = push_request(Some(send_message_req::Message::SingleMessageContent(
+            ripple_proto::ripple_pb::SingleMessageContent {
+                text: "hi".to_string(),
+                ..Default::default()
+            },
+        )));
+        let item = MessageItem::try_from(&req).unwrap();
+        assert_eq!(item.message_type, MessageItemType::Text);
+        assert_eq!(item.message_id, "7");
+        assert_eq!(item.group_id, None);
+    }
+
+    #[test]
+    fn message_item_keeps_unknown_content() {
+        let req = push_request(None);
+        let item = MessageItem::try_from(&req).unwrap();
+        assert_eq!(item.message_type, MessageItemType::Unknown);
+        assert!(item.is_unsupported());
+    }
+
+    #[test]
+    fn unknown_json_message_keeps_raw_content() {
+        let value = serde_json::json!({
+            "conversationId": "c1",
+            "messageId": "7",
+            "senderId": "1",
+            "sendTimestamp": "1700000000000",
+            "messageType": 9,
+            "commandType": 0,
+            "poll": {"question": "lunch?"}
+        });
+        let item = MessageItem::from_json(value).unwrap();
+        assert_eq!(item.message_type, MessageItemType::Unknown);
+        let raw = item.raw_content.expect("raw content kept");
+        assert_eq!(raw.format(), "json");
+        let decoded = raw.decode().unwrap();
+        assert!(decoded.is_unsupported());
+        assert!(decoded.raw_content.is_some());
+    }
+
+    #[test]
+    fn raw_ws_frame_decodes_known_message() {
+        let req = push_request(Some(send_message_req::Message::SingleMessageContent(
+            ripple_proto::ripple_pb::SingleMessageContent {
+                text: "hi".to_string(),
+                ..Default::default()
+            },
+        )));
+        let frame = WsMessage {
+            message_type: Some(ws_message::MessageType::PushMessageRequest(req)),
+        }
+        .encode_to_vec();
+        let item = RawMessageContent::WsFrame(frame).decode().unwrap();
+        assert_eq!(item.message_type, MessageItemType::Text);
+        assert!(item.raw_content.is_none());
+    }
+
+    #[test]
+    fn message_item_rejects_missing_message_data() {
+        let req = PushMessageRequest {
+            payload: Some(push_message_request::Payload::MessagePayload(
+                PushMessagePayload::default(),
+            )),
+            ..Default::default()
+        };
+        assert_eq!(
+            MessageItem::try_from(&req).unwrap_err(),
+            ConversionError::MissingMessageData
+        );
+    }
+
+    #[test]
+    fn message_item_rejects_event_payload() {
+        let req = PushMessageRequest {
+            payload: Some(push_message_request::Payload::EventPayload(
+                PushEventPayload::default(),
+            )),
+            ..Default::default()
+        };
+        assert_eq!(
+            MessageItem::try_from(&req).unwrap_err(),
+            ConversionError::MissingPayload
+        );
+    }
+}
@@ -1,5 +1,5 @@
 use anyhow::anyhow;
-use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine as _};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
 
 use serde::{Deserialize, Serialize};
 use serde_json;
@@ -17,7 +17,7 @@ use serde_json;
   "jti": "7051c9fb-639a-4174-96b7-5dcaba315bff"
 }
  */
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     aud: String,        // Optional. Audience
     exp: usize, // Required (validate_exp defaults to true in validation). Expiration time (as UTC timestamp)
@@ -33,17 +33,23 @@ impl Claims {
     pub fn get_sub(&self) -> String {
         self.sub.clone()
     }
+
+    pub fn get_exp(&self) -> usize {
+        self.exp
+    }
 }
 
 pub struct AuthTokenParser();
 
 impl AuthTokenParser {
+    /// Reads the claims without any validation; use `TokenValidator` for tokens from the network.
     pub fn decode_jwt_payload(token: &str) -> anyhow::Result<Claims> {
         let parts: Vec<&str> = token.split('.').collect();
         if parts.len() != 3 {
             return Err(anyhow!("Failed to split the JWT into parts"));
         }
-        let payload = STANDARD_NO_PAD.decode(parts[1])?;
+        // JWTs use unpadded base64url, but tolerate encoders that still pad
+        let payload = URL_SAFE_NO_PAD.decode(parts[1].trim_end_matches('='))?;
         let claims: Claims = serde_json::from_slice(&payload)?;
         Ok(claims)
     }
@@ -2,6 +2,10 @@ pub mod api_paths;
 pub mod api_response;
 pub mod auth_token_parser;
 pub mod oauth_client;
+pub mod retry;
+#[allow(clippy::module_inception)]
 pub mod ripple_api;
+pub mod token_manager;
+pub mod token_validator;
 
 pub use ripple_api::RippleApi;
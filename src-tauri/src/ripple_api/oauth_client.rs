@@ -1,16 +1,32 @@
 use crate::app_config::AppConfig;
 use anyhow::anyhow;
 use oauth2::basic::{
-    BasicClient, BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse,
-    BasicTokenResponse,
+    BasicClient, BasicErrorResponse, BasicErrorResponseType, BasicRevocationErrorResponse,
+    BasicTokenIntrospectionResponse, BasicTokenResponse,
 };
+use oauth2::url::{form_urlencoded, Url};
 use std::sync::{Arc, RwLock};
 
 use oauth2::{
     reqwest, AuthType, AuthUrl, Client, ClientId, ClientSecret, CsrfToken, EndpointNotSet,
-    EndpointSet, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, RefreshToken, Scope,
-    StandardRevocableToken, TokenUrl,
+    EndpointSet, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, RefreshToken, RequestTokenError,
+    Scope, StandardRevocableToken, TokenUrl,
 };
+use tracing::warn;
+
+/// Failure modes of a refresh-token exchange.
+///
+/// `SessionExpired` means the refresh token itself is no longer accepted (`invalid_grant`)
+/// and the user has to sign in again; everything else is worth retrying later.
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum TokenRefreshError {
+    #[error("No authentication token found. Please login.")]
+    NotLoggedIn,
+    #[error("Session expired. Please login again.")]
+    SessionExpired,
+    #[error("Failed to refresh_token: {0}")]
+    Failed(String),
+}
 
 #[derive(Clone)]
 pub struct OauthClient {
@@ -26,6 +42,10 @@ pub struct OauthClient {
         EndpointNotSet,
         EndpointSet,
     >,
+    client_id: String,
+    client_secret: String,
+    /// RFC 7009 endpoint, called directly because oauth2 only revokes over https.
+    revocation_url: Option<Url>,
     reqwest_client: reqwest::Client,
     state: Arc<RwLock<Option<CsrfToken>>>,
     pkce_verifier: Arc<RwLock<Option<String>>>,
@@ -39,8 +59,16 @@ impl OauthClient {
             .set_auth_uri(AuthUrl::new(app_config.oauth2_auth_url.clone())?)
             .set_token_uri(TokenUrl::new(app_config.oauth2_token_url.clone())?)
             .set_redirect_uri(RedirectUrl::new(app_config.oauth2_redirect_uri.clone())?);
+        let revocation_url = app_config
+            .oauth2_revocation_url
+            .as_deref()
+            .map(Url::parse)
+            .transpose()?;
         Ok(OauthClient {
             client,
+            client_id: app_config.oauth2_client_id.clone(),
+            client_secret: app_config.oauth2_client_secret.clone(),
+            revocation_url,
             reqwest_client,
             state: Arc::new(RwLock::new(None)),
             pkce_verifier: Arc::new(RwLock::new(None)),
@@ -71,7 +99,7 @@ impl OauthClient {
         match *guard_state {
             Some(ref csrf_token) => csrf_token.secret() == state,
             None => {
-                eprintln!("State is None, cannot compare with provided state.");
+                warn!("State is None, cannot compare with provided state.");
                 false
             }
         }
@@ -96,12 +124,73 @@ impl OauthClient {
     pub async fn refresh_token(
         &self,
         old_refresh_token: String,
-    ) -> anyhow::Result<BasicTokenResponse> {
+    ) -> Result<BasicTokenResponse, TokenRefreshError> {
+        if old_refresh_token.is_empty() {
+            return Err(TokenRefreshError::SessionExpired);
+        }
         let refresh_token = RefreshToken::new(old_refresh_token);
         self.client
             .exchange_refresh_token(&refresh_token)
             .request_async(&self.reqwest_client)
             .await
-            .map_err(|e| anyhow!("Failed to refresh_token: {}", e.to_string()))
+            .map_err(|e| match e {
+                RequestTokenError::ServerResponse(ref resp)
+                    if *resp.error() == BasicErrorResponseType::InvalidGrant =>
+                {
+                    TokenRefreshError::SessionExpired
+                }
+                e => TokenRefreshError::Failed(e.to_string()),
+            })
+    }
+
+    /// Revokes the refresh token first so no new access tokens can be minted, then the access token.
+    ///
+    /// Both are attempted even if the first fails; the errors are reported together.
+    pub async fn revoke_tokens(
+        &self,
+        access_token: String,
+        refresh_token: String,
+    ) -> anyhow::Result<()> {
+        let Some(revocation_url) = &self.revocation_url else {
+            return Ok(());
+        };
+        let mut tokens = Vec::new();
+        if !refresh_token.is_empty() {
+            tokens.push((refresh_token, "refresh_token"));
+        }
+        tokens.push((access_token, "access_token"));
+        let mut errors = Vec::new();
+        for (token, hint) in tokens {
+            if let Err(e) = self.revoke_token(revocation_url, &token, hint).await {
+                errors.push(format!("{}: {}", hint, e));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("Failed to revoke_token: {}", errors.join("; ")))
+        }
+    }
+
+    async fn revoke_token(&self, url: &Url, token: &str, hint: &str) -> anyhow::Result<()> {
+        let response = self
+            .reqwest_client
+            .post(url.clone())
+            // RFC 6749 §2.3.1 form-urlencodes the credentials first, as oauth2 does for the token endpoint
+            .basic_auth(
+                form_urlencode(&self.client_id),
+                Some(form_urlencode(&self.client_secret)),
+            )
+            .form(&[("token", token), ("token_type_hint", hint)])
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("status {}", response.status()));
+        }
+        Ok(())
     }
 }
+
+fn form_urlencode(value: &str) -> String {
+    form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
@@ -0,0 +1,155 @@
+use crate::app_config::ApiRetryConfig;
+use backoff::backoff::Backoff;
+use backoff::{ExponentialBackoff, ExponentialBackoffBuilder};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+use std::time::{Duration, SystemTime};
+
+/// Whether a request can be sent again without side effects. Decides which
+/// [`RetryPolicy`](crate::app_config::RetryPolicy) applies to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallClass {
+    Idempotent,
+    NonIdempotent,
+}
+
+/// Retry budget of a single API call.
+pub struct RetryState {
+    backoff: ExponentialBackoff,
+    max_retry_after: Duration,
+    retries_left: u32,
+}
+
+impl RetryState {
+    pub fn new(config: &ApiRetryConfig, class: CallClass) -> Self {
+        let policy = match class {
+            CallClass::Idempotent => &config.idempotent,
+            CallClass::NonIdempotent => &config.non_idempotent,
+        };
+        let backoff = ExponentialBackoffBuilder::new()
+            .with_initial_interval(Duration::from_millis(policy.initial_interval_ms))
+            .with_max_interval(Duration::from_millis(policy.max_interval_ms))
+            .with_max_elapsed_time(None)
+            .build();
+        RetryState {
+            backoff,
+            max_retry_after: Duration::from_millis(policy.max_retry_after_ms),
+            retries_left: policy.max_retries,
+        }
+    }
+
+    /// Delay before the next attempt, or `None` when the call should fail now. A server
+    /// supplied `retry_after` wins over the jittered backoff, even past its max interval,
+    /// unless it is longer than the policy's `max_retry_after_ms`.
+    pub fn next_delay(&mut self, retry_after: Option<Duration>) -> Option<Duration> {
+        if self.retries_left == 0 {
+            return None;
+        }
+        let delay = match retry_after {
+            Some(wait) if wait > self.max_retry_after => return None,
+            Some(wait) => wait,
+            None => self.backoff.next_backoff()?,
+        };
+        self.retries_left -= 1;
+        Some(delay)
+    }
+}
+
+/// Statuses a gateway returns while overloaded or restarting.
+pub fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Parses `Retry-After`, given either in seconds or as an HTTP date.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = httpdate::parse_http_date(value).ok()?;
+    Some(at.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_config::RetryPolicy;
+    use reqwest::header::HeaderValue;
+
+    fn config(max_retries: u32) -> ApiRetryConfig {
+        let policy = RetryPolicy {
+            max_retries,
+            initial_interval_ms: 100,
+            max_interval_ms: 1_000,
+            max_retry_after_ms: 10_000,
+        };
+        ApiRetryConfig {
+            idempotent: policy.clone(),
+            non_idempotent: RetryPolicy {
+                max_retries: 0,
+                ..policy
+            },
+        }
+    }
+
+    #[test]
+    fn stops_after_max_retries() {
+        let mut state = RetryState::new(&config(2), CallClass::Idempotent);
+        assert!(state.next_delay(None).is_some());
+        assert!(state.next_delay(None).is_some());
+        assert!(state.next_delay(None).is_none());
+    }
+
+    #[test]
+    fn non_idempotent_calls_use_their_own_policy() {
+        let mut state = RetryState::new(&config(3), CallClass::NonIdempotent);
+        assert!(state.next_delay(None).is_none());
+    }
+
+    #[test]
+    fn honors_retry_after_beyond_max_interval() {
+        let mut state = RetryState::new(&config(3), CallClass::Idempotent);
+        assert_eq!(
+            state.next_delay(Some(Duration::from_millis(800))),
+            Some(Duration::from_millis(800))
+        );
+        assert_eq!(
+            state.next_delay(Some(Duration::from_secs(5))),
+            Some(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn gives_up_on_retry_after_beyond_max_retry_after() {
+        let mut state = RetryState::new(&config(3), CallClass::Idempotent);
+        assert_eq!(state.next_delay(Some(Duration::from_secs(60))), None);
+    }
+
+    #[test]
+    fn backoff_delays_stay_within_max_interval() {
+        let mut state = RetryState::new(&config(10), CallClass::Idempotent);
+        while let Some(delay) = state.next_delay(None) {
+            assert!(delay <= Duration::from_millis(1_500));
+        }
+    }
+
+    #[test]
+    fn parses_retry_after_seconds_and_dates() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("7"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(7)));
+
+        let past = httpdate::fmt_http_date(SystemTime::UNIX_EPOCH);
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(&past).unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("soon"));
+        assert_eq!(retry_after(&headers), None);
+    }
+}
@@ -1,3 +1,6 @@
+use crate::app_config::{ApiRetryConfig, AppConfig};
+use crate::errors::ApiError;
+use crate::http_client::HttpClients;
 use crate::ripple_api::api_paths::ApiPaths;
 use crate::ripple_api::api_response::{
     AbortUploadRequest, AbortUploadResponse, AddFriendRequest, BlockUserRequest,
@@ -11,120 +14,226 @@ use crate::ripple_api::api_response::{
     UserGroupSyncResponse, UserProfileResponse,
 };
 use crate::ripple_api::oauth_client::OauthClient;
-use crate::store_engine::StoreEngine;
+use crate::ripple_api::retry::{is_transient_status, retry_after, CallClass, RetryState};
+use crate::ripple_api::token_manager::TokenManager;
+use crate::ripple_api::token_validator::TokenValidator;
+use crate::store_engine::store_engine::{RippleStorage, Token};
 use anyhow::anyhow;
 use mime::Mime;
+use oauth2::basic::BasicTokenResponse;
 use oauth2::{reqwest, TokenResponse};
 use reqwest::{Response, StatusCode};
 use serde::Serialize;
 use sha2::{Digest, Sha256};
 use std::future::Future;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+const REVOKE_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Clone)]
 pub struct RippleApi<E>
 where
-    E: StoreEngine,
+    E: RippleStorage,
 {
     api_paths: ApiPaths,
     reqwest_client: reqwest::Client,
+    /// Client with the longer upload timeouts, used for avatar and attachment bodies.
+    upload_client: reqwest::Client,
     oauth_client: OauthClient,
     store_engine: E,
+    token_manager: TokenManager<E>,
+    token_validator: TokenValidator<E>,
+    retry_config: ApiRetryConfig,
 }
 
 impl<S> RippleApi<S>
 where
-    S: StoreEngine,
+    S: RippleStorage,
 {
+    /// Client for the account whose token and data live in `store_engine`, with the gateways,
+    /// token checks and retry policy taken from `app_config`.
+    pub fn from_config(
+        app_config: &AppConfig,
+        http_clients: HttpClients,
+        oauth_client: OauthClient,
+        store_engine: S,
+    ) -> Self {
+        let token_validator =
+            TokenValidator::new(app_config, http_clients.api.clone(), store_engine.clone());
+        Self::new(
+            app_config.upload_gateway_url.clone(),
+            app_config.api_gateway_url.clone(),
+            http_clients,
+            oauth_client,
+            token_validator,
+            store_engine,
+            app_config.api_retry.clone(),
+        )
+    }
+
     pub fn new(
         upload_gateway_url: String,
         api_gateway_url: String,
-        reqwest_client: reqwest::Client,
+        http_clients: HttpClients,
         oauth_client: OauthClient,
+        token_validator: TokenValidator<S>,
         store_engine: S,
+        retry_config: ApiRetryConfig,
     ) -> Self {
         let api_paths = ApiPaths::new(&upload_gateway_url, &api_gateway_url);
+        let token_manager = TokenManager::new(
+            oauth_client.clone(),
+            token_validator.clone(),
+            store_engine.clone(),
+        );
         RippleApi {
             api_paths,
-            reqwest_client,
+            reqwest_client: http_clients.api,
+            upload_client: http_clients.upload,
             oauth_client,
             store_engine,
+            token_manager,
+            token_validator,
+            retry_config,
         }
     }
 
+    pub fn token_manager(&self) -> &TokenManager<S> {
+        &self.token_manager
+    }
+
+    /// Runs an authenticated request, refreshing the access token on 401 and retrying
+    /// transient failures as allowed by the policy for `class`. Each call is traced under an
+    /// `api_call` span carrying the endpoint path, final status, latency and retry count.
+    #[tracing::instrument(
+        name = "api_call",
+        skip_all,
+        fields(endpoint, status, latency_ms, retries)
+    )]
     async fn execute_with_auth_retry<F, Fut>(
         &self,
         api_call: F,
+        class: CallClass,
         unauthorized_max_retries: u8,
     ) -> anyhow::Result<Response>
     where
         F: Fn(String) -> Fut,
         Fut: Future<Output = anyhow::Result<Response>>,
     {
+        let span = tracing::Span::current();
+        let started = Instant::now();
         let mut attempts = 0u8;
+        let mut retry = RetryState::new(&self.retry_config, class);
+        let mut retries = 0u32;
+        let mut access_token = self.token_manager.access_token().await?;
         loop {
-            let token = match self.store_engine.get_token().await? {
-                Some(t) => t,
-                None => {
-                    anyhow::bail!("No authentication token found. Please login.");
+            // The closures only build and send the request, so any failure is a transport one
+            let res = match api_call(access_token.clone()).await {
+                Ok(res) => res,
+                Err(e) => {
+                    span.record("latency_ms", started.elapsed().as_millis() as u64);
+                    let Some(delay) = retry.next_delay(None) else {
+                        warn!("API request failed: {}", e);
+                        return Err(ApiError::network(e.to_string()).into());
+                    };
+                    warn!("API request failed, retrying in {:?}: {}", delay, e);
+                    retries += 1;
+                    span.record("retries", retries);
+                    tokio::time::sleep(delay).await;
+                    continue;
                 }
             };
-            let res = api_call(token.access_token).await?;
+            span.record("endpoint", res.url().path());
+            span.record("status", res.status().as_u16());
+            span.record("latency_ms", started.elapsed().as_millis() as u64);
+            debug!("API call completed");
             match res.status() {
                 StatusCode::OK => return Ok(res),
                 StatusCode::UNAUTHORIZED => {
                     if attempts < unauthorized_max_retries {
                         attempts += 1;
-                        match self.oauth_client.refresh_token(token.refresh_token).await {
-                            Ok(token_response) => {
-                                self.store_engine
-                                    .save_token(
-                                        token_response.access_token().secret(),
-                                        token_response.refresh_token().unwrap().secret(),
-                                    )
-                                    .await?;
-                                continue;
-                            }
-                            Err(e) => {
-                                return Err(anyhow!(
-                                    "Token refresh failed: {}. Please login again.",
-                                    e
-                                ));
-                            }
-                        }
+                        access_token = self.token_manager.refresh(&access_token).await?;
                     } else {
-                        return Err(anyhow!("Authentication failed. Please login again."));
+                        return Err(ApiError::Unauthorized {
+                            message: "Authentication failed. Please login again.".to_string(),
+                        }
+                        .into());
                     }
                 }
-                _ => {
-                    return Err(anyhow::anyhow!(
-                        "API call failed with status: {}",
-                        res.status()
-                    ));
+                status if is_transient_status(status) => {
+                    let Some(delay) = retry.next_delay(retry_after(res.headers())) else {
+                        return Err(Self::error_from_response(res).await.into());
+                    };
+                    warn!("API call returned {}, retrying in {:?}", status, delay);
+                    retries += 1;
+                    span.record("retries", retries);
+                    tokio::time::sleep(delay).await;
                 }
+                _ => return Err(Self::error_from_response(res).await.into()),
             }
         }
     }
 
-    pub fn oauth_auth_url(&self) -> String {
-        self.oauth_client.auth_url()
+    /// Classifies a non-success response, preferring the message and code of a JSON error body.
+    async fn error_from_response(res: Response) -> ApiError {
+        let status = res.status();
+        let retry_after = retry_after(res.headers()).map(|wait| wait.as_secs());
+        let body = res.json::<CommonResponse>().await.ok();
+        match body {
+            // On a 4xx the application code says more than the status, e.g. "blocked by user"
+            Some(body)
+                if status.is_client_error()
+                    && status != StatusCode::TOO_MANY_REQUESTS
+                    && body.code != 200 =>
+            {
+                ApiError::from_body_code(body.code, body.message)
+            }
+            Some(body) => ApiError::from_status(status, retry_after, body.message),
+            None => ApiError::from_status(
+                status,
+                retry_after,
+                status.canonical_reason().unwrap_or("Unexpected response"),
+            ),
+        }
     }
 
-    pub fn oauth_state_equal(&self, state: &str) -> bool {
-        self.oauth_client.state_equal(state)
+    /// Validates a freshly issued token response and stores it for this account.
+    pub async fn oauth_save_token(
+        &self,
+        token_response: &BasicTokenResponse,
+    ) -> anyhow::Result<()> {
+        self.token_validator
+            .validate(token_response.access_token().secret())
+            .await?;
+        let refresh_token = match token_response.refresh_token() {
+            Some(t) => t.secret().clone(),
+            None => {
+                warn!("No refresh token issued, session cannot be renewed");
+                String::new()
+            }
+        };
+        let token = Token::issued_now(
+            token_response.access_token().secret().clone(),
+            refresh_token,
+            token_response.expires_in(),
+        );
+        self.store_engine.tokens().save_token(&token).await
     }
 
-    pub async fn oauth_request_token(&self, code: String) -> anyhow::Result<()> {
-        match self.oauth_client.request_token(code).await {
-            Ok(token_response) => {
-                self.store_engine
-                    .save_token(
-                        token_response.access_token().secret(),
-                        token_response.refresh_token().unwrap().secret(),
-                    )
-                    .await
-            }
-            Err(e) => Err(anyhow!("Failed to request token: {:#?}", e)),
-        }
+    /// Revokes the stored tokens on the authorization server. Does not touch local storage.
+    pub async fn oauth_revoke_token(&self) -> anyhow::Result<()> {
+        let token = match self.store_engine.tokens().get_token().await? {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+        tokio::time::timeout(
+            REVOKE_TIMEOUT,
+            self.oauth_client
+                .revoke_tokens(token.access_token, token.refresh_token),
+        )
+        .await
+        .map_err(|_| anyhow!("Token revocation timed out"))?
     }
 
     /// Upload avatar image and get the URL back
@@ -150,7 +259,7 @@ where
                             .text("hash", hex_hash)
                             .text("originalFilename", clone_filename)
                             .part("avatar", part);
-                        self.reqwest_client
+                        self.upload_client
                             .put(&self.api_paths.upload_avatar)
                             .header("Authorization", format!("Bearer {}", access_token))
                             .multipart(form)
@@ -159,6 +268,7 @@ where
                             .map_err(|e| anyhow!("Failed to upload avatar: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -194,7 +304,7 @@ where
                             .text("hash", hex_hash)
                             .text("originalFilename", clone_filename)
                             .part("avatar", part);
-                        self.reqwest_client
+                        self.upload_client
                             .put(&url)
                             .header("Authorization", format!("Bearer {}", access_token))
                             .multipart(form)
@@ -203,6 +313,7 @@ where
                             .map_err(|e| anyhow!("Failed to upload group avatar: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -222,6 +333,7 @@ where
                         .await
                         .map_err(|e| anyhow!("Failed to get user profile: {}", e))
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -246,6 +358,7 @@ where
                             .map_err(|e| anyhow!("Failed to get user profile: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -270,6 +383,7 @@ where
                             .map_err(|e| anyhow!("Failed to update profile: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -287,6 +401,7 @@ where
                         .await
                         .map_err(|e| anyhow!("Failed to delete user avatar: {}", e))
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -313,6 +428,7 @@ where
                             .map_err(|e| anyhow!("Failed to add friend: {}", e))
                     }
                 },
+                CallClass::NonIdempotent,
                 1,
             )
             .await?;
@@ -334,6 +450,7 @@ where
                             .map_err(|e| anyhow!("Failed to remove friend: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -364,6 +481,7 @@ where
                             .map_err(|e| anyhow!("Failed to update friend: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -390,6 +508,7 @@ where
                             .map_err(|e| anyhow!("Failed to block user: {}", e))
                     }
                 },
+                CallClass::NonIdempotent,
                 1,
             )
             .await?;
@@ -411,6 +530,7 @@ where
                             .map_err(|e| anyhow!("Failed to unblock user: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -441,6 +561,7 @@ where
                             .map_err(|e| anyhow!("Failed to update blocked user: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -479,6 +600,7 @@ where
                             .map_err(|e| anyhow!("Failed to get relations: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -511,6 +633,7 @@ where
                             .map_err(|e| anyhow!("Failed to sync relations: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -549,6 +672,7 @@ where
                             .map_err(|e| anyhow!("Failed to get conversations: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -581,6 +705,7 @@ where
                             .map_err(|e| anyhow!("Failed to sync conversations: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -614,6 +739,7 @@ where
                             .map_err(|e| anyhow!("Failed to get conversation summaries: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -644,6 +770,7 @@ where
                             .map_err(|e| anyhow!("Failed to send message: {}", e))
                     }
                 },
+                CallClass::NonIdempotent,
                 1,
             )
             .await?;
@@ -690,6 +817,7 @@ where
                             .map_err(|e| anyhow!("Failed to read messages: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -736,6 +864,7 @@ where
                             .map_err(|e| anyhow!("Failed to read messages after: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -769,6 +898,7 @@ where
                             .map_err(|e| anyhow!("Failed to update read position: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -805,6 +935,7 @@ where
                             .map_err(|e| anyhow!("Failed to create group: {}", e))
                     }
                 },
+                CallClass::NonIdempotent,
                 1,
             )
             .await?;
@@ -836,6 +967,7 @@ where
                             .map_err(|e| anyhow!("Failed to update group: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -874,6 +1006,7 @@ where
                             .map_err(|e| anyhow!("Failed to add group members: {}", e))
                     }
                 },
+                CallClass::NonIdempotent,
                 1,
             )
             .await?;
@@ -914,6 +1047,7 @@ where
                             .map_err(|e| anyhow!("Failed to get group members: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -936,6 +1070,7 @@ where
                             .map_err(|e| anyhow!("Failed to leave group: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -973,6 +1108,7 @@ where
                             .map_err(|e| anyhow!("Failed to sync group members: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -1011,6 +1147,7 @@ where
                             .map_err(|e| anyhow!("Failed to get user groups: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -1043,6 +1180,7 @@ where
                             .map_err(|e| anyhow!("Failed to sync user groups: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -1079,6 +1217,7 @@ where
                             .map_err(|e| anyhow!("Failed to initiate attachment upload: {}", e))
                     }
                 },
+                CallClass::NonIdempotent,
                 1,
             )
             .await?;
@@ -1101,10 +1240,10 @@ where
                     let file_sha256 = file_sha256.clone();
                     let original_filename = original_filename.clone();
                     async move {
-                        let part = reqwest::multipart::Part::bytes(file_data)
-                            .file_name(original_filename);
+                        let part =
+                            reqwest::multipart::Part::bytes(file_data).file_name(original_filename);
                         let form = reqwest::multipart::Form::new().part("file", part);
-                        self.reqwest_client
+                        self.upload_client
                             .put(&self.api_paths.attachment_single)
                             .header("Authorization", format!("Bearer {}", access_token))
                             .query(&[("objectName", &object_name), ("fileSha256", &file_sha256)])
@@ -1114,6 +1253,7 @@ where
                             .map_err(|e| anyhow!("Failed to upload attachment single: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -1140,7 +1280,7 @@ where
                             .mime_str("application/octet-stream")
                             .unwrap();
                         let form = reqwest::multipart::Form::new().part("chunk", part);
-                        self.reqwest_client
+                        self.upload_client
                             .put(&self.api_paths.attachment_chunk)
                             .header("Authorization", format!("Bearer {}", access_token))
                             .query(&[
@@ -1154,6 +1294,7 @@ where
                             .map_err(|e| anyhow!("Failed to upload attachment chunk: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
@@ -1186,6 +1327,7 @@ where
                             .map_err(|e| anyhow!("Failed to complete attachment upload: {}", e))
                     }
                 },
+                CallClass::NonIdempotent,
                 1,
             )
             .await?;
@@ -1214,9 +1356,23 @@ where
                             .map_err(|e| anyhow!("Failed to abort attachment upload: {}", e))
                     }
                 },
+                CallClass::Idempotent,
                 1,
             )
             .await?;
         Ok(res.json::<AbortUploadResponse>().await?)
     }
+
+    /// Download an attachment from the file URL of a message. File URLs point at the object
+    /// storage and carry no API token.
+    pub async fn download_attachment(&self, file_url: &str) -> anyhow::Result<Vec<u8>> {
+        let res = self
+            .upload_client
+            .get(file_url)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| anyhow!("Failed to download attachment: {}", e))?;
+        Ok(res.bytes().await?.to_vec())
+    }
 }
@@ -0,0 +1,238 @@
+use crate::ripple_api::auth_token_parser::AuthTokenParser;
+use crate::ripple_api::oauth_client::{OauthClient, TokenRefreshError};
+use crate::ripple_api::token_validator::TokenValidator;
+use crate::store_engine::store_engine::{RippleStorage, Token};
+use futures_util::future::{BoxFuture, Shared};
+use futures_util::FutureExt;
+use oauth2::TokenResponse;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+
+/// Refresh the access token this many seconds before its `exp` claim.
+const REFRESH_MARGIN_SECS: u64 = 60;
+/// Delay before the scheduler retries after a failed refresh.
+const REFRESH_RETRY_SECS: u64 = 30;
+
+type RefreshFuture = Shared<BoxFuture<'static, Result<String, TokenRefreshError>>>;
+type SessionExpiredHandler = Arc<dyn Fn() + Send + Sync>;
+
+/// Owns the OAuth token lifecycle: hands out access tokens, refreshes them
+/// shortly before they expire and makes sure concurrent callers share a single
+/// in-flight refresh request instead of racing each other.
+#[derive(Clone)]
+pub struct TokenManager<S>
+where
+    S: RippleStorage,
+{
+    oauth_client: OauthClient,
+    token_validator: TokenValidator<S>,
+    store_engine: S,
+    in_flight: Arc<Mutex<Option<RefreshFuture>>>,
+    scheduler: Arc<Mutex<Option<JoinHandle<()>>>>,
+    on_session_expired: Arc<RwLock<Option<SessionExpiredHandler>>>,
+}
+
+impl<S> TokenManager<S>
+where
+    S: RippleStorage,
+{
+    pub fn new(
+        oauth_client: OauthClient,
+        token_validator: TokenValidator<S>,
+        store_engine: S,
+    ) -> Self {
+        TokenManager {
+            oauth_client,
+            token_validator,
+            store_engine,
+            in_flight: Arc::new(Mutex::new(None)),
+            scheduler: Arc::new(Mutex::new(None)),
+            on_session_expired: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// Registers the callback run once the server rejects the refresh token.
+    ///
+    /// Tokens are already cleared from the store when it is invoked.
+    pub fn set_session_expired_handler(&self, handler: impl Fn() + Send + Sync + 'static) {
+        self.on_session_expired
+            .write()
+            .unwrap()
+            .replace(Arc::new(handler));
+    }
+
+    /// Returns a usable access token, refreshing it first when it is about to expire.
+    pub async fn access_token(&self) -> anyhow::Result<String> {
+        let token = match self.store_engine.tokens().get_token().await? {
+            Some(t) => t,
+            None => anyhow::bail!("No authentication token found. Please login."),
+        };
+        match Self::time_until_refresh(&token) {
+            Some(delay) if delay.is_zero() => self.refresh(&token.access_token).await,
+            _ => Ok(token.access_token),
+        }
+    }
+
+    /// Refreshes the token pair unless `stale_access_token` has already been replaced.
+    ///
+    /// All callers arriving while a refresh is running await the same future.
+    pub async fn refresh(&self, stale_access_token: &str) -> anyhow::Result<String> {
+        let refresh_future = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.as_ref() {
+                Some(fut) => fut.clone(),
+                None => {
+                    let fut = Self::refresh_token_pair(
+                        self.oauth_client.clone(),
+                        self.token_validator.clone(),
+                        self.store_engine.clone(),
+                        self.on_session_expired.read().unwrap().clone(),
+                        stale_access_token.to_string(),
+                    )
+                    .boxed()
+                    .shared();
+                    in_flight.replace(fut.clone());
+                    fut
+                }
+            }
+        };
+        let result = refresh_future.clone().await;
+        {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            if in_flight
+                .as_ref()
+                .is_some_and(|fut| fut.ptr_eq(&refresh_future))
+            {
+                in_flight.take();
+            }
+        }
+        result.map_err(anyhow::Error::from)
+    }
+
+    /// Starts (or restarts) the background task that refreshes the token before `exp`.
+    pub fn start_refresh_scheduler(&self) {
+        let manager = self.clone();
+        let handle = tokio::spawn(async move {
+            manager.run_refresh_scheduler().await;
+        });
+        if let Some(previous) = self.scheduler.lock().unwrap().replace(handle) {
+            previous.abort();
+        }
+    }
+
+    pub fn stop_refresh_scheduler(&self) {
+        if let Some(handle) = self.scheduler.lock().unwrap().take() {
+            handle.abort();
+        }
+    }
+
+    async fn run_refresh_scheduler(&self) {
+        loop {
+            let token = match self.store_engine.tokens().get_token().await {
+                Ok(Some(t)) => t,
+                Ok(None) => {
+                    info!("No token stored, refresh scheduler exiting");
+                    return;
+                }
+                Err(e) => {
+                    error!("Failed to load token: {}", e);
+                    return;
+                }
+            };
+            let delay = match Self::time_until_refresh(&token) {
+                Some(delay) => delay,
+                None => {
+                    warn!("Access token has no known expiry, scheduler exiting");
+                    return;
+                }
+            };
+            debug!("Next token refresh in {} seconds", delay.as_secs());
+            tokio::time::sleep(delay).await;
+            if let Err(e) = self.refresh(&token.access_token).await {
+                if matches!(
+                    e.downcast_ref::<TokenRefreshError>(),
+                    Some(TokenRefreshError::SessionExpired | TokenRefreshError::NotLoggedIn)
+                ) {
+                    info!("Session ended, refresh scheduler exiting");
+                    return;
+                }
+                warn!("Scheduled token refresh failed: {}", e);
+                tokio::time::sleep(Duration::from_secs(REFRESH_RETRY_SECS)).await;
+            }
+        }
+    }
+
+    /// Time left until the token should be refreshed, or `None` if neither the JWT `exp` nor
+    /// the stored expiry is known.
+    fn time_until_refresh(token: &Token) -> Option<Duration> {
+        let exp = match AuthTokenParser::decode_jwt_payload(&token.access_token) {
+            Ok(claims) => claims.get_exp() as u64,
+            Err(_) => (token.expires_at? / 1000) as u64,
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let refresh_at = exp.saturating_sub(REFRESH_MARGIN_SECS);
+        Some(Duration::from_secs(refresh_at.saturating_sub(now)))
+    }
+
+    async fn refresh_token_pair(
+        oauth_client: OauthClient,
+        token_validator: TokenValidator<S>,
+        store_engine: S,
+        on_session_expired: Option<SessionExpiredHandler>,
+        stale_access_token: String,
+    ) -> Result<String, TokenRefreshError> {
+        let token = match store_engine.tokens().get_token().await {
+            Ok(Some(t)) => t,
+            Ok(None) => return Err(TokenRefreshError::NotLoggedIn),
+            Err(e) => return Err(TokenRefreshError::Failed(e.to_string())),
+        };
+        if token.access_token != stale_access_token {
+            // Someone else refreshed while we were waiting
+            return Ok(token.access_token);
+        }
+        let token_response = match oauth_client
+            .refresh_token(token.refresh_token.clone())
+            .await
+        {
+            Ok(response) => response,
+            Err(TokenRefreshError::SessionExpired) => {
+                warn!("Refresh token rejected, session expired");
+                if let Err(e) = store_engine.tokens().clear_token().await {
+                    error!("Failed to clear tokens: {}", e);
+                }
+                if let Some(handler) = on_session_expired {
+                    handler();
+                }
+                return Err(TokenRefreshError::SessionExpired);
+            }
+            Err(e) => return Err(e),
+        };
+        let access_token = token_response.access_token().secret().to_string();
+        token_validator
+            .validate(&access_token)
+            .await
+            .map_err(|e| TokenRefreshError::Failed(e.to_string()))?;
+        // Servers that don't rotate refresh tokens omit it; keep using the previous one
+        let refresh_token = token_response
+            .refresh_token()
+            .map(|t| t.secret().as_str())
+            .unwrap_or(&token.refresh_token);
+        let token = Token::issued_now(
+            access_token.clone(),
+            refresh_token.to_string(),
+            token_response.expires_in(),
+        );
+        store_engine
+            .tokens()
+            .save_token(&token)
+            .await
+            .map_err(|e| TokenRefreshError::Failed(e.to_string()))?;
+        info!("Access token refreshed");
+        Ok(access_token)
+    }
+}
@@ -0,0 +1,177 @@
+use crate::app_config::AppConfig;
+use crate::ripple_api::auth_token_parser::Claims;
+use crate::store_engine::store_engine::RippleStorage;
+use anyhow::anyhow;
+use jsonwebtoken::jwk::{AlgorithmParameters, Jwk, JwkSet};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use oauth2::reqwest;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::info;
+
+/// Algorithms the auth server signs with; tokens claiming anything else are rejected.
+const ALLOWED_ALGORITHMS: [Algorithm; 2] = [Algorithm::RS256, Algorithm::ES256];
+
+/// Minimum time between two JWKS fetches. Tokens with a key id the last fetch did not have
+/// are rejected until it has passed, instead of each one hitting the auth server.
+const JWKS_REFETCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Outcome of the last JWKS fetch; `keys` is `None` when it failed.
+struct JwksFetch {
+    at: Instant,
+    keys: Option<JwkSet>,
+}
+
+/// Validates access tokens issued by the auth server before they are stored.
+///
+/// `exp`, `nbf`, `aud` and (when configured) `iss` are always checked. The signature is
+/// only verified when `oauth2_jwks_url` is set; the JWKS document is cached in the store
+/// and re-fetched when a token references an unknown key id, at most once a minute. The
+/// algorithm comes from the key, never from the token header, which only has to agree with
+/// it. A validator built without a store only keeps the last fetched JWKS in memory. Clones
+/// share the fetch state.
+#[derive(Clone)]
+pub struct TokenValidator<S>
+where
+    S: RippleStorage,
+{
+    audience: String,
+    issuer: Option<String>,
+    jwks_url: Option<String>,
+    reqwest_client: reqwest::Client,
+    store_engine: Option<S>,
+    last_fetch: Arc<Mutex<Option<JwksFetch>>>,
+}
+
+impl<S> TokenValidator<S>
+where
+    S: RippleStorage,
+{
+    pub fn new(app_config: &AppConfig, reqwest_client: reqwest::Client, store_engine: S) -> Self {
+        TokenValidator {
+            audience: app_config.oauth2_client_id.clone(),
+            issuer: app_config.oauth2_issuer.clone(),
+            jwks_url: app_config.oauth2_jwks_url.clone(),
+            reqwest_client,
+            store_engine: Some(store_engine),
+            last_fetch: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// For tokens that arrive before there is a store to cache the JWKS in, such as the
+    /// first token of an account.
+    pub fn without_cache(app_config: &AppConfig, reqwest_client: reqwest::Client) -> Self {
+        TokenValidator {
+            audience: app_config.oauth2_client_id.clone(),
+            issuer: app_config.oauth2_issuer.clone(),
+            jwks_url: app_config.oauth2_jwks_url.clone(),
+            reqwest_client,
+            store_engine: None,
+            last_fetch: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub async fn validate(&self, token: &str) -> anyhow::Result<Claims> {
+        let header = decode_header(token)?;
+        if !ALLOWED_ALGORITHMS.contains(&header.alg) {
+            return Err(anyhow!("Unsupported JWT algorithm {:?}", header.alg));
+        }
+        let (key, algorithm) = match &self.jwks_url {
+            Some(jwks_url) => {
+                let kid = header
+                    .kid
+                    .ok_or_else(|| anyhow!("JWT header has no kid, cannot verify signature"))?;
+                let jwk = self.jwk(jwks_url, &kid).await?;
+                (DecodingKey::from_jwk(&jwk)?, jwk_algorithm(&jwk)?)
+            }
+            None => (DecodingKey::from_secret(&[]), header.alg),
+        };
+        if header.alg != algorithm {
+            return Err(anyhow!(
+                "JWT algorithm {:?} does not match the key's {:?}",
+                header.alg,
+                algorithm
+            ));
+        }
+        let mut validation = Validation::new(algorithm);
+        validation.set_audience(&[&self.audience]);
+        validation.validate_nbf = true;
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        if self.jwks_url.is_none() {
+            validation.insecure_disable_signature_validation();
+        }
+        let token_data = decode::<Claims>(token, &key, &validation)
+            .map_err(|e| anyhow!("Invalid access token: {}", e))?;
+        Ok(token_data.claims)
+    }
+
+    async fn jwk(&self, jwks_url: &str, kid: &str) -> anyhow::Result<Jwk> {
+        let cached = match &self.store_engine {
+            Some(store_engine) => store_engine.get_jwks().await?,
+            None => None,
+        };
+        if let Some(document) = cached {
+            let jwks: JwkSet = serde_json::from_str(&document)?;
+            if let Some(jwk) = jwks.find(kid) {
+                return Ok(jwk.clone());
+            }
+        }
+        // Unknown kid: the server may have rotated its keys, refresh the cache once
+        let mut last_fetch = self.last_fetch.lock().await;
+        if let Some(fetch) = last_fetch
+            .as_ref()
+            .filter(|fetch| fetch.at.elapsed() < JWKS_REFETCH_INTERVAL)
+        {
+            return fetch
+                .keys
+                .as_ref()
+                .and_then(|jwks| jwks.find(kid))
+                .cloned()
+                .ok_or_else(|| anyhow!("No JWK found for kid {}", kid));
+        }
+        *last_fetch = Some(JwksFetch {
+            at: Instant::now(),
+            keys: None,
+        });
+        info!("Fetching JWKS from {}", jwks_url);
+        let document = self
+            .reqwest_client
+            .get(jwks_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let jwks: JwkSet = serde_json::from_str(&document)?;
+        let jwk = jwks.find(kid).cloned();
+        // A document without the key is not cached, so a bad response cannot evict keys
+        // that still verify the tokens we hold
+        if let (Some(store_engine), Some(_)) = (&self.store_engine, &jwk) {
+            store_engine.save_jwks(&document).await?;
+        }
+        *last_fetch = Some(JwksFetch {
+            at: Instant::now(),
+            keys: Some(jwks),
+        });
+        jwk.ok_or_else(|| anyhow!("No JWK found for kid {}", kid))
+    }
+}
+
+/// The `alg` a key is published with, or the allowed algorithm of its key type.
+fn jwk_algorithm(jwk: &Jwk) -> anyhow::Result<Algorithm> {
+    let algorithm = match (jwk.common.key_algorithm, &jwk.algorithm) {
+        (Some(key_algorithm), _) => Algorithm::from_str(&key_algorithm.to_string())
+            .map_err(|_| anyhow!("JWK algorithm {} cannot sign tokens", key_algorithm))?,
+        (None, AlgorithmParameters::RSA(_)) => Algorithm::RS256,
+        (None, AlgorithmParameters::EllipticCurve(_)) => Algorithm::ES256,
+        (None, _) => return Err(anyhow!("JWK has neither alg nor a supported key type")),
+    };
+    if !ALLOWED_ALGORITHMS.contains(&algorithm) {
+        return Err(anyhow!("Unsupported JWK algorithm {:?}", algorithm));
+    }
+    Ok(algorithm)
+}
@@ -1,18 +1,24 @@
+use crate::errors::ConversionError;
 use crate::ripple_api::api_response::{
-    CommonResponse, ConversationChange, ConversationOperation, ConversationSyncData,
-    GroupMemberData, GroupMemberOperation, GroupSyncData, MessageItem, ReadMessagesData,
-    RelationChange, RelationOperation, RelationUser, RelationUsers, RelationsSyncData,
-    UserGroupChange, UserGroupData, UserGroupOperation, UserGroupSyncData, UserProfileData,
+    ApiEnvelope, CommonResponse, ConversationChange, ConversationOperation, ConversationSyncData,
+    GroupChangeDetail, GroupMemberData, GroupMemberOperation, GroupSyncData, MessageItem,
+    ReadMessagesData, RelationChange, RelationOperation, RelationUser, RelationUsers,
+    RelationsSyncData, UserGroupChange, UserGroupData, UserGroupOperation, UserGroupSyncData,
+    UserProfileData,
 };
-use crate::ripple_api::auth_token_parser::AuthTokenParser;
 use crate::ripple_api::RippleApi;
-use crate::ripple_syncer::event_emitter::UIConversations;
+use crate::ripple_syncer::event_emitter::{EventEmitter, UIConversations};
 use crate::ripple_syncer::incremental_operations::{process_incremental_operations, Operation};
+use crate::ripple_syncer::ui_event::SyncDomain;
 
 use crate::store_engine::store_engine::{
     ConversationRecord, ConversationStorageAction, GroupMemberStorageAction, RelationStorageAction,
     RippleStorage, Token, UserGroupStorageAction,
 };
+use futures_util::stream::{self, StreamExt};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
 use uuid::Uuid;
 
 #[derive(Debug)]
@@ -69,6 +75,13 @@ pub enum GroupMemberSyncResult {
     NoChange,
 }
 
+/// Step of one domain's sync, as reported by [`DataSyncManager::init_with_events`].
+enum SyncPhase {
+    Started(SyncDomain),
+    Completed(SyncDomain, i64),
+    Failed(SyncDomain, String),
+}
+
 #[derive(Clone)]
 pub struct DataSyncManager<S: RippleStorage> {
     ripple_api: RippleApi<S>,
@@ -83,43 +96,170 @@ impl<S: RippleStorage> DataSyncManager<S> {
         }
     }
 
+    /// Critical startup phase: everything the main views need before they can render.
+    /// Group members are synced afterwards by [`Self::sync_all_groups_members`].
     pub async fn init(&self) -> anyhow::Result<()> {
+        self.init_reporting(|phase| match phase {
+            SyncPhase::Started(domain) => debug!("Syncing {}", domain.as_str()),
+            SyncPhase::Completed(domain, timestamp) => {
+                debug!("Synced {} at {}", domain.as_str(), timestamp)
+            }
+            SyncPhase::Failed(domain, error) => {
+                warn!("Failed to sync {}: {}", domain.as_str(), error)
+            }
+        })
+        .await
+    }
+
+    /// [`Self::init`] reporting each domain to the UI through the sync lifecycle events.
+    pub async fn init_with_events<E: EventEmitter>(&self, emitter: &E) -> anyhow::Result<()> {
+        self.init_reporting(|phase| {
+            let emitted = match phase {
+                SyncPhase::Started(domain) => emitter.emit_sync_started(domain),
+                SyncPhase::Completed(domain, timestamp) => {
+                    emitter.emit_sync_completed(domain, timestamp)
+                }
+                SyncPhase::Failed(domain, error) => emitter.emit_sync_failed(domain, error),
+            };
+            if let Err(e) = emitted {
+                warn!("{}", e);
+            }
+        })
+        .await
+    }
+
+    async fn init_reporting(&self, report: impl Fn(SyncPhase)) -> anyhow::Result<()> {
         let uuid = self.store_engine.get_device_id().await?;
         if uuid.is_none() {
             let new_id = Uuid::new_v4();
             self.store_engine.save_device_id(&new_id).await?;
         }
+        self.redecode_unsupported_messages().await?;
         self.sync_user_profile().await?;
-        if !self.exist_relations().await? {
-            self.sync_all_relations().await?;
-        } else {
-            self.process_relations_sync(false).await?;
-        }
-        if !self.exist_conversations().await? {
-            self.sync_all_conversations().await?;
-        } else {
-            self.process_conversations_sync(false).await?;
-            self.sync_conversation_summaries().await?;
-        }
-        if !self.exist_user_groups().await? {
-            self.sync_all_user_groups().await?;
-        } else {
-            self.process_user_groups_sync(false).await?;
+        self.sync_domain(SyncDomain::Relations, &report, async {
+            if !self.exist_relations().await? {
+                self.sync_all_relations().await
+            } else {
+                self.process_relations_sync(false).await.map(drop)
+            }
+        })
+        .await?;
+        self.sync_domain(SyncDomain::Conversations, &report, async {
+            if !self.exist_conversations().await? {
+                self.sync_all_conversations().await
+            } else {
+                self.process_conversations_sync(false).await?;
+                self.sync_conversation_summaries().await
+            }
+        })
+        .await?;
+        self.sync_domain(SyncDomain::UserGroups, &report, async {
+            if !self.exist_user_groups().await? {
+                self.sync_all_user_groups().await
+            } else {
+                self.process_user_groups_sync(false).await.map(drop)
+            }
+        })
+        .await
+    }
+
+    async fn sync_domain(
+        &self,
+        domain: SyncDomain,
+        report: &impl Fn(SyncPhase),
+        sync: impl std::future::Future<Output = anyhow::Result<()>>,
+    ) -> anyhow::Result<()> {
+        report(SyncPhase::Started(domain));
+        let synced = match sync.await {
+            Ok(()) => self.record_sync_success(domain).await,
+            Err(e) => Err(e),
+        };
+        match synced {
+            Ok(timestamp) => {
+                report(SyncPhase::Completed(domain, timestamp));
+                Ok(())
+            }
+            Err(e) => {
+                report(SyncPhase::Failed(domain, e.to_string()));
+                Err(e)
+            }
         }
+    }
 
-        let user_groups = self.store_engine.get_all_user_groups().await?;
-        for group in user_groups {
-            if !self
-                .store_engine
-                .exist_group_members(&group.group_id)
-                .await?
-            {
-                self.sync_all_group_members(&group.group_id).await?;
-            } else {
-                self.process_group_members_sync(&group.group_id, false)
-                    .await?;
+    /// Stores now as the last successful sync time of `domain` and returns it (unix millis).
+    pub async fn record_sync_success(&self, domain: SyncDomain) -> anyhow::Result<i64> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+        self.store_engine
+            .save_sync_timestamp(domain.as_str(), timestamp)
+            .await?;
+        Ok(timestamp)
+    }
+
+    /// Last successful sync time (unix millis) keyed by domain; never-synced domains are absent.
+    pub async fn get_sync_timestamps(&self) -> anyhow::Result<HashMap<String, i64>> {
+        Ok(self
+            .store_engine
+            .get_sync_timestamps()
+            .await?
+            .into_iter()
+            .collect())
+    }
+
+    /// Background startup phase: syncs the members of every joined group, at most
+    /// `concurrency` groups at a time, emitting progress after each group.
+    pub async fn sync_all_groups_members<E: EventEmitter>(
+        &self,
+        concurrency: usize,
+        emitter: &E,
+    ) -> anyhow::Result<()> {
+        let group_ids: Vec<String> = self
+            .store_engine
+            .get_all_user_groups()
+            .await?
+            .into_iter()
+            .map(|group| group.group_id)
+            .collect();
+        let total = group_ids.len();
+        let mut completed = 0;
+        let mut failed = 0;
+        if let Err(e) = emitter.emit_sync_started(SyncDomain::GroupMembers) {
+            warn!("{}", e);
+        }
+        let mut results = stream::iter(group_ids)
+            .map(|group_id| async move {
+                let result = self.sync_group_members(&group_id).await;
+                (group_id, result)
+            })
+            // A limit of 0 would never poll a single group
+            .buffer_unordered(concurrency.max(1));
+        while let Some((group_id, result)) = results.next().await {
+            completed += 1;
+            if let Err(e) = result {
+                // One failing group should not stop the others; it is retried on next open
+                warn!("Failed to sync members of group {}: {}", group_id, e);
+                failed += 1;
+            }
+            if let Err(e) = emitter.emit_sync_progress(
+                SyncDomain::GroupMembers,
+                Some(group_id),
+                completed,
+                total,
+            ) {
+                warn!("{}", e);
             }
         }
+        let emitted = if failed == 0 {
+            let timestamp = self.record_sync_success(SyncDomain::GroupMembers).await?;
+            emitter.emit_sync_completed(SyncDomain::GroupMembers, timestamp)
+        } else {
+            emitter.emit_sync_failed(
+                SyncDomain::GroupMembers,
+                format!("Failed to sync members of {} of {} groups", failed, total),
+            )
+        };
+        if let Err(e) = emitted {
+            warn!("{}", e);
+        }
         Ok(())
     }
 
@@ -132,47 +272,39 @@ impl<S: RippleStorage> DataSyncManager<S> {
     }
 
     pub async fn get_token(&self) -> anyhow::Result<Token> {
-        match self.store_engine.get_token().await? {
+        match self.store_engine.tokens().get_token().await? {
             Some(token) => Ok(token),
             None => anyhow::bail!("Auth token not found in storage"),
         }
     }
 
+    /// Returns an access token that is not about to expire, refreshing it if needed.
+    pub async fn get_access_token(&self) -> anyhow::Result<String> {
+        self.ripple_api.token_manager().access_token().await
+    }
+
+    pub fn start_token_refresh(&self) {
+        self.ripple_api.token_manager().start_refresh_scheduler();
+    }
+
+    pub fn stop_token_refresh(&self) {
+        self.ripple_api.token_manager().stop_refresh_scheduler();
+    }
+
     pub async fn exists_token(&self) -> anyhow::Result<bool> {
-        self.store_engine.exists_token().await
+        self.store_engine.tokens().exists_token().await
     }
 
     pub async fn clear_token(&self) -> anyhow::Result<()> {
-        self.store_engine.clear_token().await
+        self.store_engine.tokens().clear_token().await
     }
 
-    pub async fn check_and_clear_on_user_change(&self) -> anyhow::Result<bool> {
-        let token = self.get_token().await?;
-        let claims = AuthTokenParser::decode_jwt_payload(&token.access_token)?;
-        let new_user_id = claims.get_sub();
-
-        let stored_user_id = self.store_engine.get_stored_user_id().await?;
+    pub async fn revoke_token(&self) -> anyhow::Result<()> {
+        self.ripple_api.oauth_revoke_token().await
+    }
 
-        match stored_user_id {
-            Some(old_user_id) if old_user_id != new_user_id => {
-                // User changed, clear all data
-                println!(
-                    "User changed from {} to {}, clearing all stored data",
-                    old_user_id, new_user_id
-                );
-                self.store_engine.clear_all_data().await?;
-                self.store_engine.save_user_id(&new_user_id).await?;
-                Ok(true)
-            }
-            None => {
-                self.store_engine.save_user_id(&new_user_id).await?;
-                Ok(false)
-            }
-            Some(_) => {
-                // Same user, no action needed
-                Ok(false)
-            }
-        }
+    pub async fn clear_all_data(&self) -> anyhow::Result<()> {
+        self.store_engine.clear_all_data().await
     }
 
     pub async fn exists_profile(&self) -> anyhow::Result<bool> {
@@ -189,15 +321,7 @@ impl<S: RippleStorage> DataSyncManager<S> {
     }
 
     pub async fn sync_user_profile(&self) -> anyhow::Result<()> {
-        let profile_response = self.ripple_api.get_user_profile().await?;
-
-        if profile_response.code != 200 {
-            anyhow::bail!(
-                "Failed to get user profile: code={}, message={}",
-                profile_response.code,
-                profile_response.message
-            )
-        }
+        let profile_response = self.ripple_api.get_user_profile().await?.into_result()?;
 
         self.store_engine
             .save_user_profile(profile_response.data)
@@ -210,19 +334,13 @@ impl<S: RippleStorage> DataSyncManager<S> {
         let mut all_users = Vec::new();
         let mut next_page_token: Option<String> = None;
         let page_size = 50; // Max page size
-        let mut last_version: Option<String> = None;
+        let last_version: Option<String>;
         loop {
             let relations_response = self
                 .ripple_api
                 .get_relations(next_page_token.clone(), page_size)
-                .await?;
-            if relations_response.code != 200 {
-                anyhow::bail!(
-                    "Failed to get relations: code={}, message={}",
-                    relations_response.code,
-                    relations_response.message
-                )
-            }
+                .await?
+                .into_result()?;
             all_users.extend(relations_response.data.users);
             if !relations_response.data.has_more {
                 last_version = relations_response.data.last_version;
@@ -230,10 +348,9 @@ impl<S: RippleStorage> DataSyncManager<S> {
             }
             next_page_token = relations_response.data.next_page_token;
         }
-        if !all_users.is_empty() && last_version.is_some() {
-            self.store_engine.clear_all_relations().await?;
+        if let Some(last_version) = last_version.filter(|_| !all_users.is_empty()) {
             self.store_engine
-                .apply_relation_all(all_users.clone(), &last_version.unwrap())
+                .apply_relation_all(all_users.clone(), &last_version)
                 .await?;
         }
         Ok(())
@@ -243,14 +360,11 @@ impl<S: RippleStorage> DataSyncManager<S> {
         &self,
         last_version: Option<String>,
     ) -> anyhow::Result<RelationsSyncData> {
-        let sync_response = self.ripple_api.sync_relations(last_version).await?;
-        if sync_response.code != 200 {
-            anyhow::bail!(
-                "Failed to sync relations: code={}, message={}",
-                sync_response.code,
-                sync_response.message
-            )
-        }
+        let sync_response = self
+            .ripple_api
+            .sync_relations(last_version)
+            .await?
+            .into_result()?;
         Ok(sync_response.data)
     }
 
@@ -260,7 +374,21 @@ impl<S: RippleStorage> DataSyncManager<S> {
     ) -> anyhow::Result<Option<RelationSyncResult>> {
         let last_version = self.get_relation_version().await?;
         let sync_data = self.sync_incremental_relation_change(last_version).await?;
-        if sync_data.full_sync {
+        let version = sync_version(
+            sync_data.latest_version,
+            sync_data.changes.last().map(|c| &c.version),
+        );
+        let batch = if sync_data.full_sync
+            || has_unknown_operation(SyncDomain::Relations, &sync_data.changes, |c| {
+                matches!(c.operation, RelationOperation::Unknown)
+            }) {
+            None
+        } else {
+            convert_changes(SyncDomain::Relations, sync_data.changes, |c| {
+                (c.user_id.clone(), c.operation)
+            })
+        };
+        let Some((keys, actions)) = batch else {
             self.sync_all_relations().await?;
             if need_result {
                 return Ok(Some(RelationSyncResult::FullSync {
@@ -270,26 +398,23 @@ impl<S: RippleStorage> DataSyncManager<S> {
                 }));
             }
             return Ok(None);
-        }
-        if sync_data.changes.is_empty() {
+        };
+        if actions.is_empty() {
             return Ok(if need_result {
                 Some(RelationSyncResult::NoChange)
             } else {
                 None
             });
         }
-        let mut operations: Vec<Operation<RelationUser, RelationOperation>> = Vec::new();
-        for change in sync_data.changes.into_iter() {
-            let id = change.user_id.clone();
-            let op = change.operation.clone();
-            let version = change.version.clone();
-            let relation_storage_action = self.to_relation_storage_action(change);
-            let data = self
-                .store_engine
-                .apply_relation_action(relation_storage_action, version, true)
-                .await?;
-            operations.push(Operation { id, op, data });
-        }
+        let results = self
+            .store_engine
+            .apply_relation_actions(actions, &version, true)
+            .await?;
+        let operations: Vec<Operation<RelationUser, RelationOperation>> = keys
+            .into_iter()
+            .zip(results)
+            .map(|((id, op), data)| Operation { id, op, data })
+            .collect();
         let result = process_incremental_operations(operations);
 
         Ok(Some(RelationSyncResult::IncrementalSync {
@@ -311,37 +436,28 @@ impl<S: RippleStorage> DataSyncManager<S> {
         let mut all_conversations = Vec::new();
         let mut next_page_token: Option<String> = None;
         let page_size = 50; // Default page size
-        let mut last_version: Option<String> = None;
+        let last_version: Option<String>;
         // Paginate through all conversations
         loop {
             let conversations_response = self
                 .ripple_api
                 .get_conversations(next_page_token.clone(), page_size)
-                .await?;
-            if conversations_response.code != 200 {
-                anyhow::bail!(
-                    "Failed to get conversations: code={}, message={}",
-                    conversations_response.code,
-                    conversations_response.message
-                )
-            }
+                .await?
+                .into_result()?;
             all_conversations.extend(conversations_response.data.conversations);
             if !conversations_response.data.has_more {
-                if conversations_response.data.last_version.is_some() {
-                    last_version = conversations_response.data.last_version;
-                }
+                last_version = conversations_response.data.last_version;
                 break;
             }
             next_page_token = conversations_response.data.next_page_token;
         }
-        if !all_conversations.is_empty() && last_version.is_some() {
+        if let Some(last_version) = last_version.filter(|_| !all_conversations.is_empty()) {
             let storage_conversation_data: Vec<ConversationRecord> = all_conversations
                 .into_iter()
                 .map(|item| item.into())
                 .collect();
-            self.store_engine.clear_all_conversations().await?;
             self.store_engine
-                .apply_conversation_all(storage_conversation_data, &last_version.unwrap())
+                .apply_conversation_all(storage_conversation_data, &last_version)
                 .await?;
         }
         Ok(())
@@ -351,15 +467,11 @@ impl<S: RippleStorage> DataSyncManager<S> {
         &self,
         last_version: Option<String>,
     ) -> anyhow::Result<ConversationSyncData> {
-        let sync_response = self.ripple_api.sync_conversations(last_version).await?;
-
-        if sync_response.code != 200 {
-            anyhow::bail!(
-                "Failed to sync conversations: code={}, message={}",
-                sync_response.code,
-                sync_response.message
-            )
-        }
+        let sync_response = self
+            .ripple_api
+            .sync_conversations(last_version)
+            .await?
+            .into_result()?;
         Ok(sync_response.data)
     }
 
@@ -371,8 +483,21 @@ impl<S: RippleStorage> DataSyncManager<S> {
         let sync_data = self
             .sync_incremental_conversation_change(last_version)
             .await?;
-
-        if sync_data.full_sync {
+        let version = sync_version(
+            sync_data.latest_version,
+            sync_data.changes.last().map(|c| &c.version),
+        );
+        let batch = if sync_data.full_sync
+            || has_unknown_operation(SyncDomain::Conversations, &sync_data.changes, |c| {
+                matches!(c.operation, ConversationOperation::Unknown)
+            }) {
+            None
+        } else {
+            convert_changes(SyncDomain::Conversations, sync_data.changes, |c| {
+                (c.conversation_id.clone(), c.operation)
+            })
+        };
+        let Some((keys, actions)) = batch else {
             self.sync_all_conversations().await?;
             if need_result {
                 return Ok(Some(ConversationSyncResult::FullSync {
@@ -380,27 +505,24 @@ impl<S: RippleStorage> DataSyncManager<S> {
                 }));
             }
             return Ok(None);
-        }
+        };
 
-        if sync_data.changes.is_empty() {
+        if actions.is_empty() {
             return Ok(if need_result {
                 Some(ConversationSyncResult::NoChange)
             } else {
                 None
             });
         }
-        let mut operations: Vec<Operation<ConversationRecord, ConversationOperation>> = Vec::new();
-        for change in sync_data.changes.into_iter() {
-            let id = change.conversation_id.clone();
-            let version = change.version.clone();
-            let op = change.operation.clone();
-            let relation_storage_action = self.to_conversation_storage_action(change);
-            let data = self
-                .store_engine
-                .apply_conversation_action(relation_storage_action, version, true)
-                .await?;
-            operations.push(Operation { id, op, data });
-        }
+        let results = self
+            .store_engine
+            .apply_conversation_actions(actions, &version, true)
+            .await?;
+        let operations: Vec<Operation<ConversationRecord, ConversationOperation>> = keys
+            .into_iter()
+            .zip(results)
+            .map(|((id, op), data)| Operation { id, op, data })
+            .collect();
         let result = process_incremental_operations(operations);
 
         Ok(Some(ConversationSyncResult::IncrementalSync {
@@ -433,6 +555,23 @@ impl<S: RippleStorage> DataSyncManager<S> {
             .await
     }
 
+    /// Looks a conversation up locally, syncing conversations first when it is not known yet
+    /// (for example because it was started since the last sync).
+    pub async fn find_conversation(
+        &self,
+        conversation_id: &str,
+    ) -> anyhow::Result<Option<ConversationRecord>> {
+        if let Some(conversation) = self.get_conversation(conversation_id).await? {
+            return Ok(Some(conversation));
+        }
+        if self.exist_conversations().await? {
+            self.process_conversations_sync(false).await?;
+        } else {
+            self.sync_all_conversations().await?;
+        }
+        self.get_conversation(conversation_id).await
+    }
+
     pub async fn sync_conversation_summaries(&self) -> anyhow::Result<()> {
         let conversations = self.store_engine.get_all_conversations().await?;
         if conversations.is_empty() {
@@ -447,28 +586,13 @@ impl<S: RippleStorage> DataSyncManager<S> {
         let response = self
             .ripple_api
             .get_conversation_summaries(conversation_ids)
-            .await?;
+            .await?
+            .into_result()?;
 
-        if response.code != 200 {
-            anyhow::bail!(
-                "Failed to get conversation summaries: code={}, message={}",
-                response.code,
-                response.message
-            )
-        }
-
-        for summary in response.data.summaries {
-            // Pass raw template text to storage - frontend handles personalization
-            self.store_engine
-                .update_conversation_summary(
-                    &summary.conversation_id,
-                    summary.unread_count,
-                    summary.last_message_id,
-                    summary.last_message_text.clone(),
-                    Some(summary.last_message_timestamp),
-                )
-                .await?;
-        }
+        // Pass raw template text to storage - frontend handles personalization
+        self.store_engine
+            .update_conversation_summaries(response.data.summaries)
+            .await?;
 
         Ok(())
     }
@@ -520,8 +644,8 @@ impl<S: RippleStorage> DataSyncManager<S> {
                 .cloned()
                 .unwrap_or_else(|| "0".to_string());
 
-            println!(
-                "[DataSyncManager] Filling gap for {}: after_id={}, server_last={:?}",
+            debug!(
+                "Filling gap for {}: after_id={}, server_last={:?}",
                 conversation_id, after_id, server_last_msg_id
             );
 
@@ -536,8 +660,8 @@ impl<S: RippleStorage> DataSyncManager<S> {
                 }
 
                 let fetched_count = api_response.data.messages.len();
-                println!(
-                    "[DataSyncManager] Fetched {} new messages for {}",
+                debug!(
+                    "Fetched {} new messages for {}",
                     fetched_count, conversation_id
                 );
 
@@ -546,9 +670,9 @@ impl<S: RippleStorage> DataSyncManager<S> {
                     after_id = last_msg.message_id.clone();
                 }
 
-                for msg in &api_response.data.messages {
-                    self.store_engine.store_message(msg.clone()).await?;
-                }
+                self.store_engine
+                    .store_messages(api_response.data.messages.clone())
+                    .await?;
                 storage_messages.extend(api_response.data.messages);
 
                 // If we got less than requested, we've reached the end
@@ -556,6 +680,7 @@ impl<S: RippleStorage> DataSyncManager<S> {
                     break;
                 }
             }
+            self.record_sync_success(SyncDomain::Messages).await?;
         }
 
         Ok(ReadMessagesData {
@@ -586,21 +711,36 @@ impl<S: RippleStorage> DataSyncManager<S> {
         let api_response = self
             .ripple_api
             .read_messages(conversation_id.clone(), before_message_id, read_size)
+            .await?
+            .into_result()?;
+
+        self.store_engine
+            .store_messages(api_response.data.messages.clone())
             .await?;
 
-        if api_response.code != 200 {
-            anyhow::bail!(
-                "Failed to read messages before: code={}, message={}",
-                api_response.code,
-                api_response.message
-            )
-        }
+        Ok(api_response.data)
+    }
 
-        for msg in &api_response.data.messages {
-            self.store_engine.store_message(msg.clone()).await?;
+    /// Decodes messages stored with raw content again and replaces the rows whose type this
+    /// client now understands. Returns the number of messages that were upgraded.
+    pub async fn redecode_unsupported_messages(&self) -> anyhow::Result<usize> {
+        let mut decoded = Vec::new();
+        for (message_id, raw) in self.store_engine.get_unsupported_messages().await? {
+            match raw.decode() {
+                Ok(item) if !item.is_unsupported() => decoded.push(item),
+                Ok(_) => {}
+                Err(e) => warn!(
+                    "Failed to decode raw content of message {}: {}",
+                    message_id, e
+                ),
+            }
         }
-
-        Ok(api_response.data)
+        let count = decoded.len();
+        if count > 0 {
+            info!("Re-decoded {} previously unsupported messages", count);
+            self.store_engine.store_messages(decoded).await?;
+        }
+        Ok(count)
     }
 
     /// Store a message in local cache (for WebSocket-received messages)
@@ -620,93 +760,6 @@ impl<S: RippleStorage> DataSyncManager<S> {
         Ok(response)
     }
 
-    fn to_relation_storage_action(&self, change: RelationChange) -> RelationStorageAction {
-        match change.operation {
-            RelationOperation::AddFriend => RelationStorageAction::Upsert(change.into()),
-            RelationOperation::UpdateFriendRemarkName => RelationStorageAction::UpdateRemarkName {
-                user_id: change.user_id,
-                remark_name: change.remark_name.unwrap_or_default(),
-            },
-            RelationOperation::DeleteFriend => RelationStorageAction::Delete {
-                user_id: change.user_id,
-            },
-            RelationOperation::AddBlock => RelationStorageAction::UpdateFlags {
-                user_id: change.user_id,
-                flags: change.relation_flags,
-            },
-            RelationOperation::DeleteBlock => RelationStorageAction::Delete {
-                user_id: change.user_id,
-            },
-            RelationOperation::UnblockRestoreFriend => RelationStorageAction::UpdateFlags {
-                user_id: change.user_id,
-                flags: change.relation_flags,
-            },
-            RelationOperation::HideBlock => RelationStorageAction::UpdateFlags {
-                user_id: change.user_id,
-                flags: change.relation_flags,
-            },
-            RelationOperation::UpdateFriendNickName => RelationStorageAction::UpdateNickName {
-                user_id: change.user_id,
-                nick_name: change.nick_name.unwrap_or_default(),
-            },
-            RelationOperation::UpdateFriendAvatar => RelationStorageAction::UpdateAvatar {
-                user_id: change.user_id,
-                avatar: change.avatar,
-            },
-            RelationOperation::BlockStranger => RelationStorageAction::Upsert(change.into()),
-            RelationOperation::SyncFriendInfo => RelationStorageAction::UpdateNickNameAvatar {
-                user_id: change.user_id,
-                nick_name: change.nick_name.unwrap_or_default(),
-                avatar: change.avatar,
-            },
-            _ => {
-                panic!("[DataSyncManager] Unknown relation operation")
-            }
-        }
-    }
-
-    fn to_conversation_storage_action(
-        &self,
-        change: ConversationChange,
-    ) -> ConversationStorageAction {
-        match change.operation {
-            ConversationOperation::CreateConversation => {
-                ConversationStorageAction::Create(change.into())
-            }
-            ConversationOperation::UpdateLastReadMessageId => {
-                ConversationStorageAction::UpdateLastReadMessageId {
-                    conversation_id: change.conversation_id,
-                    last_read_message_id: change.last_read_message_id.unwrap(),
-                }
-            }
-            ConversationOperation::UpdateConversationName => {
-                ConversationStorageAction::UpdateName {
-                    conversation_id: change.conversation_id,
-                    name: change.name.unwrap(),
-                }
-            }
-            ConversationOperation::UpdateConversationAvatar => {
-                ConversationStorageAction::UpdateAvatar {
-                    conversation_id: change.conversation_id,
-                    avatar: change.avatar.unwrap(),
-                }
-            }
-            ConversationOperation::UpdateConversationNameAvatar => {
-                ConversationStorageAction::UpdateNameAvatar {
-                    conversation_id: change.conversation_id,
-                    name: change.name.unwrap(),
-                    avatar: change.avatar.unwrap(),
-                }
-            }
-            ConversationOperation::RemoverConversation => ConversationStorageAction::Delete {
-                conversation_id: change.conversation_id,
-            },
-            _ => {
-                panic!("[DataSyncManager] Unknown conversation operation")
-            }
-        }
-    }
-
     pub async fn exist_user_groups(&self) -> anyhow::Result<bool> {
         self.store_engine.exist_user_groups().await
     }
@@ -715,23 +768,15 @@ impl<S: RippleStorage> DataSyncManager<S> {
         let mut all_groups: Vec<UserGroupData> = Vec::new();
         let mut next_page_token: Option<String> = None;
         let page_size = 50;
-        let mut last_version: Option<String> = None;
+        let last_version: Option<String>;
 
         loop {
             let response = self
                 .ripple_api
                 .get_user_groups(next_page_token.clone(), page_size)
-                .await?;
-            if response.code != 200 {
-                anyhow::bail!(
-                    "Failed to get user groups: code={}, message={}",
-                    response.code,
-                    response.message
-                )
-            }
-            let groups: Vec<UserGroupData> =
-                response.data.groups.into_iter().map(|g| g.into()).collect();
-            all_groups.extend(groups);
+                .await?
+                .into_result()?;
+            all_groups.extend(response.data.groups);
 
             if !response.data.has_more {
                 last_version = response.data.last_version;
@@ -741,10 +786,11 @@ impl<S: RippleStorage> DataSyncManager<S> {
         }
 
         // Store all groups with the lastVersion
-        self.store_engine.clear_all_user_groups().await?;
-        if !all_groups.is_empty() {
+        if all_groups.is_empty() {
+            self.store_engine.clear_all_user_groups().await?;
+        } else if let Some(last_version) = last_version {
             self.store_engine
-                .apply_user_group_all(all_groups, &last_version.unwrap())
+                .apply_user_group_all(all_groups, &last_version)
                 .await?;
         }
         Ok(())
@@ -754,14 +800,11 @@ impl<S: RippleStorage> DataSyncManager<S> {
         &self,
         last_version: Option<String>,
     ) -> anyhow::Result<UserGroupSyncData> {
-        let sync_response = self.ripple_api.sync_user_groups(last_version).await?;
-        if sync_response.code != 200 {
-            anyhow::bail!(
-                "Failed to sync user groups: code={}, message={}",
-                sync_response.code,
-                sync_response.message
-            )
-        }
+        let sync_response = self
+            .ripple_api
+            .sync_user_groups(last_version)
+            .await?
+            .into_result()?;
         Ok(sync_response.data)
     }
 
@@ -773,8 +816,21 @@ impl<S: RippleStorage> DataSyncManager<S> {
         let sync_data = self
             .sync_incremental_user_group_change(last_version)
             .await?;
-
-        if sync_data.full_sync {
+        let version = sync_version(
+            sync_data.latest_version,
+            sync_data.changes.last().map(|c| &c.version),
+        );
+        let batch = if sync_data.full_sync
+            || has_unknown_operation(SyncDomain::UserGroups, &sync_data.changes, |c| {
+                matches!(c.operation, UserGroupOperation::Unknown)
+            }) {
+            None
+        } else {
+            convert_changes(SyncDomain::UserGroups, sync_data.changes, |c| {
+                (c.group_id.clone(), c.operation)
+            })
+        };
+        let Some((keys, actions)) = batch else {
             self.sync_all_user_groups().await?;
             if need_result {
                 return Ok(Some(UserGroupSyncResult::FullSync {
@@ -782,28 +838,24 @@ impl<S: RippleStorage> DataSyncManager<S> {
                 }));
             }
             return Ok(None);
-        }
+        };
 
-        if sync_data.changes.is_empty() {
+        if actions.is_empty() {
             return Ok(if need_result {
                 Some(UserGroupSyncResult::NoChange)
             } else {
                 None
             });
         }
-
-        let mut operations: Vec<Operation<UserGroupData, UserGroupOperation>> = Vec::new();
-        for change in sync_data.changes.into_iter() {
-            let id = change.group_id.clone();
-            let op = change.operation.clone();
-            let version = change.version.clone();
-            let storage_action = self.to_user_group_storage_action(change);
-            let data = self
-                .store_engine
-                .apply_user_group_action(storage_action, version, true)
-                .await?;
-            operations.push(Operation { id, op, data });
-        }
+        let results = self
+            .store_engine
+            .apply_user_group_actions(actions, &version, true)
+            .await?;
+        let operations: Vec<Operation<UserGroupData, UserGroupOperation>> = keys
+            .into_iter()
+            .zip(results)
+            .map(|((id, op), data)| Operation { id, op, data })
+            .collect();
         let result = process_incremental_operations(operations);
 
         Ok(Some(UserGroupSyncResult::IncrementalSync {
@@ -821,47 +873,20 @@ impl<S: RippleStorage> DataSyncManager<S> {
         self.store_engine.get_user_group_version().await
     }
 
-    fn to_user_group_storage_action(&self, change: UserGroupChange) -> UserGroupStorageAction {
-        let op: UserGroupOperation = change.operation.into();
-        match op {
-            UserGroupOperation::Join => UserGroupStorageAction::Upsert(change.into()),
-            UserGroupOperation::Quit => UserGroupStorageAction::Delete {
-                group_id: change.group_id,
-            },
-            UserGroupOperation::UpdateGroupName => UserGroupStorageAction::UpdateName {
-                group_id: change.group_id,
-                name: change.group_name.unwrap_or_default(),
-            },
-            UserGroupOperation::UpdateGroupAvatar => UserGroupStorageAction::UpdateAvatar {
-                group_id: change.group_id,
-                avatar: change.group_avatar,
-            },
-            UserGroupOperation::Unknown => {
-                panic!("[DataSyncManager] Unknown user group operation")
-            }
-        }
-    }
-
     // ==================== Group Members Methods ====================
 
     pub async fn sync_all_group_members(&self, group_id: &str) -> anyhow::Result<()> {
         let mut all_members: Vec<GroupMemberData> = Vec::new();
         let mut next_page_token: Option<String> = None;
         let page_size = 50;
-        let mut last_version: Option<String> = None;
+        let last_version: Option<String>;
 
         loop {
             let response = self
                 .ripple_api
                 .get_group_members(group_id.to_string(), next_page_token.clone(), page_size)
-                .await?;
-            if response.code != 200 {
-                anyhow::bail!(
-                    "Failed to get group members: code={}, message={}",
-                    response.code,
-                    response.message
-                )
-            }
+                .await?
+                .into_result()?;
             all_members.extend(response.data.members);
             if !response.data.has_more {
                 last_version = response.data.last_version;
@@ -871,8 +896,9 @@ impl<S: RippleStorage> DataSyncManager<S> {
         }
 
         // Store all members with the lastVersion
-        self.store_engine.clear_group_members(group_id).await?;
-        if !all_members.is_empty() {
+        if all_members.is_empty() {
+            self.store_engine.clear_group_members(group_id).await?;
+        } else {
             self.store_engine
                 .apply_group_member_all(group_id, all_members, &last_version.unwrap_or_default())
                 .await?;
@@ -888,14 +914,8 @@ impl<S: RippleStorage> DataSyncManager<S> {
         let sync_response = self
             .ripple_api
             .sync_group_members(group_id.to_string(), last_version)
-            .await?;
-        if sync_response.code != 200 {
-            anyhow::bail!(
-                "Failed to sync group members: code={}, message={}",
-                sync_response.code,
-                sync_response.message
-            )
-        }
+            .await?
+            .into_result()?;
         Ok(sync_response.data)
     }
 
@@ -908,8 +928,27 @@ impl<S: RippleStorage> DataSyncManager<S> {
         let sync_data = self
             .sync_incremental_group_member_change(group_id, last_version)
             .await?;
-
-        if sync_data.full_sync {
+        // The changes array may contain multiple groups, only ours is applied
+        let group_changes: Vec<_> = sync_data
+            .changes
+            .iter()
+            .filter(|c| c.group_id == group_id)
+            .collect();
+        let batch = if sync_data.full_sync
+            || has_unknown_operation(SyncDomain::GroupMembers, &group_changes, |c| {
+                c.data
+                    .iter()
+                    .any(|d| matches!(d.operation, GroupMemberOperation::Unknown))
+            }) {
+            None
+        } else {
+            convert_changes(
+                SyncDomain::GroupMembers,
+                group_changes.iter().flat_map(|c| c.data.iter()),
+                |d| (d.user_id.clone().unwrap_or_default(), d.operation),
+            )
+        };
+        let Some((keys, actions)) = batch else {
             self.sync_all_group_members(group_id).await?;
             if need_result {
                 return Ok(Some(GroupMemberSyncResult::FullSync {
@@ -918,38 +957,28 @@ impl<S: RippleStorage> DataSyncManager<S> {
                 }));
             }
             return Ok(None);
-        }
+        };
 
-        if sync_data.changes.is_empty() {
+        if actions.is_empty() {
             return Ok(if need_result {
                 Some(GroupMemberSyncResult::NoChange)
             } else {
                 None
             });
         }
-
-        // Process changes - the changes array may contain multiple groups, filter for our group
-        let mut operations: Vec<Operation<GroupMemberData, GroupMemberOperation>> = Vec::new();
-        for change in sync_data.changes.into_iter() {
-            if change.group_id != group_id {
-                continue;
-            }
-            let version = change.version.clone();
-            for detail in change.data.into_iter() {
-                let op: GroupMemberOperation = detail.operation.into();
-                let user_id = detail.user_id.clone().unwrap_or_default();
-                let storage_action = self.to_group_member_storage_action(&detail);
-                let data = self
-                    .store_engine
-                    .apply_group_member_action(group_id, storage_action, version.clone(), true)
-                    .await?;
-                operations.push(Operation {
-                    id: user_id,
-                    op,
-                    data,
-                });
-            }
-        }
+        let version = sync_version(
+            sync_data.latest_version,
+            group_changes.last().map(|c| &c.version),
+        );
+        let results = self
+            .store_engine
+            .apply_group_member_actions(group_id, actions, &version, true)
+            .await?;
+        let operations: Vec<Operation<GroupMemberData, GroupMemberOperation>> = keys
+            .into_iter()
+            .zip(results)
+            .map(|((id, op), data)| Operation { id, op, data })
+            .collect();
 
         let result = process_incremental_operations(operations);
 
@@ -965,19 +994,46 @@ impl<S: RippleStorage> DataSyncManager<S> {
         self.store_engine.get_all_group_members(group_id).await
     }
 
-    pub async fn exist_group_members(&self, group_id: &str) -> anyhow::Result<bool> {
-        self.store_engine.exist_group_members(group_id).await
-    }
-
-    pub async fn store_group_members(
+    /// Returns the members of a group, fetching them the first time the group is opened
+    /// if the background sync has not reached it yet.
+    pub async fn ensure_group_members<E: EventEmitter>(
         &self,
         group_id: &str,
-        members: Vec<GroupMemberData>,
-    ) -> anyhow::Result<()> {
-        // Use empty version for manually fetched members (not from sync)
-        self.store_engine
-            .apply_group_member_all(group_id, members, "")
-            .await
+        emitter: &E,
+    ) -> anyhow::Result<Vec<GroupMemberData>> {
+        if !self.store_engine.exist_group_members(group_id).await? {
+            info!("Fetching members of group {} on first open", group_id);
+            if let Err(e) = self.sync_all_group_members(group_id).await {
+                if let Err(emit_err) =
+                    emitter.emit_sync_failed(SyncDomain::GroupMembers, e.to_string())
+                {
+                    warn!("{}", emit_err);
+                }
+                return Err(e);
+            }
+            if let Err(e) = emitter.emit_sync_progress(
+                SyncDomain::GroupMembers,
+                Some(group_id.to_string()),
+                1,
+                1,
+            ) {
+                warn!("{}", e);
+            }
+        }
+        self.store_engine.get_all_group_members(group_id).await
+    }
+
+    async fn sync_group_members(&self, group_id: &str) -> anyhow::Result<()> {
+        if !self.store_engine.exist_group_members(group_id).await? {
+            self.sync_all_group_members(group_id).await
+        } else {
+            self.process_group_members_sync(group_id, false).await?;
+            Ok(())
+        }
+    }
+
+    pub async fn exist_group_members(&self, group_id: &str) -> anyhow::Result<bool> {
+        self.store_engine.exist_group_members(group_id).await
     }
 
     async fn get_group_member_version(&self, group_id: &str) -> anyhow::Result<Option<String>> {
@@ -987,36 +1043,417 @@ impl<S: RippleStorage> DataSyncManager<S> {
     pub async fn clear_group_members(&self, group_id: &str) -> anyhow::Result<()> {
         self.store_engine.clear_group_members(group_id).await
     }
+}
 
-    fn to_group_member_storage_action(
-        &self,
-        detail: &crate::ripple_api::api_response::GroupChangeDetail,
-    ) -> GroupMemberStorageAction {
-        let op: GroupMemberOperation = detail.operation.into();
-        match op {
+/// Version to persist with an incremental sync batch: the server's latest version, falling
+/// back to the version of the last applied change.
+fn sync_version(latest_version: Option<String>, last_change_version: Option<&String>) -> String {
+    latest_version
+        .or_else(|| last_change_version.cloned())
+        .unwrap_or_default()
+}
+
+/// Returns true if any change carries an operation this client does not understand. The
+/// incremental batch is then discarded in favour of a full sync, so the local state cannot
+/// drift from the server because of a change we had to skip.
+fn has_unknown_operation<C>(
+    domain: SyncDomain,
+    changes: &[C],
+    is_unknown: impl Fn(&C) -> bool,
+) -> bool {
+    let unknown = changes.iter().filter(|change| is_unknown(change)).count();
+    if unknown > 0 {
+        warn!(
+            "{} {} change(s) with unknown operation, falling back to full sync",
+            unknown,
+            domain.as_str()
+        );
+    }
+    unknown > 0
+}
+
+/// Converts every change of an incremental batch into a storage action, keyed for the sync
+/// result. Returns `None` if any change cannot be converted: saving the batch version after
+/// skipping a change would leave the store behind the server for good, so the caller falls
+/// back to a full sync instead.
+fn convert_changes<C, A, K>(
+    domain: SyncDomain,
+    changes: impl IntoIterator<Item = C>,
+    key: impl Fn(&C) -> K,
+) -> Option<(Vec<K>, Vec<A>)>
+where
+    A: TryFrom<C, Error = ConversionError>,
+{
+    let mut keys = Vec::new();
+    let mut actions = Vec::new();
+    for change in changes {
+        let change_key = key(&change);
+        match A::try_from(change) {
+            Ok(action) => {
+                keys.push(change_key);
+                actions.push(action);
+            }
+            Err(e) => {
+                warn!(
+                    "Cannot apply {} change, falling back to full sync: {}",
+                    domain.as_str(),
+                    e
+                );
+                return None;
+            }
+        }
+    }
+    Some((keys, actions))
+}
+
+impl TryFrom<RelationChange> for RelationStorageAction {
+    type Error = ConversionError;
+
+    fn try_from(change: RelationChange) -> Result<Self, Self::Error> {
+        Ok(match change.operation {
+            RelationOperation::AddFriend => RelationStorageAction::Upsert(change.into()),
+            RelationOperation::UpdateFriendRemarkName => RelationStorageAction::UpdateRemarkName {
+                user_id: change.user_id,
+                remark_name: change.remark_name.unwrap_or_default(),
+            },
+            RelationOperation::DeleteFriend => RelationStorageAction::Delete {
+                user_id: change.user_id,
+            },
+            RelationOperation::AddBlock => RelationStorageAction::UpdateFlags {
+                user_id: change.user_id,
+                flags: change.relation_flags,
+            },
+            RelationOperation::DeleteBlock => RelationStorageAction::Delete {
+                user_id: change.user_id,
+            },
+            RelationOperation::UnblockRestoreFriend => RelationStorageAction::UpdateFlags {
+                user_id: change.user_id,
+                flags: change.relation_flags,
+            },
+            RelationOperation::HideBlock => RelationStorageAction::UpdateFlags {
+                user_id: change.user_id,
+                flags: change.relation_flags,
+            },
+            RelationOperation::UpdateFriendNickName => RelationStorageAction::UpdateNickName {
+                user_id: change.user_id,
+                nick_name: change.nick_name.unwrap_or_default(),
+            },
+            RelationOperation::UpdateFriendAvatar => RelationStorageAction::UpdateAvatar {
+                user_id: change.user_id,
+                avatar: change.avatar,
+            },
+            RelationOperation::BlockStranger => RelationStorageAction::Upsert(change.into()),
+            RelationOperation::SyncFriendInfo => RelationStorageAction::UpdateNickNameAvatar {
+                user_id: change.user_id,
+                nick_name: change.nick_name.unwrap_or_default(),
+                avatar: change.avatar,
+            },
+            RelationOperation::Unknown => {
+                return Err(ConversionError::UnknownOperation("relation"));
+            }
+        })
+    }
+}
+
+impl TryFrom<ConversationChange> for ConversationStorageAction {
+    type Error = ConversionError;
+
+    fn try_from(change: ConversationChange) -> Result<Self, Self::Error> {
+        let missing = |field| ConversionError::MissingField {
+            context: "conversation change",
+            field,
+        };
+        Ok(match change.operation {
+            ConversationOperation::CreateConversation => {
+                ConversationStorageAction::Create(change.try_into()?)
+            }
+            ConversationOperation::UpdateLastReadMessageId => {
+                ConversationStorageAction::UpdateLastReadMessageId {
+                    conversation_id: change.conversation_id,
+                    last_read_message_id: change
+                        .last_read_message_id
+                        .ok_or_else(|| missing("lastReadMessageId"))?,
+                }
+            }
+            ConversationOperation::UpdateConversationName => {
+                ConversationStorageAction::UpdateName {
+                    conversation_id: change.conversation_id,
+                    name: change.name.ok_or_else(|| missing("name"))?,
+                }
+            }
+            ConversationOperation::UpdateConversationAvatar => {
+                ConversationStorageAction::UpdateAvatar {
+                    conversation_id: change.conversation_id,
+                    avatar: change.avatar.ok_or_else(|| missing("avatar"))?,
+                }
+            }
+            ConversationOperation::UpdateConversationNameAvatar => {
+                ConversationStorageAction::UpdateNameAvatar {
+                    conversation_id: change.conversation_id,
+                    name: change.name.ok_or_else(|| missing("name"))?,
+                    avatar: change.avatar.ok_or_else(|| missing("avatar"))?,
+                }
+            }
+            ConversationOperation::RemoverConversation => ConversationStorageAction::Delete {
+                conversation_id: change.conversation_id,
+            },
+            ConversationOperation::Unknown => {
+                return Err(ConversionError::UnknownOperation("conversation"));
+            }
+        })
+    }
+}
+
+impl TryFrom<UserGroupChange> for UserGroupStorageAction {
+    type Error = ConversionError;
+
+    fn try_from(change: UserGroupChange) -> Result<Self, Self::Error> {
+        Ok(match change.operation {
+            UserGroupOperation::Join => UserGroupStorageAction::Upsert(change.into()),
+            UserGroupOperation::Quit => UserGroupStorageAction::Delete {
+                group_id: change.group_id,
+            },
+            UserGroupOperation::UpdateGroupName => UserGroupStorageAction::UpdateName {
+                group_id: change.group_id,
+                name: change.group_name.unwrap_or_default(),
+            },
+            UserGroupOperation::UpdateGroupAvatar => UserGroupStorageAction::UpdateAvatar {
+                group_id: change.group_id,
+                avatar: change.group_avatar,
+            },
+            UserGroupOperation::Unknown => {
+                return Err(ConversionError::UnknownOperation("user group"));
+            }
+        })
+    }
+}
+
+impl TryFrom<&GroupChangeDetail> for GroupMemberStorageAction {
+    type Error = ConversionError;
+
+    fn try_from(detail: &GroupChangeDetail) -> Result<Self, Self::Error> {
+        let missing = |field| ConversionError::MissingField {
+            context: "group member change",
+            field,
+        };
+        let user_id = || detail.user_id.clone().ok_or_else(|| missing("userId"));
+        let name = || detail.name.clone().ok_or_else(|| missing("name"));
+        Ok(match detail.operation {
             GroupMemberOperation::MemberJoin | GroupMemberOperation::CreateGroup => {
                 GroupMemberStorageAction::Upsert(GroupMemberData {
-                    user_id: detail.user_id.clone().unwrap(),
-                    name: detail.name.clone().unwrap(),
+                    user_id: user_id()?,
+                    name: name()?,
                     avatar: detail.avatar.clone(),
                 })
             }
             GroupMemberOperation::MemberQuit | GroupMemberOperation::DeleteGroup => {
                 GroupMemberStorageAction::Delete {
-                    user_id: detail.user_id.clone().unwrap(),
+                    user_id: user_id()?,
                 }
             }
             GroupMemberOperation::MemberUpdateName => GroupMemberStorageAction::UpdateName {
-                user_id: detail.user_id.clone().unwrap(),
-                name: detail.name.clone().unwrap(),
+                user_id: user_id()?,
+                name: name()?,
             },
             GroupMemberOperation::MemberUpdateAvatar => GroupMemberStorageAction::UpdateAvatar {
-                user_id: detail.user_id.clone().unwrap(),
+                user_id: user_id()?,
                 avatar: detail.avatar.clone(),
             },
             GroupMemberOperation::Unknown => {
-                panic!("[DataSyncManager] Unknown group member operation")
+                return Err(ConversionError::UnknownOperation("group member"));
             }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relation_change(operation: RelationOperation) -> RelationChange {
+        RelationChange {
+            version: "1".to_string(),
+            operation,
+            user_id: "u1".to_string(),
+            nick_name: Some("nick".to_string()),
+            avatar: None,
+            remark_name: None,
+            relation_flags: 1,
         }
     }
+
+    fn conversation_change(operation: ConversationOperation) -> ConversationChange {
+        ConversationChange {
+            version: "1".to_string(),
+            operation,
+            conversation_id: "c1".to_string(),
+            peer_id: None,
+            group_id: None,
+            last_read_message_id: None,
+            name: Some("name".to_string()),
+            avatar: None,
+        }
+    }
+
+    fn group_change_detail(operation: GroupMemberOperation) -> GroupChangeDetail {
+        GroupChangeDetail {
+            operation,
+            user_id: Some("u1".to_string()),
+            name: None,
+            avatar: None,
+            group_name: None,
+            group_avatar: None,
+        }
+    }
+
+    #[test]
+    fn relation_change_maps_known_operation() {
+        let action =
+            RelationStorageAction::try_from(relation_change(RelationOperation::AddBlock)).unwrap();
+        assert!(matches!(
+            action,
+            RelationStorageAction::UpdateFlags { ref user_id, flags: 1 } if user_id == "u1"
+        ));
+    }
+
+    #[test]
+    fn relation_change_rejects_unknown_operation() {
+        assert_eq!(
+            RelationStorageAction::try_from(relation_change(RelationOperation::Unknown))
+                .unwrap_err(),
+            ConversionError::UnknownOperation("relation")
+        );
+    }
+
+    #[test]
+    fn conversation_change_maps_known_operation() {
+        let action = ConversationStorageAction::try_from(conversation_change(
+            ConversationOperation::UpdateConversationName,
+        ))
+        .unwrap();
+        assert!(matches!(
+            action,
+            ConversationStorageAction::UpdateName { ref name, .. } if name == "name"
+        ));
+    }
+
+    #[test]
+    fn conversation_change_rejects_missing_field() {
+        assert_eq!(
+            ConversationStorageAction::try_from(conversation_change(
+                ConversationOperation::UpdateLastReadMessageId,
+            ))
+            .unwrap_err(),
+            ConversionError::MissingField {
+                context: "conversation change",
+                field: "lastReadMessageId",
+            }
+        );
+    }
+
+    #[test]
+    fn conversation_create_rejects_missing_name() {
+        let mut change = conversation_change(ConversationOperation::CreateConversation);
+        change.name = None;
+        assert_eq!(
+            ConversationStorageAction::try_from(change).unwrap_err(),
+            ConversionError::MissingField {
+                context: "conversation change",
+                field: "name",
+            }
+        );
+    }
+
+    #[test]
+    fn conversation_change_rejects_unknown_operation() {
+        assert_eq!(
+            ConversationStorageAction::try_from(conversation_change(
+                ConversationOperation::Unknown
+            ))
+            .unwrap_err(),
+            ConversionError::UnknownOperation("conversation")
+        );
+    }
+
+    #[test]
+    fn user_group_change_rejects_unknown_operation() {
+        let change = UserGroupChange {
+            version: "1".to_string(),
+            operation: UserGroupOperation::Unknown,
+            group_id: "g1".to_string(),
+            group_name: None,
+            group_avatar: None,
+        };
+        assert_eq!(
+            UserGroupStorageAction::try_from(change).unwrap_err(),
+            ConversionError::UnknownOperation("user group")
+        );
+    }
+
+    #[test]
+    fn group_member_change_maps_known_operation() {
+        let detail = group_change_detail(GroupMemberOperation::MemberQuit);
+        let action = GroupMemberStorageAction::try_from(&detail).unwrap();
+        assert!(matches!(
+            action,
+            GroupMemberStorageAction::Delete { ref user_id } if user_id == "u1"
+        ));
+    }
+
+    #[test]
+    fn group_member_change_rejects_missing_field() {
+        let detail = group_change_detail(GroupMemberOperation::MemberJoin);
+        assert_eq!(
+            GroupMemberStorageAction::try_from(&detail).unwrap_err(),
+            ConversionError::MissingField {
+                context: "group member change",
+                field: "name",
+            }
+        );
+    }
+
+    #[test]
+    fn group_member_change_rejects_unknown_operation() {
+        let detail = group_change_detail(GroupMemberOperation::Unknown);
+        assert_eq!(
+            GroupMemberStorageAction::try_from(&detail).unwrap_err(),
+            ConversionError::UnknownOperation("group member")
+        );
+    }
+
+    #[test]
+    fn unknown_operation_triggers_full_sync() {
+        let changes = vec![
+            relation_change(RelationOperation::AddFriend),
+            relation_change(RelationOperation::Unknown),
+        ];
+        let is_unknown = |c: &RelationChange| matches!(c.operation, RelationOperation::Unknown);
+        assert!(has_unknown_operation(
+            SyncDomain::Relations,
+            &changes,
+            is_unknown
+        ));
+        assert!(!has_unknown_operation(
+            SyncDomain::Relations,
+            &changes[..1],
+            is_unknown
+        ));
+    }
+
+    #[test]
+    fn unconvertible_change_triggers_full_sync() {
+        let key = |c: &ConversationChange| c.conversation_id.clone();
+        let changes = vec![
+            conversation_change(ConversationOperation::UpdateConversationName),
+            conversation_change(ConversationOperation::UpdateLastReadMessageId),
+        ];
+        let batch: Option<(Vec<String>, Vec<ConversationStorageAction>)> =
+            convert_changes(SyncDomain::Conversations, changes.clone(), key);
+        assert!(batch.is_none());
+
+        let (keys, actions): (Vec<String>, Vec<ConversationStorageAction>) =
+            convert_changes(SyncDomain::Conversations, changes.into_iter().take(1), key).unwrap();
+        assert_eq!(keys, vec!["c1".to_string()]);
+        assert_eq!(actions.len(), 1);
+    }
 }
@@ -1,9 +1,11 @@
 use crate::ripple_api::api_response::{RelationUser, UserGroupData, UserProfileData};
 use crate::ripple_syncer::event_emitter::{EventEmitter, UIConversationItem, UIMessageItem};
 use crate::ripple_syncer::ui_event::{
-    ConversationReceivedMessageEvent, MessageUpdateEvent, UIEvent,
+    ConversationReceivedMessageEvent, MessageUpdateEvent, SyncCompletedEvent, SyncDomain,
+    SyncFailedEvent, SyncProgressEvent, SyncStartedEvent, UIEvent,
 };
 use tauri::{AppHandle, Emitter};
+use tracing::debug;
 
 #[derive(Clone)]
 pub struct DefaultEventEmitter {
@@ -18,42 +20,42 @@ impl DefaultEventEmitter {
 
 impl EventEmitter for DefaultEventEmitter {
     fn emit_user_profile_updated(&self, profile: UserProfileData) -> anyhow::Result<()> {
-        println!("Emitting user profile updated event");
+        debug!("Emitting user profile updated event");
         self.app_handle
             .emit(UIEvent::UserProfileUpdated.to_string().as_str(), &profile)
             .map_err(|e| anyhow::anyhow!("Failed to emit user profile updated event: {}", e))
     }
 
     fn emit_relation_insert(&self, user: RelationUser) -> anyhow::Result<()> {
-        println!("Emitting relation insert event");
+        debug!("Emitting relation insert event");
         self.app_handle
             .emit(UIEvent::RelationInserted.to_string().as_str(), &user)
             .map_err(|e| anyhow::anyhow!("Failed to emit relation insert event: {}", e))
     }
 
     fn emit_relation_update(&self, user: RelationUser) -> anyhow::Result<()> {
-        println!("Emitting relation updated event");
+        debug!("Emitting relation updated event");
         self.app_handle
             .emit(UIEvent::RelationUpdated.to_string().as_str(), &user)
             .map_err(|e| anyhow::anyhow!("Failed to emit relation updated event: {}", e))
     }
 
     fn emit_relation_delete(&self, user_id: String) -> anyhow::Result<()> {
-        println!("Emitting relation deleted event");
+        debug!("Emitting relation deleted event");
         self.app_handle
             .emit(UIEvent::RelationDeleted.to_string().as_str(), &user_id)
             .map_err(|e| anyhow::anyhow!("Failed to emit relation deleted event: {}", e))
     }
 
     fn emit_relations_clear_all(&self) -> anyhow::Result<()> {
-        println!("Emitting relations cleared event");
+        debug!("Emitting relations cleared event");
         self.app_handle
             .emit(UIEvent::RelationClearedAll.to_string().as_str(), &())
             .map_err(|e| anyhow::anyhow!("Failed to emit relations cleared event: {}", e))
     }
 
     fn emit_conversation_insert(&self, conversation: UIConversationItem) -> anyhow::Result<()> {
-        println!("Emitting conversation insert event");
+        debug!("Emitting conversation insert event");
         self.app_handle
             .emit(
                 UIEvent::ConversationInserted.to_string().as_str(),
@@ -63,7 +65,7 @@ impl EventEmitter for DefaultEventEmitter {
     }
 
     fn emit_conversation_update(&self, conversation: UIConversationItem) -> anyhow::Result<()> {
-        println!("Emitting conversation update event");
+        debug!("Emitting conversation update event");
         self.app_handle
             .emit(
                 UIEvent::ConversationUpdated.to_string().as_str(),
@@ -73,7 +75,7 @@ impl EventEmitter for DefaultEventEmitter {
     }
 
     fn emit_conversation_delete(&self, conversation_id: String) -> anyhow::Result<()> {
-        println!("Emitting conversation delete event");
+        debug!("Emitting conversation delete event");
         self.app_handle
             .emit(
                 UIEvent::ConversationsDeleted.to_string().as_str(),
@@ -83,7 +85,7 @@ impl EventEmitter for DefaultEventEmitter {
     }
 
     fn emit_conversation_delete_all(&self) -> anyhow::Result<()> {
-        println!("Emitting conversation delete all event");
+        debug!("Emitting conversation delete all event");
         self.app_handle
             .emit(UIEvent::ConversationsClearedAll.to_string().as_str(), &())
             .map_err(|e| anyhow::anyhow!("Failed to emit conversation delete all event: {}", e))
@@ -96,7 +98,7 @@ impl EventEmitter for DefaultEventEmitter {
         message: String,
         timestamp: String,
     ) -> anyhow::Result<()> {
-        println!("Emitting conversations received event");
+        debug!("Emitting conversations received event");
         let event = ConversationReceivedMessageEvent {
             conversation_id,
             unread_count,
@@ -116,7 +118,7 @@ impl EventEmitter for DefaultEventEmitter {
         action: i32,
         message: Option<UIMessageItem>,
     ) -> anyhow::Result<()> {
-        println!("Emitting message updated event: action={}", action);
+        debug!("Emitting message updated event: action={}", action);
         let event = MessageUpdateEvent { action, message };
         self.app_handle
             .emit(UIEvent::MessageUpdated.to_string().as_str(), &event)
@@ -124,7 +126,7 @@ impl EventEmitter for DefaultEventEmitter {
     }
 
     fn emit_messages_cleared(&self) -> anyhow::Result<()> {
-        println!("Emitting messages cleared event");
+        debug!("Emitting messages cleared event");
         let event = MessageUpdateEvent {
             action: -1, // CLEAR action
             message: None,
@@ -135,30 +137,98 @@ impl EventEmitter for DefaultEventEmitter {
     }
 
     fn emit_user_group_insert(&self, group: UserGroupData) -> anyhow::Result<()> {
-        println!("Emitting user group insert event");
+        debug!("Emitting user group insert event");
         self.app_handle
             .emit(UIEvent::UserGroupInserted.to_string().as_str(), &group)
             .map_err(|e| anyhow::anyhow!("Failed to emit user group insert event: {}", e))
     }
 
     fn emit_user_group_update(&self, group: UserGroupData) -> anyhow::Result<()> {
-        println!("Emitting user group update event");
+        debug!("Emitting user group update event");
         self.app_handle
             .emit(UIEvent::UserGroupUpdated.to_string().as_str(), &group)
             .map_err(|e| anyhow::anyhow!("Failed to emit user group update event: {}", e))
     }
 
     fn emit_user_group_delete(&self, group_id: String) -> anyhow::Result<()> {
-        println!("Emitting user group delete event");
+        debug!("Emitting user group delete event");
         self.app_handle
             .emit(UIEvent::UserGroupDeleted.to_string().as_str(), &group_id)
             .map_err(|e| anyhow::anyhow!("Failed to emit user group delete event: {}", e))
     }
 
     fn emit_user_groups_clear_all(&self) -> anyhow::Result<()> {
-        println!("Emitting user groups cleared all event");
+        debug!("Emitting user groups cleared all event");
         self.app_handle
             .emit(UIEvent::UserGroupsClearedAll.to_string().as_str(), &())
             .map_err(|e| anyhow::anyhow!("Failed to emit user groups cleared all event: {}", e))
     }
+
+    fn emit_session_expired(&self) -> anyhow::Result<()> {
+        debug!("Emitting session expired event");
+        self.app_handle
+            .emit(UIEvent::SessionExpired.to_string().as_str(), &())
+            .map_err(|e| anyhow::anyhow!("Failed to emit session expired event: {}", e))
+    }
+
+    fn emit_account_switched(&self, user_id: String) -> anyhow::Result<()> {
+        debug!("Emitting account switched event");
+        self.app_handle
+            .emit(UIEvent::AccountSwitched.to_string().as_str(), &user_id)
+            .map_err(|e| anyhow::anyhow!("Failed to emit account switched event: {}", e))
+    }
+
+    fn emit_sync_started(&self, domain: SyncDomain) -> anyhow::Result<()> {
+        debug!("Emitting sync started event: {}", domain.as_str());
+        self.app_handle
+            .emit(
+                UIEvent::SyncStarted.to_string().as_str(),
+                &SyncStartedEvent { domain },
+            )
+            .map_err(|e| anyhow::anyhow!("Failed to emit sync started event: {}", e))
+    }
+
+    fn emit_sync_progress(
+        &self,
+        domain: SyncDomain,
+        group_id: Option<String>,
+        completed: usize,
+        total: usize,
+    ) -> anyhow::Result<()> {
+        debug!(
+            "Emitting sync progress event: {} {}/{}",
+            domain.as_str(),
+            completed,
+            total
+        );
+        let event = SyncProgressEvent {
+            domain,
+            group_id,
+            completed,
+            total,
+        };
+        self.app_handle
+            .emit(UIEvent::SyncProgress.to_string().as_str(), &event)
+            .map_err(|e| anyhow::anyhow!("Failed to emit sync progress event: {}", e))
+    }
+
+    fn emit_sync_completed(&self, domain: SyncDomain, timestamp: i64) -> anyhow::Result<()> {
+        debug!("Emitting sync completed event: {}", domain.as_str());
+        self.app_handle
+            .emit(
+                UIEvent::SyncCompleted.to_string().as_str(),
+                &SyncCompletedEvent { domain, timestamp },
+            )
+            .map_err(|e| anyhow::anyhow!("Failed to emit sync completed event: {}", e))
+    }
+
+    fn emit_sync_failed(&self, domain: SyncDomain, error: String) -> anyhow::Result<()> {
+        debug!("Emitting sync failed event: {}", domain.as_str());
+        self.app_handle
+            .emit(
+                UIEvent::SyncFailed.to_string().as_str(),
+                &SyncFailedEvent { domain, error },
+            )
+            .map_err(|e| anyhow::anyhow!("Failed to emit sync failed event: {}", e))
+    }
 }
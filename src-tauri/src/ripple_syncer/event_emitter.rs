@@ -1,6 +1,8 @@
+use crate::errors::ConversionError;
 use crate::ripple_api::api_response::{
-    GroupMemberData, RelationUser, UserGroupData, UserProfileData,
+    MessageItemType, RelationUser, UserGroupData, UserProfileData,
 };
+use crate::ripple_syncer::ui_event::SyncDomain;
 use crate::store_engine::store_engine::ConversationRecord;
 use ripple_proto::ripple_pb::{push_message_request, send_message_req, PushMessageRequest};
 use serde::{Deserialize, Serialize};
@@ -73,6 +75,10 @@ impl From<Vec<ConversationRecord>> for UIConversations {
     }
 }
 
+/// Content shown for messages whose type this client does not understand.
+pub const UNSUPPORTED_MESSAGE_PLACEHOLDER: &str =
+    "Unsupported message, please update Ripple to view it";
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct UIMessageItem {
     #[serde(rename = "messageId")]
@@ -97,13 +103,15 @@ pub struct UIMessageItem {
     pub file_name: Option<String>,
 }
 
-impl From<PushMessageRequest> for UIMessageItem {
-    fn from(req: PushMessageRequest) -> Self {
+impl TryFrom<PushMessageRequest> for UIMessageItem {
+    type Error = ConversionError;
+
+    fn try_from(req: PushMessageRequest) -> Result<Self, Self::Error> {
         match req.payload {
             Some(push_message_request::Payload::MessagePayload(msg_payload)) => {
                 let message_data = msg_payload
                     .message_data
-                    .expect("MessagePayload must have message_data");
+                    .ok_or(ConversionError::MissingMessageData)?;
                 match &message_data.message {
                     Some(send_message_req::Message::SingleMessageContent(msg_context)) => {
                         // Map file_url and file_name, treating empty strings as None
@@ -123,7 +131,7 @@ impl From<PushMessageRequest> for UIMessageItem {
                         } else {
                             None
                         };
-                        UIMessageItem {
+                        Ok(UIMessageItem {
                             message_id: message_data.message_id.to_string(),
                             conversation_id: message_data.conversation_id,
                             sender_id: message_data.sender_id.to_string(),
@@ -135,7 +143,7 @@ impl From<PushMessageRequest> for UIMessageItem {
                             command_data: None,
                             file_url,
                             file_name,
-                        }
+                        })
                     }
                     Some(send_message_req::Message::GroupCommandMessageContent(cmd_content)) => {
                         // For group commands, group_id is always present
@@ -144,7 +152,7 @@ impl From<PushMessageRequest> for UIMessageItem {
                         } else {
                             None
                         };
-                        UIMessageItem {
+                        Ok(UIMessageItem {
                             message_id: message_data.message_id.to_string(),
                             conversation_id: message_data.conversation_id,
                             sender_id: message_data.sender_id.to_string(),
@@ -156,12 +164,25 @@ impl From<PushMessageRequest> for UIMessageItem {
                             command_data: Some(cmd_content.text.clone()),
                             file_url: None,
                             file_name: None,
-                        }
+                        })
                     }
-                    _ => panic!("Unsupported message type in PushMessageRequest"),
+                    None => Ok(UIMessageItem {
+                        message_id: message_data.message_id.to_string(),
+                        conversation_id: message_data.conversation_id,
+                        sender_id: message_data.sender_id.to_string(),
+                        group_id: (message_data.group_id != 0)
+                            .then(|| message_data.group_id.to_string()),
+                        content: UNSUPPORTED_MESSAGE_PLACEHOLDER.to_string(),
+                        timestamp: message_data.send_timestamp,
+                        message_type: MessageItemType::Unknown.into(),
+                        command_type: None,
+                        command_data: None,
+                        file_url: None,
+                        file_name: None,
+                    }),
                 }
             }
-            _ => panic!("Invalid PushMessageRequest payload"),
+            _ => Err(ConversionError::MissingPayload),
         }
     }
 }
@@ -202,4 +223,71 @@ pub trait EventEmitter: Send + Sync + Clone + 'static {
     fn emit_user_group_update(&self, group: UserGroupData) -> anyhow::Result<()>;
     fn emit_user_group_delete(&self, group_id: String) -> anyhow::Result<()>;
     fn emit_user_groups_clear_all(&self) -> anyhow::Result<()>;
+
+    fn emit_session_expired(&self) -> anyhow::Result<()>;
+    fn emit_account_switched(&self, user_id: String) -> anyhow::Result<()>;
+
+    fn emit_sync_started(&self, domain: SyncDomain) -> anyhow::Result<()>;
+    fn emit_sync_progress(
+        &self,
+        domain: SyncDomain,
+        group_id: Option<String>,
+        completed: usize,
+        total: usize,
+    ) -> anyhow::Result<()>;
+    fn emit_sync_completed(&self, domain: SyncDomain, timestamp: i64) -> anyhow::Result<()>;
+    fn emit_sync_failed(&self, domain: SyncDomain, error: String) -> anyhow::Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ripple_proto::ripple_pb::{GroupCommandMessageContent, PushMessagePayload, SendMessageReq};
+
+    fn push_request(message: Option<send_message_req::Message>) -> PushMessageRequest {
+        PushMessageRequest {
+            payload: Some(push_message_request::Payload::MessagePayload(
+                PushMessagePayload {
+                    message_data: Some(SendMessageReq {
+                        conversation_id: "c1".to_string(),
+                        message_id: 7,
+                        group_id: 42,
+                        message,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn ui_message_from_group_command() {
+        let req = push_request(Some(send_message_req::Message::GroupCommandMessageContent(
+            GroupCommandMessageContent {
+                command_type: 1,
+                text: "joined".to_string(),
+            },
+        )));
+        let item = UIMessageItem::try_from(req).unwrap();
+        assert_eq!(item.message_type, 2);
+        assert_eq!(item.group_id.as_deref(), Some("42"));
+        assert_eq!(item.command_type, Some(1));
+    }
+
+    #[test]
+    fn ui_message_renders_unknown_content_as_placeholder() {
+        let item = UIMessageItem::try_from(push_request(None)).unwrap();
+        assert_eq!(item.message_type, i32::from(MessageItemType::Unknown));
+        assert_eq!(item.content, UNSUPPORTED_MESSAGE_PLACEHOLDER);
+    }
+
+    #[test]
+    fn ui_message_rejects_missing_payload() {
+        assert_eq!(
+            UIMessageItem::try_from(PushMessageRequest::default()).unwrap_err(),
+            ConversionError::MissingPayload
+        );
+    }
 }
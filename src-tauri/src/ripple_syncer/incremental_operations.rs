@@ -32,13 +32,11 @@ enum TrackingState<T> {
 
 #[derive(Debug, Clone)]
 pub struct InsertItem<T> {
-    pub id: String,
     pub data: T,
 }
 
 #[derive(Debug, Clone)]
 pub struct UpdateItem<T> {
-    pub id: String,
     pub data: T,
 }
 
@@ -133,10 +131,10 @@ where
     for (id, state) in tracking {
         match state {
             TrackingState::New { data } => {
-                inserts.push(InsertItem { id, data });
+                inserts.push(InsertItem { data });
             }
             TrackingState::Existing { data } => {
-                updates.push(UpdateItem { id, data });
+                updates.push(UpdateItem { data });
             }
             TrackingState::Deleted { .. } => {
                 deletes.push(DeleteItem { id });
@@ -1,12 +1,14 @@
 pub mod data_sync_manager;
+#[cfg(feature = "desktop")]
 pub mod default_event_emitter;
 pub mod event_emitter;
 pub mod incremental_operations;
 pub mod ripple_ws_sync_handler;
 pub mod sync_handler;
 
-mod ui_event;
+pub mod ui_event;
 
 pub use data_sync_manager::DataSyncManager;
+#[cfg(feature = "desktop")]
 pub use default_event_emitter::DefaultEventEmitter;
 pub use ripple_ws_sync_handler::RippleWsSyncHandler;
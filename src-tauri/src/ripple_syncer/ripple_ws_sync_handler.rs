@@ -1,26 +1,24 @@
 use crate::ripple_syncer::data_sync_manager::{
-    ConversationSyncResult, GroupMemberSyncResult, RelationSyncResult, UserGroupSyncResult,
+    ConversationSyncResult, RelationSyncResult, UserGroupSyncResult,
+};
+use crate::ripple_syncer::event_emitter::{
+    EventEmitter, UIMessageItem, UNSUPPORTED_MESSAGE_PLACEHOLDER,
 };
-use crate::ripple_syncer::event_emitter::{EventEmitter, UIMessageItem};
 
 use crate::ripple_syncer::sync_handler::RippleSyncHandler;
+use crate::ripple_syncer::ui_event::SyncDomain;
 
-use crate::ripple_api::api_response::{MessageCommandType, MessageItem, MessageItemType};
+use crate::ripple_api::api_response::{
+    MessageCommandType, MessageItem, MessageItemType, RawMessageContent,
+};
 use crate::ripple_syncer::DataSyncManager;
 use crate::ripple_ws::sync_aware_ws_message_handler::PushNotification;
 use crate::store_engine::store_engine::RippleStorage;
+use prost::bytes::Bytes;
 use ripple_proto::ripple_pb::{push_message_request, PushMessageRequest};
-
-const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "webp", "svg", "bmp", "ico"];
-
-/// Check if a file is an image based on its extension
-fn is_image_file(file_name: &str) -> bool {
-    if let Some(ext) = file_name.rsplit('.').next() {
-        IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str())
-    } else {
-        false
-    }
-}
+use std::future::Future;
+use std::time::Instant;
+use tracing::{debug, error, info, warn};
 
 #[derive(Clone)]
 pub struct RippleWsSyncHandler<S, E>
@@ -48,72 +46,56 @@ where
     E: EventEmitter,
 {
     async fn handle_self_info_update_sync(&self, push_notification: PushNotification) {
-        println!(
-            "[RippleWsSyncHandler] Handling self info update sync for user ID: {}",
+        debug!(
+            "Handling self info update sync for user ID: {}",
             push_notification.send_user_id
         );
         match self.data_sync.sync_user_profile().await {
-            Ok(()) => {
-                match self.data_sync.get_profile().await {
-                    Ok(Some(profile_data)) => {
-                        println!(
-                            "[RippleWsSyncHandler] User avatar URL: {}",
-                            profile_data
-                                .avatar
-                                .as_ref()
-                                .unwrap_or(&"None".to_string())
-                        );
-                        if let Err(e) = self
-                            .emitter
-                            .emit_user_profile_updated(profile_data.into())
-                        {
-                            eprintln!(
-                                "[RippleWsSyncHandler] Failed to emit user profile updated event: {}",
-                                e
-                            );
-                        } else {
-                            println!("[RippleWsSyncHandler] Successfully synced and emitted user profile update");
-                        };
-                    }
-                    Ok(None) => {
-                        eprintln!(
-                            "[RippleWsSyncHandler] Failed to retrieve user profile after sync"
-                        );
-                    }
-                    Err(e) => {
-                        eprintln!(
-                            "[RippleWsSyncHandler] Failed to retrieve user profile from DB: {}",
-                            e
-                        );
-                    }
+            Ok(()) => match self.data_sync.get_profile().await {
+                Ok(Some(profile_data)) => {
+                    debug!(
+                        "User avatar URL: {}",
+                        profile_data.avatar.as_ref().unwrap_or(&"None".to_string())
+                    );
+                    if let Err(e) = self.emitter.emit_user_profile_updated(profile_data) {
+                        warn!("Failed to emit user profile updated event: {}", e);
+                    } else {
+                        debug!("Successfully synced and emitted user profile update");
+                    };
                 }
-            }
+                Ok(None) => {
+                    error!("Failed to retrieve user profile after sync");
+                }
+                Err(e) => {
+                    error!("Failed to retrieve user profile from DB: {}", e);
+                }
+            },
             Err(e) => {
-                eprintln!("[RippleWsSyncHandler] Failed to sync user profile: {}", e);
+                error!("Failed to sync user profile: {}", e);
             }
         }
     }
 
     async fn handle_relations_update_sync(&self, push_notification: PushNotification) {
-        println!(
-            "[RippleWsSyncHandler] Handling relation update sync for user ID: {}",
+        debug!(
+            "Handling relation update sync for user ID: {}",
             push_notification.send_user_id
         );
-        if let Ok(result) = self.data_sync.process_relations_sync(true).await {
+        if let Some(result) = self
+            .track_sync(
+                SyncDomain::Relations,
+                self.data_sync.process_relations_sync(true),
+            )
+            .await
+        {
             match result {
                 Some(RelationSyncResult::FullSync { relations }) => {
                     self.emitter.emit_relations_clear_all().unwrap_or_else(|e| {
-                        eprintln!(
-                            "[RippleWsSyncHandler] Failed to emit relations clear all event: {}",
-                            e
-                        );
+                        warn!("Failed to emit relations clear all event: {}", e);
                     });
                     for user in relations.users {
                         self.emitter.emit_relation_insert(user).unwrap_or_else(|e| {
-                            eprintln!(
-                                "[RippleWsSyncHandler] Failed to emit relation insert event: {}",
-                                e
-                            );
+                            warn!("Failed to emit relation insert event: {}", e);
                         });
                     }
                 }
@@ -124,50 +106,52 @@ where
                 }) => {
                     for user in insert {
                         self.emitter.emit_relation_insert(user).unwrap_or_else(|e| {
-                            eprintln!(
-                                "[RippleWsSyncHandler] Failed to emit relation insert event: {}",
-                                e
-                            );
+                            warn!("Failed to emit relation insert event: {}", e);
                         });
                     }
                     for user in update {
                         self.emitter.emit_relation_update(user).unwrap_or_else(|e| {
-                            eprintln!(
-                                "[RippleWsSyncHandler] Failed to emit relation update event: {}",
-                                e
-                            );
+                            warn!("Failed to emit relation update event: {}", e);
                         });
                     }
                     for user_id in delete {
                         self.emitter
                             .emit_relation_delete(user_id)
                             .unwrap_or_else(|e| {
-                                eprintln!(
-                                "[RippleWsSyncHandler] Failed to emit relation delete event: {}",
-                                e
-                            );
+                                warn!("Failed to emit relation delete event: {}", e);
                             });
                     }
                 }
                 Some(RelationSyncResult::NoChange) | None => {
-                    println!("[RippleWsSyncHandler] No relation changes");
+                    debug!("No relation changes");
                 }
             }
-        } else {
-            eprintln!("[RippleWsSyncHandler] Failed to sync relations");
         }
     }
 
     async fn handle_conversation_update_sync(&self, push_notification: PushNotification) {
-        println!(
-            "[RippleWsSyncHandler] Handling conversation update sync for user ID: {}",
+        debug!(
+            "Handling conversation update sync for user ID: {}",
             push_notification.send_user_id
         );
         self.handle_conversation_sync().await;
     }
 
-    async fn handle_message_update_sync(&self, push_req: PushMessageRequest) {
-        let storage_message: MessageItem = (&push_req).into();
+    async fn handle_message_update_sync(&self, push_req: PushMessageRequest, raw_frame: Bytes) {
+        let mut storage_message = match MessageItem::try_from(&push_req) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Skipping message push that cannot be stored: {}", e);
+                return;
+            }
+        };
+        if storage_message.is_unsupported() {
+            debug!(
+                "Storing unsupported message {} with its raw frame",
+                storage_message.message_id
+            );
+            storage_message.raw_content = Some(RawMessageContent::WsFrame(raw_frame.to_vec()));
+        }
 
         // Extract unread_count from the push message payload
         let unread_count = match &push_req.payload {
@@ -177,8 +161,8 @@ where
             _ => 0,
         };
 
-        println!(
-            "[RippleWsSyncHandler] handle_message_update_sync: message_type={:?}, command_type={:?}, conversation_id={}, unread_count={}",
+        debug!(
+            "handle_message_update_sync: message_type={:?}, command_type={:?}, conversation_id={}, unread_count={}",
             storage_message.message_type, storage_message.command_type, storage_message.conversation_id, unread_count
         );
         match self
@@ -188,18 +172,15 @@ where
         {
             Ok(exists) => {
                 if !exists {
-                    println!(
-                        "[RippleWsSyncHandler] Conversation does not exist for ID: {}, sync new conversation",
+                    info!(
+                        "Conversation does not exist for ID: {}, sync new conversation",
                         storage_message.conversation_id.as_str()
                     );
                     self.handle_conversation_sync().await;
                 }
             }
             Err(e) => {
-                eprintln!(
-                    "[RippleWsSyncHandler] Failed to check conversation existence: {}",
-                    e
-                );
+                error!("Failed to check conversation existence: {}", e);
             }
         }
 
@@ -211,7 +192,7 @@ where
         let message = match storage_message.message_type {
             MessageItemType::Text => storage_message.text.clone().unwrap_or_default(),
             MessageItemType::Command => storage_message.command_data.clone().unwrap_or_default(),
-            _ => String::new(),
+            MessageItemType::Unknown => UNSUPPORTED_MESSAGE_PLACEHOLDER.to_string(),
         };
 
         if let Err(e) = self.emitter.emit_conversations_received(
@@ -220,23 +201,21 @@ where
             message,
             storage_message.send_timestamp.clone(),
         ) {
-            eprintln!(
-                "[RippleWsSyncHandler] Failed to emit conversation update: {}",
-                e
-            );
+            warn!("Failed to emit conversation update: {}", e);
         }
+        // A pushed message is not a sync; only gap fills record the messages domain
         if let Err(e) = self.data_sync.store_message(storage_message).await {
-            eprintln!(
-                "[RippleWsSyncHandler] Failed to store message in cache: {}",
-                e
-            );
+            error!("Failed to store pushed message: {}", e);
         }
-        let ui_message: UIMessageItem = push_req.into();
-        if let Err(e) = self.emitter.emit_message_updated(0, Some(ui_message)) {
-            eprintln!(
-                "[RippleWsSyncHandler] Failed to emit message updated: {}",
-                e
-            );
+        match UIMessageItem::try_from(push_req) {
+            Ok(ui_message) => {
+                if let Err(e) = self.emitter.emit_message_updated(0, Some(ui_message)) {
+                    warn!("Failed to emit message updated: {}", e);
+                }
+            }
+            Err(e) => {
+                warn!("Failed to convert message for UI: {}", e);
+            }
         }
     }
 }
@@ -247,22 +226,26 @@ where
     S: RippleStorage,
 {
     async fn handle_conversation_sync(&self) {
-        if let Ok(result) = self.data_sync.process_conversations_sync(true).await {
+        if let Some(result) = self
+            .track_sync(
+                SyncDomain::Conversations,
+                self.data_sync.process_conversations_sync(true),
+            )
+            .await
+        {
             match result {
                 Some(ConversationSyncResult::FullSync { conversations }) => {
-                    self.emitter.emit_conversation_delete_all().unwrap_or_else(|e| {
-                        eprintln!(
-                            "[RippleWsSyncHandler] Failed to emit conversation delete all event: {}",
-                            e
-                        );
-                    });
-                    for convo in conversations.conversations {
-                        self.emitter.emit_conversation_insert(convo).unwrap_or_else(|e| {
-                            eprintln!(
-                                "[RippleWsSyncHandler] Failed to emit conversation insert event: {}",
-                                e
-                            );
+                    self.emitter
+                        .emit_conversation_delete_all()
+                        .unwrap_or_else(|e| {
+                            warn!("Failed to emit conversation delete all event: {}", e);
                         });
+                    for convo in conversations.conversations {
+                        self.emitter
+                            .emit_conversation_insert(convo)
+                            .unwrap_or_else(|e| {
+                                warn!("Failed to emit conversation insert event: {}", e);
+                            });
                     }
                 }
                 Some(ConversationSyncResult::IncrementalSync {
@@ -271,36 +254,31 @@ where
                     delete,
                 }) => {
                     for convo in insert.conversations {
-                        self.emitter.emit_conversation_insert(convo).unwrap_or_else(|e| {
-                            eprintln!(
-                                "[RippleWsSyncHandler] Failed to emit conversation insert event: {}",
-                                e
-                            );
-                        });
+                        self.emitter
+                            .emit_conversation_insert(convo)
+                            .unwrap_or_else(|e| {
+                                warn!("Failed to emit conversation insert event: {}", e);
+                            });
                     }
                     for convo in update.conversations {
-                        self.emitter.emit_conversation_update(convo).unwrap_or_else(|e| {
-                            eprintln!(
-                                "[RippleWsSyncHandler] Failed to emit conversation update event: {}",
-                                e
-                            );
-                        });
+                        self.emitter
+                            .emit_conversation_update(convo)
+                            .unwrap_or_else(|e| {
+                                warn!("Failed to emit conversation update event: {}", e);
+                            });
                     }
                     for convo_id in delete {
-                        self.emitter.emit_conversation_delete(convo_id).unwrap_or_else(|e| {
-                            eprintln!(
-                                "[RippleWsSyncHandler] Failed to emit conversation delete event: {}",
-                                e
-                            );
-                        });
+                        self.emitter
+                            .emit_conversation_delete(convo_id)
+                            .unwrap_or_else(|e| {
+                                warn!("Failed to emit conversation delete event: {}", e);
+                            });
                     }
                 }
                 Some(ConversationSyncResult::NoChange) | None => {
-                    println!("[RippleWsSyncHandler] No conversation changes");
+                    debug!("No conversation changes");
                 }
             }
-        } else {
-            eprintln!("[RippleWsSyncHandler] Failed to sync conversations");
         }
     }
 
@@ -312,7 +290,7 @@ where
         let group_id = match &storage_message.group_id {
             Some(gid) => gid.clone(),
             None => {
-                eprintln!("[RippleWsSyncHandler] GroupCommand message has no group_id");
+                warn!("GroupCommand message has no group_id");
                 return;
             }
         };
@@ -321,8 +299,8 @@ where
         let is_self_leaving = matches!(storage_message.command_type, MessageCommandType::GroupQuit)
             && storage_message.sender_id == current_user_id;
 
-        println!(
-            "[RippleWsSyncHandler] Handling group command: {:?} for group: {}, sender: {}, current_user: {}, is_self_leaving: {}",
+        debug!(
+            "Handling group command: {:?} for group: {}, sender: {}, current_user: {}, is_self_leaving: {}",
             storage_message.command_type, &group_id, &storage_message.sender_id, &current_user_id, is_self_leaving
         );
 
@@ -330,57 +308,55 @@ where
             MessageCommandType::GroupQuit if is_self_leaving => {
                 // Current user left the group: sync conversations (to remove the group conversation)
                 // and clear group_members cache. Do NOT call process_group_members_sync (will return 403).
-                println!("[RippleWsSyncHandler] Self left group, syncing conversations and clearing group members cache");
+                info!("Self left group, syncing conversations and clearing group members cache");
                 self.handle_conversation_sync().await;
                 if let Err(e) = self.data_sync.clear_group_members(&group_id).await {
-                    eprintln!(
-                        "[RippleWsSyncHandler] Failed to clear group members cache: {}",
-                        e
-                    );
+                    error!("Failed to clear group members cache: {}", e);
                 }
             }
             MessageCommandType::GroupJoin | MessageCommandType::GroupQuit => {
                 // Other user joined or left: sync user_groups and group_members
                 self.sync_user_groups_and_emit().await;
-                let _ = self
-                    .data_sync
-                    .process_group_members_sync(&group_id, false)
-                    .await;
+                self.track_sync(
+                    SyncDomain::GroupMembers,
+                    self.data_sync.process_group_members_sync(&group_id, false),
+                )
+                .await;
             }
             MessageCommandType::InfoUpdate => {
                 // Group info updated: sync conversation metadata and user_groups
-                println!("[RippleWsSyncHandler] Group info updated, syncing conversations and user groups");
+                info!("Group info updated, syncing conversations and user groups");
                 self.handle_conversation_sync().await;
                 self.sync_user_groups_and_emit().await;
                 // Note: NO group_members sync - members unchanged
             }
-            MessageCommandType::Empty | MessageCommandType::Unknown | _ => {
+            MessageCommandType::Empty | MessageCommandType::Unknown => {
                 // Unknown command types: no sync needed
-                println!("[RippleWsSyncHandler] Unknown or empty command type, no sync performed");
+                info!("Unknown or empty command type, no sync performed");
             }
         }
     }
 
     async fn sync_user_groups_and_emit(&self) {
-        if let Ok(result) = self.data_sync.process_user_groups_sync(true).await {
+        if let Some(result) = self
+            .track_sync(
+                SyncDomain::UserGroups,
+                self.data_sync.process_user_groups_sync(true),
+            )
+            .await
+        {
             match result {
                 Some(UserGroupSyncResult::FullSync { groups }) => {
                     self.emitter
                         .emit_user_groups_clear_all()
                         .unwrap_or_else(|e| {
-                            eprintln!(
-                            "[RippleWsSyncHandler] Failed to emit user groups clear all event: {}",
-                            e
-                        );
+                            warn!("Failed to emit user groups clear all event: {}", e);
                         });
                     for group in groups {
                         self.emitter
                             .emit_user_group_insert(group)
                             .unwrap_or_else(|e| {
-                                eprintln!(
-                                "[RippleWsSyncHandler] Failed to emit user group insert event: {}",
-                                e
-                            );
+                                warn!("Failed to emit user group insert event: {}", e);
                             });
                     }
                 }
@@ -393,39 +369,62 @@ where
                         self.emitter
                             .emit_user_group_insert(group)
                             .unwrap_or_else(|e| {
-                                eprintln!(
-                                "[RippleWsSyncHandler] Failed to emit user group insert event: {}",
-                                e
-                            );
+                                warn!("Failed to emit user group insert event: {}", e);
                             });
                     }
                     for group in update {
                         self.emitter
                             .emit_user_group_update(group)
                             .unwrap_or_else(|e| {
-                                eprintln!(
-                                "[RippleWsSyncHandler] Failed to emit user group update event: {}",
-                                e
-                            );
+                                warn!("Failed to emit user group update event: {}", e);
                             });
                     }
                     for group_id in delete {
                         self.emitter
                             .emit_user_group_delete(group_id)
                             .unwrap_or_else(|e| {
-                                eprintln!(
-                                "[RippleWsSyncHandler] Failed to emit user group delete event: {}",
-                                e
-                            );
+                                warn!("Failed to emit user group delete event: {}", e);
                             });
                     }
                 }
                 Some(UserGroupSyncResult::NoChange) | None => {
-                    println!("[RippleWsSyncHandler] No user group changes");
+                    debug!("No user group changes");
+                }
+            }
+        }
+    }
+
+    /// Runs one sync operation, reporting it to the UI and recording the time of success.
+    #[tracing::instrument(name = "sync", skip_all, fields(domain = domain.as_str()))]
+    async fn track_sync<T>(
+        &self,
+        domain: SyncDomain,
+        sync: impl Future<Output = anyhow::Result<T>>,
+    ) -> Option<T> {
+        if let Err(e) = self.emitter.emit_sync_started(domain) {
+            warn!("{}", e);
+        }
+        let started = Instant::now();
+        match sync.await {
+            Ok(result) => {
+                debug!("Sync completed in {} ms", started.elapsed().as_millis());
+                match self.data_sync.record_sync_success(domain).await {
+                    Ok(timestamp) => {
+                        if let Err(e) = self.emitter.emit_sync_completed(domain, timestamp) {
+                            warn!("{}", e);
+                        }
+                    }
+                    Err(e) => warn!("Failed to record {} sync time: {}", domain.as_str(), e),
+                }
+                Some(result)
+            }
+            Err(e) => {
+                warn!("Failed to sync {}: {}", domain.as_str(), e);
+                if let Err(e) = self.emitter.emit_sync_failed(domain, e.to_string()) {
+                    warn!("{}", e);
                 }
+                None
             }
-        } else {
-            eprintln!("[RippleWsSyncHandler] Failed to sync user groups");
         }
     }
 }
@@ -1,10 +1,13 @@
 use crate::ripple_ws::sync_aware_ws_message_handler::PushNotification;
+use prost::bytes::Bytes;
 use ripple_proto::ripple_pb::PushMessageRequest;
 
 #[trait_variant::make(RippleSyncHandler: Send)]
+#[allow(dead_code)] // only the Send variant is used
 pub trait SyncHandler: Sync + Clone + 'static {
     async fn handle_self_info_update_sync(&self, push_req: PushNotification);
     async fn handle_relations_update_sync(&self, push_req: PushNotification);
     async fn handle_conversation_update_sync(&self, push_req: PushNotification);
-    async fn handle_message_update_sync(&self, push_req: PushMessageRequest);
+    /// `raw_frame` is the binary frame the message arrived in, kept for unsupported messages.
+    async fn handle_message_update_sync(&self, push_req: PushMessageRequest, raw_frame: Bytes);
 }
@@ -18,6 +18,61 @@ pub struct ConversationReceivedMessageEvent {
     pub timestamp: String,
 }
 
+/// Data set kept in sync with the server, reported in sync events.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+pub enum SyncDomain {
+    #[serde(rename = "relations")]
+    Relations,
+    #[serde(rename = "conversations")]
+    Conversations,
+    #[serde(rename = "user-groups")]
+    UserGroups,
+    #[serde(rename = "group-members")]
+    GroupMembers,
+    #[serde(rename = "messages")]
+    Messages,
+}
+
+impl SyncDomain {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SyncDomain::Relations => "relations",
+            SyncDomain::Conversations => "conversations",
+            SyncDomain::UserGroups => "user-groups",
+            SyncDomain::GroupMembers => "group-members",
+            SyncDomain::Messages => "messages",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SyncStartedEvent {
+    pub domain: SyncDomain,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SyncProgressEvent {
+    pub domain: SyncDomain,
+    /// Group the progress step belongs to, for group member syncs
+    #[serde(rename = "groupId", skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<String>,
+    pub completed: usize,
+    pub total: usize,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SyncCompletedEvent {
+    pub domain: SyncDomain,
+    /// Unix timestamp in milliseconds
+    pub timestamp: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SyncFailedEvent {
+    pub domain: SyncDomain,
+    pub error: String,
+}
+
 pub enum UIEvent {
     UserProfileUpdated,
     RelationInserted,
@@ -34,6 +89,12 @@ pub enum UIEvent {
     UserGroupUpdated,
     UserGroupDeleted,
     UserGroupsClearedAll,
+    SessionExpired,
+    AccountSwitched,
+    SyncStarted,
+    SyncProgress,
+    SyncCompleted,
+    SyncFailed,
 }
 
 impl Display for UIEvent {
@@ -56,6 +117,12 @@ impl Display for UIEvent {
             UIEvent::UserGroupUpdated => "user-group-updated".to_string(),
             UIEvent::UserGroupDeleted => "user-group-deleted".to_string(),
             UIEvent::UserGroupsClearedAll => "user-groups-cleared-all".to_string(),
+            UIEvent::SessionExpired => "session-expired".to_string(),
+            UIEvent::AccountSwitched => "account-switched".to_string(),
+            UIEvent::SyncStarted => "sync-started".to_string(),
+            UIEvent::SyncProgress => "sync-progress".to_string(),
+            UIEvent::SyncCompleted => "sync-completed".to_string(),
+            UIEvent::SyncFailed => "sync-failed".to_string(),
         };
         write!(f, "{}", str)
     }
@@ -0,0 +1,107 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Instant;
+
+const MAX_CONNECTION_EVENTS: usize = 200;
+const MAX_HEARTBEAT_SAMPLES: usize = 100;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionEventKind {
+    Connected,
+    Disconnected,
+    ConnectFailed,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionEvent {
+    pub timestamp: i64,
+    pub session: u64,
+    pub kind: ConnectionEventKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HeartbeatSample {
+    pub timestamp: i64,
+    pub rtt_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionStatsSnapshot {
+    pub events: Vec<ConnectionEvent>,
+    pub heartbeat_rtts: Vec<HeartbeatSample>,
+}
+
+/// Recent WebSocket connection history and heartbeat round trips, kept in memory for
+/// diagnostics. Both lists are bounded; the oldest entries are dropped first.
+#[derive(Default)]
+pub struct ConnectionStats {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    events: VecDeque<ConnectionEvent>,
+    heartbeat_rtts: VecDeque<HeartbeatSample>,
+    // client timestamp of the last heartbeat sent and when it left
+    pending_heartbeat: Option<(i64, Instant)>,
+}
+
+impl ConnectionStats {
+    pub fn record_event(&self, session: u64, kind: ConnectionEventKind, detail: Option<String>) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.events.len() == MAX_CONNECTION_EVENTS {
+            inner.events.pop_front();
+        }
+        inner.events.push_back(ConnectionEvent {
+            timestamp: now_millis(),
+            session,
+            kind,
+            detail,
+        });
+        if !matches!(kind, ConnectionEventKind::Connected) {
+            inner.pending_heartbeat = None;
+        }
+    }
+
+    pub fn record_heartbeat_sent(&self, client_timestamp: i64) {
+        self.inner.lock().unwrap().pending_heartbeat = Some((client_timestamp, Instant::now()));
+    }
+
+    /// Matches a heartbeat response against the last heartbeat sent. Responses to older
+    /// heartbeats are ignored rather than producing inflated round trip times.
+    pub fn record_heartbeat_response(&self, client_timestamp: i64) {
+        let mut inner = self.inner.lock().unwrap();
+        let sent_at = match inner.pending_heartbeat {
+            Some((timestamp, sent_at)) if timestamp == client_timestamp => sent_at,
+            _ => return,
+        };
+        inner.pending_heartbeat = None;
+        if inner.heartbeat_rtts.len() == MAX_HEARTBEAT_SAMPLES {
+            inner.heartbeat_rtts.pop_front();
+        }
+        inner.heartbeat_rtts.push_back(HeartbeatSample {
+            timestamp: now_millis(),
+            rtt_ms: sent_at.elapsed().as_millis() as u64,
+        });
+    }
+
+    pub fn snapshot(&self) -> ConnectionStatsSnapshot {
+        let inner = self.inner.lock().unwrap();
+        ConnectionStatsSnapshot {
+            events: inner.events.iter().cloned().collect(),
+            heartbeat_rtts: inner.heartbeat_rtts.iter().cloned().collect(),
+        }
+    }
+}
+
+fn now_millis() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
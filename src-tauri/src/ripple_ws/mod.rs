@@ -1,7 +1,9 @@
+pub mod connection_stats;
 pub mod ripple_ws_manager;
 pub mod sync_aware_ws_message_handler;
 pub mod syncer_control;
-mod ws_message_handler;
+pub mod ws_message_handler;
+pub mod ws_token_source;
 mod ws_utils;
 
 pub use ripple_ws_manager::RippleWsManager;
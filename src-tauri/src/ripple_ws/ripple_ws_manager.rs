@@ -1,9 +1,9 @@
 use crate::ripple_api::auth_token_parser::AuthTokenParser;
-use crate::ripple_syncer::DataSyncManager;
+use crate::ripple_ws::connection_stats::{ConnectionEventKind, ConnectionStats};
 use crate::ripple_ws::syncer_control::SyncerControl;
 use crate::ripple_ws::ws_message_handler::RippleWsMsgHandler;
+use crate::ripple_ws::ws_token_source::RippleWsTokenSource;
 use crate::ripple_ws::ws_utils::WsUtilsHeartbeatRequest;
-use crate::DefaultStoreEngine;
 use backoff::backoff::Backoff;
 use backoff::ExponentialBackoff;
 use futures_channel::mpsc::UnboundedSender;
@@ -14,150 +14,221 @@ use tokio::sync::Mutex;
 use tokio_tungstenite::connect_async;
 use tokio_tungstenite::tungstenite::client::IntoClientRequest;
 use tokio_tungstenite::tungstenite::Message;
+use tracing::{error, info, info_span, warn, Instrument};
 
-const HEADER_AUTHORIZATION: &'static str = "Authorization";
-const HEADER_RIPPLE_DEVICE_ID: &'static str = "Ripple-Device-ID";
+const HEADER_AUTHORIZATION: &str = "Authorization";
+const HEADER_RIPPLE_DEVICE_ID: &str = "Ripple-Device-ID";
 
-pub struct RippleWsManager<R>
+pub struct RippleWsManager<R, T>
 where
     R: RippleWsMsgHandler + SyncerControl,
+    T: RippleWsTokenSource,
 {
     message_handler: R,
     sender_tx: Arc<Mutex<Option<UnboundedSender<Message>>>>,
     is_running: Arc<AtomicBool>,
-    data_sync: DataSyncManager<DefaultStoreEngine>,
+    token_source: T,
+    connection_stats: Arc<ConnectionStats>,
 }
 
-impl<R> RippleWsManager<R>
+impl<R, T> RippleWsManager<R, T>
 where
     R: RippleWsMsgHandler + SyncerControl,
+    T: RippleWsTokenSource,
 {
     pub fn new(
         msg_handler: R,
-        data_sync: DataSyncManager<DefaultStoreEngine>,
-    ) -> RippleWsManager<R> {
+        token_source: T,
+        connection_stats: Arc<ConnectionStats>,
+    ) -> RippleWsManager<R, T> {
         RippleWsManager {
             message_handler: msg_handler,
             sender_tx: Arc::new(Mutex::new(None)),
             is_running: Arc::new(AtomicBool::new(false)),
-            data_sync,
+            token_source,
+            connection_stats,
         }
     }
+    /// Connects in a task on the ambient tokio runtime and keeps reconnecting until [`stop`](Self::stop).
     pub async fn start(&self, ws_url: &str) -> anyhow::Result<()> {
-        if self.is_running.load(Ordering::SeqCst) {
+        if self
+            .is_running
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
             anyhow::bail!("WebSocket manager is running");
         }
-        self.is_running.store(true, Ordering::SeqCst);
-        let token = self.data_sync.get_token().await?;
-        let claims = AuthTokenParser::decode_jwt_payload(&token.access_token)?;
+        let result = self.spawn_connection(ws_url).await;
+        if result.is_err() {
+            // Nothing was spawned, so a later start must be allowed to try again
+            self.is_running.store(false, Ordering::SeqCst);
+        }
+        result
+    }
+
+    async fn spawn_connection(&self, ws_url: &str) -> anyhow::Result<()> {
+        let access_token = self.token_source.access_token().await?;
+        let claims = AuthTokenParser::decode_jwt_payload(&access_token)?;
         let user_id = claims.get_sub();
-        let device_id = self.data_sync.get_device_id().await?;
-        println!(
-            "Ws client run: User ID: {} Device ID: {}",
-            user_id, device_id
+        let device_id = self.token_source.device_id().await?;
+        let ws_span = info_span!(
+            "ws",
+            user_id = %user_id,
+            device_id = %device_id,
+            session = tracing::field::Empty
         );
+        info!(parent: &ws_span, "WebSocket client starting");
         let mut request = ws_url.into_client_request()?;
-        request.headers_mut().insert(
-            HEADER_AUTHORIZATION,
-            format!("Bearer {}", &token.access_token).parse()?,
-        );
         request
             .headers_mut()
             .insert(HEADER_RIPPLE_DEVICE_ID, device_id.to_string().parse()?);
+        let token_source = self.token_source.clone();
         let sender_tx_clone = self.sender_tx.clone();
         let msg_handler_clone = self.message_handler.clone();
         let is_running_clone = self.is_running.clone();
+        let stats = self.connection_stats.clone();
         self.message_handler.start_syncer().await?;
-        tauri::async_runtime::spawn(async move {
-            let mut backoff = ExponentialBackoff::default();
-            loop {
-                // Check if we should stop before attempting to connect
-                if !is_running_clone.load(Ordering::SeqCst) {
-                    println!("WebSocket manager stopped, exiting reconnection loop");
-                    break;
-                }
-                let result = connect_async(request.clone()).await;
-                match result {
-                    Ok((ws_stream, _)) => {
-                        backoff.reset();
-                        let (mut ws_write, mut ws_read) = ws_stream.split();
-                        // release previous sender if any
-                        if let Some(mut older_sender) = sender_tx_clone.lock().await.take() {
-                            let _ = older_sender.send(Message::Close(None)).await;
+        tokio::spawn(
+            async move {
+                let mut backoff = ExponentialBackoff::default();
+                let mut session = 0u64;
+                // Some(reason) when the task gives up on its own rather than being stopped
+                let gave_up = 'reconnect: loop {
+                    // Check if we should stop before attempting to connect
+                    if !is_running_clone.load(Ordering::SeqCst) {
+                        info!("WebSocket manager stopped, exiting reconnection loop");
+                        break None;
+                    }
+                    // Fetch the token on every attempt so reconnects never reuse an expired one
+                    let result = match token_source.access_token().await {
+                        Ok(access_token) => {
+                            let mut request = request.clone();
+                            match format!("Bearer {}", access_token).parse() {
+                                Ok(value) => {
+                                    request.headers_mut().insert(HEADER_AUTHORIZATION, value);
+                                    connect_async(request).await.map_err(anyhow::Error::from)
+                                }
+                                Err(e) => Err(anyhow::Error::from(e)),
+                            }
                         }
+                        Err(e) => Err(e),
+                    };
+                    match result {
+                        Ok((ws_stream, _)) => {
+                            backoff.reset();
+                            session += 1;
+                            let session_span = tracing::Span::current();
+                            session_span.record("session", session);
+                            info!("WebSocket connected");
+                            stats.record_event(session, ConnectionEventKind::Connected, None);
+                            let (mut ws_write, mut ws_read) = ws_stream.split();
+                            // release previous sender if any
+                            if let Some(mut older_sender) = sender_tx_clone.lock().await.take() {
+                                let _ = older_sender.send(Message::Close(None)).await;
+                            }
 
-                        let (sender_tx, mut sender_rx) =
-                            futures_channel::mpsc::unbounded::<Message>();
-                        sender_tx_clone.lock().await.replace(sender_tx.clone());
-                        let mut heartbeat_req = WsUtilsHeartbeatRequest::new(user_id.clone());
-                        tauri::async_runtime::spawn(async move {
-                            let mut heartbeat_interval =
-                                tokio::time::interval(tokio::time::Duration::from_secs(15));
-                            loop {
-                                tokio::select! {
-                                    Some(message) = sender_rx.next() => {
-                                        let is_close = message.is_close();
-                                        if let Err(e) = ws_write.send(message).await {
-                                            eprintln!("WebSocket send error: {}", e);
-                                            break;
+                            let (sender_tx, mut sender_rx) =
+                                futures_channel::mpsc::unbounded::<Message>();
+                            sender_tx_clone.lock().await.replace(sender_tx.clone());
+                            let mut heartbeat_req = WsUtilsHeartbeatRequest::new(user_id.clone());
+                            let writer_stats = stats.clone();
+                            let writer = async move {
+                                let mut heartbeat_interval =
+                                    tokio::time::interval(tokio::time::Duration::from_secs(15));
+                                loop {
+                                    tokio::select! {
+                                        Some(message) = sender_rx.next() => {
+                                            let is_close = message.is_close();
+                                            if let Err(e) = ws_write.send(message).await {
+                                                warn!("WebSocket send error: {}", e);
+                                                break;
+                                            }
+                                            if is_close {
+                                                break;
+                                            }
                                         }
-                                        if is_close {
-                                            break;
-                                        }
-                                    }
-                                    _ = heartbeat_interval.tick() => {
-                                        let buf = heartbeat_req.get_heartbeat_request_buf();
-                                        if let Err(e) = ws_write.send(Message::binary(buf)).await {
-                                            eprintln!("WebSocket heartbeat error: {}", e);
-                                            break;
+                                        _ = heartbeat_interval.tick() => {
+                                            let buf = heartbeat_req.get_heartbeat_request_buf();
+                                            writer_stats.record_heartbeat_sent(heartbeat_req.timestamp());
+                                            if let Err(e) = ws_write.send(Message::binary(buf)).await {
+                                                warn!("WebSocket heartbeat error: {}", e);
+                                                break;
+                                            }
                                         }
                                     }
                                 }
+                            };
+                            tokio::spawn(writer.instrument(session_span));
+                            msg_handler_clone.notify_connect().await;
+                            let mut disconnect_reason = None;
+                            while let Some(message) = ws_read.next().await {
+                                if message.is_err() {
+                                    let e = message.err().unwrap();
+                                    warn!("WebSocket read error: {}", e);
+                                    disconnect_reason = Some(e.to_string());
+                                    break;
+                                }
+                                let result = msg_handler_clone
+                                    .handle_message(&sender_tx, message.unwrap())
+                                    .await;
+                                if result.is_err() {
+                                    let e = result.err().unwrap();
+                                    error!("WebSocket message handling error: {}", e);
+                                    stats.record_event(
+                                        session,
+                                        ConnectionEventKind::Stopped,
+                                        Some(e.to_string()),
+                                    );
+                                    break 'reconnect Some(e.to_string());
+                                }
                             }
-                        });
-                        msg_handler_clone.notify_connect().await;
-                        while let Some(message) = ws_read.next().await {
-                            if message.is_err() {
-                                eprintln!("WebSocket read error: {}", message.err().unwrap());
-                                break;
-                            }
-                            let result = msg_handler_clone
-                                .handle_message(&sender_tx, message.unwrap())
-                                .await;
-                            if result.is_err() {
-                                eprintln!(
-                                    "WebSocket message handling error: {}",
-                                    result.err().unwrap()
-                                );
-                                return;
+                            msg_handler_clone.notify_disconnect().await;
+                            info!("WebSocket disconnected");
+                            stats.record_event(
+                                session,
+                                ConnectionEventKind::Disconnected,
+                                disconnect_reason,
+                            );
+                            // Check if we should stop after disconnect
+                            if !is_running_clone.load(Ordering::SeqCst) {
+                                info!("WebSocket manager stopped after disconnect");
+                                break None;
                             }
                         }
-                        msg_handler_clone.notify_disconnect().await;
-                        // Check if we should stop after disconnect
-                        if !is_running_clone.load(Ordering::SeqCst) {
-                            println!("WebSocket manager stopped after disconnect");
-                            break;
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("WebSocket connection error: {}", e);
-                        // Check if we should stop before retrying
-                        if !is_running_clone.load(Ordering::SeqCst) {
-                            println!("WebSocket manager stopped, not retrying");
-                            break;
-                        }
-                        if let Some(duration) = backoff.next_backoff() {
-                            tokio::time::sleep(duration).await;
-                        } else {
-                            eprintln!(
-                                "WebSocket max retries reached. Stopping reconnection attempts."
+                        Err(e) => {
+                            error!("WebSocket connection error: {}", e);
+                            stats.record_event(
+                                session,
+                                ConnectionEventKind::ConnectFailed,
+                                Some(e.to_string()),
                             );
-                            break;
+                            // Check if we should stop before retrying
+                            if !is_running_clone.load(Ordering::SeqCst) {
+                                info!("WebSocket manager stopped, not retrying");
+                                break None;
+                            }
+                            if let Some(duration) = backoff.next_backoff() {
+                                tokio::time::sleep(duration).await;
+                            } else {
+                                error!("WebSocket max retries reached, giving up");
+                                stats.record_event(
+                                    session,
+                                    ConnectionEventKind::Stopped,
+                                    Some("max retries reached".to_string()),
+                                );
+                                break Some("max retries reached".to_string());
+                            }
                         }
                     }
+                };
+                if let Some(reason) = gave_up {
+                    // stop() was never called, so clear the flag here to let start() run again
+                    is_running_clone.store(false, Ordering::SeqCst);
+                    msg_handler_clone.notify_ws_stop(reason).await;
                 }
             }
-        });
+            .instrument(ws_span),
+        );
         Ok(())
     }
     pub async fn send_message(&self, msg: Message) -> anyhow::Result<()> {
@@ -1,8 +1,11 @@
+use crate::errors::ConversionError;
 use crate::ripple_syncer::sync_handler::RippleSyncHandler;
+use crate::ripple_ws::connection_stats::ConnectionStats;
 use crate::ripple_ws::syncer_control::SyncerControl;
 use crate::ripple_ws::ws_message_handler::RippleWsMsgHandler;
 use futures_channel::mpsc::UnboundedSender;
 use futures_util::StreamExt;
+use prost::bytes::Bytes;
 use prost::Message as ProstMessage;
 use ripple_proto::ripple_pb;
 use ripple_proto::ripple_pb::push_message_request::Payload;
@@ -12,6 +15,7 @@ use std::sync::Arc;
 use tokio::sync::watch::Sender;
 use tokio::sync::{watch, Mutex};
 use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, error, info, warn};
 
 pub struct PushNotification {
     pub event_type: PushEventType,
@@ -21,13 +25,17 @@ pub struct PushNotification {
 }
 
 impl PushNotification {
-    fn new(event_type: i32, push_req: &ripple_pb::PushMessageRequest) -> Self {
-        PushNotification {
-            event_type: PushEventType::try_from(event_type).unwrap(),
+    fn new(
+        event_type: i32,
+        push_req: &ripple_pb::PushMessageRequest,
+    ) -> Result<Self, ConversionError> {
+        Ok(PushNotification {
+            event_type: PushEventType::try_from(event_type)
+                .map_err(|_| ConversionError::UnknownEventType(event_type))?,
             send_user_id: push_req.send_user_id.parse().unwrap_or(0),
             receive_user_id: push_req.receive_user_id.parse().unwrap_or(0),
             receive_device_id: push_req.receive_device_id.clone(),
-        }
+        })
     }
 }
 
@@ -35,7 +43,7 @@ struct SyncAwareWsMessageHandlerInner {
     self_update_sender: Option<UnboundedSender<PushNotification>>,
     relation_update_sender: Option<UnboundedSender<PushNotification>>,
     conversation_update_sender: Option<UnboundedSender<PushNotification>>,
-    message_update_sender: Option<UnboundedSender<ripple_pb::PushMessageRequest>>,
+    message_update_sender: Option<UnboundedSender<(ripple_pb::PushMessageRequest, Bytes)>>,
     watch_tx: Option<Sender<bool>>,
 }
 
@@ -46,13 +54,14 @@ where
 {
     inner: Arc<Mutex<SyncAwareWsMessageHandlerInner>>,
     syncer: S,
+    connection_stats: Arc<ConnectionStats>,
 }
 
 impl<S> SyncAwareWsMessageHandler<S>
 where
     S: RippleSyncHandler,
 {
-    pub fn new(syncer: S) -> SyncAwareWsMessageHandler<S> {
+    pub fn new(syncer: S, connection_stats: Arc<ConnectionStats>) -> SyncAwareWsMessageHandler<S> {
         SyncAwareWsMessageHandler {
             inner: Arc::new(Mutex::new(SyncAwareWsMessageHandlerInner {
                 self_update_sender: None,
@@ -62,6 +71,7 @@ where
                 watch_tx: None,
             })),
             syncer,
+            connection_stats,
         }
     }
 
@@ -70,14 +80,14 @@ where
         mut receiver: futures_channel::mpsc::UnboundedReceiver<PushNotification>,
         mut watch_rx: watch::Receiver<bool>,
     ) {
-        tauri::async_runtime::spawn(async move {
+        tokio::spawn(async move {
             loop {
                 tokio::select! {
                     Some(push_req) = receiver.next() => {
                         syncer.handle_self_info_update_sync(push_req).await;
                     }
                     _ = watch_rx.changed() => {
-                        if *watch_rx.borrow() == true {
+                        if *watch_rx.borrow() {
                             break;
                         }
                     }
@@ -91,14 +101,14 @@ where
         mut receiver: futures_channel::mpsc::UnboundedReceiver<PushNotification>,
         mut watch_rx: watch::Receiver<bool>,
     ) {
-        tauri::async_runtime::spawn(async move {
+        tokio::spawn(async move {
             loop {
                 tokio::select! {
                     Some(push_req) = receiver.next() => {
                         syncer.handle_relations_update_sync(push_req).await;
                     }
                     _ = watch_rx.changed() => {
-                        if *watch_rx.borrow() == true {
+                        if *watch_rx.borrow() {
                             break;
                         }
                     }
@@ -112,14 +122,14 @@ where
         mut receiver: futures_channel::mpsc::UnboundedReceiver<PushNotification>,
         mut watch_rx: watch::Receiver<bool>,
     ) {
-        tauri::async_runtime::spawn(async move {
+        tokio::spawn(async move {
             loop {
                 tokio::select! {
                     Some(push_req) = receiver.next() => {
                         syncer.handle_conversation_update_sync(push_req).await;
                     }
                     _ = watch_rx.changed() => {
-                        if *watch_rx.borrow() == true {
+                        if *watch_rx.borrow() {
                             break;
                         }
                     }
@@ -130,20 +140,23 @@ where
 
     fn spawn_message_update_handler(
         syncer: S,
-        mut receiver: futures_channel::mpsc::UnboundedReceiver<ripple_pb::PushMessageRequest>,
+        mut receiver: futures_channel::mpsc::UnboundedReceiver<(
+            ripple_pb::PushMessageRequest,
+            Bytes,
+        )>,
         mut watch_rx: watch::Receiver<bool>,
     ) {
-        tauri::async_runtime::spawn(async move {
-            println!("[spawn_message_update_handler] Handler started, waiting for messages...");
+        tokio::spawn(async move {
+            debug!("Handler started, waiting for messages...");
             loop {
                 tokio::select! {
-                    Some(push_req) = receiver.next() => {
-                        println!("[spawn_message_update_handler] Received message, calling handle_message_update_sync");
-                        syncer.handle_message_update_sync(push_req).await;
+                    Some((push_req, raw_frame)) = receiver.next() => {
+                        debug!("Received message, calling handle_message_update_sync");
+                        syncer.handle_message_update_sync(push_req, raw_frame).await;
                     }
                     _ = watch_rx.changed() => {
-                        if *watch_rx.borrow() == true {
-                            println!("[spawn_message_update_handler] Received stop signal, breaking loop");
+                        if *watch_rx.borrow() {
+                            debug!("Received stop signal, breaking loop");
                             break;
                         }
                     }
@@ -166,7 +179,7 @@ where
         let (conversation_update_sender, _conversation_update_receiver) =
             futures_channel::mpsc::unbounded::<PushNotification>();
         let (message_update_sender, message_update_receiver) =
-            futures_channel::mpsc::unbounded::<ripple_pb::PushMessageRequest>();
+            futures_channel::mpsc::unbounded::<(ripple_pb::PushMessageRequest, Bytes)>();
 
         let mut inner = self.inner.lock().await;
         inner.watch_tx.replace(watch_tx);
@@ -211,109 +224,127 @@ where
 {
     async fn handle_message(
         &self,
-        send_tx: &UnboundedSender<Message>,
+        _send_tx: &UnboundedSender<Message>,
         message: Message,
     ) -> anyhow::Result<()> {
         if message.is_binary() {
-            if let Ok(ws) = ripple_pb::WsMessage::decode(message.into_data()) {
+            let raw_frame = message.into_data();
+            if let Ok(ws) = ripple_pb::WsMessage::decode(raw_frame.clone()) {
                 match ws.message_type {
                     Some(MessageType::PushMessageRequest(push_message)) => {
                         match &push_message.payload {
                             Some(Payload::EventPayload(event_payload)) => {
                                 // Handle event notifications (sync triggers)
                                 for event_type in &event_payload.event_types {
-                                    println!("GatewayEventType: {:?}", event_type);
-                                    match PushEventType::try_from(*event_type) {
-                                        Ok(PushEventType::SelfInfoUpdate) => {
-                                            if let Some(sender) =
-                                                &self.inner.lock().await.self_update_sender
-                                            {
-                                                let _ =
-                                                    sender.unbounded_send(PushNotification::new(
-                                                        *event_type,
-                                                        &push_message,
-                                                    ));
+                                    debug!("GatewayEventType: {:?}", event_type);
+                                    let notification =
+                                        match PushNotification::new(*event_type, &push_message) {
+                                            Ok(notification) => notification,
+                                            Err(e) => {
+                                                warn!("Skipping push event: {}", e);
+                                                continue;
                                             }
+                                        };
+                                    let inner = self.inner.lock().await;
+                                    let sender = match notification.event_type {
+                                        PushEventType::SelfInfoUpdate => &inner.self_update_sender,
+                                        PushEventType::RelationUpdate => {
+                                            &inner.relation_update_sender
                                         }
-                                        Ok(PushEventType::RelationUpdate) => {
-                                            if let Some(sender) =
-                                                &self.inner.lock().await.relation_update_sender
-                                            {
-                                                let _ =
-                                                    sender.unbounded_send(PushNotification::new(
-                                                        *event_type,
-                                                        &push_message,
-                                                    ));
-                                            }
-                                        }
-                                        Ok(PushEventType::ConversationUpdate) => {
-                                            if let Some(sender) =
-                                                &self.inner.lock().await.conversation_update_sender
-                                            {
-                                                let _ =
-                                                    sender.unbounded_send(PushNotification::new(
-                                                        *event_type,
-                                                        &push_message,
-                                                    ));
-                                            }
+                                        PushEventType::ConversationUpdate => {
+                                            &inner.conversation_update_sender
                                         }
                                         _ => {
-                                            eprintln!(
-                                                "Unexpected GatewayEventType: {}",
-                                                *event_type
-                                            );
+                                            warn!("Unexpected GatewayEventType: {}", *event_type);
+                                            continue;
                                         }
+                                    };
+                                    if let Some(sender) = sender {
+                                        let _ = sender.unbounded_send(notification);
                                     }
                                 }
                             }
                             Some(Payload::MessagePayload(message_payload)) => {
                                 // Handle message delivery
-                                println!(
+                                debug!(
                                     "MessagePayload received: {:?}",
                                     message_payload.message_type
                                 );
                                 if let Some(sender) = &self.inner.lock().await.message_update_sender
                                 {
-                                    println!("[SyncAwareWsMessageHandler] Sending to message_update_sender");
-                                    let _ = sender.unbounded_send(push_message.clone());
+                                    debug!("Sending to message_update_sender");
+                                    let _ = sender
+                                        .unbounded_send((push_message.clone(), raw_frame.clone()));
                                 } else {
-                                    eprintln!("[SyncAwareWsMessageHandler] message_update_sender is None, syncer not started?");
+                                    warn!("message_update_sender is None, syncer not started?");
                                 }
                             }
                             None => {
-                                eprintln!("PushMessageRequest has no payload");
+                                warn!("PushMessageRequest has no payload");
                             }
                         }
                     }
+                    Some(MessageType::HeartbeatResponse(heartbeat)) => {
+                        self.connection_stats
+                            .record_heartbeat_response(heartbeat.client_timestamp);
+                    }
                     _ => {}
                 }
             } else {
-                println!("Failed to decode WsMessage");
+                info!("Failed to decode WsMessage");
             }
         } else if message.is_text() {
             let text = message.into_text().unwrap();
-            println!("Received text message: {}", text);
+            debug!("Received text frame ({} bytes)", text.len());
         } else if message.is_close() {
-            println!("Received close message");
+            debug!("Received close message");
         } else if message.is_ping() {
-            println!("Received ping message");
+            debug!("Received ping message");
         } else if message.is_pong() {
-            println!("Received pong message");
+            debug!("Received pong message");
         } else {
-            println!("Received other type of message");
+            debug!("Received other type of message");
         }
         Ok(())
     }
 
     async fn notify_connect(&self) {
-        println!("WebSocket connected.");
+        info!("WebSocket connected.");
     }
 
     async fn notify_disconnect(&self) {
-        println!("WebSocket disconnected.");
+        info!("WebSocket disconnected.");
     }
 
     async fn notify_ws_stop(&self, err_msg: String) {
-        eprintln!("WebSocket stopped: {}", err_msg);
+        error!("WebSocket stopped: {}", err_msg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_notification_from_known_event_type() {
+        let req = ripple_pb::PushMessageRequest {
+            send_user_id: "12".to_string(),
+            receive_user_id: "not-a-number".to_string(),
+            ..Default::default()
+        };
+        let notification =
+            PushNotification::new(PushEventType::RelationUpdate as i32, &req).unwrap();
+        assert_eq!(notification.event_type, PushEventType::RelationUpdate);
+        assert_eq!(notification.send_user_id, 12);
+        assert_eq!(notification.receive_user_id, 0);
+    }
+
+    #[test]
+    fn push_notification_rejects_unknown_event_type() {
+        let req = ripple_pb::PushMessageRequest::default();
+        assert_eq!(
+            PushNotification::new(99, &req).err(),
+            Some(ConversionError::UnknownEventType(99))
+        );
     }
 }
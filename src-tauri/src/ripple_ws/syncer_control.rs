@@ -1,3 +1,4 @@
+#[allow(async_fn_in_trait)] // only implemented and called inside this crate
 pub trait SyncerControl {
     async fn start_syncer(&self) -> anyhow::Result<()>;
     async fn stop_syncer(&self) -> anyhow::Result<()>;
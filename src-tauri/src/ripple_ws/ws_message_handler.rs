@@ -2,6 +2,7 @@ use futures_channel::mpsc::UnboundedSender;
 use tokio_tungstenite::tungstenite::Message;
 
 #[trait_variant::make(RippleWsMsgHandler: Send)]
+#[allow(dead_code)] // only the Send variant is used
 pub trait WsMessageHandler: Sync + Clone + 'static {
     async fn handle_message(
         &self,
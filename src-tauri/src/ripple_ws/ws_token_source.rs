@@ -0,0 +1,22 @@
+use crate::ripple_syncer::DataSyncManager;
+use crate::store_engine::store_engine::RippleStorage;
+use uuid::Uuid;
+
+/// Credentials the WebSocket client presents when it (re)connects.
+#[trait_variant::make(RippleWsTokenSource: Send)]
+#[allow(dead_code)] // only the Send variant is used
+pub trait WsTokenSource: Sync + Clone + 'static {
+    /// A currently valid access token; asked for again on every reconnect.
+    async fn access_token(&self) -> anyhow::Result<String>;
+    async fn device_id(&self) -> anyhow::Result<Uuid>;
+}
+
+impl<S: RippleStorage> RippleWsTokenSource for DataSyncManager<S> {
+    async fn access_token(&self) -> anyhow::Result<String> {
+        self.get_access_token().await
+    }
+
+    async fn device_id(&self) -> anyhow::Result<Uuid> {
+        self.get_device_id().await
+    }
+}
@@ -20,6 +20,11 @@ impl WsUtilsHeartbeatRequest {
         }
     }
 
+    /// Client timestamp of the last request built, echoed back in the heartbeat response.
+    pub fn timestamp(&self) -> i64 {
+        self.heartbeat_request.timestamp
+    }
+
     pub fn get_heartbeat_request_buf(&mut self) -> Vec<u8> {
         self.heartbeat_request.timestamp = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
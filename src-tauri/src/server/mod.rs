@@ -0,0 +1,79 @@
+#[cfg(feature = "desktop")]
+mod oauth_callback;
+
+#[cfg(feature = "desktop")]
+pub use oauth_callback::callback_router;
+
+use axum::Router;
+use std::net::SocketAddr;
+use tokio::net::ToSocketAddrs;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+/// A local HTTP server that can be stopped gracefully and started again.
+pub struct Server {
+    close_tx: Option<tokio::sync::oneshot::Sender<()>>,
+    server_handle: Option<JoinHandle<()>>,
+    local_addr: Option<SocketAddr>,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Server {
+            close_tx: None,
+            server_handle: None,
+            local_addr: None,
+        }
+    }
+
+    /// Serves `app` on `addr` and returns the bound address. If the server is already running
+    /// it keeps its routes and address.
+    pub async fn start(
+        &mut self,
+        addr: impl ToSocketAddrs,
+        app: Router,
+    ) -> anyhow::Result<SocketAddr> {
+        if let (Some(_), Some(local_addr)) = (&self.server_handle, self.local_addr) {
+            info!("Server is already running.");
+            return Ok(local_addr);
+        }
+
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        info!("listening on {}", local_addr);
+        let (close_tx, close_rx) = tokio::sync::oneshot::channel::<()>();
+        self.close_tx = Some(close_tx);
+
+        let handle = tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app)
+                .with_graceful_shutdown(async move {
+                    _ = close_rx.await;
+                    info!("Server is shutting down gracefully...");
+                })
+                .await
+            {
+                error!("Server error: {}", e);
+            }
+        });
+
+        self.server_handle = Some(handle);
+        self.local_addr = Some(local_addr);
+        Ok(local_addr)
+    }
+
+    pub async fn stop(&mut self) {
+        info!("Stopping server...");
+        self.local_addr = None;
+        if let Some(tx) = self.close_tx.take() {
+            let _ = tx.send(());
+            info!("Server stop signal sent.");
+
+            if let Some(handle) = self.server_handle.take() {
+                let _ = handle.await;
+                info!("Server stopped successfully.");
+            }
+        } else {
+            info!("Server is not running.");
+        }
+    }
+}
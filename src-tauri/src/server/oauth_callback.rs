@@ -0,0 +1,128 @@
+use crate::desktop::DefaultAccountManager;
+use crate::server::oauth_callback::HtmlFile::{
+    AuthFailed, AuthSuccess, AuthSuccessRestart, InvalidState,
+};
+use axum::extract::{Query, State};
+use axum::response::Html;
+use axum::routing::get;
+use axum::Router;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use tauri::path::BaseDirectory;
+use tauri::{AppHandle, Emitter, Manager};
+use tracing::{error, warn};
+
+enum HtmlFile {
+    InvalidState,
+    AuthSuccess,
+    AuthFailed,
+    AuthSuccessRestart,
+}
+
+impl Display for HtmlFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let filename = match self {
+            InvalidState => "invalid-state.html",
+            AuthSuccess => "auth-success.html",
+            AuthFailed => "auth-failed.html",
+            AuthSuccessRestart => "auth-success-restart.html",
+        };
+        write!(f, "{}", filename)
+    }
+}
+
+async fn load_html_file(app: &AppHandle, html_file: HtmlFile) -> String {
+    let path = format!("resources/{}", html_file);
+    let resource_path = match app.path().resolve(path, BaseDirectory::Resource) {
+        Err(e) => {
+            error!("Failed to resolve resource path: {}", e);
+            return format!(
+                "<h1>Error</h1><p>Failed to resolve resource path: {}</p><p>Details: {}</p>",
+                html_file, e
+            );
+        }
+        Ok(path_buffer) => path_buffer,
+    };
+    match tokio::fs::read_to_string(&resource_path).await {
+        Ok(content) => content,
+        Err(e) => {
+            error!(
+                "Failed to read HTML file {} from path {:?}: {}",
+                html_file, resource_path, e
+            );
+            format!(
+                "<h1>Error</h1><p>Failed to read file: {}</p><p>Path: {:?}</p><p>Reason: {}</p>",
+                html_file, resource_path, e
+            )
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct AuthenticationState {
+    success: bool,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct CallbackParams {
+    code: String,
+    state: String,
+}
+
+#[derive(Clone)]
+struct ApiState {
+    app_handle: AppHandle,
+}
+
+/// Routes of the loopback server the OAuth redirect lands on during desktop sign-in.
+pub fn callback_router(app_handle: AppHandle) -> Router {
+    Router::new()
+        .route("/callback", get(handler))
+        .with_state(ApiState { app_handle })
+}
+
+async fn handler(
+    State(api_state): State<ApiState>,
+    Query(params): Query<CallbackParams>,
+) -> Html<String> {
+    let accounts = api_state.app_handle.state::<DefaultAccountManager>();
+    if !accounts.oauth_state_equal(&params.state) {
+        return Html(load_html_file(&api_state.app_handle, InvalidState).await);
+    }
+    match accounts.login_with_code(params.code).await {
+        Ok(_) => {
+            // Initialize data first (profile, relations, conversations), then the WebSocket
+            if let Err(e) = accounts.start_active_session().await {
+                error!("Failed to start account session: {}", e);
+                return Html(load_html_file(&api_state.app_handle, AuthSuccessRestart).await);
+            }
+
+            // Emit auth success event to frontend AFTER initialization completes
+            if let Err(e) = api_state.app_handle.emit(
+                "auth-result",
+                AuthenticationState {
+                    success: true,
+                    message: "Authentication successful".to_string(),
+                },
+            ) {
+                warn!("Failed to emit auth-result event: {}", e);
+            }
+
+            Html(load_html_file(&api_state.app_handle, AuthSuccess).await)
+        }
+        Err(e) => {
+            // Emit auth failure event to frontend
+            if let Err(e) = api_state.app_handle.emit(
+                "auth-result",
+                AuthenticationState {
+                    success: false,
+                    message: e.to_string(),
+                },
+            ) {
+                warn!("Failed to emit auth-result event: {}", e);
+            }
+            Html(load_html_file(&api_state.app_handle, AuthFailed).await)
+        }
+    }
+}
@@ -0,0 +1,442 @@
+//! Sources of the secrets that encrypt the local databases, one secret per key name.
+//!
+//! The OS keyring is the default. Machines without a keyring backend (headless Linux, minimal
+//! desktops without Secret Service) fall back to `$RIPPLE_DB_KEY` or a passphrase instead of
+//! failing to create the first store. A source switched to at runtime (`set_db_key_source`, or
+//! `ripple-cli key-source`) is remembered in `key_source.json` and takes precedence over the
+//! app config.
+
+use crate::app_config::{default_db_key_var, DatabaseKeyConfig};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::Engine;
+use keyring::Entry;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// Service name of the keyring entries, shared with the token store.
+pub(super) const KEYRING_SERVICE: &str = "ripple-im-app";
+const KEY_SOURCE_FILE: &str = "key_source.json";
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+
+pub trait KeyProvider: Send + Sync {
+    /// How the source is called in messages, e.g. `OS keyring`.
+    fn name(&self) -> &'static str;
+
+    /// The secret for `key_name`, created on first use by sources that can store one.
+    fn key(&self, key_name: &str) -> anyhow::Result<String>;
+
+    /// The secret for `key_name` if the source has one, without creating it.
+    fn existing_key(&self, key_name: &str) -> anyhow::Result<Option<String>>;
+
+    /// Replaces the secret for `key_name`. Only sources that store random secrets can.
+    fn set_key(&self, _key_name: &str, _key: &str) -> anyhow::Result<()> {
+        anyhow::bail!("Keys from the {} cannot be replaced", self.name())
+    }
+
+    /// Makes the secret of `from` the secret of `to`.
+    fn move_key(&self, from: &str, to: &str) -> anyhow::Result<()>;
+
+    fn delete_key(&self, key_name: &str) -> anyhow::Result<()>;
+
+    /// Whether `key` cannot work until `unlock` is given a passphrase.
+    fn is_locked(&self) -> bool {
+        false
+    }
+
+    fn unlock(&self, _passphrase: &str) {}
+
+    /// Forgets the passphrase given to `unlock`.
+    fn lock(&self) {}
+}
+
+/// A random secret per key name in the OS keyring.
+pub struct KeyringKeyProvider;
+
+impl KeyringKeyProvider {
+    /// Whether a keyring backend answers at all.
+    pub fn is_available() -> bool {
+        // Without a platform backend the keyring crate keeps entries in memory only
+        if cfg!(all(target_os = "linux", not(feature = "linux-keyring"))) {
+            debug!("OS keyring unavailable: built without the linux-keyring feature");
+            return false;
+        }
+        match Entry::new(KEYRING_SERVICE, "availability-probe").and_then(|e| e.get_password()) {
+            Ok(_) | Err(keyring::Error::NoEntry) => true,
+            Err(e) => {
+                debug!("OS keyring unavailable: {}", e);
+                false
+            }
+        }
+    }
+}
+
+impl KeyProvider for KeyringKeyProvider {
+    fn name(&self) -> &'static str {
+        "OS keyring"
+    }
+
+    fn key(&self, key_name: &str) -> anyhow::Result<String> {
+        if let Some(key) = self.existing_key(key_name)? {
+            return Ok(key);
+        }
+        let key = Uuid::new_v4().simple().to_string();
+        self.set_key(key_name, &key)?;
+        Ok(key)
+    }
+
+    fn existing_key(&self, key_name: &str) -> anyhow::Result<Option<String>> {
+        match Entry::new(KEYRING_SERVICE, key_name)?.get_password() {
+            Ok(key) => Ok(Some(key)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(anyhow::anyhow!(
+                "Failed to retrieve cipher key from keyring: {}",
+                e
+            )),
+        }
+    }
+
+    fn set_key(&self, key_name: &str, key: &str) -> anyhow::Result<()> {
+        Entry::new(KEYRING_SERVICE, key_name)?.set_password(key)?;
+        Ok(())
+    }
+
+    fn move_key(&self, from: &str, to: &str) -> anyhow::Result<()> {
+        let source = Entry::new(KEYRING_SERVICE, from)?;
+        Entry::new(KEYRING_SERVICE, to)?.set_password(&source.get_password()?)?;
+        source.delete_credential()?;
+        Ok(())
+    }
+
+    fn delete_key(&self, key_name: &str) -> anyhow::Result<()> {
+        match Entry::new(KEYRING_SERVICE, key_name)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Keys derived from a passphrase with Argon2id. The salt and parameters of each key name
+/// are kept in `<kdf_dir>/<key name>.kdf.json`; the passphrase only in memory.
+pub struct PassphraseKeyProvider {
+    kdf_dir: PathBuf,
+    passphrase: RwLock<Option<String>>,
+}
+
+impl PassphraseKeyProvider {
+    pub fn new(kdf_dir: PathBuf) -> Self {
+        PassphraseKeyProvider {
+            kdf_dir,
+            passphrase: RwLock::new(None),
+        }
+    }
+
+    fn kdf_path(&self, key_name: &str) -> PathBuf {
+        self.kdf_dir.join(format!("{}.kdf.json", key_name))
+    }
+
+    fn passphrase(&self) -> anyhow::Result<String> {
+        self.passphrase
+            .read()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("The database is locked; enter the passphrase first"))
+    }
+
+    fn load_params(&self, key_name: &str) -> anyhow::Result<Option<KdfParams>> {
+        match std::fs::read(self.kdf_path(key_name)) {
+            Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl KeyProvider for PassphraseKeyProvider {
+    fn name(&self) -> &'static str {
+        "passphrase"
+    }
+
+    fn key(&self, key_name: &str) -> anyhow::Result<String> {
+        let passphrase = self.passphrase()?;
+        let params = match self.load_params(key_name)? {
+            Some(params) => params,
+            None => {
+                let params = KdfParams::generate();
+                std::fs::create_dir_all(&self.kdf_dir)?;
+                std::fs::write(self.kdf_path(key_name), serde_json::to_vec(&params)?)?;
+                params
+            }
+        };
+        params.derive_key(&passphrase)
+    }
+
+    fn existing_key(&self, key_name: &str) -> anyhow::Result<Option<String>> {
+        match self.load_params(key_name)? {
+            Some(params) => Ok(Some(params.derive_key(&self.passphrase()?)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn move_key(&self, from: &str, to: &str) -> anyhow::Result<()> {
+        if self.kdf_path(from).exists() {
+            std::fs::rename(self.kdf_path(from), self.kdf_path(to))?;
+        }
+        Ok(())
+    }
+
+    fn delete_key(&self, key_name: &str) -> anyhow::Result<()> {
+        match std::fs::remove_file(self.kdf_path(key_name)) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn is_locked(&self) -> bool {
+        self.passphrase.read().unwrap().is_none()
+    }
+
+    fn unlock(&self, passphrase: &str) {
+        self.passphrase
+            .write()
+            .unwrap()
+            .replace(passphrase.to_string());
+    }
+
+    fn lock(&self) {
+        self.passphrase.write().unwrap().take();
+    }
+}
+
+/// One fixed secret for every key name, from an environment variable or a file.
+pub enum StaticKeyProvider {
+    Env(String),
+    File(PathBuf),
+}
+
+impl KeyProvider for StaticKeyProvider {
+    fn name(&self) -> &'static str {
+        match self {
+            StaticKeyProvider::Env(_) => "environment",
+            StaticKeyProvider::File(_) => "key file",
+        }
+    }
+
+    fn key(&self, _key_name: &str) -> anyhow::Result<String> {
+        let key = match self {
+            StaticKeyProvider::Env(var) => std::env::var(var)
+                .map_err(|_| anyhow::anyhow!("Set {} to the database key", var))?,
+            StaticKeyProvider::File(path) => std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read key file {}: {}", path.display(), e))?
+                .trim_end_matches(['\r', '\n'])
+                .to_string(),
+        };
+        if key.is_empty() {
+            anyhow::bail!("The database key from the {} is empty", self.name());
+        }
+        Ok(key)
+    }
+
+    fn existing_key(&self, key_name: &str) -> anyhow::Result<Option<String>> {
+        self.key(key_name).map(Some)
+    }
+
+    fn move_key(&self, _from: &str, _to: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn delete_key(&self, _key_name: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// The provider for `source`, failing if it is the keyring and no keyring backend answers.
+pub fn key_provider(
+    source: &DatabaseKeyConfig,
+    kdf_dir: &Path,
+) -> anyhow::Result<Arc<dyn KeyProvider>> {
+    Ok(match source {
+        DatabaseKeyConfig::Keyring => {
+            if !KeyringKeyProvider::is_available() {
+                anyhow::bail!("The OS keyring is not available on this machine");
+            }
+            Arc::new(KeyringKeyProvider)
+        }
+        DatabaseKeyConfig::Passphrase => {
+            Arc::new(PassphraseKeyProvider::new(kdf_dir.to_path_buf()))
+        }
+        DatabaseKeyConfig::Env { var } => Arc::new(StaticKeyProvider::Env(var.clone())),
+        DatabaseKeyConfig::File { path } => Arc::new(StaticKeyProvider::File(path.clone())),
+    })
+}
+
+/// Like `key_provider`, but a missing keyring falls back to `$RIPPLE_DB_KEY` when it is set
+/// and to a passphrase otherwise. The fallback is saved as the key source of `dir`, so the
+/// databases keyed with it still open once the keyring is back.
+///
+/// Databases that already exist were keyed by the keyring, so with `has_databases` a missing
+/// keyring is an error instead: no other source could open them.
+pub fn key_provider_with_fallback(
+    dir: &Path,
+    source: &DatabaseKeyConfig,
+    has_databases: bool,
+) -> anyhow::Result<Arc<dyn KeyProvider>> {
+    let kdf_dir = dir.join("keys");
+    let e = match key_provider(source, &kdf_dir) {
+        Ok(provider) => return Ok(provider),
+        Err(e) => e,
+    };
+    if has_databases {
+        anyhow::bail!("{}; its keys are needed to open the existing databases", e);
+    }
+    let var = default_db_key_var();
+    let fallback = if std::env::var_os(&var).is_some() {
+        DatabaseKeyConfig::Env { var }
+    } else {
+        DatabaseKeyConfig::Passphrase
+    };
+    let provider = key_provider(&fallback, &kdf_dir)?;
+    save_key_source(dir, &fallback)?;
+    warn!("{}; using the {} for database keys", e, provider.name());
+    Ok(provider)
+}
+
+/// Whether `dir` or one of its subdirectories holds a database.
+pub fn has_databases(dir: &Path) -> bool {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return false;
+    };
+    dir.join("sqlite.db").exists()
+        || entries
+            .flatten()
+            .any(|entry| entry.path().join("sqlite.db").exists())
+}
+
+/// The key source remembered in `dir`, else `configured`.
+pub fn load_key_source(
+    dir: &Path,
+    configured: &DatabaseKeyConfig,
+) -> anyhow::Result<DatabaseKeyConfig> {
+    match std::fs::read(dir.join(KEY_SOURCE_FILE)) {
+        Ok(data) => Ok(serde_json::from_slice(&data)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(configured.clone()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn save_key_source(dir: &Path, source: &DatabaseKeyConfig) -> anyhow::Result<()> {
+    std::fs::write(
+        dir.join(KEY_SOURCE_FILE),
+        serde_json::to_vec_pretty(source)?,
+    )?;
+    Ok(())
+}
+
+/// Argon2id parameters and salt of a passphrase-derived key.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct KdfParams {
+    algorithm: String,
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+    /// Base64
+    salt: String,
+}
+
+impl KdfParams {
+    /// Argon2id with the crate defaults (19 MiB, 2 passes), the OWASP baseline.
+    pub(super) fn generate() -> Self {
+        let mut salt = [0u8; SALT_LEN];
+        rand::rngs::OsRng.fill_bytes(&mut salt);
+        let params = Params::default();
+        KdfParams {
+            algorithm: "argon2id".to_string(),
+            memory_kib: params.m_cost(),
+            iterations: params.t_cost(),
+            parallelism: params.p_cost(),
+            salt: base64::engine::general_purpose::STANDARD.encode(salt),
+        }
+    }
+
+    /// The SQLCipher raw key literal (`x'…'`) for `passphrase`, which skips SQLCipher's own KDF.
+    pub(super) fn derive_key(&self, passphrase: &str) -> anyhow::Result<String> {
+        if self.algorithm != "argon2id" {
+            anyhow::bail!("Unsupported key derivation {}", self.algorithm);
+        }
+        let salt = base64::engine::general_purpose::STANDARD.decode(&self.salt)?;
+        let params = Params::new(
+            self.memory_kib,
+            self.iterations,
+            self.parallelism,
+            Some(KEY_LEN),
+        )
+        .map_err(|e| anyhow::anyhow!("Invalid key derivation parameters: {}", e))?;
+        let mut key = [0u8; KEY_LEN];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| anyhow::anyhow!("Failed to derive key: {}", e))?;
+        Ok(format!("x'{}'", base16ct::lower::encode_string(&key)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store_engine::TempDir;
+
+    #[test]
+    fn passphrase_keys_need_the_passphrase() {
+        let dir = TempDir::new("ripple-keys");
+        let keys = PassphraseKeyProvider::new(dir.path().to_path_buf());
+        assert!(keys.is_locked());
+        assert!(keys.key("acct").is_err());
+
+        keys.unlock("correct horse");
+        assert_eq!(keys.existing_key("acct").unwrap(), None);
+        let key = keys.key("acct").unwrap();
+        assert!(key.starts_with("x'"));
+        assert_eq!(keys.key("acct").unwrap(), key);
+        assert_ne!(keys.key("other").unwrap(), key);
+
+        keys.unlock("battery staple");
+        assert_ne!(keys.key("acct").unwrap(), key);
+        keys.lock();
+        assert!(keys.existing_key("acct").is_err());
+    }
+
+    #[test]
+    fn key_files_are_trimmed() {
+        let dir = TempDir::new("ripple-keys");
+        let path = dir.path().join("db.key");
+        std::fs::write(&path, "secret\r\n").unwrap();
+        let keys = StaticKeyProvider::File(path.clone());
+        assert_eq!(keys.key("a").unwrap(), "secret");
+        assert_eq!(keys.key("b").unwrap(), "secret");
+        assert!(keys.set_key("a", "other").is_err());
+
+        std::fs::write(&path, "\n").unwrap();
+        assert!(keys.key("a").is_err());
+    }
+
+    #[test]
+    fn saved_key_source_overrides_the_config() {
+        let dir = TempDir::new("ripple-keys");
+        let configured = DatabaseKeyConfig::Keyring;
+        assert_eq!(
+            load_key_source(dir.path(), &configured).unwrap(),
+            configured
+        );
+
+        save_key_source(dir.path(), &DatabaseKeyConfig::Passphrase).unwrap();
+        assert_eq!(
+            load_key_source(dir.path(), &configured).unwrap(),
+            DatabaseKeyConfig::Passphrase
+        );
+    }
+}
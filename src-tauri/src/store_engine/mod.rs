@@ -1,5 +1,81 @@
+pub mod key_provider;
+mod sqlite_backup;
+#[allow(clippy::module_inception)]
 pub mod store_engine;
 pub mod store_sqlite;
+pub mod token_store;
 
-pub use store_engine::StoreEngine;
+pub use key_provider::KeyProvider;
+pub use sqlite_backup::BackupInfo;
 pub use store_sqlite::SqliteStore;
+
+use crate::app_config::AppConfig;
+use crate::DefaultStoreEngine;
+use std::path::PathBuf;
+#[cfg(feature = "memory-store")]
+use store_engine::MemoryStore;
+
+/// Opens the store of the enabled storage feature for one account.
+#[cfg(feature = "memory-store")]
+pub async fn create_store(
+    _data_dir: PathBuf,
+    _key_name: &str,
+    _keys: &dyn KeyProvider,
+    _app_config: &AppConfig,
+) -> anyhow::Result<DefaultStoreEngine> {
+    Ok(MemoryStore::new())
+}
+
+#[cfg(feature = "memory-store")]
+pub async fn close_store(_store: &DefaultStoreEngine) -> anyhow::Result<()> {
+    Ok(())
+}
+
+/// Closes the store; an error means its files may not be complete on their own.
+#[cfg(feature = "sqlite-store")]
+pub async fn close_store(store: &DefaultStoreEngine) -> anyhow::Result<()> {
+    store.close().await
+}
+
+/// Opens the store of the enabled storage feature for one account.
+#[cfg(feature = "sqlite-store")]
+pub async fn create_store(
+    data_dir: PathBuf,
+    key_name: &str,
+    keys: &dyn KeyProvider,
+    app_config: &AppConfig,
+) -> anyhow::Result<DefaultStoreEngine> {
+    SqliteStore::new(data_dir, key_name, keys, &app_config.database_cipher)
+        .await?
+        .with_token_storage(app_config.token_storage, key_name)
+        .await
+}
+
+/// A fresh directory under the system temp dir, removed with its contents when dropped.
+#[cfg(test)]
+pub(crate) struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub(crate) fn new(prefix: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("{}-{}", prefix, uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub(crate) fn path(&self) -> &std::path::Path {
+        &self.0
+    }
+
+    /// Where a store opened on this directory keeps its database.
+    pub(crate) fn db_path(&self) -> PathBuf {
+        self.0.join("sqlite.db")
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,478 @@
+//! Passphrase-encrypted backups of a `SqliteStore`.
+//!
+//! The database key is tied to this machine's key provider, so a backup is re-keyed with an Argon2id key
+//! derived from a passphrase and can be restored on any machine. An archive is `MAGIC`, a
+//! length-prefixed JSON header with the KDF parameters, then the SQLCipher database itself,
+//! keyed with the derived raw key.
+
+use super::key_provider::{KdfParams, KeyProvider};
+use super::store_sqlite::{key_literal, SqliteStore, MIGRATOR};
+use crate::app_config::DatabaseCipherConfig;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{ConnectOptions, Connection, SqliteConnection};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+const MAGIC: &[u8; 8] = b"RPLBAK\r\n";
+const FORMAT_VERSION: u32 = 1;
+/// Headers are a few hundred bytes; anything bigger is not an archive.
+const MAX_HEADER_LEN: u32 = 64 * 1024;
+
+/// What a backup archive says about itself, readable before the passphrase is checked.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupInfo {
+    pub format: u32,
+    /// Unix milliseconds
+    pub created_at: i64,
+    /// Newest migration applied to the backed up database
+    pub schema_version: i64,
+    kdf: KdfParams,
+}
+
+impl SqliteStore {
+    /// Writes a copy of the database to `path`, encrypted with a key derived from `passphrase`.
+    ///
+    /// OAuth tokens and the device id are left out, so an archive holds no credentials and
+    /// a restored store registers as a new device.
+    pub async fn backup_to(&self, path: &Path, passphrase: &str) -> anyhow::Result<BackupInfo> {
+        if passphrase.is_empty() {
+            anyhow::bail!("The backup passphrase must not be empty");
+        }
+        let kdf = KdfParams::generate();
+        let key = kdf.derive_key(passphrase)?;
+        let staging = with_suffix(path, ".partial");
+        remove_if_exists(&staging)?;
+
+        let mut conn = self.writer.acquire().await?;
+        sqlx::query("ATTACH DATABASE ? AS backup KEY ?")
+            .bind(staging.to_string_lossy().into_owned())
+            .bind(&key)
+            .execute(&mut *conn)
+            .await?;
+        let exported = async {
+            sqlx::query("SELECT sqlcipher_export('backup')")
+                .execute(&mut *conn)
+                .await?;
+            // Zero the dropped credentials instead of leaving them in the copy's free pages
+            sqlx::query("PRAGMA backup.secure_delete = ON")
+                .execute(&mut *conn)
+                .await?;
+            sqlx::query("DELETE FROM backup.oauth_tokens")
+                .execute(&mut *conn)
+                .await?;
+            sqlx::query("UPDATE backup.app_metadata SET device_id = NULL")
+                .execute(&mut *conn)
+                .await?;
+            let (version,): (Option<i64>,) =
+                sqlx::query_as("SELECT MAX(version) FROM backup._sqlx_migrations")
+                    .fetch_one(&mut *conn)
+                    .await?;
+            anyhow::Ok(version.unwrap_or(0))
+        }
+        .await;
+        sqlx::query("DETACH DATABASE backup")
+            .execute(&mut *conn)
+            .await?;
+        let schema_version = match exported {
+            Ok(version) => version,
+            Err(e) => {
+                let _ = std::fs::remove_file(&staging);
+                return Err(e);
+            }
+        };
+
+        let info = BackupInfo {
+            format: FORMAT_VERSION,
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64,
+            schema_version,
+            kdf,
+        };
+        let written = write_archive(path, &info, &staging);
+        let _ = std::fs::remove_file(&staging);
+        if let Err(e) = written {
+            let _ = std::fs::remove_file(path);
+            return Err(e);
+        }
+        info!(
+            "Backed up database (schema {}) to {}",
+            info.schema_version,
+            path.display()
+        );
+        Ok(info)
+    }
+
+    /// Restores the archive at `path` as a new `sqlite.db` in `data_dir`, encrypted with the
+    /// secret `key_name` of `keys` and `cipher`, and opens it.
+    ///
+    /// Archives from an older schema are migrated on open; archives with migrations this build
+    /// does not know are refused.
+    pub async fn restore_from(
+        path: &Path,
+        passphrase: &str,
+        data_dir: PathBuf,
+        key_name: &str,
+        keys: &dyn KeyProvider,
+        cipher: &DatabaseCipherConfig,
+    ) -> anyhow::Result<(SqliteStore, BackupInfo)> {
+        let key = keys.key(key_name)?;
+        Self::restore_with_key(path, passphrase, &data_dir, key, cipher).await
+    }
+
+    async fn restore_with_key(
+        path: &Path,
+        passphrase: &str,
+        data_dir: &Path,
+        key: String,
+        cipher: &DatabaseCipherConfig,
+    ) -> anyhow::Result<(SqliteStore, BackupInfo)> {
+        let db_path = data_dir.join("sqlite.db");
+        if db_path.exists() {
+            anyhow::bail!(
+                "{} already has a database; restore into an empty directory",
+                data_dir.display()
+            );
+        }
+        let staging = data_dir.join("sqlite.db.restore");
+        let info = read_archive(path, &staging)?;
+        let restored =
+            Self::import(&staging, &info, passphrase, &db_path, key.clone(), cipher).await;
+        let _ = std::fs::remove_file(&staging);
+        if let Err(e) = restored {
+            let _ = std::fs::remove_file(&db_path);
+            return Err(e);
+        }
+        let store = SqliteStore::open(&db_path, key, cipher).await?;
+        info!(
+            "Restored database (schema {}) from {}",
+            info.schema_version,
+            path.display()
+        );
+        Ok((store, info))
+    }
+
+    /// Checks the decrypted archive at `staging` and copies it into a new database at `db_path`.
+    async fn import(
+        staging: &Path,
+        info: &BackupInfo,
+        passphrase: &str,
+        db_path: &Path,
+        store_key: String,
+        cipher: &DatabaseCipherConfig,
+    ) -> anyhow::Result<()> {
+        let key = info.kdf.derive_key(passphrase)?;
+        let mut backup = SqliteConnectOptions::new()
+            .filename(staging)
+            .pragma("key", key_literal(&key))
+            .read_only(true)
+            .connect()
+            .await?;
+        sqlx::query("SELECT count(*) FROM sqlite_master")
+            .execute(&mut backup)
+            .await
+            .map_err(|_| anyhow::anyhow!("Wrong passphrase or damaged backup"))?;
+        check_schema(&mut backup).await?;
+        backup.close().await?;
+
+        let mut conn = Self::connect_options(db_path, store_key, cipher)?
+            .connect()
+            .await?;
+        sqlx::query("ATTACH DATABASE ? AS backup KEY ?")
+            .bind(staging.to_string_lossy().into_owned())
+            .bind(&key)
+            .execute(&mut conn)
+            .await?;
+        sqlx::query("SELECT sqlcipher_export('main', 'backup')")
+            .execute(&mut conn)
+            .await?;
+        sqlx::query("DETACH DATABASE backup")
+            .execute(&mut conn)
+            .await?;
+        conn.close().await?;
+        Ok(())
+    }
+}
+
+/// Every migration applied to the backup must be one of ours, unchanged.
+async fn check_schema(conn: &mut SqliteConnection) -> anyhow::Result<()> {
+    let applied: Vec<(i64, Vec<u8>)> = sqlx::query_as(
+        "SELECT version, checksum FROM _sqlx_migrations WHERE success ORDER BY version",
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| anyhow::anyhow!("Backup has no migration history: {}", e))?;
+    for (version, checksum) in applied {
+        let migration = MIGRATOR
+            .iter()
+            .find(|m| m.version == version && !m.migration_type.is_down_migration())
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Backup has schema version {}, which this version of the app does not know; update the app first",
+                    version
+                )
+            })?;
+        if *migration.checksum != *checksum {
+            anyhow::bail!(
+                "Migration {} of the backup differs from this app's; it was made by an incompatible build",
+                version
+            );
+        }
+    }
+    Ok(())
+}
+
+fn write_archive(path: &Path, info: &BackupInfo, db: &Path) -> anyhow::Result<()> {
+    let header = serde_json::to_vec(info)?;
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(MAGIC)?;
+    out.write_all(&(header.len() as u32).to_le_bytes())?;
+    out.write_all(&header)?;
+    std::io::copy(&mut File::open(db)?, &mut out)?;
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    Ok(())
+}
+
+/// Checks the header of the archive at `path` and extracts the database to `db`.
+fn read_archive(path: &Path, db: &Path) -> anyhow::Result<BackupInfo> {
+    let mut input = BufReader::new(File::open(path)?);
+    let not_a_backup = || anyhow::anyhow!("{} is not a Ripple backup", path.display());
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic).map_err(|_| not_a_backup())?;
+    if &magic != MAGIC {
+        return Err(not_a_backup());
+    }
+    let mut len = [0u8; 4];
+    input.read_exact(&mut len).map_err(|_| not_a_backup())?;
+    let len = u32::from_le_bytes(len);
+    if len > MAX_HEADER_LEN {
+        return Err(not_a_backup());
+    }
+    let mut header = vec![0u8; len as usize];
+    input.read_exact(&mut header).map_err(|_| not_a_backup())?;
+    let info: BackupInfo = serde_json::from_slice(&header).map_err(|_| not_a_backup())?;
+    if info.format != FORMAT_VERSION {
+        anyhow::bail!(
+            "Backup format {} is not supported by this version of the app",
+            info.format
+        );
+    }
+    remove_if_exists(db)?;
+    let written = std::io::copy(&mut input, &mut File::create(db)?);
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(db);
+        return Err(e.into());
+    }
+    Ok(info)
+}
+
+pub(super) fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Deletes the `-wal` and `-shm` files of the database at `db`.
+pub(super) fn remove_journal_files(db: &Path) -> anyhow::Result<()> {
+    remove_if_exists(&with_suffix(db, "-wal"))?;
+    remove_if_exists(&with_suffix(db, "-shm"))
+}
+
+pub(super) fn remove_if_exists(path: &Path) -> anyhow::Result<()> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store_engine::store_engine::{RippleStorage, Token};
+    use crate::store_engine::TempDir;
+
+    const CIPHER: DatabaseCipherConfig = DatabaseCipherConfig::SQLCIPHER_4;
+
+    /// A data directory with its own cipher key, standing in for the key provider's secret.
+    struct TempStore {
+        dir: TempDir,
+        key: String,
+    }
+
+    impl TempStore {
+        fn new() -> Self {
+            TempStore {
+                dir: TempDir::new("ripple-backup"),
+                key: uuid::Uuid::new_v4().simple().to_string(),
+            }
+        }
+
+        async fn open(&self) -> SqliteStore {
+            SqliteStore::open(&self.dir.db_path(), self.key.clone(), &CIPHER)
+                .await
+                .unwrap()
+        }
+
+        async fn restore(
+            &self,
+            archive: &Path,
+            passphrase: &str,
+        ) -> anyhow::Result<(SqliteStore, BackupInfo)> {
+            SqliteStore::restore_with_key(
+                archive,
+                passphrase,
+                self.dir.path(),
+                self.key.clone(),
+                &CIPHER,
+            )
+            .await
+        }
+    }
+
+    async fn backed_up_store() -> (TempStore, PathBuf) {
+        let source = TempStore::new();
+        let store = source.open().await;
+        store.save_user_id("1001").await.unwrap();
+        store
+            .tokens()
+            .save_token(&Token::issued_now(
+                "access".to_string(),
+                "refresh".to_string(),
+                None,
+            ))
+            .await
+            .unwrap();
+        store.save_device_id(&uuid::Uuid::new_v4()).await.unwrap();
+        let archive = source.dir.path().join("history.rplbak");
+        let info = store.backup_to(&archive, "correct horse").await.unwrap();
+        assert_eq!(
+            info.schema_version,
+            MIGRATOR.iter().map(|m| m.version).max().unwrap()
+        );
+        store.close().await.unwrap();
+        (source, archive)
+    }
+
+    #[tokio::test]
+    async fn restores_into_a_fresh_store_without_credentials() {
+        let (_source, archive) = backed_up_store().await;
+        let target = TempStore::new();
+
+        let (store, _) = target.restore(&archive, "correct horse").await.unwrap();
+
+        assert_eq!(
+            store.get_stored_user_id().await.unwrap().as_deref(),
+            Some("1001")
+        );
+        assert!(store.tokens().get_token().await.unwrap().is_none());
+        assert!(store.get_device_id().await.unwrap().is_none());
+        store.close().await.unwrap();
+        assert!(!target.dir.path().join("sqlite.db.restore").exists());
+        // The restored database opens with the provider's secret alone
+        let reopened = target.open().await;
+        assert_eq!(
+            reopened.get_stored_user_id().await.unwrap().as_deref(),
+            Some("1001")
+        );
+        reopened.close().await.unwrap();
+    }
+
+    /// Every page of the SQLCipher 4 database in `archive`, decrypted with the raw key for
+    /// `passphrase` but without checking the HMACs, free pages included.
+    fn decrypted_pages(archive: &Path, passphrase: &str) -> Vec<u8> {
+        use aes::cipher::block_padding::NoPadding;
+        use aes::cipher::{BlockDecryptMut, KeyIvInit};
+        const PAGE_SIZE: usize = 4096;
+        // 16 byte IV followed by a 64 byte HMAC-SHA512 at the end of each page
+        const RESERVE: usize = 80;
+
+        let db = archive.with_extension("db");
+        let info = read_archive(archive, &db).unwrap();
+        let key = info.kdf.derive_key(passphrase).unwrap();
+        let key = base16ct::lower::decode_vec(&key[2..key.len() - 1]).unwrap();
+        let bytes = std::fs::read(&db).unwrap();
+        let mut plain = Vec::with_capacity(bytes.len());
+        for (index, page) in bytes.chunks(PAGE_SIZE).enumerate() {
+            // The first 16 bytes of page 1 are the plaintext salt
+            let start = if index == 0 { 16 } else { 0 };
+            let iv = &page[PAGE_SIZE - RESERVE..PAGE_SIZE - RESERVE + 16];
+            let mut data = page[start..PAGE_SIZE - RESERVE].to_vec();
+            cbc::Decryptor::<aes::Aes256>::new(key.as_slice().into(), iv.into())
+                .decrypt_padded_mut::<NoPadding>(&mut data)
+                .unwrap();
+            plain.extend_from_slice(&data);
+        }
+        plain
+    }
+
+    fn contains(haystack: &[u8], needle: &str) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle.as_bytes())
+    }
+
+    #[tokio::test]
+    async fn archive_pages_hold_no_credentials() {
+        let source = TempStore::new();
+        let store = source.open().await;
+        store.save_user_id("user-5a8b1001").await.unwrap();
+        store
+            .tokens()
+            .save_token(&Token::issued_now(
+                "access-3f9c0d2e".to_string(),
+                "refresh-7d1e4b6a".to_string(),
+                None,
+            ))
+            .await
+            .unwrap();
+        let device_id = uuid::Uuid::new_v4();
+        store.save_device_id(&device_id).await.unwrap();
+        let archive = source.dir.path().join("history.rplbak");
+        store.backup_to(&archive, "correct horse").await.unwrap();
+        store.close().await.unwrap();
+
+        let pages = decrypted_pages(&archive, "correct horse");
+
+        assert!(contains(&pages, "user-5a8b1001"));
+        assert!(!contains(&pages, "access-3f9c0d2e"));
+        assert!(!contains(&pages, "refresh-7d1e4b6a"));
+        assert!(!contains(&pages, &device_id.to_string()));
+    }
+
+    #[tokio::test]
+    async fn wrong_passphrase_leaves_no_database() {
+        let (_source, archive) = backed_up_store().await;
+        let target = TempStore::new();
+
+        let err = target.restore(&archive, "wrong").await.err().unwrap();
+
+        assert!(err.to_string().contains("Wrong passphrase"), "{}", err);
+        assert!(!target.dir.db_path().exists());
+    }
+
+    #[tokio::test]
+    async fn refuses_unknown_schema_versions() {
+        let source = TempStore::new();
+        let store = source.open().await;
+        sqlx::query(
+            "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time) VALUES (9999, 'from the future', TRUE, x'00', 0)",
+        )
+        .execute(&store.writer)
+        .await
+        .unwrap();
+        let archive = source.dir.path().join("future.rplbak");
+        store.backup_to(&archive, "pass").await.unwrap();
+        store.close().await.unwrap();
+        let target = TempStore::new();
+
+        let err = target.restore(&archive, "pass").await.err().unwrap();
+
+        assert!(err.to_string().contains("schema version 9999"), "{}", err);
+        assert!(!target.dir.db_path().exists());
+    }
+}
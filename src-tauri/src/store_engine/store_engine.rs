@@ -1,16 +1,52 @@
+use crate::errors::ConversionError;
 use crate::ripple_api::api_response::{
-    ConversationChange, ConversationItem, GroupMemberData, MessageItem, RelationUser,
-    UserGroupData, UserProfileData,
+    ConversationChange, ConversationItem, ConversationSummary, GroupMemberData, MessageItem,
+    RawMessageContent, RelationUser, UserGroupData, UserProfileData,
 };
 
+#[cfg(any(feature = "memory-store", test))]
+use crate::store_engine::token_store::MemoryTokenStore;
+use crate::store_engine::token_store::TokenStore;
+use serde::{Deserialize, Serialize};
+#[cfg(any(feature = "memory-store", test))]
 use std::collections::{BTreeMap, HashMap};
 use std::option::Option;
+#[cfg(any(feature = "memory-store", test))]
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+#[cfg(any(feature = "memory-store", test))]
+use tracing::debug;
 use uuid::Uuid;
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Token {
     pub access_token: String,
     pub refresh_token: String,
+    /// Unix milliseconds
+    pub issued_at: i64,
+    /// Unix milliseconds; `None` when the server did not say
+    pub expires_at: Option<i64>,
+}
+
+impl Token {
+    /// A token pair issued now that is valid for `expires_in`.
+    pub fn issued_now(
+        access_token: String,
+        refresh_token: String,
+        expires_in: Option<Duration>,
+    ) -> Token {
+        let issued_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+        Token {
+            access_token,
+            refresh_token,
+            issued_at,
+            expires_at: expires_in.map(|d| issued_at + d.as_millis() as i64),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -44,9 +80,11 @@ impl From<ConversationItem> for ConversationRecord {
     }
 }
 
-impl From<ConversationChange> for ConversationRecord {
-    fn from(item: ConversationChange) -> Self {
-        ConversationRecord {
+impl TryFrom<ConversationChange> for ConversationRecord {
+    type Error = ConversionError;
+
+    fn try_from(item: ConversationChange) -> Result<Self, Self::Error> {
+        Ok(ConversationRecord {
             conversation_id: item.conversation_id,
             peer_id: item.peer_id,
             group_id: item.group_id,
@@ -55,9 +93,12 @@ impl From<ConversationChange> for ConversationRecord {
             unread_count: 0, // ConversationChange doesn't include unread_count
             last_message_text: None, // ConversationChange doesn't include last_message_text
             last_message_timestamp: None, // ConversationChange doesn't include last_message_timestamp
-            name: item.name.unwrap(),
+            name: item.name.ok_or(ConversionError::MissingField {
+                context: "conversation change",
+                field: "name",
+            })?,
             avatar: item.avatar,
-        }
+        })
     }
 }
 
@@ -147,16 +188,19 @@ pub enum GroupMemberStorageAction {
 }
 
 #[trait_variant::make(RippleStorage: Send)]
+#[allow(dead_code)] // only the Send variant is used
 pub trait StoreEngine: Sync + Clone + 'static {
-    async fn exists_token(&self) -> anyhow::Result<bool>;
+    /// Where this account's OAuth tokens are kept.
+    fn tokens(&self) -> &dyn TokenStore;
+
     async fn exist_relations(&self) -> anyhow::Result<bool>;
     async fn exist_conversations(&self) -> anyhow::Result<bool>;
 
     async fn get_device_id(&self) -> anyhow::Result<Option<Uuid>>;
     async fn save_device_id(&self, device_id: &Uuid) -> anyhow::Result<()>;
-    async fn get_token(&self) -> anyhow::Result<Option<Token>>;
-    async fn save_token(&self, access_token: &str, refresh_token: &str) -> anyhow::Result<()>;
-    async fn clear_token(&self) -> anyhow::Result<()>;
+
+    async fn get_jwks(&self) -> anyhow::Result<Option<String>>;
+    async fn save_jwks(&self, jwks: &str) -> anyhow::Result<()>;
 
     async fn get_stored_user_id(&self) -> anyhow::Result<Option<String>>;
     async fn save_user_id(&self, user_id: &str) -> anyhow::Result<()>;
@@ -165,17 +209,25 @@ pub trait StoreEngine: Sync + Clone + 'static {
     async fn get_user_profile(&self) -> anyhow::Result<Option<UserProfileData>>;
     async fn save_user_profile(&self, profile: UserProfileData) -> anyhow::Result<()>;
 
+    /// Last successful sync time (unix millis) per sync domain.
+    async fn get_sync_timestamps(&self) -> anyhow::Result<Vec<(String, i64)>>;
+    async fn save_sync_timestamp(&self, domain: &str, timestamp: i64) -> anyhow::Result<()>;
+    /// Number of stored rows per table, for diagnostics.
+    async fn get_table_row_counts(&self) -> anyhow::Result<Vec<(String, i64)>>;
+
+    /// Replaces all relations and stores `last_version` in a single transaction.
     async fn apply_relation_all(
         &self,
         action: Vec<RelationUser>,
         last_version: &str,
     ) -> anyhow::Result<()>;
-    async fn apply_relation_action(
+    /// Applies all changes of one sync response and stores `version` in a single transaction.
+    async fn apply_relation_actions(
         &self,
-        action: RelationStorageAction,
-        version: String,
+        actions: Vec<RelationStorageAction>,
+        version: &str,
         need_result: bool,
-    ) -> anyhow::Result<Option<RelationUser>>;
+    ) -> anyhow::Result<Vec<Option<RelationUser>>>;
 
     async fn get_relation(&self, user_id: &str) -> anyhow::Result<Option<RelationUser>>;
     async fn get_all_relations(&self) -> anyhow::Result<Vec<RelationUser>>;
@@ -187,12 +239,12 @@ pub trait StoreEngine: Sync + Clone + 'static {
         conversations: Vec<ConversationRecord>,
         last_version: &str,
     ) -> anyhow::Result<()>;
-    async fn apply_conversation_action(
+    async fn apply_conversation_actions(
         &self,
-        action: ConversationStorageAction,
-        version: String,
+        actions: Vec<ConversationStorageAction>,
+        version: &str,
         need_result: bool,
-    ) -> anyhow::Result<Option<ConversationRecord>>;
+    ) -> anyhow::Result<Vec<Option<ConversationRecord>>>;
     async fn conversation_exists(&self, conversation_id: &str) -> anyhow::Result<bool>;
     async fn get_conversation_by_id(
         &self,
@@ -201,15 +253,12 @@ pub trait StoreEngine: Sync + Clone + 'static {
     async fn get_all_conversations(&self) -> anyhow::Result<Vec<ConversationRecord>>;
     async fn get_conversation_version(&self) -> anyhow::Result<Option<String>>;
     async fn clear_all_conversations(&self) -> anyhow::Result<()>;
-    async fn update_conversation_summary(
+    async fn update_conversation_summaries(
         &self,
-        conversation_id: &str,
-        unread_count: i64,
-        last_message_id: Option<String>,
-        last_message_text: Option<String>,
-        last_message_timestamp: Option<i64>,
+        summaries: Vec<ConversationSummary>,
     ) -> anyhow::Result<()>;
     async fn store_message(&self, message: MessageItem) -> anyhow::Result<()>;
+    async fn store_messages(&self, messages: Vec<MessageItem>) -> anyhow::Result<()>;
     async fn get_latest_message(
         &self,
         conversation_id: &str,
@@ -225,6 +274,8 @@ pub trait StoreEngine: Sync + Clone + 'static {
         before_message_id: &str,
         limit: u32,
     ) -> anyhow::Result<Vec<MessageItem>>;
+    /// Raw content of messages stored by a client that could not decode their type
+    async fn get_unsupported_messages(&self) -> anyhow::Result<Vec<(String, RawMessageContent)>>;
 
     async fn exist_user_groups(&self) -> anyhow::Result<bool>;
     async fn apply_user_group_all(
@@ -232,12 +283,12 @@ pub trait StoreEngine: Sync + Clone + 'static {
         groups: Vec<UserGroupData>,
         version: &str,
     ) -> anyhow::Result<()>;
-    async fn apply_user_group_action(
+    async fn apply_user_group_actions(
         &self,
-        action: UserGroupStorageAction,
-        version: String,
+        actions: Vec<UserGroupStorageAction>,
+        version: &str,
         need_result: bool,
-    ) -> anyhow::Result<Option<UserGroupData>>;
+    ) -> anyhow::Result<Vec<Option<UserGroupData>>>;
     async fn get_all_user_groups(&self) -> anyhow::Result<Vec<UserGroupData>>;
     async fn get_user_group_version(&self) -> anyhow::Result<Option<String>>;
     async fn clear_all_user_groups(&self) -> anyhow::Result<()>;
@@ -249,27 +300,33 @@ pub trait StoreEngine: Sync + Clone + 'static {
         members: Vec<GroupMemberData>,
         version: &str,
     ) -> anyhow::Result<()>;
-    async fn apply_group_member_action(
+    async fn apply_group_member_actions(
         &self,
         group_id: &str,
-        action: GroupMemberStorageAction,
-        version: String,
+        actions: Vec<GroupMemberStorageAction>,
+        version: &str,
         need_result: bool,
-    ) -> anyhow::Result<Option<GroupMemberData>>;
+    ) -> anyhow::Result<Vec<Option<GroupMemberData>>>;
     async fn get_all_group_members(&self, group_id: &str) -> anyhow::Result<Vec<GroupMemberData>>;
-    async fn get_group_member(&self, group_id: &str, user_id: &str) -> anyhow::Result<Option<GroupMemberData>>;
+    async fn get_group_member(
+        &self,
+        group_id: &str,
+        user_id: &str,
+    ) -> anyhow::Result<Option<GroupMemberData>>;
     async fn get_group_member_version(&self, group_id: &str) -> anyhow::Result<Option<String>>;
     async fn clear_group_members(&self, group_id: &str) -> anyhow::Result<()>;
 }
 
+#[cfg(any(feature = "memory-store", test))]
 #[derive(Clone)]
 pub struct MemoryStore {
     inner: Arc<tokio::sync::Mutex<InnerStore>>,
+    tokens: Arc<MemoryTokenStore>,
 }
 
+#[cfg(any(feature = "memory-store", test))]
 struct InnerStore {
-    access_token: Option<String>,
-    refresh_token: Option<String>,
+    jwks: Option<String>,
     uuid: Option<Uuid>,
     user_id: Option<String>,
     user_profile: Option<UserProfileData>,
@@ -284,14 +341,29 @@ struct InnerStore {
     // Group Members: group_id -> (user_id -> member)
     group_members: HashMap<String, HashMap<String, GroupMemberData>>,
     group_member_versions: HashMap<String, String>,
+    sync_timestamps: HashMap<String, i64>,
+    // Number of actions a batch may apply before it fails, for rollback tests
+    #[cfg(test)]
+    fail_after: Option<usize>,
+}
+
+#[cfg(any(feature = "memory-store", test))]
+impl InnerStore {
+    fn check_injected_failure(&self, _applied: usize) -> anyhow::Result<()> {
+        #[cfg(test)]
+        if self.fail_after == Some(_applied) {
+            anyhow::bail!("Injected failure after {} actions", _applied);
+        }
+        Ok(())
+    }
 }
 
+#[cfg(any(feature = "memory-store", test))]
 impl MemoryStore {
     pub fn new() -> Self {
         MemoryStore {
             inner: Arc::new(tokio::sync::Mutex::new(InnerStore {
-                access_token: None,
-                refresh_token: None,
+                jwks: None,
                 uuid: None,
                 user_id: None,
                 user_profile: None,
@@ -304,15 +376,26 @@ impl MemoryStore {
                 user_groups_version: None,
                 group_members: HashMap::new(),
                 group_member_versions: HashMap::new(),
+                sync_timestamps: HashMap::new(),
+                #[cfg(test)]
+                fail_after: None,
             })),
+            tokens: Arc::new(MemoryTokenStore::default()),
         }
     }
 }
 
+#[cfg(any(feature = "memory-store", test))]
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(any(feature = "memory-store", test))]
 impl RippleStorage for MemoryStore {
-    async fn exists_token(&self) -> anyhow::Result<bool> {
-        // always return false for in-memory store
-        Ok(false)
+    fn tokens(&self) -> &dyn TokenStore {
+        self.tokens.as_ref()
     }
 
     async fn exist_relations(&self) -> anyhow::Result<bool> {
@@ -340,28 +423,14 @@ impl RippleStorage for MemoryStore {
         Ok(())
     }
 
-    async fn get_token(&self) -> anyhow::Result<Option<Token>> {
+    async fn get_jwks(&self) -> anyhow::Result<Option<String>> {
         let inner = self.inner.lock().await;
-        if inner.access_token.is_none() || inner.refresh_token.is_none() {
-            return Ok(None);
-        }
-        Ok(Some(Token {
-            access_token: inner.access_token.clone().unwrap(),
-            refresh_token: inner.refresh_token.clone().unwrap(),
-        }))
-    }
-
-    async fn save_token(&self, access_token: &str, refresh_token: &str) -> anyhow::Result<()> {
-        let mut inner = self.inner.lock().await;
-        inner.access_token = Some(access_token.to_string());
-        inner.refresh_token = Some(refresh_token.to_string());
-        Ok(())
+        Ok(inner.jwks.clone())
     }
 
-    async fn clear_token(&self) -> anyhow::Result<()> {
+    async fn save_jwks(&self, jwks: &str) -> anyhow::Result<()> {
         let mut inner = self.inner.lock().await;
-        inner.access_token = None;
-        inner.refresh_token = None;
+        inner.jwks = Some(jwks.to_string());
         Ok(())
     }
 
@@ -378,8 +447,7 @@ impl RippleStorage for MemoryStore {
 
     async fn clear_all_data(&self) -> anyhow::Result<()> {
         let mut inner = self.inner.lock().await;
-        // Clear all data except device_id (uuid) and token (which is the new user's token)
-        // Token and user_id are managed separately by check_and_clear_on_user_change
+        // Clear all data except device_id (uuid), token and user_id
         inner.user_profile = None;
         inner.relations.clear();
         inner.relation_version = None;
@@ -390,6 +458,7 @@ impl RippleStorage for MemoryStore {
         inner.user_groups_version = None;
         inner.group_members.clear();
         inner.group_member_versions.clear();
+        inner.sync_timestamps.clear();
         Ok(())
     }
 
@@ -404,6 +473,45 @@ impl RippleStorage for MemoryStore {
         Ok(())
     }
 
+    async fn get_sync_timestamps(&self) -> anyhow::Result<Vec<(String, i64)>> {
+        let inner = self.inner.lock().await;
+        Ok(inner
+            .sync_timestamps
+            .iter()
+            .map(|(domain, timestamp)| (domain.clone(), *timestamp))
+            .collect())
+    }
+
+    async fn save_sync_timestamp(&self, domain: &str, timestamp: i64) -> anyhow::Result<()> {
+        let mut inner = self.inner.lock().await;
+        inner.sync_timestamps.insert(domain.to_string(), timestamp);
+        Ok(())
+    }
+
+    async fn get_table_row_counts(&self) -> anyhow::Result<Vec<(String, i64)>> {
+        let inner = self.inner.lock().await;
+        Ok(vec![
+            ("relations".to_string(), inner.relations.len() as i64),
+            (
+                "conversations".to_string(),
+                inner.conversations.len() as i64,
+            ),
+            (
+                "messages".to_string(),
+                inner.messages.values().map(|m| m.len() as i64).sum(),
+            ),
+            ("user_groups".to_string(), inner.user_groups.len() as i64),
+            (
+                "group_members".to_string(),
+                inner.group_members.values().map(|m| m.len() as i64).sum(),
+            ),
+            (
+                "sync_status".to_string(),
+                inner.sync_timestamps.len() as i64,
+            ),
+        ])
+    }
+
     async fn apply_relation_all(
         &self,
         action: Vec<RelationUser>,
@@ -417,44 +525,33 @@ impl RippleStorage for MemoryStore {
         inner.relation_version = Some(last_version.to_string());
         Ok(())
     }
-    async fn apply_relation_action(
+    async fn apply_relation_actions(
         &self,
-        action: RelationStorageAction,
-        version: String,
+        actions: Vec<RelationStorageAction>,
+        version: &str,
         need_result: bool,
-    ) -> anyhow::Result<Option<RelationUser>> {
+    ) -> anyhow::Result<Vec<Option<RelationUser>>> {
         let mut inner = self.inner.lock().await;
-        inner.relation_version = Some(version);
-        match action {
-            RelationStorageAction::Upsert(relation) => {
-                inner
-                    .relations
-                    .insert(relation.user_id.clone(), relation.clone());
-                if need_result {
-                    Ok(Some(relation))
-                } else {
-                    Ok(None)
-                }
-            }
-            RelationStorageAction::UpdateRemarkName {
-                user_id,
-                remark_name,
-            } => match inner.relations.get_mut(&user_id) {
-                Some(relation) => {
-                    relation.remark_name = Some(remark_name);
+        // Apply to a copy and swap it in at the end so a failed batch changes nothing
+        let mut relations = inner.relations.clone();
+        let mut results = Vec::with_capacity(actions.len());
+        for action in actions {
+            inner.check_injected_failure(results.len())?;
+            let result: anyhow::Result<Option<RelationUser>> = match action {
+                RelationStorageAction::Upsert(relation) => {
+                    relations.insert(relation.user_id.clone(), relation.clone());
                     if need_result {
-                        Ok(Some(relation.clone()))
+                        Ok(Some(relation))
                     } else {
                         Ok(None)
                     }
                 }
-                None => Ok(None),
-            },
-
-            RelationStorageAction::UpdateNickName { user_id, nick_name } => {
-                match inner.relations.get_mut(&user_id) {
+                RelationStorageAction::UpdateRemarkName {
+                    user_id,
+                    remark_name,
+                } => match relations.get_mut(&user_id) {
                     Some(relation) => {
-                        relation.nick_name = nick_name;
+                        relation.remark_name = Some(remark_name);
                         if need_result {
                             Ok(Some(relation.clone()))
                         } else {
@@ -462,27 +559,61 @@ impl RippleStorage for MemoryStore {
                         }
                     }
                     None => Ok(None),
+                },
+
+                RelationStorageAction::UpdateNickName { user_id, nick_name } => {
+                    match relations.get_mut(&user_id) {
+                        Some(relation) => {
+                            relation.nick_name = nick_name;
+                            if need_result {
+                                Ok(Some(relation.clone()))
+                            } else {
+                                Ok(None)
+                            }
+                        }
+                        None => Ok(None),
+                    }
                 }
-            }
 
-            RelationStorageAction::UpdateAvatar { user_id, avatar } => {
-                match inner.relations.get_mut(&user_id) {
-                    Some(relation) => {
-                        relation.avatar = avatar;
-                        if need_result {
-                            Ok(Some(relation.clone()))
-                        } else {
-                            Ok(None)
+                RelationStorageAction::UpdateAvatar { user_id, avatar } => {
+                    match relations.get_mut(&user_id) {
+                        Some(relation) => {
+                            relation.avatar = avatar;
+                            if need_result {
+                                Ok(Some(relation.clone()))
+                            } else {
+                                Ok(None)
+                            }
                         }
+                        None => Ok(None),
                     }
-                    None => Ok(None),
                 }
-            }
 
-            RelationStorageAction::UpdateFlags { user_id, flags } => {
-                match inner.relations.get_mut(&user_id) {
+                RelationStorageAction::UpdateFlags { user_id, flags } => {
+                    match relations.get_mut(&user_id) {
+                        Some(relation) => {
+                            relation.relation_flags = flags;
+                            if need_result {
+                                Ok(Some(relation.clone()))
+                            } else {
+                                Ok(None)
+                            }
+                        }
+                        None => Ok(None),
+                    }
+                }
+                RelationStorageAction::Delete { user_id } => {
+                    relations.remove(&user_id);
+                    Ok(None)
+                }
+                RelationStorageAction::UpdateNickNameAvatar {
+                    user_id,
+                    nick_name,
+                    avatar,
+                } => match relations.get_mut(&user_id) {
                     Some(relation) => {
-                        relation.relation_flags = flags;
+                        relation.nick_name = nick_name;
+                        relation.avatar = avatar;
                         if need_result {
                             Ok(Some(relation.clone()))
                         } else {
@@ -490,29 +621,13 @@ impl RippleStorage for MemoryStore {
                         }
                     }
                     None => Ok(None),
-                }
-            }
-            RelationStorageAction::Delete { user_id } => {
-                inner.relations.remove(&user_id);
-                Ok(None)
-            }
-            RelationStorageAction::UpdateNickNameAvatar {
-                user_id,
-                nick_name,
-                avatar,
-            } => match inner.relations.get_mut(&user_id) {
-                Some(relation) => {
-                    relation.nick_name = nick_name;
-                    relation.avatar = avatar;
-                    if need_result {
-                        Ok(Some(relation.clone()))
-                    } else {
-                        Ok(None)
-                    }
-                }
-                None => Ok(None),
-            },
+                },
+            };
+            results.push(result?);
         }
+        inner.relations = relations;
+        inner.relation_version = Some(version.to_string());
+        Ok(results)
     }
 
     async fn get_relation(&self, user_id: &str) -> anyhow::Result<Option<RelationUser>> {
@@ -552,92 +667,99 @@ impl RippleStorage for MemoryStore {
         Ok(())
     }
 
-    async fn apply_conversation_action(
+    async fn apply_conversation_actions(
         &self,
-        action: ConversationStorageAction,
-        version: String,
+        actions: Vec<ConversationStorageAction>,
+        version: &str,
         need_result: bool,
-    ) -> anyhow::Result<Option<ConversationRecord>> {
+    ) -> anyhow::Result<Vec<Option<ConversationRecord>>> {
         let mut inner = self.inner.lock().await;
-        inner.conversation_version = Some(version);
-        match action {
-            ConversationStorageAction::Create(conversation) => {
-                inner
-                    .conversations
-                    .insert(conversation.conversation_id.clone(), conversation.clone());
-                if need_result {
-                    Ok(Some(conversation))
-                } else {
-                    Ok(None)
-                }
-            }
-            ConversationStorageAction::UpdateLastReadMessageId {
-                conversation_id,
-                last_read_message_id,
-            } => match inner.conversations.get_mut(&conversation_id) {
-                Some(conv) => {
-                    conv.last_read_message_id = Some(last_read_message_id);
+        let mut conversations = inner.conversations.clone();
+        let mut results = Vec::with_capacity(actions.len());
+        for action in actions {
+            inner.check_injected_failure(results.len())?;
+            let result: anyhow::Result<Option<ConversationRecord>> = match action {
+                ConversationStorageAction::Create(conversation) => {
+                    conversations
+                        .insert(conversation.conversation_id.clone(), conversation.clone());
                     if need_result {
-                        Ok(Some(conv.clone()))
+                        Ok(Some(conversation))
                     } else {
                         Ok(None)
                     }
                 }
-                None => Ok(None),
-            },
-            ConversationStorageAction::UpdateName {
-                conversation_id,
-                name,
-            } => match inner.conversations.get_mut(&conversation_id) {
-                Some(conv) => {
-                    conv.name = name;
-                    if need_result {
-                        Ok(Some(conv.clone()))
-                    } else {
-                        Ok(None)
+                ConversationStorageAction::UpdateLastReadMessageId {
+                    conversation_id,
+                    last_read_message_id,
+                } => match conversations.get_mut(&conversation_id) {
+                    Some(conv) => {
+                        conv.last_read_message_id = Some(last_read_message_id);
+                        if need_result {
+                            Ok(Some(conv.clone()))
+                        } else {
+                            Ok(None)
+                        }
                     }
-                }
-                None => Ok(None),
-            },
-            ConversationStorageAction::UpdateAvatar {
-                conversation_id,
-                avatar,
-            } => match inner.conversations.get_mut(&conversation_id) {
-                Some(conv) => {
-                    conv.avatar = Some(avatar);
-                    if need_result {
-                        Ok(Some(conv.clone()))
-                    } else {
-                        Ok(None)
+                    None => Ok(None),
+                },
+                ConversationStorageAction::UpdateName {
+                    conversation_id,
+                    name,
+                } => match conversations.get_mut(&conversation_id) {
+                    Some(conv) => {
+                        conv.name = name;
+                        if need_result {
+                            Ok(Some(conv.clone()))
+                        } else {
+                            Ok(None)
+                        }
                     }
-                }
-                None => Ok(None),
-            },
-            ConversationStorageAction::UpdateNameAvatar {
-                conversation_id,
-                name,
-                avatar,
-            } => match inner.conversations.get_mut(&conversation_id) {
-                Some(conv) => {
-                    conv.name = name;
-                    conv.avatar = Some(avatar);
+                    None => Ok(None),
+                },
+                ConversationStorageAction::UpdateAvatar {
+                    conversation_id,
+                    avatar,
+                } => match conversations.get_mut(&conversation_id) {
+                    Some(conv) => {
+                        conv.avatar = Some(avatar);
+                        if need_result {
+                            Ok(Some(conv.clone()))
+                        } else {
+                            Ok(None)
+                        }
+                    }
+                    None => Ok(None),
+                },
+                ConversationStorageAction::UpdateNameAvatar {
+                    conversation_id,
+                    name,
+                    avatar,
+                } => match conversations.get_mut(&conversation_id) {
+                    Some(conv) => {
+                        conv.name = name;
+                        conv.avatar = Some(avatar);
+                        if need_result {
+                            Ok(Some(conv.clone()))
+                        } else {
+                            Ok(None)
+                        }
+                    }
+                    None => Ok(None),
+                },
+                ConversationStorageAction::Delete { conversation_id } => {
+                    let removed = conversations.remove(&conversation_id);
                     if need_result {
-                        Ok(Some(conv.clone()))
+                        Ok(removed)
                     } else {
                         Ok(None)
                     }
                 }
-                None => Ok(None),
-            },
-            ConversationStorageAction::Delete { conversation_id } => {
-                let removed = inner.conversations.remove(&conversation_id);
-                if need_result {
-                    Ok(removed)
-                } else {
-                    Ok(None)
-                }
-            }
+            };
+            results.push(result?);
         }
+        inner.conversations = conversations;
+        inner.conversation_version = Some(version.to_string());
+        Ok(results)
     }
     async fn conversation_exists(&self, conversation_id: &str) -> anyhow::Result<bool> {
         let inner = self.inner.lock().await;
@@ -668,54 +790,56 @@ impl RippleStorage for MemoryStore {
         Ok(())
     }
 
-    async fn update_conversation_summary(
+    async fn update_conversation_summaries(
         &self,
-        conversation_id: &str,
-        unread_count: i64,
-        last_message_id: Option<String>,
-        last_message_text: Option<String>,
-        last_message_timestamp: Option<i64>,
+        summaries: Vec<ConversationSummary>,
     ) -> anyhow::Result<()> {
         let mut inner = self.inner.lock().await;
-        if let Some(conv) = inner.conversations.get_mut(conversation_id) {
-            conv.unread_count = unread_count;
-            conv.last_message_id = last_message_id;
-            conv.last_message_text = last_message_text;
-            conv.last_message_timestamp = last_message_timestamp;
+        for summary in summaries {
+            if let Some(conv) = inner.conversations.get_mut(&summary.conversation_id) {
+                conv.unread_count = summary.unread_count;
+                conv.last_message_id = summary.last_message_id;
+                conv.last_message_text = summary.last_message_text;
+                conv.last_message_timestamp = Some(summary.last_message_timestamp);
+            }
         }
         Ok(())
     }
 
     async fn store_message(&self, message: MessageItem) -> anyhow::Result<()> {
-        let mut inner = self.inner.lock().await;
+        RippleStorage::store_messages(self, vec![message]).await
+    }
 
-        // Store the message
-        let conversation_messages = inner
-            .messages
-            .entry(message.conversation_id.clone())
-            .or_insert_with(BTreeMap::new);
-        conversation_messages.insert(message.message_id.clone(), message.clone());
-
-        // Update conversation's last_message_id if this message is newer
-        if let Some(conv) = inner.conversations.get_mut(&message.conversation_id) {
-            let should_update = match &conv.last_message_id {
-                Some(existing_id) => message.message_id > *existing_id,
-                None => true,
-            };
-            if should_update {
-                println!(
-                    "[StoreEngine] store_message: updating last_message_id from {:?} to {}",
-                    conv.last_message_id, message.message_id
+    async fn store_messages(&self, messages: Vec<MessageItem>) -> anyhow::Result<()> {
+        let mut inner = self.inner.lock().await;
+        for message in messages {
+            // Store the message
+            let conversation_messages = inner
+                .messages
+                .entry(message.conversation_id.clone())
+                .or_insert_with(BTreeMap::new);
+            conversation_messages.insert(message.message_id.clone(), message.clone());
+
+            // Update conversation's last_message_id if this message is newer
+            if let Some(conv) = inner.conversations.get_mut(&message.conversation_id) {
+                let should_update = match &conv.last_message_id {
+                    Some(existing_id) => message.message_id > *existing_id,
+                    None => true,
+                };
+                if should_update {
+                    debug!(
+                        "store_message: updating last_message_id from {:?} to {}",
+                        conv.last_message_id, message.message_id
+                    );
+                    conv.last_message_id = Some(message.message_id.clone());
+                }
+            } else {
+                debug!(
+                    "store_message: conversation {} not found in store",
+                    message.conversation_id
                 );
-                conv.last_message_id = Some(message.message_id.clone());
             }
-        } else {
-            println!(
-                "[StoreEngine] store_message: conversation {} not found in store",
-                message.conversation_id
-            );
         }
-
         Ok(())
     }
 
@@ -731,7 +855,7 @@ impl RippleStorage for MemoryStore {
                 return Ok(None);
             }
         };
-        Ok(messages.iter().rev().next().map(|(_, msg)| msg.clone()))
+        Ok(messages.iter().next_back().map(|(_, msg)| msg.clone()))
     }
 
     async fn get_latest_messages(
@@ -744,10 +868,7 @@ impl RippleStorage for MemoryStore {
         let messages = match inner.messages.get(conversation_id) {
             Some(msgs) => msgs,
             None => {
-                println!(
-                    "[StoreEngine] No messages found for conv_id {}",
-                    conversation_id
-                );
+                debug!("No messages found for conv_id {}", conversation_id);
                 return Ok(Vec::new());
             }
         };
@@ -758,7 +879,7 @@ impl RippleStorage for MemoryStore {
             .map(|(_, msg)| msg.clone())
             .collect();
         result.reverse();
-        println!("[StoreEngine] Returning {} latest messages", result.len());
+        debug!("Returning {} latest messages", result.len());
         Ok(result)
     }
 
@@ -776,10 +897,24 @@ impl RippleStorage for MemoryStore {
                 return Ok(Vec::new());
             }
         };
-        Ok(messages
+        // The newest `limit` messages before the id, oldest first
+        let mut page: Vec<MessageItem> = messages
             .range(..before_message_id.to_string())
+            .rev()
             .take(limit as usize)
             .map(|(_, msg)| msg.clone())
+            .collect();
+        page.reverse();
+        Ok(page)
+    }
+
+    async fn get_unsupported_messages(&self) -> anyhow::Result<Vec<(String, RawMessageContent)>> {
+        let inner = self.inner.lock().await;
+        Ok(inner
+            .messages
+            .values()
+            .flat_map(|messages| messages.values())
+            .filter_map(|msg| Some((msg.message_id.clone(), msg.raw_content.clone()?)))
             .collect())
     }
 
@@ -803,59 +938,69 @@ impl RippleStorage for MemoryStore {
         Ok(())
     }
 
-    async fn apply_user_group_action(
+    async fn apply_user_group_actions(
         &self,
-        action: UserGroupStorageAction,
-        version: String,
+        actions: Vec<UserGroupStorageAction>,
+        version: &str,
         need_result: bool,
-    ) -> anyhow::Result<Option<UserGroupData>> {
+    ) -> anyhow::Result<Vec<Option<UserGroupData>>> {
         let mut inner = self.inner.lock().await;
-        inner.user_groups_version = Some(version);
-        match action {
-            UserGroupStorageAction::Upsert(group) => {
-                inner
-                    .user_groups
-                    .insert(group.group_id.clone(), group.clone());
-                if need_result {
-                    Ok(Some(group))
-                } else {
-                    Ok(None)
+        let mut user_groups = inner.user_groups.clone();
+        let mut group_members = inner.group_members.clone();
+        let mut group_member_versions = inner.group_member_versions.clone();
+        let mut results = Vec::with_capacity(actions.len());
+        for action in actions {
+            inner.check_injected_failure(results.len())?;
+            let result: anyhow::Result<Option<UserGroupData>> = match action {
+                UserGroupStorageAction::Upsert(group) => {
+                    user_groups.insert(group.group_id.clone(), group.clone());
+                    if need_result {
+                        Ok(Some(group))
+                    } else {
+                        Ok(None)
+                    }
                 }
-            }
-            UserGroupStorageAction::UpdateName { group_id, name } => {
-                match inner.user_groups.get_mut(&group_id) {
-                    Some(group) => {
-                        group.group_name = name;
-                        if need_result {
-                            Ok(Some(group.clone()))
-                        } else {
-                            Ok(None)
+                UserGroupStorageAction::UpdateName { group_id, name } => {
+                    match user_groups.get_mut(&group_id) {
+                        Some(group) => {
+                            group.group_name = name;
+                            if need_result {
+                                Ok(Some(group.clone()))
+                            } else {
+                                Ok(None)
+                            }
                         }
+                        None => Ok(None),
                     }
-                    None => Ok(None),
                 }
-            }
-            UserGroupStorageAction::UpdateAvatar { group_id, avatar } => {
-                match inner.user_groups.get_mut(&group_id) {
-                    Some(group) => {
-                        group.group_avatar = avatar;
-                        if need_result {
-                            Ok(Some(group.clone()))
-                        } else {
-                            Ok(None)
+                UserGroupStorageAction::UpdateAvatar { group_id, avatar } => {
+                    match user_groups.get_mut(&group_id) {
+                        Some(group) => {
+                            group.group_avatar = avatar;
+                            if need_result {
+                                Ok(Some(group.clone()))
+                            } else {
+                                Ok(None)
+                            }
                         }
+                        None => Ok(None),
                     }
-                    None => Ok(None),
                 }
-            }
-            UserGroupStorageAction::Delete { group_id } => {
-                inner.user_groups.remove(&group_id);
-                // Also remove group members when user quits a group
-                inner.group_members.remove(&group_id);
-                inner.group_member_versions.remove(&group_id);
-                Ok(None)
-            }
+                UserGroupStorageAction::Delete { group_id } => {
+                    user_groups.remove(&group_id);
+                    // Also remove group members when user quits a group
+                    group_members.remove(&group_id);
+                    group_member_versions.remove(&group_id);
+                    Ok(None)
+                }
+            };
+            results.push(result?);
         }
+        inner.user_groups = user_groups;
+        inner.group_members = group_members;
+        inner.group_member_versions = group_member_versions;
+        inner.user_groups_version = Some(version.to_string());
+        Ok(results)
     }
 
     async fn get_all_user_groups(&self) -> anyhow::Result<Vec<UserGroupData>> {
@@ -902,62 +1047,73 @@ impl RippleStorage for MemoryStore {
         Ok(())
     }
 
-    async fn apply_group_member_action(
+    async fn apply_group_member_actions(
         &self,
         group_id: &str,
-        action: GroupMemberStorageAction,
-        version: String,
+        actions: Vec<GroupMemberStorageAction>,
+        version: &str,
         need_result: bool,
-    ) -> anyhow::Result<Option<GroupMemberData>> {
+    ) -> anyhow::Result<Vec<Option<GroupMemberData>>> {
         let mut inner = self.inner.lock().await;
-        inner
-            .group_member_versions
-            .insert(group_id.to_string(), version);
-        let group_members = inner
+        let mut group_members = inner
             .group_members
-            .entry(group_id.to_string())
-            .or_insert_with(HashMap::new);
-
-        match action {
-            GroupMemberStorageAction::Upsert(member) => {
-                group_members.insert(member.user_id.clone(), member.clone());
-                if need_result {
-                    Ok(Some(member))
-                } else {
-                    Ok(None)
-                }
-            }
-            GroupMemberStorageAction::UpdateName { user_id, name } => {
-                match group_members.get_mut(&user_id) {
-                    Some(member) => {
-                        member.name = name;
+            .get(group_id)
+            .cloned()
+            .unwrap_or_default();
+        let mut results = Vec::with_capacity(actions.len());
+        for action in actions {
+            inner.check_injected_failure(results.len())?;
+            let result: anyhow::Result<Option<GroupMemberData>> = {
+                match action {
+                    GroupMemberStorageAction::Upsert(member) => {
+                        group_members.insert(member.user_id.clone(), member.clone());
                         if need_result {
-                            Ok(Some(member.clone()))
+                            Ok(Some(member))
                         } else {
                             Ok(None)
                         }
                     }
-                    None => Ok(None),
-                }
-            }
-            GroupMemberStorageAction::UpdateAvatar { user_id, avatar } => {
-                match group_members.get_mut(&user_id) {
-                    Some(member) => {
-                        member.avatar = avatar;
-                        if need_result {
-                            Ok(Some(member.clone()))
-                        } else {
-                            Ok(None)
+                    GroupMemberStorageAction::UpdateName { user_id, name } => {
+                        match group_members.get_mut(&user_id) {
+                            Some(member) => {
+                                member.name = name;
+                                if need_result {
+                                    Ok(Some(member.clone()))
+                                } else {
+                                    Ok(None)
+                                }
+                            }
+                            None => Ok(None),
                         }
                     }
-                    None => Ok(None),
+                    GroupMemberStorageAction::UpdateAvatar { user_id, avatar } => {
+                        match group_members.get_mut(&user_id) {
+                            Some(member) => {
+                                member.avatar = avatar;
+                                if need_result {
+                                    Ok(Some(member.clone()))
+                                } else {
+                                    Ok(None)
+                                }
+                            }
+                            None => Ok(None),
+                        }
+                    }
+                    GroupMemberStorageAction::Delete { user_id } => {
+                        group_members.remove(&user_id);
+                        Ok(None)
+                    }
                 }
-            }
-            GroupMemberStorageAction::Delete { user_id } => {
-                group_members.remove(&user_id);
-                Ok(None)
-            }
+            };
+            results.push(result?);
         }
+        inner
+            .group_members
+            .insert(group_id.to_string(), group_members);
+        inner
+            .group_member_versions
+            .insert(group_id.to_string(), version.to_string());
+        Ok(results)
     }
 
     async fn get_all_group_members(&self, group_id: &str) -> anyhow::Result<Vec<GroupMemberData>> {
@@ -968,7 +1124,11 @@ impl RippleStorage for MemoryStore {
         }
     }
 
-    async fn get_group_member(&self, group_id: &str, user_id: &str) -> anyhow::Result<Option<GroupMemberData>> {
+    async fn get_group_member(
+        &self,
+        group_id: &str,
+        user_id: &str,
+    ) -> anyhow::Result<Option<GroupMemberData>> {
         let inner = self.inner.lock().await;
         match inner.group_members.get(group_id) {
             Some(members) => Ok(members.get(user_id).cloned()),
@@ -988,3 +1148,54 @@ impl RippleStorage for MemoryStore {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryStore, RelationStorageAction, RelationUser, RippleStorage};
+
+    fn relation(user_id: &str, nick_name: &str) -> RelationUser {
+        RelationUser {
+            user_id: user_id.to_string(),
+            nick_name: nick_name.to_string(),
+            avatar: None,
+            remark_name: None,
+            relation_flags: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_batch_leaves_state_untouched() {
+        let store = MemoryStore::new();
+        store
+            .apply_relation_all(vec![relation("1001", "alice")], "v1")
+            .await
+            .unwrap();
+        store.inner.lock().await.fail_after = Some(2);
+
+        let result = store
+            .apply_relation_actions(
+                vec![
+                    RelationStorageAction::UpdateNickName {
+                        user_id: "1001".to_string(),
+                        nick_name: "alicia".to_string(),
+                    },
+                    RelationStorageAction::Upsert(relation("1002", "bob")),
+                    RelationStorageAction::Delete {
+                        user_id: "1001".to_string(),
+                    },
+                ],
+                "v2",
+                false,
+            )
+            .await;
+
+        assert!(result.is_err());
+        let relations = store.get_all_relations().await.unwrap();
+        assert_eq!(relations.len(), 1);
+        assert_eq!(relations[0].nick_name, "alice");
+        assert_eq!(
+            store.get_relation_version().await.unwrap().as_deref(),
+            Some("v1")
+        );
+    }
+}
@@ -1,82 +1,1018 @@
+use super::key_provider::{KeyProvider, KeyringKeyProvider};
+use super::sqlite_backup::{remove_if_exists, remove_journal_files, with_suffix};
+use super::token_store::{move_token, KeyringTokenStore, SqliteTokenStore, TokenStore};
+use crate::app_config::{DatabaseCipherConfig, TokenStorageConfig};
 use crate::ripple_api::api_response::{
-    GroupMemberData, MessageCommandType, MessageItem, MessageItemType, RelationUser, UserGroupData,
-    UserProfileData,
+    ConversationSummary, GroupMemberData, MessageCommandType, MessageItem, MessageItemType,
+    RawMessageContent, RelationUser, UserGroupData, UserProfileData,
 };
 use crate::store_engine::store_engine::{
     ConversationRecord, ConversationStorageAction, GroupMemberStorageAction, RelationStorageAction,
-    RippleStorage, Token, UserGroupStorageAction,
+    RippleStorage, UserGroupStorageAction,
 };
-use keyring::Entry;
-use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool};
-use std::path::PathBuf;
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::{
+    SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqlitePool, SqlitePoolOptions,
+    SqliteSynchronous,
+};
+use sqlx::{ConnectOptions, Connection, SqliteExecutor};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
+use tracing::{debug, info, warn};
 use uuid::Uuid;
 
-#[derive(Clone)]
-pub struct SqliteStore {
-    pool: SqlitePool,
-}
+pub(super) static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// `PRAGMA key` value for `key`. SQLx passes pragma values through verbatim, and an unquoted
+/// key that starts with a digit is not valid SQL.
+pub(super) fn key_literal(key: &str) -> String {
+    format!("'{}'", key.replace('\'', "''"))
+}
+
+/// Key name holding the next secret while `rotate_cipher_key` runs.
+fn pending_key_name(key_name: &str) -> String {
+    format!("{}-pending", key_name)
+}
+
+/// Plaintext file next to the database naming the cipher settings it was last opened with.
+/// Without it, or when the settings changed, `open` probes for older settings first.
+fn cipher_marker_path(db_path: &Path) -> PathBuf {
+    with_suffix(db_path, ".cipher")
+}
+
+fn cipher_marker(cipher: &DatabaseCipherConfig) -> String {
+    format!(
+        "{} {} {} {}",
+        cipher.page_size,
+        cipher.kdf_iter,
+        cipher.hmac_algorithm.as_str(),
+        cipher.kdf_algorithm.as_str()
+    )
+}
+
+fn cannot_decrypt(db_path: &Path) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Cannot decrypt {}: the key or the cipher settings do not match",
+        db_path.display()
+    )
+}
+
+/// Cipher settings end up in pragmas verbatim, so only sane values get that far.
+fn validate_cipher(cipher: &DatabaseCipherConfig) -> anyhow::Result<()> {
+    if !(512..=65536).contains(&cipher.page_size) || !cipher.page_size.is_power_of_two() {
+        anyhow::bail!(
+            "Invalid database page size {}; use a power of two from 512 to 65536",
+            cipher.page_size
+        );
+    }
+    if cipher.kdf_iter == 0 {
+        anyhow::bail!("The database KDF needs at least one iteration");
+    }
+    Ok(())
+}
+
+/// SQLITE_NOTADB: the key or the cipher settings do not match the file.
+fn is_not_a_database(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .and_then(|e| e.code())
+        .is_some_and(|code| code == "26")
+}
+
+/// Read connections besides the single writer. WAL lets them read while the writer commits,
+/// so history pages load during a sync.
+const READER_CONNECTIONS: u32 = 4;
+
+/// WAL checkpoint policy: the writer copies the WAL back into the database every 1000 pages
+/// (about 4 MiB), the WAL file is cut back to 64 MiB after a checkpoint, and `close` empties
+/// it so a closed database can be copied, renamed or rekeyed on its own.
+const WAL_AUTOCHECKPOINT_PAGES: u32 = 1000;
+const WAL_SIZE_LIMIT_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Settings older databases may still be encrypted with, newest first. Opening such a
+/// database re-encrypts it with the configured settings.
+const PREVIOUS_CIPHER_SETTINGS: &[DatabaseCipherConfig] = &[DatabaseCipherConfig::SQLCIPHER_3];
+
+// Column tuples of the relation, conversation and message SELECTs, in query order
+type RelationRow = (String, String, Option<String>, Option<String>, i32);
+type ConversationRow = (
+    String,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    i64,
+    Option<String>,
+    Option<i64>,
+    String,
+    Option<String>,
+);
+type MessageRow = (
+    String,
+    String,
+    String,
+    Option<String>,
+    Option<String>,
+    String,
+    i32,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    i32,
+    Option<String>,
+);
+
+#[derive(Clone)]
+pub struct SqliteStore {
+    /// One connection, so writes queue here instead of failing with SQLITE_BUSY.
+    pub(super) writer: SqlitePool,
+    /// Read-only connections for queries that do not change anything.
+    pub(super) reader: SqlitePool,
+    tokens: Arc<dyn TokenStore>,
+}
+
+impl SqliteStore {
+    /// Opens (or creates) `sqlite.db` in `data_dir`, encrypted with the secret `key_name` of
+    /// `keys`.
+    pub async fn new(
+        data_dir: PathBuf,
+        key_name: &str,
+        keys: &dyn KeyProvider,
+        cipher: &DatabaseCipherConfig,
+    ) -> anyhow::Result<Self> {
+        let db_path = data_dir.join("sqlite.db");
+        let key = keys.key(key_name)?;
+        let key = Self::finish_key_rotation(&db_path, key_name, keys, key, cipher).await?;
+        Self::open(&db_path, key, cipher).await
+    }
+
+    /// Opens (or creates) the database at `db_path` with an explicit cipher key.
+    pub(super) async fn open(
+        db_path: &Path,
+        key: String,
+        cipher: &DatabaseCipherConfig,
+    ) -> anyhow::Result<Self> {
+        debug!("Using database path: {}", db_path.display());
+        // Each probe runs the KDF, so only databases without matching settings on record get one
+        let recorded = std::fs::read_to_string(cipher_marker_path(db_path))
+            .is_ok_and(|marker| marker == cipher_marker(cipher));
+        if db_path.exists() && !recorded {
+            match Self::detect_cipher(db_path, &key, cipher).await? {
+                Some(current) if current == *cipher => {}
+                Some(current) => Self::migrate_cipher(db_path, &key, &current, cipher).await?,
+                None => return Err(cannot_decrypt(db_path)),
+            }
+        }
+        let options = Self::connect_options(db_path, key, cipher)?;
+        // Kept open so readers always find the WAL index of a live writer
+        let writer = match SqlitePoolOptions::new()
+            .max_connections(1)
+            .min_connections(1)
+            .connect_with(
+                options
+                    .clone()
+                    .journal_mode(SqliteJournalMode::Wal)
+                    .synchronous(SqliteSynchronous::Normal)
+                    .pragma("wal_autocheckpoint", WAL_AUTOCHECKPOINT_PAGES.to_string())
+                    .pragma("journal_size_limit", WAL_SIZE_LIMIT_BYTES.to_string()),
+            )
+            .await
+        {
+            Ok(writer) => writer,
+            Err(e) if is_not_a_database(&e) => return Err(cannot_decrypt(db_path)),
+            Err(e) => return Err(e.into()),
+        };
+        MIGRATOR.run(&writer).await?;
+        std::fs::write(cipher_marker_path(db_path), cipher_marker(cipher))?;
+        let reader = SqlitePoolOptions::new()
+            .max_connections(READER_CONNECTIONS)
+            .connect_with(options.read_only(true).create_if_missing(false))
+            .await?;
+        Ok(SqliteStore {
+            tokens: Arc::new(SqliteTokenStore::new(writer.clone())),
+            writer,
+            reader,
+        })
+    }
+
+    /// Keeps the tokens in `tokens` instead of the database.
+    pub fn with_token_store(mut self, tokens: Arc<dyn TokenStore>) -> Self {
+        self.tokens = tokens;
+        self
+    }
+
+    /// Keeps the tokens where `storage` says, moving a token left in the other place by an
+    /// earlier setting. `key_name` names the account, as for the database key.
+    pub async fn with_token_storage(
+        self,
+        storage: TokenStorageConfig,
+        key_name: &str,
+    ) -> anyhow::Result<Self> {
+        let database_tokens = SqliteTokenStore::new(self.writer.clone());
+        if !KeyringKeyProvider::is_available() {
+            if storage == TokenStorageConfig::Keyring {
+                warn!("The OS keyring is not available; keeping tokens in the database");
+            }
+            return Ok(self);
+        }
+        let keyring_tokens = Arc::new(KeyringTokenStore::new(key_name));
+        match storage {
+            TokenStorageConfig::Database => {
+                if let Err(e) = move_token(keyring_tokens.as_ref(), &database_tokens).await {
+                    warn!("Failed to move tokens from the keyring: {}", e);
+                }
+                Ok(self)
+            }
+            TokenStorageConfig::Keyring => {
+                move_token(&database_tokens, keyring_tokens.as_ref()).await?;
+                Ok(self.with_token_store(keyring_tokens))
+            }
+        }
+    }
+
+    /// Closes both pools after copying the WAL back into the database. The pools are closed
+    /// even if the checkpoint fails, but then the database file alone is not the whole data
+    /// and must not be copied, renamed or rekeyed.
+    pub async fn close(&self) -> anyhow::Result<()> {
+        self.reader.close().await;
+        let checkpoint: Result<(i64, i64, i64), sqlx::Error> =
+            sqlx::query_as("PRAGMA wal_checkpoint(TRUNCATE)")
+                .fetch_one(&self.writer)
+                .await;
+        self.writer.close().await;
+        match checkpoint {
+            Ok((0, _, _)) => Ok(()),
+            Ok(_) => anyhow::bail!("Failed to checkpoint the WAL: the database is busy"),
+            Err(e) => anyhow::bail!("Failed to checkpoint the WAL: {}", e),
+        }
+    }
+
+    /// Moves the closed database `from` onto `to`. The `-wal` and `-shm` files of the
+    /// database it replaces go first, so they are not read as part of the new one; the empty
+    /// ones `from` leaves behind are removed after. The recorded cipher settings move along.
+    pub fn move_database(from: &Path, to: &Path) -> anyhow::Result<()> {
+        remove_journal_files(to)?;
+        remove_if_exists(&cipher_marker_path(to))?;
+        std::fs::rename(from, to)?;
+        remove_journal_files(from)?;
+        let marker = cipher_marker_path(from);
+        if marker.exists() {
+            std::fs::rename(&marker, cipher_marker_path(to))?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the secret `key_name` and any rotation left pending for it.
+    pub fn delete_cipher_key(keys: &dyn KeyProvider, key_name: &str) -> anyhow::Result<()> {
+        keys.delete_key(key_name)?;
+        keys.delete_key(&pending_key_name(key_name))
+    }
+
+    /// Re-encrypts the closed database in `data_dir` under a new random secret.
+    ///
+    /// The new secret is parked under a `-pending` key name until `PRAGMA rekey` has
+    /// succeeded, so a rotation interrupted halfway is completed by the next `new` instead of
+    /// locking the data out. Only providers that store secrets (the keyring) can rotate.
+    pub async fn rotate_cipher_key(
+        data_dir: &Path,
+        key_name: &str,
+        keys: &dyn KeyProvider,
+        cipher: &DatabaseCipherConfig,
+    ) -> anyhow::Result<()> {
+        // Opening finishes an earlier rotation and brings the cipher settings up to date
+        Self::new(data_dir.to_path_buf(), key_name, keys, cipher)
+            .await?
+            .close()
+            .await?;
+        let db_path = data_dir.join("sqlite.db");
+        let old_key = keys.key(key_name)?;
+        let new_key = Uuid::new_v4().simple().to_string();
+        let pending = pending_key_name(key_name);
+        keys.set_key(&pending, &new_key)?;
+        if let Err(e) = Self::rekey(&db_path, &old_key, &new_key, cipher).await {
+            let _ = keys.delete_key(&pending);
+            return Err(e);
+        }
+        keys.set_key(key_name, &new_key)?;
+        keys.delete_key(&pending)?;
+        info!("Rotated the database key of {}", db_path.display());
+        Ok(())
+    }
+
+    /// Re-encrypts the closed database in `data_dir` from the secret `key_name` of `from` to
+    /// the one of `to`, then drops the secret from `from`.
+    ///
+    /// Nothing changes if the rekey fails, so the caller can keep using `from`.
+    pub async fn migrate_key(
+        data_dir: &Path,
+        key_name: &str,
+        from: &dyn KeyProvider,
+        to: &dyn KeyProvider,
+        cipher: &DatabaseCipherConfig,
+    ) -> anyhow::Result<()> {
+        let db_path = data_dir.join("sqlite.db");
+        if !db_path.exists() {
+            return Ok(());
+        }
+        Self::new(data_dir.to_path_buf(), key_name, from, cipher)
+            .await?
+            .close()
+            .await?;
+        let old_key = from.key(key_name)?;
+        let new_key = to.key(key_name)?;
+        if old_key != new_key {
+            Self::rekey(&db_path, &old_key, &new_key, cipher).await?;
+        }
+        // A new passphrase keeps the salt file, which both providers share
+        if from.name() != to.name() {
+            if let Err(e) = Self::delete_cipher_key(from, key_name) {
+                debug!("Failed to delete the old key {}: {}", key_name, e);
+            }
+        }
+        info!(
+            "Moved the database key of {} from the {} to the {}",
+            db_path.display(),
+            from.name(),
+            to.name()
+        );
+        Ok(())
+    }
+
+    /// Promotes a `-pending` secret left by an interrupted `rotate_cipher_key` if the database
+    /// was already rekeyed with it, and returns the key to open the database with.
+    async fn finish_key_rotation(
+        db_path: &Path,
+        key_name: &str,
+        keys: &dyn KeyProvider,
+        key: String,
+        cipher: &DatabaseCipherConfig,
+    ) -> anyhow::Result<String> {
+        let pending = pending_key_name(key_name);
+        let Some(pending_key) = keys.existing_key(&pending)? else {
+            return Ok(key);
+        };
+        if pending_key == key {
+            // Providers with one secret for every key name have nothing pending
+            return Ok(key);
+        }
+        let rekeyed = db_path.exists()
+            && Self::detect_cipher(db_path, &key, cipher).await?.is_none()
+            && Self::detect_cipher(db_path, &pending_key, cipher)
+                .await?
+                .is_some();
+        if rekeyed {
+            keys.set_key(key_name, &pending_key)?;
+        }
+        keys.delete_key(&pending)?;
+        Ok(if rekeyed { pending_key } else { key })
+    }
+
+    /// Connection options for the database at `db_path` with the given cipher settings. The
+    /// journal mode is left as the file has it; `open` switches the writer to WAL.
+    pub(super) fn connect_options(
+        db_path: &Path,
+        key: String,
+        cipher: &DatabaseCipherConfig,
+    ) -> anyhow::Result<SqliteConnectOptions> {
+        validate_cipher(cipher)?;
+        let db_url = format!("sqlite:{}", db_path.display());
+        Ok(SqliteConnectOptions::from_str(&db_url)?
+            .pragma("key", key_literal(&key))
+            .pragma("cipher_page_size", cipher.page_size.to_string())
+            .pragma("kdf_iter", cipher.kdf_iter.to_string())
+            .pragma("cipher_hmac_algorithm", cipher.hmac_algorithm.as_str())
+            .pragma("cipher_kdf_algorithm", cipher.kdf_algorithm.as_str())
+            .foreign_keys(false)
+            .create_if_missing(true))
+    }
+
+    /// Which of the configured and the previous cipher settings decrypt the database at
+    /// `db_path` with `key`; `None` when none do, e.g. because the key is wrong.
+    async fn detect_cipher(
+        db_path: &Path,
+        key: &str,
+        cipher: &DatabaseCipherConfig,
+    ) -> anyhow::Result<Option<DatabaseCipherConfig>> {
+        for candidate in std::iter::once(cipher).chain(PREVIOUS_CIPHER_SETTINGS) {
+            let options = Self::connect_options(db_path, key.to_string(), candidate)?
+                .create_if_missing(false)
+                .read_only(true);
+            let opened = async {
+                let mut conn = options.connect().await?;
+                sqlx::query("SELECT count(*) FROM sqlite_master")
+                    .execute(&mut conn)
+                    .await?;
+                conn.close().await
+            }
+            .await;
+            match opened {
+                Ok(()) => return Ok(Some(candidate.clone())),
+                Err(e) if is_not_a_database(&e) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Rewrites the database at `db_path` from the `from` to the `to` cipher settings.
+    ///
+    /// `PRAGMA rekey` only changes the key, so the data is exported into a copy with the new
+    /// settings that then replaces the original.
+    async fn migrate_cipher(
+        db_path: &Path,
+        key: &str,
+        from: &DatabaseCipherConfig,
+        to: &DatabaseCipherConfig,
+    ) -> anyhow::Result<()> {
+        validate_cipher(to)?;
+        let migrated = db_path.with_extension("db.rekey");
+        if migrated.exists() {
+            std::fs::remove_file(&migrated)?;
+        }
+        let mut conn = Self::connect_options(db_path, key.to_string(), from)?
+            .connect()
+            .await?;
+        let exported = async {
+            sqlx::query("ATTACH DATABASE ? AS migrated KEY ?")
+                .bind(migrated.to_string_lossy().into_owned())
+                .bind(key)
+                .execute(&mut conn)
+                .await?;
+            for pragma in [
+                format!("PRAGMA migrated.cipher_page_size = {}", to.page_size),
+                format!("PRAGMA migrated.kdf_iter = {}", to.kdf_iter),
+                format!(
+                    "PRAGMA migrated.cipher_hmac_algorithm = {}",
+                    to.hmac_algorithm.as_str()
+                ),
+                format!(
+                    "PRAGMA migrated.cipher_kdf_algorithm = {}",
+                    to.kdf_algorithm.as_str()
+                ),
+            ] {
+                sqlx::query(&pragma).execute(&mut conn).await?;
+            }
+            sqlx::query("SELECT sqlcipher_export('migrated')")
+                .execute(&mut conn)
+                .await?;
+            sqlx::query("DETACH DATABASE migrated")
+                .execute(&mut conn)
+                .await?;
+            anyhow::Ok(())
+        }
+        .await;
+        conn.close().await?;
+        if let Err(e) = exported {
+            let _ = std::fs::remove_file(&migrated);
+            return Err(e);
+        }
+        Self::move_database(&migrated, db_path)?;
+        info!(
+            "Re-encrypted {} with {} KDF iterations and {}-byte pages",
+            db_path.display(),
+            to.kdf_iter,
+            to.page_size
+        );
+        Ok(())
+    }
+
+    /// Re-encrypts the closed database at `db_path` under `new_key` and checks that it opens
+    /// with it before the caller promotes the key: `sqlite3_rekey_v2` can fail without
+    /// `PRAGMA rekey` reporting an error.
+    async fn rekey(
+        db_path: &Path,
+        old_key: &str,
+        new_key: &str,
+        cipher: &DatabaseCipherConfig,
+    ) -> anyhow::Result<()> {
+        let mut conn = Self::connect_options(db_path, old_key.to_string(), cipher)?
+            .create_if_missing(false)
+            .connect()
+            .await?;
+        let rekeyed = sqlx::query(&format!("PRAGMA rekey = {}", key_literal(new_key)))
+            .execute(&mut conn)
+            .await;
+        conn.close().await?;
+        rekeyed?;
+        match Self::detect_cipher(db_path, new_key, cipher).await? {
+            Some(current) if current == *cipher => Ok(()),
+            _ => anyhow::bail!(
+                "Rekeying {} did not take effect: it does not open with the new key",
+                db_path.display()
+            ),
+        }
+    }
+
+    async fn fetch_relation<'e, E: SqliteExecutor<'e>>(
+        executor: E,
+        user_id: &str,
+    ) -> anyhow::Result<Option<RelationUser>> {
+        let r: Option<RelationRow> = sqlx::query_as(
+            "SELECT user_id, nick_name, avatar, remark_name, relation_flags FROM relations WHERE user_id = ?",
+        )
+        .bind(user_id)
+        .fetch_optional(executor)
+        .await?;
+
+        match r {
+            Some((user_id, nick_name, avatar, remark_name, relation_flags)) => {
+                Ok(Some(RelationUser {
+                    user_id,
+                    nick_name,
+                    avatar,
+                    remark_name,
+                    relation_flags,
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn fetch_conversation<'e, E: SqliteExecutor<'e>>(
+        executor: E,
+        conversation_id: &str,
+    ) -> anyhow::Result<Option<ConversationRecord>> {
+        let r: Option<ConversationRow> = sqlx::query_as(
+            "SELECT conversation_id, peer_id, group_id, last_message_id, last_read_message_id, unread_count, last_message_text, last_message_timestamp, name, avatar FROM conversations WHERE conversation_id = ?",
+        )
+        .bind(conversation_id)
+        .fetch_optional(executor)
+        .await?;
+
+        match r {
+            Some((
+                conversation_id,
+                peer_id,
+                group_id,
+                last_message_id,
+                last_read_message_id,
+                unread_count,
+                last_message_text,
+                last_message_timestamp,
+                name,
+                avatar,
+            )) => Ok(Some(ConversationRecord {
+                conversation_id,
+                peer_id,
+                group_id,
+                last_message_id,
+                last_read_message_id,
+                unread_count,
+                last_message_text,
+                last_message_timestamp,
+                name,
+                avatar,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    async fn fetch_group_member<'e, E: SqliteExecutor<'e>>(
+        executor: E,
+        group_id: &str,
+        user_id: &str,
+    ) -> anyhow::Result<Option<GroupMemberData>> {
+        let r: Option<(String, String, Option<String>)> = sqlx::query_as(
+            "SELECT user_id, name, avatar FROM group_members WHERE group_id = ? AND user_id = ?",
+        )
+        .bind(group_id)
+        .bind(user_id)
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(r.map(|(user_id, name, avatar)| GroupMemberData {
+            user_id,
+            name,
+            avatar,
+        }))
+    }
+
+    async fn apply_relation_action(
+        conn: &mut SqliteConnection,
+        action: RelationStorageAction,
+        need_result: bool,
+    ) -> anyhow::Result<Option<RelationUser>> {
+        match action {
+            RelationStorageAction::Upsert(relation) => {
+                sqlx::query(
+                    "INSERT OR REPLACE INTO relations (user_id, nick_name, avatar, remark_name, relation_flags) VALUES (?, ?, ?, ?, ?)",
+                )
+                .bind(&relation.user_id)
+                .bind(&relation.nick_name)
+                .bind(&relation.avatar)
+                .bind(&relation.remark_name)
+                .bind(relation.relation_flags)
+                .execute(&mut *conn)
+                .await?;
+                if need_result {
+                    Ok(Some(relation))
+                } else {
+                    Ok(None)
+                }
+            }
+            RelationStorageAction::UpdateRemarkName {
+                user_id,
+                remark_name,
+            } => {
+                sqlx::query("UPDATE relations SET remark_name = ? WHERE user_id = ?")
+                    .bind(&remark_name)
+                    .bind(&user_id)
+                    .execute(&mut *conn)
+                    .await?;
+                if need_result {
+                    Self::fetch_relation(&mut *conn, &user_id).await
+                } else {
+                    Ok(None)
+                }
+            }
+            RelationStorageAction::UpdateNickName { user_id, nick_name } => {
+                sqlx::query("UPDATE relations SET nick_name = ? WHERE user_id = ?")
+                    .bind(&nick_name)
+                    .bind(&user_id)
+                    .execute(&mut *conn)
+                    .await?;
+                if need_result {
+                    Self::fetch_relation(&mut *conn, &user_id).await
+                } else {
+                    Ok(None)
+                }
+            }
+            RelationStorageAction::UpdateAvatar { user_id, avatar } => {
+                sqlx::query("UPDATE relations SET avatar = ? WHERE user_id = ?")
+                    .bind(&avatar)
+                    .bind(&user_id)
+                    .execute(&mut *conn)
+                    .await?;
+                if need_result {
+                    Self::fetch_relation(&mut *conn, &user_id).await
+                } else {
+                    Ok(None)
+                }
+            }
+            RelationStorageAction::UpdateFlags { user_id, flags } => {
+                sqlx::query("UPDATE relations SET relation_flags = ? WHERE user_id = ?")
+                    .bind(flags)
+                    .bind(&user_id)
+                    .execute(&mut *conn)
+                    .await?;
+                if need_result {
+                    Self::fetch_relation(&mut *conn, &user_id).await
+                } else {
+                    Ok(None)
+                }
+            }
+            RelationStorageAction::Delete { user_id } => {
+                sqlx::query("DELETE FROM relations WHERE user_id = ?")
+                    .bind(&user_id)
+                    .execute(&mut *conn)
+                    .await?;
+                Ok(None)
+            }
+            RelationStorageAction::UpdateNickNameAvatar {
+                user_id,
+                nick_name,
+                avatar,
+            } => {
+                sqlx::query("UPDATE relations SET nick_name = ?, avatar = ? WHERE user_id = ?")
+                    .bind(&nick_name)
+                    .bind(&avatar)
+                    .bind(&user_id)
+                    .execute(&mut *conn)
+                    .await?;
+                if need_result {
+                    Self::fetch_relation(&mut *conn, &user_id).await
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    async fn apply_conversation_action(
+        conn: &mut SqliteConnection,
+        action: ConversationStorageAction,
+        need_result: bool,
+    ) -> anyhow::Result<Option<ConversationRecord>> {
+        match action {
+            ConversationStorageAction::Create(conv) => {
+                sqlx::query(
+                    "INSERT OR REPLACE INTO conversations (conversation_id, peer_id, group_id, last_message_id, last_read_message_id, unread_count, last_message_text, last_message_timestamp, name, avatar) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                )
+                .bind(&conv.conversation_id)
+                .bind(&conv.peer_id)
+                .bind(&conv.group_id)
+                .bind(&conv.last_message_id)
+                .bind(&conv.last_read_message_id)
+                .bind(conv.unread_count)
+                .bind(&conv.last_message_text)
+                .bind(conv.last_message_timestamp)
+                .bind(&conv.name)
+                .bind(&conv.avatar)
+                .execute(&mut *conn)
+                .await?;
+                if need_result {
+                    Ok(Some(conv))
+                } else {
+                    Ok(None)
+                }
+            }
+            ConversationStorageAction::UpdateLastReadMessageId {
+                conversation_id,
+                last_read_message_id,
+            } => {
+                sqlx::query(
+                    "UPDATE conversations SET last_read_message_id = ? WHERE conversation_id = ?",
+                )
+                .bind(&last_read_message_id)
+                .bind(&conversation_id)
+                .execute(&mut *conn)
+                .await?;
+                if need_result {
+                    Self::fetch_conversation(&mut *conn, &conversation_id).await
+                } else {
+                    Ok(None)
+                }
+            }
+            ConversationStorageAction::UpdateName {
+                conversation_id,
+                name,
+            } => {
+                sqlx::query("UPDATE conversations SET name = ? WHERE conversation_id = ?")
+                    .bind(&name)
+                    .bind(&conversation_id)
+                    .execute(&mut *conn)
+                    .await?;
+                if need_result {
+                    Self::fetch_conversation(&mut *conn, &conversation_id).await
+                } else {
+                    Ok(None)
+                }
+            }
+            ConversationStorageAction::UpdateAvatar {
+                conversation_id,
+                avatar,
+            } => {
+                sqlx::query("UPDATE conversations SET avatar = ? WHERE conversation_id = ?")
+                    .bind(&avatar)
+                    .bind(&conversation_id)
+                    .execute(&mut *conn)
+                    .await?;
+                if need_result {
+                    Self::fetch_conversation(&mut *conn, &conversation_id).await
+                } else {
+                    Ok(None)
+                }
+            }
+            ConversationStorageAction::UpdateNameAvatar {
+                conversation_id,
+                name,
+                avatar,
+            } => {
+                sqlx::query(
+                    "UPDATE conversations SET name = ?, avatar = ? WHERE conversation_id = ?",
+                )
+                .bind(&name)
+                .bind(&avatar)
+                .bind(&conversation_id)
+                .execute(&mut *conn)
+                .await?;
+                if need_result {
+                    Self::fetch_conversation(&mut *conn, &conversation_id).await
+                } else {
+                    Ok(None)
+                }
+            }
+            ConversationStorageAction::Delete { conversation_id } => {
+                let removed = if need_result {
+                    Self::fetch_conversation(&mut *conn, &conversation_id).await?
+                } else {
+                    None
+                };
+                sqlx::query("DELETE FROM conversations WHERE conversation_id = ?")
+                    .bind(&conversation_id)
+                    .execute(&mut *conn)
+                    .await?;
+                Ok(removed)
+            }
+        }
+    }
+
+    async fn apply_user_group_action(
+        conn: &mut SqliteConnection,
+        action: UserGroupStorageAction,
+        need_result: bool,
+    ) -> anyhow::Result<Option<UserGroupData>> {
+        match action {
+            UserGroupStorageAction::Upsert(group) => {
+                sqlx::query(
+                    "INSERT OR REPLACE INTO user_groups (group_id, group_name, group_avatar) VALUES (?, ?, ?)",
+                )
+                .bind(&group.group_id)
+                .bind(&group.group_name)
+                .bind(&group.group_avatar)
+                .execute(&mut *conn)
+                .await?;
+                if need_result {
+                    Ok(Some(group))
+                } else {
+                    Ok(None)
+                }
+            }
+            UserGroupStorageAction::UpdateName { group_id, name } => {
+                sqlx::query("UPDATE user_groups SET group_name = ? WHERE group_id = ?")
+                    .bind(&name)
+                    .bind(&group_id)
+                    .execute(&mut *conn)
+                    .await?;
+                if need_result {
+                    let r: Option<(String, String, Option<String>)> = sqlx::query_as(
+                        "SELECT group_id, group_name, group_avatar FROM user_groups WHERE group_id = ?",
+                    )
+                    .bind(&group_id)
+                    .fetch_optional(&mut *conn)
+                    .await?;
+                    Ok(r.map(|(group_id, group_name, group_avatar)| UserGroupData {
+                        group_id,
+                        group_name,
+                        group_avatar,
+                    }))
+                } else {
+                    Ok(None)
+                }
+            }
+            UserGroupStorageAction::UpdateAvatar { group_id, avatar } => {
+                sqlx::query("UPDATE user_groups SET group_avatar = ? WHERE group_id = ?")
+                    .bind(&avatar)
+                    .bind(&group_id)
+                    .execute(&mut *conn)
+                    .await?;
+                if need_result {
+                    let r: Option<(String, String, Option<String>)> = sqlx::query_as(
+                        "SELECT group_id, group_name, group_avatar FROM user_groups WHERE group_id = ?",
+                    )
+                    .bind(&group_id)
+                    .fetch_optional(&mut *conn)
+                    .await?;
+                    Ok(r.map(|(group_id, group_name, group_avatar)| UserGroupData {
+                        group_id,
+                        group_name,
+                        group_avatar,
+                    }))
+                } else {
+                    Ok(None)
+                }
+            }
+            UserGroupStorageAction::Delete { group_id } => {
+                sqlx::query("DELETE FROM user_groups WHERE group_id = ?")
+                    .bind(&group_id)
+                    .execute(&mut *conn)
+                    .await?;
+                // Also remove group members when user quits a group
+                sqlx::query("DELETE FROM group_members WHERE group_id = ?")
+                    .bind(&group_id)
+                    .execute(&mut *conn)
+                    .await?;
+                sqlx::query("DELETE FROM group_member_versions WHERE group_id = ?")
+                    .bind(&group_id)
+                    .execute(&mut *conn)
+                    .await?;
+                Ok(None)
+            }
+        }
+    }
 
-impl SqliteStore {
-    pub async fn new(app_data_dir: PathBuf) -> anyhow::Result<Self> {
-        let db_path = app_data_dir.join("sqlite.db");
-        println!("[SqliteStore] Using database path: {}", db_path.display());
-        let db_url = format!("sqlite:{}", db_path.display());
-        let pool = Self::load_db(db_url).await?;
-        Ok(SqliteStore { pool })
-    }
-
-    fn get_cipher_key() -> anyhow::Result<String> {
-        let entry = Entry::new("ripple-im-app", "ripple")?;
-        match entry.get_password() {
-            Ok(password) => Ok(password),
-            Err(keyring::Error::NoEntry) => {
-                let new_password = Uuid::new_v4().simple().to_string();
-                entry.set_password(&new_password)?;
-                Ok(new_password)
+    async fn apply_group_member_action(
+        conn: &mut SqliteConnection,
+        group_id: &str,
+        action: GroupMemberStorageAction,
+        need_result: bool,
+    ) -> anyhow::Result<Option<GroupMemberData>> {
+        match action {
+            GroupMemberStorageAction::Upsert(member) => {
+                sqlx::query(
+                    "INSERT OR REPLACE INTO group_members (group_id, user_id, name, avatar) VALUES (?, ?, ?, ?)",
+                )
+                .bind(group_id)
+                .bind(&member.user_id)
+                .bind(&member.name)
+                .bind(&member.avatar)
+                .execute(&mut *conn)
+                .await?;
+                if need_result {
+                    Ok(Some(member))
+                } else {
+                    Ok(None)
+                }
+            }
+            GroupMemberStorageAction::UpdateName { user_id, name } => {
+                sqlx::query("UPDATE group_members SET name = ? WHERE group_id = ? AND user_id = ?")
+                    .bind(&name)
+                    .bind(group_id)
+                    .bind(&user_id)
+                    .execute(&mut *conn)
+                    .await?;
+                if need_result {
+                    Self::fetch_group_member(&mut *conn, group_id, &user_id).await
+                } else {
+                    Ok(None)
+                }
+            }
+            GroupMemberStorageAction::UpdateAvatar { user_id, avatar } => {
+                sqlx::query(
+                    "UPDATE group_members SET avatar = ? WHERE group_id = ? AND user_id = ?",
+                )
+                .bind(&avatar)
+                .bind(group_id)
+                .bind(&user_id)
+                .execute(&mut *conn)
+                .await?;
+                if need_result {
+                    Self::fetch_group_member(&mut *conn, group_id, &user_id).await
+                } else {
+                    Ok(None)
+                }
+            }
+            GroupMemberStorageAction::Delete { user_id } => {
+                sqlx::query("DELETE FROM group_members WHERE group_id = ? AND user_id = ?")
+                    .bind(group_id)
+                    .bind(&user_id)
+                    .execute(&mut *conn)
+                    .await?;
+                Ok(None)
             }
-            Err(e) => Err(anyhow::anyhow!(
-                "Failed to retrieve cipher key from keyring: {}",
-                e
-            )),
         }
     }
 
-    async fn load_db(db_url: String) -> anyhow::Result<SqlitePool> {
-        let options = SqliteConnectOptions::from_str(&db_url)?
-            .pragma("key", Self::get_cipher_key()?)
-            .pragma("cipher_page_size", "1024")
-            .pragma("kdf_iter", "64000")
-            .pragma("cipher_hmac_algorithm", "HMAC_SHA1")
-            .pragma("cipher_kdf_algorithm", "PBKDF2_HMAC_SHA1")
-            .journal_mode(SqliteJournalMode::Delete)
-            .foreign_keys(false)
-            .create_if_missing(true);
-        let pool = SqlitePool::connect_with(options).await?;
-        let migrator = sqlx::migrate!("./migrations");
-        migrator.run(&pool).await?;
-        Ok(pool)
+    async fn insert_message(
+        conn: &mut SqliteConnection,
+        message: MessageItem,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO messages (message_id, conversation_id, sender_id, receiver_id, group_id, send_timestamp, message_type, text, file_url, file_name, command_type, command_data, raw_format, raw_content) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&message.message_id)
+        .bind(&message.conversation_id)
+        .bind(&message.sender_id)
+        .bind(&message.receiver_id)
+        .bind(&message.group_id)
+        .bind(&message.send_timestamp)
+        .bind(i32::from(message.message_type))
+        .bind(&message.text)
+        .bind(&message.file_url)
+        .bind(&message.file_name)
+        .bind(i32::from(message.command_type))
+        .bind(&message.command_data)
+        .bind(message.raw_content.as_ref().map(|raw| raw.format()))
+        .bind(message.raw_content.as_ref().map(|raw| raw.bytes()))
+        .execute(&mut *conn)
+        .await?;
+
+        // Update conversation's last_message_id if this message is newer
+        let conv = Self::fetch_conversation(&mut *conn, &message.conversation_id).await?;
+        if let Some(conv) = conv {
+            let should_update = match &conv.last_message_id {
+                Some(existing_id) => message.message_id > *existing_id,
+                None => true,
+            };
+            if should_update {
+                debug!(
+                    "store_message: updating last_message_id from {:?} to {}",
+                    conv.last_message_id, message.message_id
+                );
+                sqlx::query(
+                    "UPDATE conversations SET last_message_id = ? WHERE conversation_id = ?",
+                )
+                .bind(&message.message_id)
+                .bind(&message.conversation_id)
+                .execute(&mut *conn)
+                .await?;
+            }
+        } else {
+            debug!(
+                "store_message: conversation {} not found in store",
+                message.conversation_id
+            );
+        }
+
+        Ok(())
     }
 }
 
 impl RippleStorage for SqliteStore {
-    async fn exists_token(&self) -> anyhow::Result<bool> {
-        let r: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM oauth_tokens")
-            .fetch_one(&self.pool)
-            .await?;
-        Ok(r.0 > 0)
+    fn tokens(&self) -> &dyn TokenStore {
+        self.tokens.as_ref()
     }
 
     async fn exist_relations(&self) -> anyhow::Result<bool> {
         let r: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM relations")
-            .fetch_one(&self.pool)
+            .fetch_one(&self.reader)
             .await?;
         Ok(r.0 > 0)
     }
 
     async fn exist_conversations(&self) -> anyhow::Result<bool> {
         let r: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM conversations")
-            .fetch_one(&self.pool)
+            .fetch_one(&self.reader)
             .await?;
         Ok(r.0 > 0)
     }
@@ -84,7 +1020,7 @@ impl RippleStorage for SqliteStore {
     async fn get_device_id(&self) -> anyhow::Result<Option<Uuid>> {
         let r: (Option<String>,) =
             sqlx::query_as("SELECT device_id FROM app_metadata WHERE id = 1")
-                .fetch_one(&self.pool)
+                .fetch_one(&self.reader)
                 .await?;
         match r.0 {
             Some(id) => Ok(Some(Uuid::parse_str(&id)?)),
@@ -95,61 +1031,39 @@ impl RippleStorage for SqliteStore {
     async fn save_device_id(&self, device_id: &Uuid) -> anyhow::Result<()> {
         sqlx::query("UPDATE app_metadata SET device_id = ? WHERE id = 1")
             .bind(device_id.to_string())
-            .execute(&self.pool)
+            .execute(&self.writer)
             .await?;
         Ok(())
     }
 
-    async fn get_token(&self) -> anyhow::Result<Option<Token>> {
-        let r: Option<(String, String)> =
-            sqlx::query_as("SELECT access_token, refresh_token FROM oauth_tokens LIMIT 1")
-                .fetch_optional(&self.pool)
-                .await?;
-        match r {
-            Some((access_token, refresh_token)) => Ok(Some(Token {
-                access_token,
-                refresh_token,
-            })),
-            None => Ok(None),
-        }
-    }
-
-    async fn save_token(&self, access_token: &str, refresh_token: &str) -> anyhow::Result<()> {
-        if self.exists_token().await? {
-            sqlx::query("UPDATE oauth_tokens SET access_token = ?, refresh_token = ?")
-                .bind(access_token)
-                .bind(refresh_token)
-                .execute(&self.pool)
-                .await?;
-        } else {
-            sqlx::query("INSERT INTO oauth_tokens (access_token, refresh_token) VALUES (?, ?)")
-                .bind(access_token)
-                .bind(refresh_token)
-                .execute(&self.pool)
-                .await?;
-        }
-        Ok(())
+    async fn get_jwks(&self) -> anyhow::Result<Option<String>> {
+        let r: (Option<String>,) = sqlx::query_as("SELECT document FROM jwks_cache WHERE id = 1")
+            .fetch_one(&self.reader)
+            .await?;
+        Ok(r.0)
     }
 
-    async fn clear_token(&self) -> anyhow::Result<()> {
-        sqlx::query("DELETE FROM oauth_tokens")
-            .execute(&self.pool)
-            .await?;
+    async fn save_jwks(&self, jwks: &str) -> anyhow::Result<()> {
+        sqlx::query(
+            "UPDATE jwks_cache SET document = ?, fetched_at = CURRENT_TIMESTAMP WHERE id = 1",
+        )
+        .bind(jwks)
+        .execute(&self.writer)
+        .await?;
         Ok(())
     }
 
     async fn get_stored_user_id(&self) -> anyhow::Result<Option<String>> {
-        let r: (Option<String>,) =
-            sqlx::query_as("SELECT user_id FROM app_metadata WHERE id = 1")
-                .fetch_one(&self.pool)
-                .await?;
+        let r: (Option<String>,) = sqlx::query_as("SELECT user_id FROM app_metadata WHERE id = 1")
+            .fetch_one(&self.reader)
+            .await?;
         Ok(r.0)
     }
 
     async fn save_user_id(&self, user_id: &str) -> anyhow::Result<()> {
         sqlx::query("UPDATE app_metadata SET user_id = ? WHERE id = 1")
             .bind(user_id)
-            .execute(&self.pool)
+            .execute(&self.writer)
             .await?;
         Ok(())
     }
@@ -157,34 +1071,37 @@ impl RippleStorage for SqliteStore {
     async fn clear_all_data(&self) -> anyhow::Result<()> {
         // Clear all user data except device_id and tokens
         sqlx::query("DELETE FROM user_profile")
-            .execute(&self.pool)
+            .execute(&self.writer)
             .await?;
         sqlx::query("DELETE FROM relations")
-            .execute(&self.pool)
+            .execute(&self.writer)
             .await?;
         sqlx::query("UPDATE relations_version SET version = NULL WHERE id = 1")
-            .execute(&self.pool)
+            .execute(&self.writer)
             .await?;
         sqlx::query("DELETE FROM conversations")
-            .execute(&self.pool)
+            .execute(&self.writer)
             .await?;
         sqlx::query("UPDATE conversations_version SET version = NULL WHERE id = 1")
-            .execute(&self.pool)
+            .execute(&self.writer)
             .await?;
         sqlx::query("DELETE FROM messages")
-            .execute(&self.pool)
+            .execute(&self.writer)
             .await?;
         sqlx::query("DELETE FROM user_groups")
-            .execute(&self.pool)
+            .execute(&self.writer)
             .await?;
         sqlx::query("UPDATE user_groups_version SET version = NULL WHERE id = 1")
-            .execute(&self.pool)
+            .execute(&self.writer)
             .await?;
         sqlx::query("DELETE FROM group_members")
-            .execute(&self.pool)
+            .execute(&self.writer)
             .await?;
         sqlx::query("DELETE FROM group_member_versions")
-            .execute(&self.pool)
+            .execute(&self.writer)
+            .await?;
+        sqlx::query("DELETE FROM sync_status")
+            .execute(&self.writer)
             .await?;
         Ok(())
     }
@@ -192,7 +1109,7 @@ impl RippleStorage for SqliteStore {
     async fn get_user_profile(&self) -> anyhow::Result<Option<UserProfileData>> {
         let r: Option<(String, String, Option<String>)> =
             sqlx::query_as("SELECT user_id, nick_name, avatar FROM user_profile LIMIT 1")
-                .fetch_optional(&self.pool)
+                .fetch_optional(&self.reader)
                 .await?;
         match r {
             Some((user_id, nick_name, avatar)) => Ok(Some(UserProfileData {
@@ -211,19 +1128,53 @@ impl RippleStorage for SqliteStore {
         .bind(&profile.user_id)
         .bind(&profile.nick_name)
         .bind(&profile.avatar)
-        .execute(&self.pool)
+        .execute(&self.writer)
         .await?;
         Ok(())
     }
 
+    async fn get_sync_timestamps(&self) -> anyhow::Result<Vec<(String, i64)>> {
+        let rows: Vec<(String, i64)> =
+            sqlx::query_as("SELECT domain, last_success_at FROM sync_status")
+                .fetch_all(&self.reader)
+                .await?;
+        Ok(rows)
+    }
+
+    async fn save_sync_timestamp(&self, domain: &str, timestamp: i64) -> anyhow::Result<()> {
+        sqlx::query("INSERT OR REPLACE INTO sync_status (domain, last_success_at) VALUES (?, ?)")
+            .bind(domain)
+            .bind(timestamp)
+            .execute(&self.writer)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_table_row_counts(&self) -> anyhow::Result<Vec<(String, i64)>> {
+        let tables: Vec<(String,)> = sqlx::query_as(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+        )
+        .fetch_all(&self.reader)
+        .await?;
+        let mut counts = Vec::with_capacity(tables.len());
+        for (table,) in tables {
+            // Names come from sqlite_master, never from user input
+            let sql = format!("SELECT COUNT(*) FROM \"{}\"", table);
+            let (count,): (i64,) = sqlx::query_as(&sql).fetch_one(&self.reader).await?;
+            counts.push((table, count));
+        }
+        Ok(counts)
+    }
+
     async fn apply_relation_all(
         &self,
         relations: Vec<RelationUser>,
         last_version: &str,
     ) -> anyhow::Result<()> {
+        let mut tx = self.writer.begin().await?;
         // Clear existing relations and insert new ones
         sqlx::query("DELETE FROM relations")
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
 
         for relation in relations {
@@ -235,154 +1186,48 @@ impl RippleStorage for SqliteStore {
             .bind(&relation.avatar)
             .bind(&relation.remark_name)
             .bind(relation.relation_flags)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
         }
 
         // Update version
         sqlx::query("UPDATE relations_version SET version = ? WHERE id = 1")
             .bind(last_version)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
 
+        tx.commit().await?;
         Ok(())
     }
 
-    async fn apply_relation_action(
+    async fn apply_relation_actions(
         &self,
-        action: RelationStorageAction,
-        version: String,
+        actions: Vec<RelationStorageAction>,
+        version: &str,
         need_result: bool,
-    ) -> anyhow::Result<Option<RelationUser>> {
-        // Update version
+    ) -> anyhow::Result<Vec<Option<RelationUser>>> {
+        let mut tx = self.writer.begin().await?;
+        let mut results = Vec::with_capacity(actions.len());
+        for action in actions {
+            results.push(Self::apply_relation_action(&mut tx, action, need_result).await?);
+        }
         sqlx::query("UPDATE relations_version SET version = ? WHERE id = 1")
-            .bind(&version)
-            .execute(&self.pool)
+            .bind(version)
+            .execute(&mut *tx)
             .await?;
-
-        match action {
-            RelationStorageAction::Upsert(relation) => {
-                sqlx::query(
-                    "INSERT OR REPLACE INTO relations (user_id, nick_name, avatar, remark_name, relation_flags) VALUES (?, ?, ?, ?, ?)",
-                )
-                .bind(&relation.user_id)
-                .bind(&relation.nick_name)
-                .bind(&relation.avatar)
-                .bind(&relation.remark_name)
-                .bind(relation.relation_flags)
-                .execute(&self.pool)
-                .await?;
-                if need_result {
-                    Ok(Some(relation))
-                } else {
-                    Ok(None)
-                }
-            }
-            RelationStorageAction::UpdateRemarkName {
-                user_id,
-                remark_name,
-            } => {
-                sqlx::query("UPDATE relations SET remark_name = ? WHERE user_id = ?")
-                    .bind(&remark_name)
-                    .bind(&user_id)
-                    .execute(&self.pool)
-                    .await?;
-                if need_result {
-                    self.get_relation(&user_id).await
-                } else {
-                    Ok(None)
-                }
-            }
-            RelationStorageAction::UpdateNickName { user_id, nick_name } => {
-                sqlx::query("UPDATE relations SET nick_name = ? WHERE user_id = ?")
-                    .bind(&nick_name)
-                    .bind(&user_id)
-                    .execute(&self.pool)
-                    .await?;
-                if need_result {
-                    self.get_relation(&user_id).await
-                } else {
-                    Ok(None)
-                }
-            }
-            RelationStorageAction::UpdateAvatar { user_id, avatar } => {
-                sqlx::query("UPDATE relations SET avatar = ? WHERE user_id = ?")
-                    .bind(&avatar)
-                    .bind(&user_id)
-                    .execute(&self.pool)
-                    .await?;
-                if need_result {
-                    self.get_relation(&user_id).await
-                } else {
-                    Ok(None)
-                }
-            }
-            RelationStorageAction::UpdateFlags { user_id, flags } => {
-                sqlx::query("UPDATE relations SET relation_flags = ? WHERE user_id = ?")
-                    .bind(flags)
-                    .bind(&user_id)
-                    .execute(&self.pool)
-                    .await?;
-                if need_result {
-                    self.get_relation(&user_id).await
-                } else {
-                    Ok(None)
-                }
-            }
-            RelationStorageAction::Delete { user_id } => {
-                sqlx::query("DELETE FROM relations WHERE user_id = ?")
-                    .bind(&user_id)
-                    .execute(&self.pool)
-                    .await?;
-                Ok(None)
-            }
-            RelationStorageAction::UpdateNickNameAvatar {
-                user_id,
-                nick_name,
-                avatar,
-            } => {
-                sqlx::query("UPDATE relations SET nick_name = ?, avatar = ? WHERE user_id = ?")
-                    .bind(&nick_name)
-                    .bind(&avatar)
-                    .bind(&user_id)
-                    .execute(&self.pool)
-                    .await?;
-                if need_result {
-                    self.get_relation(&user_id).await
-                } else {
-                    Ok(None)
-                }
-            }
-        }
+        tx.commit().await?;
+        Ok(results)
     }
 
     async fn get_relation(&self, user_id: &str) -> anyhow::Result<Option<RelationUser>> {
-        let r: Option<(String, String, Option<String>, Option<String>, i32)> = sqlx::query_as(
-            "SELECT user_id, nick_name, avatar, remark_name, relation_flags FROM relations WHERE user_id = ?",
-        )
-        .bind(user_id)
-        .fetch_optional(&self.pool)
-        .await?;
-
-        match r {
-            Some((user_id, nick_name, avatar, remark_name, relation_flags)) => {
-                Ok(Some(RelationUser {
-                    user_id,
-                    nick_name,
-                    avatar,
-                    remark_name,
-                    relation_flags,
-                }))
-            }
-            None => Ok(None),
-        }
+        Self::fetch_relation(&self.reader, user_id).await
     }
 
     async fn get_all_relations(&self) -> anyhow::Result<Vec<RelationUser>> {
-        let rows: Vec<(String, String, Option<String>, Option<String>, i32)> = sqlx::query_as(
+        let rows: Vec<RelationRow> = sqlx::query_as(
             "SELECT user_id, nick_name, avatar, remark_name, relation_flags FROM relations",
         )
-        .fetch_all(&self.pool)
+        .fetch_all(&self.reader)
         .await?;
 
         Ok(rows
@@ -402,14 +1247,14 @@ impl RippleStorage for SqliteStore {
     async fn get_relation_version(&self) -> anyhow::Result<Option<String>> {
         let r: (Option<String>,) =
             sqlx::query_as("SELECT version FROM relations_version WHERE id = 1")
-                .fetch_one(&self.pool)
+                .fetch_one(&self.reader)
                 .await?;
         Ok(r.0)
     }
 
     async fn clear_all_relations(&self) -> anyhow::Result<()> {
         sqlx::query("DELETE FROM relations")
-            .execute(&self.pool)
+            .execute(&self.writer)
             .await?;
         Ok(())
     }
@@ -419,8 +1264,9 @@ impl RippleStorage for SqliteStore {
         conversations: Vec<ConversationRecord>,
         last_version: &str,
     ) -> anyhow::Result<()> {
+        let mut tx = self.writer.begin().await?;
         sqlx::query("DELETE FROM conversations")
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
 
         for conv in conversations {
@@ -437,138 +1283,43 @@ impl RippleStorage for SqliteStore {
             .bind(conv.last_message_timestamp)
             .bind(&conv.name)
             .bind(&conv.avatar)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
         }
 
         sqlx::query("UPDATE conversations_version SET version = ? WHERE id = 1")
             .bind(last_version)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
 
+        tx.commit().await?;
         Ok(())
     }
 
-    async fn apply_conversation_action(
+    async fn apply_conversation_actions(
         &self,
-        action: ConversationStorageAction,
-        version: String,
+        actions: Vec<ConversationStorageAction>,
+        version: &str,
         need_result: bool,
-    ) -> anyhow::Result<Option<ConversationRecord>> {
+    ) -> anyhow::Result<Vec<Option<ConversationRecord>>> {
+        let mut tx = self.writer.begin().await?;
+        let mut results = Vec::with_capacity(actions.len());
+        for action in actions {
+            results.push(Self::apply_conversation_action(&mut tx, action, need_result).await?);
+        }
         sqlx::query("UPDATE conversations_version SET version = ? WHERE id = 1")
-            .bind(&version)
-            .execute(&self.pool)
+            .bind(version)
+            .execute(&mut *tx)
             .await?;
-
-        match action {
-            ConversationStorageAction::Create(conv) => {
-                sqlx::query(
-                    "INSERT OR REPLACE INTO conversations (conversation_id, peer_id, group_id, last_message_id, last_read_message_id, unread_count, last_message_text, last_message_timestamp, name, avatar) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
-                )
-                .bind(&conv.conversation_id)
-                .bind(&conv.peer_id)
-                .bind(&conv.group_id)
-                .bind(&conv.last_message_id)
-                .bind(&conv.last_read_message_id)
-                .bind(conv.unread_count)
-                .bind(&conv.last_message_text)
-                .bind(conv.last_message_timestamp)
-                .bind(&conv.name)
-                .bind(&conv.avatar)
-                .execute(&self.pool)
-                .await?;
-                if need_result {
-                    Ok(Some(conv))
-                } else {
-                    Ok(None)
-                }
-            }
-            ConversationStorageAction::UpdateLastReadMessageId {
-                conversation_id,
-                last_read_message_id,
-            } => {
-                sqlx::query(
-                    "UPDATE conversations SET last_read_message_id = ? WHERE conversation_id = ?",
-                )
-                .bind(&last_read_message_id)
-                .bind(&conversation_id)
-                .execute(&self.pool)
-                .await?;
-                if need_result {
-                    self.get_conversation_by_id(&conversation_id).await
-                } else {
-                    Ok(None)
-                }
-            }
-            ConversationStorageAction::UpdateName {
-                conversation_id,
-                name,
-            } => {
-                sqlx::query("UPDATE conversations SET name = ? WHERE conversation_id = ?")
-                    .bind(&name)
-                    .bind(&conversation_id)
-                    .execute(&self.pool)
-                    .await?;
-                if need_result {
-                    self.get_conversation_by_id(&conversation_id).await
-                } else {
-                    Ok(None)
-                }
-            }
-            ConversationStorageAction::UpdateAvatar {
-                conversation_id,
-                avatar,
-            } => {
-                sqlx::query("UPDATE conversations SET avatar = ? WHERE conversation_id = ?")
-                    .bind(&avatar)
-                    .bind(&conversation_id)
-                    .execute(&self.pool)
-                    .await?;
-                if need_result {
-                    self.get_conversation_by_id(&conversation_id).await
-                } else {
-                    Ok(None)
-                }
-            }
-            ConversationStorageAction::UpdateNameAvatar {
-                conversation_id,
-                name,
-                avatar,
-            } => {
-                sqlx::query(
-                    "UPDATE conversations SET name = ?, avatar = ? WHERE conversation_id = ?",
-                )
-                .bind(&name)
-                .bind(&avatar)
-                .bind(&conversation_id)
-                .execute(&self.pool)
-                .await?;
-                if need_result {
-                    self.get_conversation_by_id(&conversation_id).await
-                } else {
-                    Ok(None)
-                }
-            }
-            ConversationStorageAction::Delete { conversation_id } => {
-                let removed = if need_result {
-                    self.get_conversation_by_id(&conversation_id).await?
-                } else {
-                    None
-                };
-                sqlx::query("DELETE FROM conversations WHERE conversation_id = ?")
-                    .bind(&conversation_id)
-                    .execute(&self.pool)
-                    .await?;
-                Ok(removed)
-            }
-        }
+        tx.commit().await?;
+        Ok(results)
     }
 
     async fn conversation_exists(&self, conversation_id: &str) -> anyhow::Result<bool> {
         let r: (i64,) =
             sqlx::query_as("SELECT COUNT(*) FROM conversations WHERE conversation_id = ?")
                 .bind(conversation_id)
-                .fetch_one(&self.pool)
+                .fetch_one(&self.reader)
                 .await?;
         Ok(r.0 > 0)
     }
@@ -577,68 +1328,14 @@ impl RippleStorage for SqliteStore {
         &self,
         conversation_id: &str,
     ) -> anyhow::Result<Option<ConversationRecord>> {
-        let r: Option<(
-            String,
-            Option<String>,
-            Option<String>,
-            Option<String>,
-            Option<String>,
-            i64,
-            Option<String>,
-            Option<i64>,
-            String,
-            Option<String>,
-        )> = sqlx::query_as(
-            "SELECT conversation_id, peer_id, group_id, last_message_id, last_read_message_id, unread_count, last_message_text, last_message_timestamp, name, avatar FROM conversations WHERE conversation_id = ?",
-        )
-        .bind(conversation_id)
-        .fetch_optional(&self.pool)
-        .await?;
-
-        match r {
-            Some((
-                conversation_id,
-                peer_id,
-                group_id,
-                last_message_id,
-                last_read_message_id,
-                unread_count,
-                last_message_text,
-                last_message_timestamp,
-                name,
-                avatar,
-            )) => Ok(Some(ConversationRecord {
-                conversation_id,
-                peer_id,
-                group_id,
-                last_message_id,
-                last_read_message_id,
-                unread_count,
-                last_message_text,
-                last_message_timestamp,
-                name,
-                avatar,
-            })),
-            None => Ok(None),
-        }
+        Self::fetch_conversation(&self.reader, conversation_id).await
     }
 
     async fn get_all_conversations(&self) -> anyhow::Result<Vec<ConversationRecord>> {
-        let rows: Vec<(
-            String,
-            Option<String>,
-            Option<String>,
-            Option<String>,
-            Option<String>,
-            i64,
-            Option<String>,
-            Option<i64>,
-            String,
-            Option<String>,
-        )> = sqlx::query_as(
+        let rows: Vec<ConversationRow> = sqlx::query_as(
             "SELECT conversation_id, peer_id, group_id, last_message_id, last_read_message_id, unread_count, last_message_text, last_message_timestamp, name, avatar FROM conversations",
         )
-        .fetch_all(&self.pool)
+        .fetch_all(&self.reader)
         .await?;
 
         Ok(rows
@@ -674,83 +1371,52 @@ impl RippleStorage for SqliteStore {
     async fn get_conversation_version(&self) -> anyhow::Result<Option<String>> {
         let r: (Option<String>,) =
             sqlx::query_as("SELECT version FROM conversations_version WHERE id = 1")
-                .fetch_one(&self.pool)
+                .fetch_one(&self.reader)
                 .await?;
         Ok(r.0)
     }
 
     async fn clear_all_conversations(&self) -> anyhow::Result<()> {
         sqlx::query("DELETE FROM conversations")
-            .execute(&self.pool)
+            .execute(&self.writer)
             .await?;
         Ok(())
     }
 
-    async fn update_conversation_summary(
+    async fn update_conversation_summaries(
         &self,
-        conversation_id: &str,
-        unread_count: i64,
-        last_message_id: Option<String>,
-        last_message_text: Option<String>,
-        last_message_timestamp: Option<i64>,
+        summaries: Vec<ConversationSummary>,
     ) -> anyhow::Result<()> {
-        sqlx::query(
-            "UPDATE conversations SET unread_count = ?, last_message_id = ?, last_message_text = ?, last_message_timestamp = ? WHERE conversation_id = ?",
-        )
-        .bind(unread_count)
-        .bind(&last_message_id)
-        .bind(&last_message_text)
-        .bind(last_message_timestamp)
-        .bind(conversation_id)
-        .execute(&self.pool)
-        .await?;
+        let mut tx = self.writer.begin().await?;
+        for summary in summaries {
+            sqlx::query(
+                "UPDATE conversations SET unread_count = ?, last_message_id = ?, last_message_text = ?, last_message_timestamp = ? WHERE conversation_id = ?",
+            )
+            .bind(summary.unread_count)
+            .bind(&summary.last_message_id)
+            .bind(&summary.last_message_text)
+            .bind(summary.last_message_timestamp)
+            .bind(&summary.conversation_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
         Ok(())
     }
 
     async fn store_message(&self, message: MessageItem) -> anyhow::Result<()> {
-        sqlx::query(
-            "INSERT OR REPLACE INTO messages (message_id, conversation_id, sender_id, receiver_id, group_id, send_timestamp, message_type, text, file_url, file_name, command_type, command_data) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
-        )
-        .bind(&message.message_id)
-        .bind(&message.conversation_id)
-        .bind(&message.sender_id)
-        .bind(&message.receiver_id)
-        .bind(&message.group_id)
-        .bind(&message.send_timestamp)
-        .bind(i32::from(message.message_type))
-        .bind(&message.text)
-        .bind(&message.file_url)
-        .bind(&message.file_name)
-        .bind(i32::from(message.command_type))
-        .bind(&message.command_data)
-        .execute(&self.pool)
-        .await?;
+        let mut tx = self.writer.begin().await?;
+        Self::insert_message(&mut tx, message).await?;
+        tx.commit().await?;
+        Ok(())
+    }
 
-        // Update conversation's last_message_id if this message is newer
-        let conv = self.get_conversation_by_id(&message.conversation_id).await?;
-        if let Some(conv) = conv {
-            let should_update = match &conv.last_message_id {
-                Some(existing_id) => message.message_id > *existing_id,
-                None => true,
-            };
-            if should_update {
-                println!(
-                    "[SqliteStore] store_message: updating last_message_id from {:?} to {}",
-                    conv.last_message_id, message.message_id
-                );
-                sqlx::query("UPDATE conversations SET last_message_id = ? WHERE conversation_id = ?")
-                    .bind(&message.message_id)
-                    .bind(&message.conversation_id)
-                    .execute(&self.pool)
-                    .await?;
-            }
-        } else {
-            println!(
-                "[SqliteStore] store_message: conversation {} not found in store",
-                message.conversation_id
-            );
+    async fn store_messages(&self, messages: Vec<MessageItem>) -> anyhow::Result<()> {
+        let mut tx = self.writer.begin().await?;
+        for message in messages {
+            Self::insert_message(&mut tx, message).await?;
         }
-
+        tx.commit().await?;
         Ok(())
     }
 
@@ -758,24 +1424,11 @@ impl RippleStorage for SqliteStore {
         &self,
         conversation_id: &str,
     ) -> anyhow::Result<Option<MessageItem>> {
-        let r: Option<(
-            String,
-            String,
-            String,
-            Option<String>,
-            Option<String>,
-            String,
-            i32,
-            Option<String>,
-            Option<String>,
-            Option<String>,
-            i32,
-            Option<String>,
-        )> = sqlx::query_as(
+        let r: Option<MessageRow> = sqlx::query_as(
             "SELECT message_id, conversation_id, sender_id, receiver_id, group_id, send_timestamp, message_type, text, file_url, file_name, command_type, command_data FROM messages WHERE conversation_id = ? ORDER BY message_id DESC LIMIT 1",
         )
         .bind(conversation_id)
-        .fetch_optional(&self.pool)
+        .fetch_optional(&self.reader)
         .await?;
 
         match r {
@@ -805,6 +1458,7 @@ impl RippleStorage for SqliteStore {
                 file_name,
                 command_type: MessageCommandType::from(command_type),
                 command_data,
+                raw_content: None,
             })),
             None => Ok(None),
         }
@@ -816,25 +1470,12 @@ impl RippleStorage for SqliteStore {
         limit: u32,
     ) -> anyhow::Result<Vec<MessageItem>> {
         // Get in descending order then reverse to get ascending order
-        let rows: Vec<(
-            String,
-            String,
-            String,
-            Option<String>,
-            Option<String>,
-            String,
-            i32,
-            Option<String>,
-            Option<String>,
-            Option<String>,
-            i32,
-            Option<String>,
-        )> = sqlx::query_as(
+        let rows: Vec<MessageRow> = sqlx::query_as(
             "SELECT message_id, conversation_id, sender_id, receiver_id, group_id, send_timestamp, message_type, text, file_url, file_name, command_type, command_data FROM messages WHERE conversation_id = ? ORDER BY message_id DESC LIMIT ?",
         )
         .bind(conversation_id)
         .bind(limit)
-        .fetch_all(&self.pool)
+        .fetch_all(&self.reader)
         .await?;
 
         let mut result: Vec<MessageItem> = rows
@@ -866,15 +1507,13 @@ impl RippleStorage for SqliteStore {
                     file_name,
                     command_type: MessageCommandType::from(command_type),
                     command_data,
+                    raw_content: None,
                 },
             )
             .collect();
 
         result.reverse();
-        println!(
-            "[SqliteStore] Returning {} latest messages",
-            result.len()
-        );
+        debug!("Returning {} latest messages", result.len());
         Ok(result)
     }
 
@@ -884,26 +1523,13 @@ impl RippleStorage for SqliteStore {
         before_message_id: &str,
         limit: u32,
     ) -> anyhow::Result<Vec<MessageItem>> {
-        let rows: Vec<(
-            String,
-            String,
-            String,
-            Option<String>,
-            Option<String>,
-            String,
-            i32,
-            Option<String>,
-            Option<String>,
-            Option<String>,
-            i32,
-            Option<String>,
-        )> = sqlx::query_as(
-            "SELECT message_id, conversation_id, sender_id, receiver_id, group_id, send_timestamp, message_type, text, file_url, file_name, command_type, command_data FROM messages WHERE conversation_id = ? AND message_id < ? ORDER BY message_id ASC LIMIT ?",
+        let rows: Vec<MessageRow> = sqlx::query_as(
+            "SELECT * FROM (SELECT message_id, conversation_id, sender_id, receiver_id, group_id, send_timestamp, message_type, text, file_url, file_name, command_type, command_data FROM messages WHERE conversation_id = ? AND message_id < ? ORDER BY message_id DESC LIMIT ?) ORDER BY message_id ASC",
         )
         .bind(conversation_id)
         .bind(before_message_id)
         .bind(limit)
-        .fetch_all(&self.pool)
+        .fetch_all(&self.reader)
         .await?;
 
         Ok(rows
@@ -935,14 +1561,30 @@ impl RippleStorage for SqliteStore {
                     file_name,
                     command_type: MessageCommandType::from(command_type),
                     command_data,
+                    raw_content: None,
                 },
             )
             .collect())
     }
 
+    async fn get_unsupported_messages(&self) -> anyhow::Result<Vec<(String, RawMessageContent)>> {
+        let rows: Vec<(String, String, Vec<u8>)> = sqlx::query_as(
+            "SELECT message_id, raw_format, raw_content FROM messages WHERE raw_content IS NOT NULL",
+        )
+        .fetch_all(&self.reader)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|(message_id, raw_format, raw_content)| {
+                RawMessageContent::from_parts(&raw_format, raw_content).map(|raw| (message_id, raw))
+            })
+            .collect())
+    }
+
     async fn exist_user_groups(&self) -> anyhow::Result<bool> {
         let r: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM user_groups")
-            .fetch_one(&self.pool)
+            .fetch_one(&self.reader)
             .await?;
         Ok(r.0 > 0)
     }
@@ -952,8 +1594,9 @@ impl RippleStorage for SqliteStore {
         groups: Vec<UserGroupData>,
         version: &str,
     ) -> anyhow::Result<()> {
+        let mut tx = self.writer.begin().await?;
         sqlx::query("DELETE FROM user_groups")
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
 
         for group in groups {
@@ -963,112 +1606,42 @@ impl RippleStorage for SqliteStore {
             .bind(&group.group_id)
             .bind(&group.group_name)
             .bind(&group.group_avatar)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
         }
 
         sqlx::query("UPDATE user_groups_version SET version = ? WHERE id = 1")
             .bind(version)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
 
+        tx.commit().await?;
         Ok(())
     }
 
-    async fn apply_user_group_action(
+    async fn apply_user_group_actions(
         &self,
-        action: UserGroupStorageAction,
-        version: String,
+        actions: Vec<UserGroupStorageAction>,
+        version: &str,
         need_result: bool,
-    ) -> anyhow::Result<Option<UserGroupData>> {
+    ) -> anyhow::Result<Vec<Option<UserGroupData>>> {
+        let mut tx = self.writer.begin().await?;
+        let mut results = Vec::with_capacity(actions.len());
+        for action in actions {
+            results.push(Self::apply_user_group_action(&mut tx, action, need_result).await?);
+        }
         sqlx::query("UPDATE user_groups_version SET version = ? WHERE id = 1")
-            .bind(&version)
-            .execute(&self.pool)
+            .bind(version)
+            .execute(&mut *tx)
             .await?;
-
-        match action {
-            UserGroupStorageAction::Upsert(group) => {
-                sqlx::query(
-                    "INSERT OR REPLACE INTO user_groups (group_id, group_name, group_avatar) VALUES (?, ?, ?)",
-                )
-                .bind(&group.group_id)
-                .bind(&group.group_name)
-                .bind(&group.group_avatar)
-                .execute(&self.pool)
-                .await?;
-                if need_result {
-                    Ok(Some(group))
-                } else {
-                    Ok(None)
-                }
-            }
-            UserGroupStorageAction::UpdateName { group_id, name } => {
-                sqlx::query("UPDATE user_groups SET group_name = ? WHERE group_id = ?")
-                    .bind(&name)
-                    .bind(&group_id)
-                    .execute(&self.pool)
-                    .await?;
-                if need_result {
-                    let r: Option<(String, String, Option<String>)> = sqlx::query_as(
-                        "SELECT group_id, group_name, group_avatar FROM user_groups WHERE group_id = ?",
-                    )
-                    .bind(&group_id)
-                    .fetch_optional(&self.pool)
-                    .await?;
-                    Ok(r.map(|(group_id, group_name, group_avatar)| UserGroupData {
-                        group_id,
-                        group_name,
-                        group_avatar,
-                    }))
-                } else {
-                    Ok(None)
-                }
-            }
-            UserGroupStorageAction::UpdateAvatar { group_id, avatar } => {
-                sqlx::query("UPDATE user_groups SET group_avatar = ? WHERE group_id = ?")
-                    .bind(&avatar)
-                    .bind(&group_id)
-                    .execute(&self.pool)
-                    .await?;
-                if need_result {
-                    let r: Option<(String, String, Option<String>)> = sqlx::query_as(
-                        "SELECT group_id, group_name, group_avatar FROM user_groups WHERE group_id = ?",
-                    )
-                    .bind(&group_id)
-                    .fetch_optional(&self.pool)
-                    .await?;
-                    Ok(r.map(|(group_id, group_name, group_avatar)| UserGroupData {
-                        group_id,
-                        group_name,
-                        group_avatar,
-                    }))
-                } else {
-                    Ok(None)
-                }
-            }
-            UserGroupStorageAction::Delete { group_id } => {
-                sqlx::query("DELETE FROM user_groups WHERE group_id = ?")
-                    .bind(&group_id)
-                    .execute(&self.pool)
-                    .await?;
-                // Also remove group members when user quits a group
-                sqlx::query("DELETE FROM group_members WHERE group_id = ?")
-                    .bind(&group_id)
-                    .execute(&self.pool)
-                    .await?;
-                sqlx::query("DELETE FROM group_member_versions WHERE group_id = ?")
-                    .bind(&group_id)
-                    .execute(&self.pool)
-                    .await?;
-                Ok(None)
-            }
-        }
+        tx.commit().await?;
+        Ok(results)
     }
 
     async fn get_all_user_groups(&self) -> anyhow::Result<Vec<UserGroupData>> {
         let rows: Vec<(String, String, Option<String>)> =
             sqlx::query_as("SELECT group_id, group_name, group_avatar FROM user_groups")
-                .fetch_all(&self.pool)
+                .fetch_all(&self.reader)
                 .await?;
 
         Ok(rows
@@ -1084,25 +1657,27 @@ impl RippleStorage for SqliteStore {
     async fn get_user_group_version(&self) -> anyhow::Result<Option<String>> {
         let r: (Option<String>,) =
             sqlx::query_as("SELECT version FROM user_groups_version WHERE id = 1")
-                .fetch_one(&self.pool)
+                .fetch_one(&self.reader)
                 .await?;
         Ok(r.0)
     }
 
     async fn clear_all_user_groups(&self) -> anyhow::Result<()> {
+        let mut tx = self.writer.begin().await?;
         sqlx::query("DELETE FROM user_groups")
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
         sqlx::query("UPDATE user_groups_version SET version = NULL WHERE id = 1")
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
+        tx.commit().await?;
         Ok(())
     }
 
     async fn exist_group_members(&self, group_id: &str) -> anyhow::Result<bool> {
         let r: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM group_members WHERE group_id = ?")
             .bind(group_id)
-            .fetch_one(&self.pool)
+            .fetch_one(&self.reader)
             .await?;
         Ok(r.0 > 0)
     }
@@ -1113,9 +1688,10 @@ impl RippleStorage for SqliteStore {
         members: Vec<GroupMemberData>,
         version: &str,
     ) -> anyhow::Result<()> {
+        let mut tx = self.writer.begin().await?;
         sqlx::query("DELETE FROM group_members WHERE group_id = ?")
             .bind(group_id)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
 
         for member in members {
@@ -1126,7 +1702,7 @@ impl RippleStorage for SqliteStore {
             .bind(&member.user_id)
             .bind(&member.name)
             .bind(&member.avatar)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
         }
 
@@ -1135,90 +1711,43 @@ impl RippleStorage for SqliteStore {
         )
         .bind(group_id)
         .bind(version)
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await?;
 
+        tx.commit().await?;
         Ok(())
     }
 
-    async fn apply_group_member_action(
+    async fn apply_group_member_actions(
         &self,
         group_id: &str,
-        action: GroupMemberStorageAction,
-        version: String,
+        actions: Vec<GroupMemberStorageAction>,
+        version: &str,
         need_result: bool,
-    ) -> anyhow::Result<Option<GroupMemberData>> {
+    ) -> anyhow::Result<Vec<Option<GroupMemberData>>> {
+        let mut tx = self.writer.begin().await?;
+        let mut results = Vec::with_capacity(actions.len());
+        for action in actions {
+            results.push(
+                Self::apply_group_member_action(&mut tx, group_id, action, need_result).await?,
+            );
+        }
         sqlx::query(
             "INSERT OR REPLACE INTO group_member_versions (group_id, version) VALUES (?, ?)",
         )
         .bind(group_id)
-        .bind(&version)
-        .execute(&self.pool)
+        .bind(version)
+        .execute(&mut *tx)
         .await?;
-
-        match action {
-            GroupMemberStorageAction::Upsert(member) => {
-                sqlx::query(
-                    "INSERT OR REPLACE INTO group_members (group_id, user_id, name, avatar) VALUES (?, ?, ?, ?)",
-                )
-                .bind(group_id)
-                .bind(&member.user_id)
-                .bind(&member.name)
-                .bind(&member.avatar)
-                .execute(&self.pool)
-                .await?;
-                if need_result {
-                    Ok(Some(member))
-                } else {
-                    Ok(None)
-                }
-            }
-            GroupMemberStorageAction::UpdateName { user_id, name } => {
-                sqlx::query(
-                    "UPDATE group_members SET name = ? WHERE group_id = ? AND user_id = ?",
-                )
-                .bind(&name)
-                .bind(group_id)
-                .bind(&user_id)
-                .execute(&self.pool)
-                .await?;
-                if need_result {
-                    self.get_group_member(group_id, &user_id).await
-                } else {
-                    Ok(None)
-                }
-            }
-            GroupMemberStorageAction::UpdateAvatar { user_id, avatar } => {
-                sqlx::query(
-                    "UPDATE group_members SET avatar = ? WHERE group_id = ? AND user_id = ?",
-                )
-                .bind(&avatar)
-                .bind(group_id)
-                .bind(&user_id)
-                .execute(&self.pool)
-                .await?;
-                if need_result {
-                    self.get_group_member(group_id, &user_id).await
-                } else {
-                    Ok(None)
-                }
-            }
-            GroupMemberStorageAction::Delete { user_id } => {
-                sqlx::query("DELETE FROM group_members WHERE group_id = ? AND user_id = ?")
-                    .bind(group_id)
-                    .bind(&user_id)
-                    .execute(&self.pool)
-                    .await?;
-                Ok(None)
-            }
-        }
+        tx.commit().await?;
+        Ok(results)
     }
 
     async fn get_all_group_members(&self, group_id: &str) -> anyhow::Result<Vec<GroupMemberData>> {
         let rows: Vec<(String, String, Option<String>)> =
             sqlx::query_as("SELECT user_id, name, avatar FROM group_members WHERE group_id = ?")
                 .bind(group_id)
-                .fetch_all(&self.pool)
+                .fetch_all(&self.reader)
                 .await?;
 
         Ok(rows
@@ -1236,39 +1765,404 @@ impl RippleStorage for SqliteStore {
         group_id: &str,
         user_id: &str,
     ) -> anyhow::Result<Option<GroupMemberData>> {
-        let r: Option<(String, String, Option<String>)> = sqlx::query_as(
-            "SELECT user_id, name, avatar FROM group_members WHERE group_id = ? AND user_id = ?",
-        )
-        .bind(group_id)
-        .bind(user_id)
-        .fetch_optional(&self.pool)
-        .await?;
-
-        Ok(r.map(|(user_id, name, avatar)| GroupMemberData {
-            user_id,
-            name,
-            avatar,
-        }))
+        Self::fetch_group_member(&self.reader, group_id, user_id).await
     }
 
     async fn get_group_member_version(&self, group_id: &str) -> anyhow::Result<Option<String>> {
         let r: Option<(String,)> =
             sqlx::query_as("SELECT version FROM group_member_versions WHERE group_id = ?")
                 .bind(group_id)
-                .fetch_optional(&self.pool)
+                .fetch_optional(&self.reader)
                 .await?;
         Ok(r.map(|(v,)| v))
     }
 
     async fn clear_group_members(&self, group_id: &str) -> anyhow::Result<()> {
+        let mut tx = self.writer.begin().await?;
         sqlx::query("DELETE FROM group_members WHERE group_id = ?")
             .bind(group_id)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
         sqlx::query("DELETE FROM group_member_versions WHERE group_id = ?")
             .bind(group_id)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
+        tx.commit().await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store_engine::TempDir;
+
+    const LEGACY: DatabaseCipherConfig = DatabaseCipherConfig::SQLCIPHER_3;
+    const CURRENT: DatabaseCipherConfig = DatabaseCipherConfig::SQLCIPHER_4;
+
+    #[tokio::test]
+    async fn legacy_database_is_reencrypted_on_open() {
+        let db = TempDir::new("ripple-cipher");
+        let store = SqliteStore::open(&db.db_path(), "k1".to_string(), &LEGACY)
+            .await
+            .unwrap();
+        store.save_user_id("1001").await.unwrap();
+        store.close().await.unwrap();
+
+        let store = SqliteStore::open(&db.db_path(), "k1".to_string(), &CURRENT)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store.get_stored_user_id().await.unwrap().as_deref(),
+            Some("1001")
+        );
+        store.close().await.unwrap();
+        assert_eq!(
+            SqliteStore::detect_cipher(&db.db_path(), "k1", &CURRENT)
+                .await
+                .unwrap(),
+            Some(CURRENT)
+        );
+        assert!(!db.path().join("sqlite.db.rekey").exists());
+    }
+
+    #[tokio::test]
+    async fn legacy_settings_are_probed_only_without_a_recorded_cipher() {
+        let db = TempDir::new("ripple-cipher");
+        let store = SqliteStore::open(&db.db_path(), "k1".to_string(), &LEGACY)
+            .await
+            .unwrap();
+        store.save_user_id("1001").await.unwrap();
+        store.close().await.unwrap();
+        let marker = cipher_marker_path(&db.db_path());
+        assert_eq!(
+            std::fs::read_to_string(&marker).unwrap(),
+            cipher_marker(&LEGACY)
+        );
+
+        // A marker naming the current settings means there is nothing older to look for
+        std::fs::write(&marker, cipher_marker(&CURRENT)).unwrap();
+        let err = SqliteStore::open(&db.db_path(), "k1".to_string(), &CURRENT)
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("Cannot decrypt"), "{}", err);
+
+        std::fs::remove_file(&marker).unwrap();
+        let store = SqliteStore::open(&db.db_path(), "k1".to_string(), &CURRENT)
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_stored_user_id().await.unwrap().as_deref(),
+            Some("1001")
+        );
+        store.close().await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&marker).unwrap(),
+            cipher_marker(&CURRENT)
+        );
+    }
+
+    #[tokio::test]
+    async fn migrate_key_moves_to_another_provider() {
+        use crate::store_engine::key_provider::{PassphraseKeyProvider, StaticKeyProvider};
+        let db = TempDir::new("ripple-cipher");
+        let key_file = db.path().join("db.key");
+        std::fs::write(&key_file, "file-secret\n").unwrap();
+        let from = StaticKeyProvider::File(key_file);
+        let to = PassphraseKeyProvider::new(db.path().join("keys"));
+        to.unlock("correct horse");
+        let store = SqliteStore::new(db.path().to_path_buf(), "acct", &from, &CURRENT)
+            .await
+            .unwrap();
+        store.save_user_id("1001").await.unwrap();
+        store.close().await.unwrap();
+
+        SqliteStore::migrate_key(db.path(), "acct", &from, &to, &CURRENT)
+            .await
+            .unwrap();
+
+        let store = SqliteStore::new(db.path().to_path_buf(), "acct", &to, &CURRENT)
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_stored_user_id().await.unwrap().as_deref(),
+            Some("1001")
+        );
+        store.close().await.unwrap();
+        assert!(
+            SqliteStore::new(db.path().to_path_buf(), "acct", &from, &CURRENT)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn rekey_replaces_the_key() {
+        let db = TempDir::new("ripple-cipher");
+        let store = SqliteStore::open(&db.db_path(), "old".to_string(), &CURRENT)
+            .await
+            .unwrap();
+        store.save_user_id("1001").await.unwrap();
+        store.close().await.unwrap();
+
+        SqliteStore::rekey(&db.db_path(), "old", "new", &CURRENT)
+            .await
+            .unwrap();
+
+        let err = SqliteStore::open(&db.db_path(), "old".to_string(), &CURRENT)
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("Cannot decrypt"), "{}", err);
+        let store = SqliteStore::open(&db.db_path(), "new".to_string(), &CURRENT)
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_stored_user_id().await.unwrap().as_deref(),
+            Some("1001")
+        );
+        store.close().await.unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_cipher_settings() {
+        let odd_pages = DatabaseCipherConfig {
+            page_size: 3000,
+            ..CURRENT
+        };
+        assert!(validate_cipher(&odd_pages).is_err());
+        assert!(validate_cipher(&LEGACY).is_ok());
+    }
+
+    fn text_message(conversation_id: &str, seq: u64) -> MessageItem {
+        MessageItem {
+            conversation_id: conversation_id.to_string(),
+            message_id: format!("{:012}", seq),
+            sender_id: "1001".to_string(),
+            receiver_id: Some("1002".to_string()),
+            group_id: None,
+            send_timestamp: seq.to_string(),
+            message_type: MessageItemType::Text,
+            text: Some(format!("message {}", seq)),
+            file_url: None,
+            file_name: None,
+            command_type: MessageCommandType::Empty,
+            command_data: None,
+            raw_content: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_do_not_wait_for_an_open_write() {
+        let db = TempDir::new("ripple-cipher");
+        let store = SqliteStore::open(&db.db_path(), "k".to_string(), &CURRENT)
+            .await
+            .unwrap();
+        store.store_message(text_message("c1", 1)).await.unwrap();
+        let (mode,): (String,) = sqlx::query_as("PRAGMA journal_mode")
+            .fetch_one(&store.reader)
+            .await
+            .unwrap();
+        assert_eq!(mode, "wal");
+        assert!(sqlx::query("DELETE FROM messages")
+            .execute(&store.reader)
+            .await
+            .is_err());
+
+        let mut tx = store.writer.begin().await.unwrap();
+        sqlx::query("DELETE FROM messages")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        let read = tokio::time::timeout(
+            std::time::Duration::from_secs(1),
+            store.get_latest_messages("c1", 10),
+        )
+        .await
+        .expect("read blocked by the open write")
+        .unwrap();
+        assert_eq!(read.len(), 1);
+        tx.rollback().await.unwrap();
+
+        store.close().await.unwrap();
+        let wal = std::fs::metadata(db.path().join("sqlite.db-wal")).map(|m| m.len());
+        assert!(matches!(wal, Ok(0) | Err(_)), "WAL left behind: {:?}", wal);
+    }
+
+    #[tokio::test]
+    async fn moved_database_drops_the_journal_it_replaces() {
+        let source = TempDir::new("ripple-cipher");
+        let store = SqliteStore::open(&source.db_path(), "k".to_string(), &CURRENT)
+            .await
+            .unwrap();
+        store.save_user_id("1001").await.unwrap();
+        store.close().await.unwrap();
+        let target = TempDir::new("ripple-cipher");
+        for name in ["sqlite.db", "sqlite.db-wal", "sqlite.db-shm"] {
+            std::fs::write(target.path().join(name), b"stale").unwrap();
+        }
+
+        SqliteStore::move_database(&source.db_path(), &target.db_path()).unwrap();
+
+        assert!(!target.path().join("sqlite.db-wal").exists());
+        assert!(!source.path().join("sqlite.db-wal").exists());
+        let store = SqliteStore::open(&target.db_path(), "k".to_string(), &CURRENT)
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_stored_user_id().await.unwrap().as_deref(),
+            Some("1001")
+        );
+        store.close().await.unwrap();
+    }
+
+    /// The setup before WAL: one pool of the default size for reads and writes, with a
+    /// rollback journal, so readers wait while a write commits.
+    fn relation(user_id: &str, nick_name: &str) -> RelationUser {
+        RelationUser {
+            user_id: user_id.to_string(),
+            nick_name: nick_name.to_string(),
+            avatar: None,
+            remark_name: None,
+            relation_flags: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_batch_is_rolled_back() {
+        let db = TempDir::new("ripple-batch");
+        let store = SqliteStore::open(&db.db_path(), "k1".to_string(), &CURRENT)
+            .await
+            .unwrap();
+        store
+            .apply_relation_all(vec![relation("1001", "alice")], "v1")
+            .await
+            .unwrap();
+        // Rejects the last action of the batch, after the others have been written
+        sqlx::query(
+            "CREATE TRIGGER reject_carol BEFORE INSERT ON relations WHEN NEW.user_id = '1003' \
+             BEGIN SELECT RAISE(ABORT, 'rejected'); END",
+        )
+        .execute(&store.writer)
+        .await
+        .unwrap();
+
+        let result = store
+            .apply_relation_actions(
+                vec![
+                    RelationStorageAction::UpdateNickName {
+                        user_id: "1001".to_string(),
+                        nick_name: "alicia".to_string(),
+                    },
+                    RelationStorageAction::Upsert(relation("1002", "bob")),
+                    RelationStorageAction::Upsert(relation("1003", "carol")),
+                ],
+                "v2",
+                false,
+            )
+            .await;
+
+        assert!(result.is_err());
+        let relations = store.get_all_relations().await.unwrap();
+        assert_eq!(relations.len(), 1);
+        assert_eq!(relations[0].nick_name, "alice");
+        assert_eq!(
+            store.get_relation_version().await.unwrap().as_deref(),
+            Some("v1")
+        );
+        store.close().await.unwrap();
+    }
+
+    async fn rollback_journal_store(db_path: &Path) -> SqliteStore {
+        let options = SqliteStore::connect_options(db_path, "k".to_string(), &CURRENT)
+            .unwrap()
+            .journal_mode(SqliteJournalMode::Delete);
+        let pool = SqlitePool::connect_with(options).await.unwrap();
+        MIGRATOR.run(&pool).await.unwrap();
+        SqliteStore {
+            tokens: Arc::new(SqliteTokenStore::new(pool.clone())),
+            writer: pool.clone(),
+            reader: pool,
+        }
+    }
+
+    /// Inserts and history pages per second while a sync writes and the UI pages back.
+    async fn sync_workload(store: &SqliteStore) -> (f64, f64) {
+        const BATCHES: u64 = 200;
+        const BATCH_SIZE: u64 = 50;
+        const READERS: usize = 4;
+        let seed: Vec<_> = (1..=1000).map(|seq| text_message("c1", seq)).collect();
+        store.store_messages(seed).await.unwrap();
+
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let started = std::time::Instant::now();
+        let writer = {
+            let store = store.clone();
+            tokio::spawn(async move {
+                for batch in 0..BATCHES {
+                    let first = 1_000_000 + batch * BATCH_SIZE;
+                    let messages = (first..first + BATCH_SIZE)
+                        .map(|seq| text_message("c2", seq))
+                        .collect();
+                    store.store_messages(messages).await.unwrap();
+                }
+                started.elapsed()
+            })
+        };
+        let readers: Vec<_> = (0..READERS)
+            .map(|_| {
+                let store = store.clone();
+                let done = done.clone();
+                tokio::spawn(async move {
+                    let mut pages = 0u64;
+                    while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                        let before = format!("{:012}", 1001 - (pages * 20) % 980);
+                        let page = store.get_messages_before("c1", &before, 20).await.unwrap();
+                        assert!(!page.is_empty());
+                        pages += 1;
+                    }
+                    pages
+                })
+            })
+            .collect();
+        let write_time = writer.await.unwrap();
+        done.store(true, std::sync::atomic::Ordering::Relaxed);
+        let mut pages = 0;
+        for reader in readers {
+            pages += reader.await.unwrap();
+        }
+        let read_time = started.elapsed();
+        (
+            (BATCHES * BATCH_SIZE) as f64 / write_time.as_secs_f64(),
+            pages as f64 / read_time.as_secs_f64(),
+        )
+    }
+
+    /// Runs the same sync workload against the rollback journal setup and the WAL one.
+    /// Run with `cargo test --release --lib concurrent_sync_throughput -- --ignored --nocapture`.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[ignore]
+    async fn concurrent_sync_throughput() {
+        let db = TempDir::new("ripple-cipher");
+        let rollback = rollback_journal_store(&db.path().join("rollback.db")).await;
+        let (rollback_inserts, rollback_pages) = sync_workload(&rollback).await;
+        rollback.writer.close().await;
+        let wal = SqliteStore::open(&db.path().join("wal.db"), "k".to_string(), &CURRENT)
+            .await
+            .unwrap();
+        let (wal_inserts, wal_pages) = sync_workload(&wal).await;
+        wal.close().await.unwrap();
+
+        println!("\n{:<34} {:>12} {:>12}", "setup", "inserts/s", "pages/s");
+        println!(
+            "{:<34} {:>12.0} {:>12.0}",
+            "rollback journal, one shared pool", rollback_inserts, rollback_pages
+        );
+        println!(
+            "{:<34} {:>12.0} {:>12.0}",
+            "WAL, one writer and a reader pool", wal_inserts, wal_pages
+        );
+    }
+}
@@ -0,0 +1,294 @@
+//! Where an account's OAuth tokens are kept, separate from the rest of its data so they can
+//! live in the OS keyring instead of the database.
+
+use crate::store_engine::key_provider::KEYRING_SERVICE;
+use crate::store_engine::store_engine::Token;
+use futures_util::future::BoxFuture;
+use keyring::Entry;
+use sqlx::SqlitePool;
+#[cfg(any(feature = "memory-store", test))]
+use std::sync::Mutex;
+
+/// Holds at most one token pair.
+pub trait TokenStore: Send + Sync {
+    fn get_token(&self) -> BoxFuture<'_, anyhow::Result<Option<Token>>>;
+    fn save_token<'a>(&'a self, token: &'a Token) -> BoxFuture<'a, anyhow::Result<()>>;
+    fn clear_token(&self) -> BoxFuture<'_, anyhow::Result<()>>;
+
+    fn exists_token(&self) -> BoxFuture<'_, anyhow::Result<bool>> {
+        Box::pin(async move { Ok(self.get_token().await?.is_some()) })
+    }
+}
+
+/// Moves the token of `from` into `to`, then clears `from`. When both hold one, the more
+/// recently issued token wins, since the refresh token of the other may already be spent.
+pub async fn move_token(from: &dyn TokenStore, to: &dyn TokenStore) -> anyhow::Result<()> {
+    let Some(token) = from.get_token().await? else {
+        return Ok(());
+    };
+    let newer = match to.get_token().await? {
+        Some(existing) => token.issued_at > existing.issued_at,
+        None => true,
+    };
+    if newer {
+        to.save_token(&token).await?;
+    }
+    from.clear_token().await
+}
+
+/// The single-row `oauth_tokens` table of an account database.
+pub struct SqliteTokenStore {
+    pool: SqlitePool,
+}
+
+impl SqliteTokenStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        SqliteTokenStore { pool }
+    }
+}
+
+impl TokenStore for SqliteTokenStore {
+    fn get_token(&self) -> BoxFuture<'_, anyhow::Result<Option<Token>>> {
+        Box::pin(async move {
+            let r: Option<(String, String, i64, Option<i64>)> = sqlx::query_as(
+                "SELECT access_token, refresh_token, issued_at, expires_at FROM oauth_tokens WHERE id = 1",
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+            Ok(r.map(
+                |(access_token, refresh_token, issued_at, expires_at)| Token {
+                    access_token,
+                    refresh_token,
+                    issued_at,
+                    expires_at,
+                },
+            ))
+        })
+    }
+
+    fn save_token<'a>(&'a self, token: &'a Token) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            sqlx::query(
+                "INSERT INTO oauth_tokens (id, access_token, refresh_token, issued_at, expires_at)
+                 VALUES (1, ?, ?, ?, ?)
+                 ON CONFLICT(id) DO UPDATE SET
+                     access_token = excluded.access_token,
+                     refresh_token = excluded.refresh_token,
+                     issued_at = excluded.issued_at,
+                     expires_at = excluded.expires_at",
+            )
+            .bind(&token.access_token)
+            .bind(&token.refresh_token)
+            .bind(token.issued_at)
+            .bind(token.expires_at)
+            .execute(&self.pool)
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn clear_token(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            sqlx::query("DELETE FROM oauth_tokens")
+                .execute(&self.pool)
+                .await?;
+            Ok(())
+        })
+    }
+}
+
+/// The token pair as JSON in one OS keyring entry, never written to disk by the app.
+pub struct KeyringTokenStore {
+    entry_name: String,
+}
+
+impl KeyringTokenStore {
+    /// The store of the account whose database key is named `key_name`.
+    pub fn new(key_name: &str) -> Self {
+        KeyringTokenStore {
+            entry_name: format!("{}-token", key_name),
+        }
+    }
+
+    fn entry(&self) -> anyhow::Result<Entry> {
+        Ok(Entry::new(KEYRING_SERVICE, &self.entry_name)?)
+    }
+}
+
+impl TokenStore for KeyringTokenStore {
+    fn get_token(&self) -> BoxFuture<'_, anyhow::Result<Option<Token>>> {
+        Box::pin(async move {
+            match self.entry()?.get_password() {
+                Ok(json) => Ok(Some(serde_json::from_str(&json)?)),
+                Err(keyring::Error::NoEntry) => Ok(None),
+                Err(e) => Err(anyhow::anyhow!("Failed to read token from keyring: {}", e)),
+            }
+        })
+    }
+
+    fn save_token<'a>(&'a self, token: &'a Token) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            self.entry()?
+                .set_password(&serde_json::to_string(token)?)
+                .map_err(|e| anyhow::anyhow!("Failed to save token to keyring: {}", e))
+        })
+    }
+
+    fn clear_token(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            match self.entry()?.delete_credential() {
+                Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+}
+
+/// Tokens kept only for the lifetime of the process.
+#[cfg(any(feature = "memory-store", test))]
+#[derive(Default)]
+pub struct MemoryTokenStore {
+    token: Mutex<Option<Token>>,
+}
+
+#[cfg(any(feature = "memory-store", test))]
+impl TokenStore for MemoryTokenStore {
+    fn get_token(&self) -> BoxFuture<'_, anyhow::Result<Option<Token>>> {
+        let token = self.token.lock().unwrap().clone();
+        Box::pin(async move { Ok(token) })
+    }
+
+    fn save_token<'a>(&'a self, token: &'a Token) -> BoxFuture<'a, anyhow::Result<()>> {
+        self.token.lock().unwrap().replace(token.clone());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn clear_token(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        self.token.lock().unwrap().take();
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_config::DatabaseCipherConfig;
+    use crate::store_engine::store_engine::RippleStorage;
+    use crate::store_engine::store_sqlite::{SqliteStore, MIGRATOR};
+    use crate::store_engine::TempDir;
+    use sqlx::migrate::Migrator;
+    use std::path::Path;
+    use std::time::Duration;
+
+    const CIPHER: DatabaseCipherConfig = DatabaseCipherConfig::SQLCIPHER_4;
+
+    fn token(access_token: &str, expires_in: Option<Duration>) -> Token {
+        Token::issued_now(access_token.to_string(), "refresh".to_string(), expires_in)
+    }
+
+    #[tokio::test]
+    async fn sqlite_keeps_one_token_with_its_expiry() {
+        let dir = TempDir::new("ripple-tokens");
+        let store = SqliteStore::open(&dir.db_path(), "k".to_string(), &CIPHER)
+            .await
+            .unwrap();
+        let tokens = store.tokens();
+        assert!(!tokens.exists_token().await.unwrap());
+
+        tokens.save_token(&token("first", None)).await.unwrap();
+        let second = token("second", Some(Duration::from_secs(300)));
+        tokens.save_token(&second).await.unwrap();
+
+        let stored = tokens.get_token().await.unwrap().unwrap();
+        assert_eq!(stored.access_token, "second");
+        assert_eq!(stored.issued_at, second.issued_at);
+        assert_eq!(stored.expires_at, Some(second.issued_at + 300_000));
+        let (rows,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM oauth_tokens")
+            .fetch_one(&store.writer)
+            .await
+            .unwrap();
+        assert_eq!(rows, 1);
+
+        tokens.clear_token().await.unwrap();
+        assert!(!tokens.exists_token().await.unwrap());
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn migration_keeps_the_newest_legacy_token() {
+        let dir = TempDir::new("ripple-tokens");
+        let db_path = dir.db_path();
+        // The schema before token metadata, with the duplicates older builds could leave
+        let legacy_dir = dir.path().join("migrations");
+        std::fs::create_dir_all(&legacy_dir).unwrap();
+        let migrations = Path::new(env!("CARGO_MANIFEST_DIR")).join("migrations");
+        for entry in std::fs::read_dir(&migrations).unwrap() {
+            let name = entry.unwrap().file_name();
+            if name.to_string_lossy().as_ref() < "0005" {
+                std::fs::copy(migrations.join(&name), legacy_dir.join(&name)).unwrap();
+            }
+        }
+        let pool = sqlx::SqlitePool::connect_with(
+            SqliteStore::connect_options(&db_path, "k".to_string(), &CIPHER).unwrap(),
+        )
+        .await
+        .unwrap();
+        Migrator::new(legacy_dir.as_path())
+            .await
+            .unwrap()
+            .run(&pool)
+            .await
+            .unwrap();
+        for access_token in ["old", "new"] {
+            sqlx::query("INSERT INTO oauth_tokens (access_token, refresh_token) VALUES (?, 'r')")
+                .bind(access_token)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        MIGRATOR.run(&pool).await.unwrap();
+
+        let stored = SqliteTokenStore::new(pool.clone())
+            .get_token()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.access_token, "new");
+        assert!(stored.issued_at > 0);
+        assert_eq!(stored.expires_at, None);
+        pool.close().await;
+    }
+
+    #[tokio::test]
+    async fn move_token_empties_the_source() {
+        let from = MemoryTokenStore::default();
+        let to = MemoryTokenStore::default();
+        from.save_token(&token("a", None)).await.unwrap();
+
+        move_token(&from, &to).await.unwrap();
+
+        assert!(!from.exists_token().await.unwrap());
+        assert_eq!(to.get_token().await.unwrap().unwrap().access_token, "a");
+    }
+
+    #[tokio::test]
+    async fn move_token_keeps_the_newer_token() {
+        let older = token("older", None);
+        let newer = Token {
+            issued_at: older.issued_at + 1000,
+            ..token("newer", None)
+        };
+        for (source, destination) in [(&newer, &older), (&older, &newer)] {
+            let from = MemoryTokenStore::default();
+            let to = MemoryTokenStore::default();
+            from.save_token(source).await.unwrap();
+            to.save_token(destination).await.unwrap();
+
+            move_token(&from, &to).await.unwrap();
+
+            assert!(!from.exists_token().await.unwrap());
+            assert_eq!(to.get_token().await.unwrap().unwrap().access_token, "newer");
+        }
+    }
+}
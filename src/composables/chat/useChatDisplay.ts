@@ -10,6 +10,7 @@ import { useChatActions } from './useChatActions';
 import { useGroupMembersCache } from './useGroupMembersCache';
 import type { RelationUser } from '../../types/relations';
 import { personalizeMessageText, type PersonalizationContext } from '../../utils/messagePersonalization';
+import { getErrorMessage } from '../../types/errors';
 
 interface UIConversations {
   conversations: ConversationDisplay[];
@@ -192,7 +193,7 @@ export function useChatDisplay(
         );
       }
     } catch (err) {
-      error.value = err instanceof Error ? err.message : 'Failed to load conversations';
+      error.value = getErrorMessage(err, 'Failed to load conversations');
       console.error('[useChatDisplay] Failed to initialize conversations:', err);
       throw err;
     } finally {
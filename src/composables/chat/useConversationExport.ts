@@ -0,0 +1,90 @@
+import { ref } from 'vue';
+import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
+import { save } from '@tauri-apps/plugin-dialog';
+import { getErrorMessage } from '../../types/errors';
+
+export type ExportFormat = 'jsonl' | 'markdown' | 'html';
+
+export interface ExportProgress {
+  conversationId: string;
+  stage: 'messages' | 'attachments' | 'done';
+  completed: number;
+  total?: number;
+}
+
+export interface ExportSummary {
+  messages: number;
+  attachments: number;
+  failedAttachments: number;
+}
+
+const FORMAT_BY_EXTENSION: Record<string, ExportFormat> = {
+  html: 'html',
+  md: 'markdown',
+  jsonl: 'jsonl',
+};
+
+/**
+ * Composable for archiving a conversation to a file
+ *
+ * The format follows the extension picked in the save dialog; downloaded attachments are
+ * embedded in HTML exports and saved next to the file otherwise.
+ */
+export function useConversationExport() {
+  const exporting = ref(false);
+  const exportProgress = ref<ExportProgress | null>(null);
+  const exportError = ref<string | null>(null);
+
+  async function exportConversation(
+    conversationId: string,
+    name: string,
+    downloadAttachments = true
+  ): Promise<ExportSummary | null> {
+    const safeName = name.replace(/[\\/:*?"<>|]/g, '_') || 'conversation';
+    const path = await save({
+      defaultPath: `${safeName}.html`,
+      filters: [
+        { name: 'Web page', extensions: ['html'] },
+        { name: 'Markdown', extensions: ['md'] },
+        { name: 'JSON Lines', extensions: ['jsonl'] },
+      ],
+    });
+    if (!path) return null;
+    const extension = path.split('.').pop()?.toLowerCase() || '';
+    const format = FORMAT_BY_EXTENSION[extension] || 'html';
+
+    exporting.value = true;
+    exportError.value = null;
+    exportProgress.value = null;
+    const unlisten = await listen<ExportProgress>('export-progress', (event) => {
+      if (event.payload.conversationId === conversationId) {
+        exportProgress.value = event.payload;
+      }
+    });
+    try {
+      const summary = await invoke<ExportSummary>('export_conversation', {
+        conversationId,
+        format,
+        attachments: downloadAttachments ? 'download' : 'reference',
+        path,
+      });
+      console.log('[useConversationExport] Export complete:', summary);
+      return summary;
+    } catch (error) {
+      exportError.value = getErrorMessage(error);
+      console.error('[useConversationExport] Export failed:', error);
+      return null;
+    } finally {
+      unlisten();
+      exporting.value = false;
+    }
+  }
+
+  return {
+    exporting,
+    exportProgress,
+    exportError,
+    exportConversation,
+  };
+}
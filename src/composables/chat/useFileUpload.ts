@@ -1,5 +1,6 @@
 import { ref, computed } from 'vue';
 import { invoke } from '@tauri-apps/api/core';
+import { getErrorMessage } from '../../types/errors';
 
 export interface UploadProgress {
   uploadMode: number; // 0=exists, 1=single, 2=chunk
@@ -74,7 +75,7 @@ export function useFileUpload() {
       console.log('[useFileUpload] Upload complete:', result.file_url);
       return result.file_url;
     } catch (error) {
-      const errorMessage = error instanceof Error ? error.message : String(error);
+      const errorMessage = getErrorMessage(error);
       uploadError.value = errorMessage;
       console.error('[useFileUpload] Upload failed:', error);
       throw error;
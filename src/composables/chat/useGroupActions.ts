@@ -2,6 +2,7 @@ import { ref } from 'vue';
 import { invoke } from '@tauri-apps/api/core';
 import type { CreateGroupParams } from '../../types/chat';
 import type { GroupMemberData } from '../../types/group';
+import { getErrorMessage } from '../../types/errors';
 
 /**
  * Composable for group-related actions
@@ -59,7 +60,7 @@ export function useGroupActions() {
 
       console.log('[useGroupActions] Members invited successfully');
     } catch (err) {
-      const message = err instanceof Error ? err.message : String(err);
+      const message = getErrorMessage(err);
       error.value = `Failed to invite members: ${message}`;
       console.error('[useGroupActions] Failed to invite members:', err);
       throw err;
@@ -83,7 +84,7 @@ export function useGroupActions() {
       console.log('[useGroupActions] Group members fetched:', response.length);
       return response;
     } catch (err) {
-      const message = err instanceof Error ? err.message : String(err);
+      const message = getErrorMessage(err);
       error.value = `Failed to fetch group members: ${message}`;
       console.error('[useGroupActions] Failed to fetch group members:', err);
       throw err;
@@ -112,7 +113,7 @@ export function useGroupActions() {
 
       console.log('[useGroupActions] Group name updated successfully');
     } catch (err) {
-      const message = err instanceof Error ? err.message : String(err);
+      const message = getErrorMessage(err);
       error.value = `Failed to update group name: ${message}`;
       console.error('[useGroupActions] Failed to update group name:', err);
       throw err;
@@ -144,7 +145,7 @@ export function useGroupActions() {
 
       console.log('[useGroupActions] Group avatar updated successfully');
     } catch (err) {
-      const message = err instanceof Error ? err.message : String(err);
+      const message = getErrorMessage(err);
       error.value = `Failed to update group avatar: ${message}`;
       console.error('[useGroupActions] Failed to update group avatar:', err);
       throw err;
@@ -167,7 +168,7 @@ export function useGroupActions() {
 
       console.log('[useGroupActions] Left group successfully');
     } catch (err) {
-      const message = err instanceof Error ? err.message : String(err);
+      const message = getErrorMessage(err);
       error.value = `Failed to leave group: ${message}`;
       console.error('[useGroupActions] Failed to leave group:', err);
       throw err;
@@ -2,6 +2,7 @@ import { ref, triggerRef, onMounted, onUnmounted } from 'vue';
 import { invoke } from '@tauri-apps/api/core';
 import { listen, type UnlistenFn } from '@tauri-apps/api/event';
 import type { GroupMemberData } from '../../types/group';
+import type { SyncProgressEvent } from '../../types/sync';
 import { MessageType, CommandType, type MessageUpdateEvent } from '../../types/chat';
 
 /**
@@ -153,6 +154,7 @@ export function useGroupMembersCache() {
    */
   function useGroupMemberChangeListener(): void {
     let unlistenFn: UnlistenFn | null = null;
+    let unlistenSyncFn: UnlistenFn | null = null;
 
     onMounted(async () => {
       unlistenFn = await listen<MessageUpdateEvent>('message-updated', (event) => {
@@ -180,6 +182,14 @@ export function useGroupMembersCache() {
         }
       });
 
+      // Background member sync finished a group: reload it if it is already displayed
+      unlistenSyncFn = await listen<SyncProgressEvent>('sync-progress', (event) => {
+        const { domain, groupId } = event.payload;
+        if (domain === 'group-members' && groupId && groupMembersCache.value.has(groupId)) {
+          refreshGroupMembers(groupId);
+        }
+      });
+
       console.log('[useGroupMembersCache] Group member change listener registered');
     });
 
@@ -188,6 +198,7 @@ export function useGroupMembersCache() {
         unlistenFn();
         console.log('[useGroupMembersCache] Group member change listener unregistered');
       }
+      unlistenSyncFn?.();
     });
   }
 
@@ -1,6 +1,7 @@
 import { ref } from 'vue';
 import { invoke, convertFileSrc } from '@tauri-apps/api/core';
 import { open } from '@tauri-apps/plugin-dialog';
+import { getErrorMessage } from '../types/errors';
 
 /**
  * Helper to convert Blob to base64 string
@@ -71,7 +72,7 @@ export function useAvatarPicker() {
       return true;
     } catch (err) {
       console.error('Error uploading avatar:', err);
-      error.value = err instanceof Error ? err.message : 'Failed to upload avatar';
+      error.value = getErrorMessage(err, 'Failed to upload avatar');
       return false;
     } finally {
       isUploading.value = false;
@@ -93,7 +94,7 @@ export function useAvatarPicker() {
       return url;
     } catch (err) {
       console.error('Error uploading image:', err);
-      error.value = err instanceof Error ? err.message : 'Failed to upload image';
+      error.value = getErrorMessage(err, 'Failed to upload image');
       return null;
     } finally {
       isUploading.value = false;
@@ -115,7 +116,7 @@ export function useAvatarPicker() {
       return true;
     } catch (err) {
       console.error('Error uploading group avatar:', err);
-      error.value = err instanceof Error ? err.message : 'Failed to upload group avatar';
+      error.value = getErrorMessage(err, 'Failed to upload group avatar');
       return false;
     } finally {
       isUploading.value = false;
@@ -1,6 +1,7 @@
 import { ref } from 'vue';
 import { invoke } from '@tauri-apps/api/core';
 import type { RelationUser } from '../types/relations';
+import { getErrorMessage } from '../types/errors';
 
 /**
  * Composable for relation management actions
@@ -48,7 +49,7 @@ export function useRelationActions() {
       return data;
     } catch (err) {
       console.error('[useRelationActions] Failed to get user profile:', err);
-      error.value = err instanceof Error ? err.message : 'Failed to get user profile';
+      error.value = getErrorMessage(err, 'Failed to get user profile');
       throw err;
     } finally {
       loading.value = false;
@@ -69,7 +70,7 @@ export function useRelationActions() {
       // Backend will emit relation-updated event after WebSocket sync
     } catch (err) {
       console.error('[useRelationActions] Failed to add friend:', err);
-      error.value = err instanceof Error ? err.message : 'Failed to add friend';
+      error.value = getErrorMessage(err, 'Failed to add friend');
       throw err;
     } finally {
       loading.value = false;
@@ -93,7 +94,7 @@ export function useRelationActions() {
       // Backend will emit relation-updated event after WebSocket sync
     } catch (err) {
       console.error('[useRelationActions] Failed to update remark name:', err);
-      error.value = err instanceof Error ? err.message : 'Failed to update remark name';
+      error.value = getErrorMessage(err, 'Failed to update remark name');
       throw err;
     } finally {
       loading.value = false;
@@ -118,7 +119,7 @@ export function useRelationActions() {
       // Backend will emit relation-updated event after WebSocket sync
     } catch (err) {
       console.error('[useRelationActions] Failed to block user:', err);
-      error.value = err instanceof Error ? err.message : 'Failed to block user';
+      error.value = getErrorMessage(err, 'Failed to block user');
       throw err;
     } finally {
       loading.value = false;
@@ -141,7 +142,7 @@ export function useRelationActions() {
       // Backend will emit relation-updated event after WebSocket sync
     } catch (err) {
       console.error('[useRelationActions] Failed to remove friend:', err);
-      error.value = err instanceof Error ? err.message : 'Failed to remove friend';
+      error.value = getErrorMessage(err, 'Failed to remove friend');
       throw err;
     } finally {
       loading.value = false;
@@ -167,7 +168,7 @@ export function useRelationActions() {
       // Event type depends on whether user was originally a friend
     } catch (err) {
       console.error('[useRelationActions] Failed to unblock user:', err);
-      error.value = err instanceof Error ? err.message : 'Failed to unblock user';
+      error.value = getErrorMessage(err, 'Failed to unblock user');
       throw err;
     } finally {
       loading.value = false;
@@ -191,7 +192,7 @@ export function useRelationActions() {
       // Backend will emit relation-updated event after WebSocket sync
     } catch (err) {
       console.error('[useRelationActions] Failed to hide blocked user:', err);
-      error.value = err instanceof Error ? err.message : 'Failed to hide blocked user';
+      error.value = getErrorMessage(err, 'Failed to hide blocked user');
       throw err;
     } finally {
       loading.value = false;
@@ -3,6 +3,7 @@ import { invoke } from '@tauri-apps/api/core';
 import type { RelationUser } from '../types/relations';
 import { useRelationEvents } from './useRelationEvents';
 import { useRelationsState } from './useRelationsState';
+import { getErrorMessage } from '../types/errors';
 
 interface UIRelationData {
   users: RelationUser[];
@@ -55,7 +56,7 @@ export function useRelationsDisplay() {
       );
     } catch (err) {
       console.error('[useRelationsDisplay] Failed to initialize relations:', err);
-      error.value = err instanceof Error ? err.message : 'Failed to load relations data';
+      error.value = getErrorMessage(err, 'Failed to load relations data');
     } finally {
       loading.value = false;
     }
@@ -0,0 +1,95 @@
+import { computed, onMounted, onUnmounted, ref } from 'vue';
+import { invoke } from '@tauri-apps/api/core';
+import { listen, type UnlistenFn } from '@tauri-apps/api/event';
+import type {
+  SyncCompletedEvent,
+  SyncDomain,
+  SyncFailedEvent,
+  SyncStartedEvent,
+  SyncStatus,
+} from '../types/sync';
+
+/** A domain not synced for this long is shown as stale */
+const STALE_AFTER_MS = 15 * 60 * 1000;
+
+/** Domains synced at startup and on every reconnect */
+const TRACKED_DOMAINS: SyncDomain[] = ['relations', 'conversations', 'user-groups'];
+
+/**
+ * Composable tracking whether the local data is in sync with the server
+ *
+ * Seeds the last successful sync times from get_sync_status, then follows the
+ * sync-started, sync-completed and sync-failed events.
+ *
+ * Usage:
+ * ```typescript
+ * const { syncing, failures, stale } = useSyncStatus();
+ * ```
+ */
+export function useSyncStatus() {
+  const lastSynced = ref<SyncStatus>({});
+  const running = ref(new Set<SyncDomain>());
+  const failures = ref<Partial<Record<SyncDomain, string>>>({});
+  const now = ref(Date.now());
+  const unlistenFns: UnlistenFn[] = [];
+  let clock: ReturnType<typeof setInterval> | undefined;
+
+  const syncing = computed(() => running.value.size > 0);
+
+  const stale = computed(() =>
+    TRACKED_DOMAINS.some((domain) => {
+      const timestamp = lastSynced.value[domain];
+      return timestamp === undefined || now.value - timestamp > STALE_AFTER_MS;
+    })
+  );
+
+  const failedDomains = computed(() => Object.keys(failures.value) as SyncDomain[]);
+
+  onMounted(async () => {
+    try {
+      lastSynced.value = await invoke<SyncStatus>('get_sync_status');
+    } catch (error) {
+      console.error('[useSyncStatus] Failed to load sync status:', error);
+    }
+
+    unlistenFns.push(
+      await listen<SyncStartedEvent>('sync-started', (event) => {
+        running.value = new Set(running.value).add(event.payload.domain);
+      }),
+      await listen<SyncCompletedEvent>('sync-completed', (event) => {
+        const { domain, timestamp } = event.payload;
+        const next = new Set(running.value);
+        next.delete(domain);
+        running.value = next;
+        lastSynced.value = { ...lastSynced.value, [domain]: timestamp };
+        const remaining = { ...failures.value };
+        delete remaining[domain];
+        failures.value = remaining;
+      }),
+      await listen<SyncFailedEvent>('sync-failed', (event) => {
+        const { domain, error } = event.payload;
+        const next = new Set(running.value);
+        next.delete(domain);
+        running.value = next;
+        failures.value = { ...failures.value, [domain]: error };
+      })
+    );
+
+    clock = setInterval(() => {
+      now.value = Date.now();
+    }, 60 * 1000);
+  });
+
+  onUnmounted(() => {
+    unlistenFns.forEach((unlisten) => unlisten());
+    if (clock) clearInterval(clock);
+  });
+
+  return {
+    lastSynced,
+    syncing,
+    stale,
+    failures,
+    failedDomains,
+  };
+}
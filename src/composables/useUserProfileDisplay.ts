@@ -2,6 +2,7 @@ import { ref, onMounted, onUnmounted } from 'vue';
 import { listen, type UnlistenFn } from '@tauri-apps/api/event';
 import { useUserProfile } from './useUserProfile';
 import type { UserProfileData } from '../types/app-state';
+import { getErrorMessage } from '../types/errors';
 
 /**
  * Composable for components that need to display user profile information
@@ -26,7 +27,7 @@ export function useUserProfileDisplay() {
       userProfile.value = profile;
     } catch (err) {
       console.error('Failed to initialize user profile:', err);
-      error.value = err instanceof Error ? err.message : 'Failed to load profile';
+      error.value = getErrorMessage(err, 'Failed to load profile');
     } finally {
       loading.value = false;
     }
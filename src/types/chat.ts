@@ -82,6 +82,21 @@ export enum MessageType {
   SINGLE = 1,
   /** Group command message */
   GROUP_COMMAND = 2,
+  /** Message type introduced by a newer server, not understood by this client */
+  UNSUPPORTED = -1,
+}
+
+/** Text shown in place of messages this client cannot display */
+export const UNSUPPORTED_MESSAGE_TEXT = 'Unsupported message, please update Ripple to view it';
+
+/**
+ * Text to display for a message, falling back to a placeholder for unsupported types
+ */
+export function getMessageDisplayText(message: Message): string {
+  if (Number(message.messageType) === MessageType.UNSUPPORTED) {
+    return UNSUPPORTED_MESSAGE_TEXT;
+  }
+  return message.text ?? '';
 }
 
 /**
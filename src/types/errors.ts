@@ -0,0 +1,41 @@
+// Errors returned by Tauri commands, serialized by the backend as `{ kind, message, ... }`
+export type CommandErrorKind =
+  | 'network'
+  | 'unauthorized'
+  | 'forbidden'
+  | 'notFound'
+  | 'rateLimited'
+  | 'validation'
+  | 'server'
+  | 'business'
+  | 'internal';
+
+export interface CommandError {
+  kind: CommandErrorKind;
+  message: string;
+  retryAfter?: number | null; // seconds, rateLimited only
+  status?: number; // HTTP status, server only
+  code?: number; // application error code, business only
+}
+
+export function isCommandError(err: unknown): err is CommandError {
+  return (
+    typeof err === 'object' &&
+    err !== null &&
+    typeof (err as CommandError).kind === 'string' &&
+    typeof (err as CommandError).message === 'string'
+  );
+}
+
+/**
+ * Get a displayable message from anything thrown by `invoke`
+ */
+export function getErrorMessage(err: unknown, fallback?: string): string {
+  if (isCommandError(err) || err instanceof Error) {
+    return err.message;
+  }
+  if (typeof err === 'string') {
+    return err;
+  }
+  return fallback ?? String(err);
+}
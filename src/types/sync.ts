@@ -0,0 +1,45 @@
+/**
+ * Data sets kept in sync with the server
+ * Matches the Rust SyncDomain enum
+ */
+export type SyncDomain = 'relations' | 'conversations' | 'user-groups' | 'group-members' | 'messages';
+
+/**
+ * Payload of the sync-started event
+ */
+export interface SyncStartedEvent {
+  domain: SyncDomain;
+}
+
+/**
+ * Payload of the sync-progress event
+ */
+export interface SyncProgressEvent {
+  domain: SyncDomain;
+  /** Group the step belongs to, for group member syncs */
+  groupId?: string;
+  completed: number;
+  total: number;
+}
+
+/**
+ * Payload of the sync-completed event
+ */
+export interface SyncCompletedEvent {
+  domain: SyncDomain;
+  /** Unix timestamp in milliseconds */
+  timestamp: number;
+}
+
+/**
+ * Payload of the sync-failed event
+ */
+export interface SyncFailedEvent {
+  domain: SyncDomain;
+  error: string;
+}
+
+/**
+ * Result of the get_sync_status command: last successful sync time (ms) per domain
+ */
+export type SyncStatus = Partial<Record<SyncDomain, number>>;